@@ -0,0 +1,120 @@
+use std::any::Any;
+use std::io::Write;
+use serialport::SerialPort;
+use alas_lib::calibration::CalibrationStatus;
+use alas_lib::state::{AlasMessage, UnsafeState};
+use crate::lcd_display::home_screen::HomeScreen;
+use crate::lcd_display::matrix_orbital::{set_cursor_bytes, BOTTOM_LEFT_BUTTON, SCREEN_WIDTH, TOP_LEFT_BUTTON};
+use crate::lcd_display::screen::Screen;
+
+/// Runs the silence-threshold calibration. The center button starts a run, or
+/// saves the thresholds once they have been proposed; that is dispatched from
+/// the LCD's button handler, since screens cannot talk to the bus.
+#[derive(Clone, PartialEq)]
+pub struct CalibrationScreen {
+    pub status: CalibrationStatus,
+}
+
+impl CalibrationScreen {
+    pub fn new(app_state: &UnsafeState) -> Self {
+        CalibrationScreen {
+            status: app_state.calibration.clone(),
+        }
+    }
+
+    fn lines(&self) -> [String; 4] {
+        match &self.status {
+            CalibrationStatus::Idle => [
+                "CALIBRATE LEVELS".to_string(),
+                "Play silence, then".to_string(),
+                "program audio".to_string(),
+                "Center: start".to_string(),
+            ],
+            CalibrationStatus::Measuring { window_secs, elapsed_secs } => [
+                "CALIBRATING".to_string(),
+                format!("Listening {}/{}s", elapsed_secs, window_secs),
+                String::new(),
+                String::new(),
+            ],
+            CalibrationStatus::Proposed { proposal } => [
+                format!("Floor{:>5.0} Prog{:>4.0}", proposal.noise_floor_db, proposal.program_db),
+                format!("Start {:.1} dB", proposal.start_threshold_db),
+                format!("Stop  {:.1} dB", proposal.stop_threshold_db),
+                "Center: save".to_string(),
+            ],
+            CalibrationStatus::Saved { proposal } => [
+                "THRESHOLDS SAVED".to_string(),
+                format!("Start {:.1} dB", proposal.start_threshold_db),
+                format!("Stop  {:.1} dB", proposal.stop_threshold_db),
+                "Center: run again".to_string(),
+            ],
+            CalibrationStatus::Failed { reason } => [
+                "CALIBRATION FAILED".to_string(),
+                reason.chars().take(SCREEN_WIDTH as usize).collect(),
+                reason.chars().skip(SCREEN_WIDTH as usize).take(SCREEN_WIDTH as usize).collect(),
+                "Center: retry".to_string(),
+            ],
+        }
+    }
+}
+
+impl Screen for CalibrationScreen {
+    fn draw_screen(&self, port: &mut dyn Write) {
+        for (row, line) in self.lines().iter().enumerate() {
+            port.write_all(&set_cursor_bytes(1, row as u8 + 1)).unwrap();
+            port.write_all(format!("{:<20}", line).as_bytes()).unwrap();
+        }
+    }
+
+    fn redraw_screen(&self, port: &mut Box<dyn SerialPort>) {
+        self.draw_screen(port);
+    }
+
+    fn handle_button(&self, app_state: &UnsafeState, button: u8) -> Option<Box<dyn Screen>> {
+        match button {
+            TOP_LEFT_BUTTON | BOTTOM_LEFT_BUTTON => Some(Box::new(HomeScreen::new(app_state))),
+            _ => None,
+        }
+    }
+
+    fn handle_message(&self, _: &UnsafeState, message: AlasMessage) -> Option<Box<dyn Screen>> {
+        match message {
+            AlasMessage::CalibrationChanged { status } => Some(Box::new(CalibrationScreen { status })),
+            _ => None,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alas_lib::calibration::CalibrationProposal;
+
+    #[test]
+    fn test_lines_fit_the_screen() {
+        let proposal = CalibrationProposal {
+            noise_floor_db: -72.0,
+            program_db: -18.0,
+            start_threshold_db: -52.5,
+            stop_threshold_db: -62.5,
+        };
+        let screen = CalibrationScreen { status: CalibrationStatus::Proposed { proposal } };
+        let lines = screen.lines();
+        assert_eq!(lines[0], "Floor  -72 Prog -18");
+        assert_eq!(lines[1], "Start -52.5 dB");
+
+        let screen = CalibrationScreen {
+            status: CalibrationStatus::Failed {
+                reason: "Program (-50.0 dB) is too close to the noise floor (-55.0 dB)".to_string(),
+            },
+        };
+        for status in [screen.status.clone(), CalibrationStatus::Idle, CalibrationStatus::Saved { proposal }] {
+            let screen = CalibrationScreen { status };
+            assert!(screen.lines().iter().all(|line| line.len() <= SCREEN_WIDTH as usize));
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::any::Any;
+use std::io::Write;
+use serialport::SerialPort;
+use alas_lib::state::{AlasMessage, UnsafeState};
+use crate::lcd_display::home_screen::HomeScreen;
+use crate::lcd_display::matrix_orbital::set_cursor_bytes;
+use crate::lcd_display::screen::Screen;
+
+/// Shown when recording has been stopped because the disk is nearly full
+#[derive(Clone)]
+pub struct DiskFullScreen {
+    pub free_mb: u64,
+}
+
+impl Screen for DiskFullScreen {
+    fn draw_screen(&self, port: &mut dyn Write) {
+        port.write_all("DISK SPACE LOW".as_bytes()).unwrap();
+        port.write_all(&set_cursor_bytes(1, 2)).unwrap();
+        port.write_all("Recording stopped".as_bytes()).unwrap();
+        port.write_all(&set_cursor_bytes(1, 3)).unwrap();
+        port.write_all(format!("{} MB free", self.free_mb).as_bytes()).unwrap();
+        port.write_all(&set_cursor_bytes(1, 4)).unwrap();
+        port.write_all("Any button: dismiss".as_bytes()).unwrap();
+    }
+
+    fn redraw_screen(&self, port: &mut Box<dyn SerialPort>) {
+        port.write_all(&set_cursor_bytes(1, 3)).unwrap();
+        port.write_all(format!("{:<20}", format!("{} MB free", self.free_mb)).as_bytes()).unwrap();
+    }
+
+    fn handle_button(&self, app_state: &UnsafeState, _: u8) -> Option<Box<dyn Screen>> {
+        Some(Box::new(HomeScreen::new(app_state)))
+    }
+
+    fn handle_message(&self, app_state: &UnsafeState, message: AlasMessage) -> Option<Box<dyn Screen>> {
+        match message {
+            AlasMessage::DiskSpaceLow { free_mb } => {
+                Some(Box::new(DiskFullScreen { free_mb }))
+            }
+            AlasMessage::DiskSpaceRecovered { .. } => {
+                Some(Box::new(HomeScreen::new(app_state)))
+            }
+            _ => None,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -2,6 +2,7 @@ use crate::lcd_display::matrix_orbital;
 use crate::lcd_display::matrix_orbital::{CENTER_BUTTON, TOP_LEFT_BUTTON};
 use crate::lcd_display::menu_screen::MenuScreen;
 use crate::lcd_display::screen::Screen;
+use alas_lib::delay::DelayStatus;
 use alas_lib::state::AlasMessage;
 use alas_lib::state::UnsafeState;
 use serialport::SerialPort;
@@ -9,10 +10,13 @@ use std::any::Any;
 use std::io::Write;
 use alas_lib::wifi::AlasWiFiState;
 use crate::lcd_display::upload_progress::UploadScreen;
+use crate::lcd_display::disk_full_screen::DiskFullScreen;
 
 impl Screen for HomeScreen {
     fn draw_screen(&self, port: &mut dyn Write) {
         port.write_all("88.7 RIDGELINE RADIO".as_bytes()).unwrap();
+        self.draw_delay(port);
+        port.write_all(&matrix_orbital::set_cursor_bytes(1, 2)).unwrap();
         port.write_all("Wi-Fi? ".as_bytes()).unwrap();
         // // TODO(!): we need to figure out how to make global state accessible to the UI.
         // // TODO(!): we can use messaging to trigger updates, but still should be central repo?
@@ -36,6 +40,9 @@ impl Screen for HomeScreen {
 
         port.write_all(&[254, 124, 3, 3, 0, self.left_volume]).unwrap();
         port.write_all(&[254, 124, 3, 4, 0, self.right_volume]).unwrap();
+
+        self.draw_marker(port);
+        self.draw_listeners(port);
     }
 
     fn redraw_screen(&self, port: &mut Box<dyn SerialPort>) {
@@ -59,6 +66,10 @@ impl Screen for HomeScreen {
         } else {
             port.write_all(b"N").unwrap();
         }
+
+        self.draw_marker(port);
+        self.draw_listeners(port);
+        self.draw_delay(port);
         // println!("Left volume {:?} Right Volume {:?}", self.left_volume, self.right_volume);
     }
 
@@ -83,6 +94,9 @@ impl Screen for HomeScreen {
                         cell_ready: self.cell_ready,
                         left_volume: left_scaled,
                         right_volume: right_scaled,
+                        marker: self.marker,
+                        listeners: self.listeners,
+                        delay: self.delay,
                     })
                 )
             }
@@ -107,6 +121,59 @@ impl Screen for HomeScreen {
                     })
                 )
             }
+            AlasMessage::MarkerAdded { count, .. } => {
+                Some(
+                    Box::new(HomeScreen {
+                        marker: MarkerIndicator::Added(count),
+                        ..*self
+                    })
+                )
+            }
+            AlasMessage::MarkerRejected => {
+                Some(
+                    Box::new(HomeScreen {
+                        marker: MarkerIndicator::Rejected,
+                        ..*self
+                    })
+                )
+            }
+            AlasMessage::RecordingStarted => {
+                Some(
+                    Box::new(HomeScreen {
+                        marker: MarkerIndicator::Hidden,
+                        ..*self
+                    })
+                )
+            }
+            AlasMessage::ListenerCountChanged { listeners } => {
+                Some(
+                    Box::new(HomeScreen {
+                        listeners,
+                        ..*self
+                    })
+                )
+            }
+            AlasMessage::StreamDelayChanged { status } => {
+                Some(
+                    Box::new(HomeScreen {
+                        delay: status,
+                        ..*self
+                    })
+                )
+            }
+            AlasMessage::DelayDumped { status } => {
+                Some(
+                    Box::new(HomeScreen {
+                        delay: Some(status),
+                        ..*self
+                    })
+                )
+            }
+            AlasMessage::DiskSpaceLow { free_mb } => {
+                Some(
+                    Box::new(DiskFullScreen { free_mb })
+                )
+            }
             _ => None,
         }
     }
@@ -116,12 +183,27 @@ impl Screen for HomeScreen {
     }
 }
 
+/// Feedback for the cue marker button, shown at the end of the second row
+#[derive(Clone, Copy, PartialEq)]
+enum MarkerIndicator {
+    Hidden,
+    /// How many markers the current recording has
+    Added(usize),
+    /// The button was pressed while nothing was recording
+    Rejected,
+}
+
 #[derive(Clone)]
 pub struct HomeScreen {
     wifi_ready: bool,
     cell_ready: bool,
     left_volume: u8,
     right_volume: u8,
+    marker: MarkerIndicator,
+    /// Listeners on our Icecast mounts, once the server has told us
+    listeners: Option<u32>,
+    /// The stream's profanity delay, when one is configured
+    delay: Option<DelayStatus>,
 }
 
 impl HomeScreen {
@@ -131,8 +213,50 @@ impl HomeScreen {
             cell_ready: app_state.cell_on,
             left_volume: 0,
             right_volume: 0,
+            marker: MarkerIndicator::Hidden,
+            listeners: app_state.listeners.total_listeners(),
+            delay: app_state.stream_delay,
         }
     }
+
+    fn draw_delay(&self, port: &mut dyn Write) {
+        // The delay depth takes the place of "RADIO" at the end of the first
+        // row, e.g. "D12s" and "D3s+" while it builds back up after a dump
+        let text = match self.delay {
+            Some(delay) => format!(
+                "D{}s{}",
+                delay.depth_secs.round() as u32,
+                if delay.rebuilding { "+" } else { "" }
+            ),
+            None => "RADIO".to_string(),
+        };
+        port.write_all(&matrix_orbital::set_cursor_bytes(16, 1)).unwrap();
+        port.write_all(format!("{:<5.5}", text).as_bytes()).unwrap();
+    }
+
+    fn draw_marker(&self, port: &mut dyn Write) {
+        let text = match self.marker {
+            MarkerIndicator::Hidden => String::new(),
+            MarkerIndicator::Added(count) => format!("M{}", count),
+            MarkerIndicator::Rejected => "M--".to_string(),
+        };
+        // Wi-Fi? Y Cell N leaves columns 17-20 free
+        port.write_all(&matrix_orbital::set_cursor_bytes(17, 2)).unwrap();
+        port.write_all(format!("{:<4.4}", text).as_bytes()).unwrap();
+    }
+
+    fn draw_listeners(&self, port: &mut dyn Write) {
+        // The level meters stop at column 16, leaving columns 17-20 on both rows
+        let (count, label) = match self.listeners {
+            Some(listeners) if listeners > 9999 => ("9999".to_string(), "LSTN"),
+            Some(listeners) => (format!("{:>4}", listeners), "LSTN"),
+            None => (String::new(), ""),
+        };
+        port.write_all(&matrix_orbital::set_cursor_bytes(17, 3)).unwrap();
+        port.write_all(format!("{:<4}", count).as_bytes()).unwrap();
+        port.write_all(&matrix_orbital::set_cursor_bytes(17, 4)).unwrap();
+        port.write_all(format!("{:<4}", label).as_bytes()).unwrap();
+    }
 }
 
 fn scale_db_to_display(db: f32) -> u8 {
@@ -140,7 +264,8 @@ fn scale_db_to_display(db: f32) -> u8 {
     let min_db = -60.0;
     let max_db = 0.0;
     let min_scale = 0.0;
-    let max_scale = 80.0;
+    // 70 pixels fills columns 3-16, leaving room for the listener count
+    let max_scale = 70.0;
 
     // Clamp the input dB value to ensure it falls within the expected range
     let db_clamped = db.clamp(min_db, max_db);
@@ -148,7 +273,7 @@ fn scale_db_to_display(db: f32) -> u8 {
     // Compute the ratio of where db_clamped falls between min_db and max_db
     let ratio = (db_clamped - min_db) / (max_db - min_db);
 
-    // Scale this ratio to the display range (0 to 80)
+    // Scale this ratio to the display range (0 to 70)
     let scaled = ratio * (max_scale - min_scale) + min_scale;
 
     // Round the scaled value and convert to integer
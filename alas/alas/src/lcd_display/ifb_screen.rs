@@ -0,0 +1,120 @@
+use std::any::Any;
+use std::io::Write;
+use serialport::SerialPort;
+use alas_lib::ifb::{IfbPlayback, IfbStatus};
+use alas_lib::state::{AlasMessage, UnsafeState};
+use crate::lcd_display::home_screen::HomeScreen;
+use crate::lcd_display::matrix_orbital::{set_cursor_bytes, BOTTOM_LEFT_BUTTON, SCREEN_WIDTH, TOP_LEFT_BUTTON};
+use crate::lcd_display::screen::Screen;
+
+/// Shows the return audio player. The center button switches it on or off;
+/// that is dispatched from the LCD's button handler, since screens cannot talk
+/// to the bus.
+#[derive(Clone, PartialEq)]
+pub struct IfbScreen {
+    pub status: IfbStatus,
+    pub configured: bool,
+}
+
+impl IfbScreen {
+    pub fn new(app_state: &UnsafeState) -> Self {
+        IfbScreen {
+            status: app_state.ifb.clone(),
+            configured: app_state.config.ifb.is_some(),
+        }
+    }
+
+    fn lines(&self) -> [String; 4] {
+        if !self.configured {
+            return [
+                "RETURN AUDIO (IFB)".to_string(),
+                "Not set up".to_string(),
+                String::new(),
+                String::new(),
+            ];
+        }
+        let playback = match self.status.playback {
+            IfbPlayback::Off => "Off",
+            IfbPlayback::Connecting => "Connecting",
+            IfbPlayback::Buffering => "Buffering",
+            IfbPlayback::Playing => "Playing",
+            IfbPlayback::Failed => "Failed",
+        };
+        let detail = match (&self.status.error, self.status.playback) {
+            (Some(error), IfbPlayback::Failed) => error.chars().take(SCREEN_WIDTH as usize).collect(),
+            (_, IfbPlayback::Off) => String::new(),
+            _ => format!("Buf {}ms Drop {}", self.status.buffered_ms, self.status.underruns)
+                .chars()
+                .take(SCREEN_WIDTH as usize)
+                .collect(),
+        };
+        [
+            "RETURN AUDIO (IFB)".to_string(),
+            playback.to_string(),
+            detail,
+            if self.status.enabled { "Center: turn off" } else { "Center: turn on" }.to_string(),
+        ]
+    }
+}
+
+impl Screen for IfbScreen {
+    fn draw_screen(&self, port: &mut dyn Write) {
+        for (row, line) in self.lines().iter().enumerate() {
+            port.write_all(&set_cursor_bytes(1, row as u8 + 1)).unwrap();
+            port.write_all(format!("{:<20}", line).as_bytes()).unwrap();
+        }
+    }
+
+    fn redraw_screen(&self, port: &mut Box<dyn SerialPort>) {
+        self.draw_screen(port);
+    }
+
+    fn handle_button(&self, app_state: &UnsafeState, button: u8) -> Option<Box<dyn Screen>> {
+        match button {
+            TOP_LEFT_BUTTON | BOTTOM_LEFT_BUTTON => Some(Box::new(HomeScreen::new(app_state))),
+            _ => None,
+        }
+    }
+
+    fn handle_message(&self, app_state: &UnsafeState, message: AlasMessage) -> Option<Box<dyn Screen>> {
+        match message {
+            AlasMessage::IfbChanged { status } => Some(Box::new(IfbScreen {
+                status,
+                configured: app_state.config.ifb.is_some(),
+            })),
+            _ => None,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_fit_the_screen() {
+        let status = IfbStatus {
+            enabled: true,
+            playback: IfbPlayback::Playing,
+            buffered_ms: 1_250,
+            underruns: 12,
+            ..Default::default()
+        };
+        let screen = IfbScreen { status, configured: true };
+        assert_eq!(screen.lines()[2], "Buf 1250ms Drop 12");
+        assert_eq!(screen.lines()[3], "Center: turn off");
+
+        let status = IfbStatus {
+            enabled: true,
+            playback: IfbPlayback::Failed,
+            error: Some("Could not reach the return feed: connection refused".to_string()),
+            ..Default::default()
+        };
+        let screen = IfbScreen { status, configured: true };
+        assert!(screen.lines().iter().all(|line| line.len() <= SCREEN_WIDTH as usize));
+    }
+}
@@ -0,0 +1,114 @@
+use std::any::Any;
+use std::io::Write;
+use serialport::SerialPort;
+use alas_lib::mixer::InputLevel;
+use alas_lib::state::{AlasMessage, UnsafeState};
+use crate::lcd_display::home_screen::HomeScreen;
+use crate::lcd_display::matrix_orbital::{set_cursor_bytes, BOTTOM_LEFT_BUTTON, SCREEN_HEIGHT, TOP_LEFT_BUTTON};
+use crate::lcd_display::screen::Screen;
+
+/// The bar sits between the name and the level, in columns 7-16
+const BAR_COLUMN: u8 = 7;
+const BAR_PIXELS: f32 = 50.0;
+const LEVEL_COLUMN: u8 = 17;
+
+/// One meter per mixed input, as many as fit on the screen
+#[derive(Clone, PartialEq)]
+pub struct InputsScreen {
+    levels: Vec<InputLevel>,
+}
+
+impl InputsScreen {
+    pub fn new(app_state: &UnsafeState) -> Self {
+        InputsScreen {
+            levels: app_state.input_levels.clone(),
+        }
+    }
+
+    fn draw_levels(&self, port: &mut dyn Write) {
+        for (row, level) in self.levels.iter().take(SCREEN_HEIGHT as usize).enumerate() {
+            let row = row as u8 + 1;
+            let name: String = level.name.chars().take(BAR_COLUMN as usize - 2).collect();
+            port.write_all(&set_cursor_bytes(1, row)).unwrap();
+            port.write_all(format!("{:<6}", name).as_bytes()).unwrap();
+            port.write_all(&[254, 124, BAR_COLUMN, row, 0, bar_width(level)]).unwrap();
+            port.write_all(&set_cursor_bytes(LEVEL_COLUMN, row)).unwrap();
+            port.write_all(level_label(level).as_bytes()).unwrap();
+        }
+    }
+}
+
+/// The louder side of the input, from -60 dB to 0 dB across the bar
+fn bar_width(level: &InputLevel) -> u8 {
+    let db = level.left_db.max(level.right_db).clamp(-60.0, 0.0);
+    ((db + 60.0) / 60.0 * BAR_PIXELS).round() as u8
+}
+
+fn level_label(level: &InputLevel) -> String {
+    if level.muted {
+        "MUTE".to_string()
+    } else {
+        format!("{:>4.0}", level.left_db.max(level.right_db))
+    }
+}
+
+impl Screen for InputsScreen {
+    fn draw_screen(&self, port: &mut dyn Write) {
+        if self.levels.is_empty() {
+            port.write_all(&set_cursor_bytes(1, 1)).unwrap();
+            port.write_all(b"INPUT METERS").unwrap();
+            port.write_all(&set_cursor_bytes(1, 2)).unwrap();
+            port.write_all(b"No inputs set up").unwrap();
+            return;
+        }
+        self.draw_levels(port);
+    }
+
+    fn redraw_screen(&self, port: &mut Box<dyn SerialPort>) {
+        self.draw_levels(port);
+    }
+
+    fn handle_button(&self, app_state: &UnsafeState, button: u8) -> Option<Box<dyn Screen>> {
+        match button {
+            TOP_LEFT_BUTTON | BOTTOM_LEFT_BUTTON => Some(Box::new(HomeScreen::new(app_state))),
+            _ => None,
+        }
+    }
+
+    fn handle_message(&self, app_state: &UnsafeState, message: AlasMessage) -> Option<Box<dyn Screen>> {
+        // The mixer keeps the levels in the state, so pick them up whenever
+        // the audio moves and redraw only if they changed
+        match message {
+            AlasMessage::VolumeChange { .. } if app_state.input_levels != self.levels => {
+                Some(Box::new(InputsScreen::new(app_state)))
+            },
+            _ => None,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bar_and_label() {
+        let mut level = InputLevel {
+            name: "Host mic".to_string(),
+            left_db: -30.0,
+            right_db: -90.0,
+            muted: false,
+        };
+        assert_eq!(bar_width(&level), 25);
+        assert_eq!(level_label(&level), " -30");
+
+        level.left_db = 3.0;
+        assert_eq!(bar_width(&level), 50);
+        level.muted = true;
+        assert_eq!(level_label(&level), "MUTE");
+    }
+}
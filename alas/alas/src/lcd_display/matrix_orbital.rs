@@ -17,9 +17,9 @@ H = Bottom button
 **/
 pub const TOP_LEFT_BUTTON: u8 = 65;
 pub const UP_BUTTON: u8 = 66;
-const LEFT_BUTTON: u8 = 68;
+pub const LEFT_BUTTON: u8 = 68;
 pub const CENTER_BUTTON: u8 = 69;
-const RIGHT_BUTTON: u8 = 67;
+pub const RIGHT_BUTTON: u8 = 67;
 pub const DOWN_BUTTON: u8 = 72;
 pub const BOTTOM_LEFT_BUTTON: u8 = 71;
 const SET_DISPLAY_BRIGHTNESS: &[u8; 2] = &[254, 156];
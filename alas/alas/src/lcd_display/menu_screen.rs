@@ -1,4 +1,7 @@
+use crate::lcd_display::calibration_screen::CalibrationScreen;
 use crate::lcd_display::home_screen::HomeScreen;
+use crate::lcd_display::ifb_screen::IfbScreen;
+use crate::lcd_display::inputs_screen::InputsScreen;
 use crate::lcd_display::ip_screen::IPScreen;
 use crate::lcd_display::status_screen::StatusScreen;
 use crate::lcd_display::matrix_orbital::{
@@ -33,9 +36,9 @@ const MENU_OPTIONS: [&str; 7] = [
     "Reconfigure WiFi",
     "Reboot",
     "Shut Down",
-    "Reserved",
-    "Reserved",
-    "Reserved",
+    "Calibrate Levels",
+    "Input Meters",
+    "Return Audio",
 ];
 
 impl Screen for MenuScreen {
@@ -138,6 +141,9 @@ impl Screen for MenuScreen {
                             }
                         }
                     },
+                    4 => Some(Box::new(CalibrationScreen::new(app_state))),
+                    5 => Some(Box::new(InputsScreen::new(app_state))),
+                    6 => Some(Box::new(IfbScreen::new(app_state))),
                     _ => Some(Box::new(HomeScreen::new(app_state))),
                 }
             }
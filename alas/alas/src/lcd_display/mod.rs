@@ -1,5 +1,8 @@
 use crate::lcd_display::home_screen::HomeScreen;
-use crate::lcd_display::matrix_orbital::clear_screen;
+use crate::lcd_display::calibration_screen::CalibrationScreen;
+use crate::lcd_display::ifb_screen::IfbScreen;
+use crate::lcd_display::matrix_orbital::{clear_screen, CENTER_BUTTON, LEFT_BUTTON, RIGHT_BUTTON};
+use alas_lib::calibration::{CalibrationStatus, DEFAULT_WINDOW_SECS};
 use alas_lib::state::AlasMessage;
 use alas_lib::state::AlasState;
 use alas_lib::state::SafeState;
@@ -11,13 +14,18 @@ use std::io::{self, Read, Write};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tokio::{join, select, signal, task};
 use udev::Enumerator;
 
+mod calibration_screen;
+mod disk_full_screen;
 mod home_screen;
+mod ifb_screen;
+mod inputs_screen;
 mod ip_screen;
 mod matrix_orbital;
 mod menu_screen;
@@ -118,6 +126,7 @@ fn change_on_air_lights_direct(is_recording: bool, is_streaming: bool, write_por
 }
 
 async fn handle_button(
+    bus: &Sender<AlasMessage>,
     display_state: DisplayState,
     app_state: &SafeState,
     button_pressed: u8,
@@ -125,6 +134,34 @@ async fn handle_button(
 ) {
     println!("📺 Button pressed: {:?}", button_pressed);
     let mut screen = display_state.write().await;
+
+    // Screens cannot talk to the bus, so button actions are dispatched here.
+    // The right button on the home screen drops a cue marker in the recording.
+    if button_pressed == RIGHT_BUTTON && screen.as_any().is::<HomeScreen>() {
+        let _ = bus.send(AlasMessage::AddMarker { label: None });
+        return;
+    }
+    // The left button dumps the profanity delay
+    if button_pressed == LEFT_BUTTON && screen.as_any().is::<HomeScreen>() {
+        let _ = bus.send(AlasMessage::DumpDelay);
+        return;
+    }
+    // The center button on the calibration screen starts a run, or saves its proposal
+    if button_pressed == CENTER_BUTTON && let Some(calibration) = screen.as_any().downcast_ref::<CalibrationScreen>() {
+        let _ = match calibration.status {
+            CalibrationStatus::Measuring { .. } => return,
+            CalibrationStatus::Proposed { .. } => bus.send(AlasMessage::ConfirmCalibration),
+            _ => bus.send(AlasMessage::StartCalibration { window_secs: DEFAULT_WINDOW_SECS }),
+        };
+        return;
+    }
+    // The center button on the return audio screen switches it on or off
+    if button_pressed == CENTER_BUTTON && let Some(ifb) = screen.as_any().downcast_ref::<IfbScreen>() {
+        if ifb.configured {
+            let _ = bus.send(AlasMessage::SetIfb { enabled: !ifb.status.enabled });
+        }
+        return;
+    }
     let app_state = app_state.read().await;
     let new_screen = (*screen).handle_button(&app_state, button_pressed);
     if let Some(new_screen) = new_screen {
@@ -254,6 +291,7 @@ async fn try_and_connect_to_serial_port(bus: &Sender<AlasMessage>, shared_state:
     let read_state = display_state.clone();
     let read_shared_state = shared_state.clone();
     let lcd_reader = start_reader_thread(
+        bus.clone(),
         read_state,
         read_shared_state,
         &mut port,
@@ -302,6 +340,7 @@ pub async fn start(
 }
 
 fn start_reader_thread(
+    bus: Sender<AlasMessage>,
     read_state: DisplayState,
     read_shared_state: SafeState,
     port: &mut Box<dyn SerialPort>
@@ -321,6 +360,7 @@ fn start_reader_thread(
                     match result {
                         Ok(Ok(button_pressed)) => {
                             handle_button(
+                                &bus,
                                 read_state.clone(),
                                 &read_shared_state,
                                 button_pressed,
@@ -378,6 +418,10 @@ fn start_writer_thread(
                             ).await;
                             // println!("📺✅️ Handling message...");
                         }
+                        // Missing a few messages only means a slightly stale screen
+                        Err(RecvError::Lagged(skipped)) => {
+                            println!("📺⚠️ LCD writer skipped {} messages", skipped);
+                        }
                         Err(e) => {
                             println!("📺❌ LCD writer error: {:?}", e);
                             break;
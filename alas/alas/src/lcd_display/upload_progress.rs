@@ -2,6 +2,7 @@ use std::any::Any;
 use std::io::Write;
 use serialport::SerialPort;
 use alas_lib::state::{AlasMessage, AlasUploadState, AlasUploadStatus, UnsafeState};
+use crate::lcd_display::disk_full_screen::DiskFullScreen;
 use crate::lcd_display::home_screen::HomeScreen;
 use crate::lcd_display::matrix_orbital::{set_cursor_bytes, DOWN_BUTTON, UP_BUTTON};
 use crate::lcd_display::screen::Screen;
@@ -45,6 +46,9 @@ impl Screen for UploadScreen {
             AlasMessage::RecordingStarted => {
                 Some(Box::new(HomeScreen::new(&app_state)))
             }
+            AlasMessage::DiskSpaceLow { free_mb } => {
+                Some(Box::new(DiskFullScreen { free_mb }))
+            }
             _ => {
                 None
             }
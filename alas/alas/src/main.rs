@@ -1,12 +1,24 @@
 mod lcd_display;
 mod web_server;
 
+use alas_lib::calibration::start_calibration_listener;
+use alas_lib::catalog::{find_catalog_file, start_catalog_listener, RecordingCatalog};
 use alas_lib::state::AlasMessage;
 use alas_lib::state::AlasState;
 use alas_lib::webhook::start_webhook_listener;
 use alas_lib::wifi::{ WiFiObserver };
 use alas_lib::cellular::{ CellObserver };
 use alas_lib::redundancy;
+use alas_lib::meter::MeterFeed;
+use alas_lib::monitor::{start_monitor, MonitorHandle};
+use alas_lib::verifier::start_off_air_verifier;
+use alas_lib::ifb::start_ifb_player;
+use alas_lib::level_history::start_level_history;
+use alas_lib::listeners::start_listener_stats_poller;
+use alas_lib::podcast::start_podcast_publisher;
+use alas_lib::schedule::start_schedule_watcher;
+use alas_lib::storage::start_storage_watcher;
+use alas_lib::transcode::{start_transcoder, TranscodeQueue};
 use serialport::SerialPort;
 use std::io::Write;
 use std::sync::Arc;
@@ -19,6 +31,7 @@ async fn main() {
     // TODO: this was originally designed to be Redux-like but then it turned evil. Refactor.
     let state = Arc::new(RwLock::new(AlasState::new()));
     let (event_bus, _) = broadcast::channel::<AlasMessage>(256);
+    let catalog = Arc::new(RwLock::new(RecordingCatalog::load(find_catalog_file())));
 
     // Initialize redundancy manager and WireGuard interface
     let redundancy_manager = redundancy::RedundancyManager::new();
@@ -36,16 +49,36 @@ async fn main() {
     let cell_observer = Arc::new(CellObserver::new(event_bus.clone(), &state));
     let cell_changes = cell_observer.listen().await;
 
-    let wifi_observer = Arc::new(WiFiObserver::new(event_bus.clone()));
+    let wifi_observer = Arc::new(WiFiObserver::new(event_bus.clone(), &state));
     let wifi_changes = wifi_observer.listen();
 
-    let audio = alas_lib::audio::start(event_bus.clone(), &state).await;
+    let schedule_watcher = start_schedule_watcher(event_bus.clone(), &state);
+
+    // Keep upload statuses up to date before anything can start uploading
+    start_catalog_listener(event_bus.subscribe(), catalog.clone()).await;
+
+    let storage_watcher = start_storage_watcher(event_bus.clone(), &state, &catalog);
+    let podcast_publisher = start_podcast_publisher(event_bus.clone(), &state, &catalog);
+    let listener_poller = start_listener_stats_poller(event_bus.clone(), &state);
+    let calibration_listener = start_calibration_listener(event_bus.clone(), &state);
+    let level_history = start_level_history(event_bus.clone(), &state);
+
+    let monitor = MonitorHandle::new();
+    let monitor_thread = start_monitor(event_bus.clone(), &state, &monitor);
+    let verifier_thread = start_off_air_verifier(event_bus.clone(), &state, &monitor);
+    let ifb_thread = start_ifb_player(event_bus.clone(), &state);
+
+    let transcode_queue = TranscodeQueue::new();
+    let transcoder = start_transcoder(event_bus.clone(), &state, &catalog, &transcode_queue);
+
+    let meter_feed = MeterFeed::new();
+    let audio = alas_lib::audio::start(event_bus.clone(), &state, &catalog, &monitor, &meter_feed, &transcode_queue).await;
     println!("Audio results are: {:?}", audio);
 
     // Start webhook listener
     start_webhook_listener(event_bus.subscribe(), state.clone()).await;
 
-    let web_server = web_server::run_rocket_server(event_bus.clone(), &state).await;
+    let web_server = web_server::run_rocket_server(event_bus.clone(), &state, &catalog, &meter_feed).await;
 
     // Wait for exit here! All code below is for clean-up!
 
@@ -59,13 +92,47 @@ async fn main() {
     println!("Waiting for cellular to unwrap...");
     let _ = cell_changes.await;
 
+    println!("Waiting for schedule watcher to unwrap...");
+    let _ = schedule_watcher.await;
+
+    println!("Waiting for storage watcher to unwrap...");
+    let _ = storage_watcher.await;
+
+    println!("Waiting for podcast publisher to unwrap...");
+    let _ = podcast_publisher.await;
+
+    println!("Waiting for listener stats poller to unwrap...");
+    let _ = listener_poller.await;
+
+    println!("Waiting for calibration listener to unwrap...");
+    let _ = calibration_listener.await;
+
+    println!("Waiting for level history to unwrap...");
+    let _ = level_history.await;
+
+    println!("Waiting for monitor to unwrap...");
+    let monitor_result = monitor_thread.await.unwrap();
+    println!("Monitor result: {:?}", monitor_result);
+
+    println!("Waiting for off-air verifier to unwrap...");
+    let verifier_result = verifier_thread.await.unwrap();
+    println!("Off-air verifier result: {:?}", verifier_result);
+
+    println!("Waiting for IFB player to unwrap...");
+    let ifb_result = ifb_thread.await.unwrap();
+    println!("IFB player result: {:?}", ifb_result);
+
+    println!("Waiting for transcoder to unwrap...");
+    let transcoder_result = transcoder.await.unwrap();
+    println!("Transcoder result: {:?}", transcoder_result);
+
     // LCD should always be last to exit so that we can display all messages
     println!("Waiting for web server to await...");
     web_server.await.expect("Oh well 3");
     println!("Waiting for lcd to unwrap...");
     lcd_thread.await.expect("Oh well 4");
     println!("Waiting for audio to unwrap...");
-    let (config_thread, icecast, recording) = audio.await.expect("Oh well 6");
+    let (config_thread, icecast, recording, logger, tones, meter) = audio.await.expect("Oh well 6");
     println!("Waiting for config thread to unwrap...");
     let result_one = config_thread.await.unwrap();
     println!("Results: {:?}", result_one);
@@ -75,4 +142,13 @@ async fn main() {
     println!("Waiting for recording to unwrap...");
     let recording_result = recording.await.unwrap();
     println!("Recording result: {:?}", recording_result);
+    println!("Waiting for logger to unwrap...");
+    let logger_result = logger.await.unwrap();
+    println!("Logger result: {:?}", logger_result);
+    println!("Waiting for tone detector to unwrap...");
+    let tones_result = tones.await.unwrap();
+    println!("Tone detector result: {:?}", tones_result);
+    println!("Waiting for meter to unwrap...");
+    let meter_result = meter.await.unwrap();
+    println!("Meter result: {:?}", meter_result);
 }
@@ -1,10 +1,12 @@
-use rocket::{get, post, routes, Route, State};
+use rocket::{delete, get, post, routes, Route, State};
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::Sender;
+use alas_lib::calibration::{confirm_calibration, CalibrationStatus, DEFAULT_WINDOW_SECS, MAX_WINDOW_SECS, MIN_WINDOW_SECS};
 use alas_lib::cellular::connect_to_cellular;
-use alas_lib::config::{load_config_async, AlasAudioConfig, AlasDropboxConfig, AlasIcecastConfig, AlasWebhookConfig};
+use alas_lib::icecast::{test_connection, IcecastError};
+use alas_lib::config::{load_config_async, AlasAdaptiveBitrateConfig, AlasAudioConfig, AlasDelayConfig, AlasDropboxConfig, AlasEncryptionConfig, AlasFallbackConfig, AlasIcecastConfig, AlasIfbConfig, AlasLevelHistoryConfig, AlasLoggerConfig, AlasMonitorConfig, AlasOffAirConfig, AlasPodcastConfig, AlasScheduleConfig, AlasStorageConfig, AlasToneConfig, AlasTranscodeConfig, AlasWebhookConfig};
 use alas_lib::state::{AlasMessage, SafeState};
 use alas_lib::wifi::WiFiNetwork;
 use alas_lib::redundancy::{RedundancyManager, RedundancyWebRequest, RedundancyWebResponse};
@@ -31,13 +33,80 @@ async fn set_audio_config(
     request: Json<AlasAudioConfig>,
     state: &State<SafeState>,
     bus: &State<Sender<AlasMessage>>
-) -> Json<AlasAudioConfig> {
+) -> Result<Json<AlasAudioConfig>, Status> {
+    let audio = request.into_inner();
+    if let Err(e) = audio.validate() {
+        eprintln!("Invalid audio config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    // Gain, pan and mute apply within a second; new devices or channels on restart
     let mut state = state.write().await;
     let mut new_config = (*state).config.clone();
-    new_config.audio = request.into_inner();
+    new_config.audio = audio;
     state.update_config(new_config);
     let _ = bus.send(AlasMessage::StreamingConfigUpdated);
-    Json(state.config.audio.clone())
+    Ok(Json(state.config.audio.clone()))
+}
+
+/// GET /config/audio/calibration
+///
+/// Where the silence-threshold calibration is at, including any thresholds
+/// waiting to be confirmed.
+#[get("/audio/calibration")]
+async fn get_calibration(state: &State<SafeState>) -> Json<CalibrationStatus> {
+    Json(state.read().await.calibration.clone())
+}
+
+/// POST /config/audio/calibration?window_secs=30
+///
+/// Starts measuring the noise floor and program level. Play the venue's quiet
+/// and its program during the window, then confirm the proposal.
+#[post("/audio/calibration?<window_secs>")]
+async fn start_calibration(
+    window_secs: Option<u32>,
+    state: &State<SafeState>,
+    bus: &State<Sender<AlasMessage>>
+) -> Status {
+    let window_secs = window_secs.unwrap_or(DEFAULT_WINDOW_SECS);
+    if !(MIN_WINDOW_SECS..=MAX_WINDOW_SECS).contains(&window_secs) {
+        eprintln!("Invalid calibration window: {}s", window_secs);
+        return Status::BadRequest;
+    }
+    if matches!(state.read().await.calibration, CalibrationStatus::Measuring { .. }) {
+        return Status::Conflict;
+    }
+    match bus.send(AlasMessage::StartCalibration { window_secs }) {
+        Ok(_) => Status::Accepted,
+        Err(_) => Status::ServiceUnavailable,
+    }
+}
+
+/// POST /config/audio/calibration/confirm
+///
+/// Saves the proposed thresholds and answers with the new audio config.
+#[post("/audio/calibration/confirm")]
+async fn confirm_calibration_thresholds(
+    state: &State<SafeState>,
+    bus: &State<Sender<AlasMessage>>
+) -> Result<Json<AlasAudioConfig>, Status> {
+    let mut state = state.write().await;
+    if confirm_calibration(&mut state).is_none() {
+        return Err(Status::Conflict);
+    }
+    let _ = bus.send(AlasMessage::CalibrationChanged { status: state.calibration.clone() });
+    Ok(Json(state.config.audio.clone()))
+}
+
+/// DELETE /config/audio/calibration
+///
+/// Stops a calibration that is running, or throws away its proposal.
+#[delete("/audio/calibration")]
+async fn cancel_calibration(bus: &State<Sender<AlasMessage>>) -> Status {
+    match bus.send(AlasMessage::CancelCalibration) {
+        Ok(_) => Status::NoContent,
+        Err(_) => Status::ServiceUnavailable,
+    }
 }
 
 #[derive(Deserialize)]
@@ -88,13 +157,58 @@ async fn set_icecast_config(
     request: Json<AlasIcecastConfig>,
     bus: &State<Sender<AlasMessage>>,
     state: &State<SafeState>
-) -> Json<AlasIcecastConfig> {
+) -> Result<Json<AlasIcecastConfig>, Status> {
+    let icecast = request.into_inner();
+    if let Err(e) = icecast.validate() {
+        eprintln!("Rejected Icecast config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
     let mut state = state.write().await;
     let mut new_config = (*state).config.clone();
-    new_config.icecast = request.into_inner();
+    new_config.icecast = icecast;
     state.update_config(new_config);
     let _ = bus.send(AlasMessage::StreamingConfigUpdated);
-    Json(state.config.icecast.clone())
+    Ok(Json(state.config.icecast.clone()))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct IcecastTestResult {
+    success: bool,
+    /// Set when the credentials were accepted but someone else is on the mount
+    mount_in_use: bool,
+    error: Option<String>,
+}
+
+/// POST /config/icecast/test
+///
+/// Checks the source credentials and that the mount is free, without taking
+/// the mount. Tests the saved settings, or the ones in the body if there are any.
+#[post("/icecast/test", format = "json", data = "<request>")]
+async fn test_icecast_config(
+    request: Option<Json<AlasIcecastConfig>>,
+    state: &State<SafeState>
+) -> Json<IcecastTestResult> {
+    let icecast = match request {
+        Some(request) => request.into_inner(),
+        None => state.read().await.config.icecast.clone(),
+    };
+
+    let result = tokio::task::spawn_blocking(move || test_connection(&icecast))
+        .await
+        .unwrap_or_else(|e| Err(IcecastError::Rejected(e.to_string())));
+
+    Json(match result {
+        Ok(()) => IcecastTestResult { success: true, mount_in_use: false, error: None },
+        // The credentials are good, but we could not stream there right now
+        Err(IcecastError::MountInUse) => IcecastTestResult {
+            success: false,
+            mount_in_use: true,
+            error: Some(IcecastError::MountInUse.to_string()),
+        },
+        Err(e) => IcecastTestResult { success: false, mount_in_use: false, error: Some(e.to_string()) },
+    })
 }
 
 #[derive(Serialize)]
@@ -282,6 +396,355 @@ async fn set_webhook_config(
     })
 }
 
+#[get("/schedule")]
+async fn get_schedule_config(state: &State<SafeState>) -> Json<AlasScheduleConfig> {
+    let state = state.read().await;
+    Json(state.config.schedule.clone().unwrap_or(AlasScheduleConfig {
+        timezone: None,
+        entries: Vec::new(),
+    }))
+}
+
+#[post("/schedule", format = "json", data = "<request>")]
+async fn set_schedule_config(
+    request: Json<Option<AlasScheduleConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasScheduleConfig>>, Status> {
+    let schedule = request.into_inner();
+    if let Some(Err(e)) = schedule.as_ref().map(AlasScheduleConfig::validate) {
+        eprintln!("Rejected schedule: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.schedule = schedule;
+    state.update_config(new_config);
+    Ok(Json(state.config.schedule.clone()))
+}
+
+#[get("/logger")]
+async fn get_logger_config(state: &State<SafeState>) -> Json<Option<AlasLoggerConfig>> {
+    let state = state.read().await;
+    Json(state.config.logger.clone())
+}
+
+#[post("/logger", format = "json", data = "<request>")]
+async fn set_logger_config(
+    request: Json<Option<AlasLoggerConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasLoggerConfig>>, Status> {
+    let logger = request.into_inner();
+    if let Some(Err(e)) = logger.as_ref().map(AlasLoggerConfig::validate) {
+        eprintln!("Invalid logger config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.logger = logger;
+    state.update_config(new_config);
+    Ok(Json(state.config.logger.clone()))
+}
+
+#[get("/storage")]
+async fn get_storage_config(state: &State<SafeState>) -> Json<AlasStorageConfig> {
+    let state = state.read().await;
+    Json(state.config.storage.clone().unwrap_or_default())
+}
+
+#[post("/storage", format = "json", data = "<request>")]
+async fn set_storage_config(
+    request: Json<AlasStorageConfig>,
+    state: &State<SafeState>
+) -> Result<Json<AlasStorageConfig>, Status> {
+    let storage = request.into_inner();
+    // Cleaning up to below the point where recording stops would never let it resume
+    if storage.target_free_mb < storage.min_free_mb {
+        return Err(Status::BadRequest);
+    }
+
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.storage = Some(storage.clone());
+    state.update_config(new_config);
+    Ok(Json(storage))
+}
+
+#[get("/monitor")]
+async fn get_monitor_config(state: &State<SafeState>) -> Json<Option<AlasMonitorConfig>> {
+    let state = state.read().await;
+    Json(state.config.monitor.clone())
+}
+
+#[post("/monitor", format = "json", data = "<request>")]
+async fn set_monitor_config(
+    request: Json<Option<AlasMonitorConfig>>,
+    state: &State<SafeState>,
+    bus: &State<Sender<AlasMessage>>
+) -> Result<Json<Option<AlasMonitorConfig>>, Status> {
+    let monitor = request.into_inner();
+    if monitor.as_ref().is_some_and(|monitor| !(0.0..=1.0).contains(&monitor.volume)) {
+        return Err(Status::BadRequest);
+    }
+
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.monitor = monitor;
+    state.update_config(new_config);
+    let _ = bus.send(AlasMessage::MonitorConfigUpdated);
+    Ok(Json(state.config.monitor.clone()))
+}
+
+#[get("/off_air")]
+async fn get_off_air_config(state: &State<SafeState>) -> Json<Option<AlasOffAirConfig>> {
+    let state = state.read().await;
+    Json(state.config.off_air.clone())
+}
+
+#[post("/off_air", format = "json", data = "<request>")]
+async fn set_off_air_config(
+    request: Json<Option<AlasOffAirConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasOffAirConfig>>, Status> {
+    let off_air = request.into_inner();
+    if off_air.as_ref().is_some_and(|off_air| off_air.tolerance_db <= 0.0) {
+        return Err(Status::BadRequest);
+    }
+
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.off_air = off_air;
+    state.update_config(new_config);
+    Ok(Json(state.config.off_air.clone()))
+}
+
+#[get("/adaptive_bitrate")]
+async fn get_adaptive_bitrate_config(state: &State<SafeState>) -> Json<Option<AlasAdaptiveBitrateConfig>> {
+    let state = state.read().await;
+    Json(state.config.adaptive_bitrate.clone())
+}
+
+#[post("/adaptive_bitrate", format = "json", data = "<request>")]
+async fn set_adaptive_bitrate_config(
+    request: Json<Option<AlasAdaptiveBitrateConfig>>,
+    state: &State<SafeState>,
+    bus: &State<Sender<AlasMessage>>
+) -> Result<Json<Option<AlasAdaptiveBitrateConfig>>, Status> {
+    let adaptive_bitrate = request.into_inner();
+    if let Some(Err(e)) = adaptive_bitrate.as_ref().map(AlasAdaptiveBitrateConfig::validate) {
+        eprintln!("Invalid adaptive bitrate config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.adaptive_bitrate = adaptive_bitrate;
+    state.update_config(new_config);
+    // Restart the stream so that it picks up the new tiers
+    let _ = bus.send(AlasMessage::StreamingConfigUpdated);
+    Ok(Json(state.config.adaptive_bitrate.clone()))
+}
+
+#[get("/fallback")]
+async fn get_fallback_config(state: &State<SafeState>) -> Json<Option<AlasFallbackConfig>> {
+    let state = state.read().await;
+    Json(state.config.fallback.clone())
+}
+
+#[post("/fallback", format = "json", data = "<request>")]
+async fn set_fallback_config(
+    request: Json<Option<AlasFallbackConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasFallbackConfig>>, Status> {
+    let fallback = request.into_inner();
+    if let Some(Err(e)) = fallback.as_ref().map(AlasFallbackConfig::validate) {
+        eprintln!("Invalid fallback config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    // The stream reloads the files the next time it needs them
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.fallback = fallback;
+    state.update_config(new_config);
+    Ok(Json(state.config.fallback.clone()))
+}
+
+#[get("/delay")]
+async fn get_delay_config(state: &State<SafeState>) -> Json<Option<AlasDelayConfig>> {
+    let state = state.read().await;
+    Json(state.config.delay.clone())
+}
+
+#[post("/delay", format = "json", data = "<request>")]
+async fn set_delay_config(
+    request: Json<Option<AlasDelayConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasDelayConfig>>, Status> {
+    let delay = request.into_inner();
+    if let Some(Err(e)) = delay.as_ref().map(AlasDelayConfig::validate) {
+        eprintln!("Invalid delay config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    // The stream picks up the new delay within a second, without reconnecting
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.delay = delay;
+    state.update_config(new_config);
+    Ok(Json(state.config.delay.clone()))
+}
+
+#[get("/level_history")]
+async fn get_level_history_config(state: &State<SafeState>) -> Json<Option<AlasLevelHistoryConfig>> {
+    let state = state.read().await;
+    Json(state.config.level_history.clone())
+}
+
+#[post("/level_history", format = "json", data = "<request>")]
+async fn set_level_history_config(
+    request: Json<Option<AlasLevelHistoryConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasLevelHistoryConfig>>, Status> {
+    let level_history = request.into_inner();
+    if let Some(Err(e)) = level_history.as_ref().map(AlasLevelHistoryConfig::validate) {
+        eprintln!("Invalid level history config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.level_history = level_history;
+    state.update_config(new_config);
+    Ok(Json(state.config.level_history.clone()))
+}
+
+#[get("/tones")]
+async fn get_tones_config(state: &State<SafeState>) -> Json<Option<AlasToneConfig>> {
+    let state = state.read().await;
+    Json(state.config.tones.clone())
+}
+
+#[post("/tones", format = "json", data = "<request>")]
+async fn set_tones_config(
+    request: Json<Option<AlasToneConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasToneConfig>>, Status> {
+    let tones = request.into_inner();
+    if let Some(Err(e)) = tones.as_ref().map(AlasToneConfig::validate) {
+        eprintln!("Invalid tone config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    // The tone detector picks up the new commands within a second
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.tones = tones;
+    state.update_config(new_config);
+    Ok(Json(state.config.tones.clone()))
+}
+
+#[get("/ifb")]
+async fn get_ifb_config(state: &State<SafeState>) -> Json<Option<AlasIfbConfig>> {
+    let state = state.read().await;
+    Json(state.config.ifb.clone())
+}
+
+#[post("/ifb", format = "json", data = "<request>")]
+async fn set_ifb_config(
+    request: Json<Option<AlasIfbConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasIfbConfig>>, Status> {
+    let ifb = request.into_inner();
+    if let Some(Err(e)) = ifb.as_ref().map(AlasIfbConfig::validate) {
+        eprintln!("Invalid IFB config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    // The player reconnects if the feed, device or buffer changed
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.ifb = ifb;
+    state.update_config(new_config);
+    Ok(Json(state.config.ifb.clone()))
+}
+
+#[get("/transcode")]
+async fn get_transcode_config(state: &State<SafeState>) -> Json<Option<AlasTranscodeConfig>> {
+    let state = state.read().await;
+    Json(state.config.transcode.clone())
+}
+
+#[post("/transcode", format = "json", data = "<request>")]
+async fn set_transcode_config(
+    request: Json<Option<AlasTranscodeConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasTranscodeConfig>>, Status> {
+    let transcode = request.into_inner();
+    if let Some(Err(e)) = transcode.as_ref().map(AlasTranscodeConfig::validate) {
+        eprintln!("Invalid transcode config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    // Applies to recordings that finish from now on
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.transcode = transcode;
+    state.update_config(new_config);
+    Ok(Json(state.config.transcode.clone()))
+}
+
+#[get("/podcast")]
+async fn get_podcast_config(state: &State<SafeState>) -> Json<Option<AlasPodcastConfig>> {
+    let state = state.read().await;
+    Json(state.config.podcast.clone())
+}
+
+#[post("/podcast", format = "json", data = "<request>")]
+async fn set_podcast_config(
+    request: Json<Option<AlasPodcastConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasPodcastConfig>>, Status> {
+    let podcast = request.into_inner();
+    if let Some(Err(e)) = podcast.as_ref().map(AlasPodcastConfig::validate) {
+        eprintln!("Invalid podcast config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    // The feeds pick this up the next time an episode is uploaded
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.podcast = podcast;
+    state.update_config(new_config);
+    Ok(Json(state.config.podcast.clone()))
+}
+
+#[get("/encryption")]
+async fn get_encryption_config(state: &State<SafeState>) -> Json<Option<AlasEncryptionConfig>> {
+    let state = state.read().await;
+    Json(state.config.encryption.clone())
+}
+
+#[post("/encryption", format = "json", data = "<request>")]
+async fn set_encryption_config(
+    request: Json<Option<AlasEncryptionConfig>>,
+    state: &State<SafeState>
+) -> Result<Json<Option<AlasEncryptionConfig>>, Status> {
+    let encryption = request.into_inner();
+    if let Some(Err(e)) = encryption.as_ref().map(AlasEncryptionConfig::validate) {
+        eprintln!("Invalid encryption config: {}", e);
+        return Err(Status::BadRequest);
+    }
+
+    // Recordings and logger hours that finish from now on are encrypted with these keys
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    new_config.encryption = encryption;
+    state.update_config(new_config);
+    Ok(Json(state.config.encryption.clone()))
+}
+
 pub fn routes() -> Vec<Route> {
     routes![
         available_wifi,
@@ -290,8 +753,13 @@ pub fn routes() -> Vec<Route> {
         set_cellular_config,
         get_icecast_config,
         set_icecast_config,
+        test_icecast_config,
         get_audio_config,
         set_audio_config,
+        get_calibration,
+        start_calibration,
+        confirm_calibration_thresholds,
+        cancel_calibration,
         get_redundancy_config,
         set_redundancy_config,
         post_dropbox_link,
@@ -299,6 +767,34 @@ pub fn routes() -> Vec<Route> {
         get_dropbox_status,
         get_webhook_config,
         set_webhook_config,
+        get_schedule_config,
+        set_schedule_config,
+        get_logger_config,
+        set_logger_config,
+        get_storage_config,
+        set_storage_config,
+        get_monitor_config,
+        set_monitor_config,
+        get_off_air_config,
+        set_off_air_config,
+        get_adaptive_bitrate_config,
+        set_adaptive_bitrate_config,
+        get_fallback_config,
+        set_fallback_config,
+        get_delay_config,
+        set_delay_config,
+        get_tones_config,
+        set_tones_config,
+        get_level_history_config,
+        set_level_history_config,
+        get_ifb_config,
+        set_ifb_config,
+        get_transcode_config,
+        set_transcode_config,
+        get_podcast_config,
+        set_podcast_config,
+        get_encryption_config,
+        set_encryption_config,
     ]
 }
 
@@ -322,17 +818,30 @@ mod tests {
             is_streaming: false,
             is_recording: false,
             is_audio_present: false,
+            is_on_fallback: false,
             audio_last_seen: 0,
             config: alas_lib::config::AlasConfig {
                 audio: AlasAudioConfig {
                     silence_duration_before_deactivation: 15,
                     silence_threshold: -55.0,
+                    silence_stop_threshold: None,
+                    stream: None,
+                    record: None,
+                    inputs: Vec::new(),
                 },
                 icecast: AlasIcecastConfig {
                     hostname: "localhost".to_string(),
                     port: 8000,
                     mount: "/test.mp3".to_string(),
                     password: "password".to_string(),
+                    user: "source".to_string(),
+                    protocol: Default::default(),
+                    tls: false,
+                    name: None,
+                    genre: None,
+                    description: None,
+                    url: None,
+                    public: false,
                 },
                 cellular: AlasCellularConfig {
                     apn: "test".to_string(),
@@ -345,12 +854,39 @@ mod tests {
                 dropbox: None,
                 redundancy: None,
                 webhook: None,
+                schedule: None,
+                logger: None,
+                storage: None,
+                monitor: None,
+                off_air: None,
+                adaptive_bitrate: None,
+                fallback: None,
+                delay: None,
+                tones: None,
+                level_history: None,
+                ifb: None,
+                transcode: None,
+                podcast: None,
+                encryption: None,
             },
             upload_state: alas_lib::state::AlasUploadState {
                 state: alas_lib::state::AlasUploadStatus::Idle,
                 progress: 0,
                 queue: Vec::new(),
             },
+            schedule: Default::default(),
+            disk_free_mb: None,
+            disk_low: false,
+            off_air: Default::default(),
+            listeners: Default::default(),
+            stream: Default::default(),
+            stream_connection: Default::default(),
+            stream_delay: None,
+            record_override: None,
+            calibration: Default::default(),
+            input_levels: Vec::new(),
+            ifb: Default::default(),
+            transcodes: Vec::new(),
         }))
     }
 
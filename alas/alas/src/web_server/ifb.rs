@@ -0,0 +1,69 @@
+use rocket::{get, post, routes, Route, State};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use tokio::sync::broadcast::Sender;
+use alas_lib::config::AlasIfbConfig;
+use alas_lib::ifb::IfbStatus;
+use alas_lib::state::{AlasMessage, SafeState};
+use crate::web_server::auth::Authenticated;
+
+/// GET /ifb
+///
+/// Whether the return audio is switched on and how playback is going.
+#[get("/")]
+async fn get_ifb_status(state: &State<SafeState>, _jwt: Authenticated) -> Json<IfbStatus> {
+    Json(state.read().await.ifb.clone())
+}
+
+/// POST /ifb?enabled=true
+///
+/// Switches the return audio on or off until ALAS restarts. Whether it starts
+/// switched on is up to the IFB config.
+#[post("/?<enabled>")]
+async fn set_ifb_enabled(
+    enabled: bool,
+    state: &State<SafeState>,
+    bus: &State<Sender<AlasMessage>>,
+    _jwt: Authenticated
+) -> Status {
+    if enabled && state.read().await.config.ifb.is_none() {
+        eprintln!("Cannot play return audio without an IFB config");
+        return Status::Conflict;
+    }
+    match bus.send(AlasMessage::SetIfb { enabled }) {
+        Ok(_) => Status::Accepted,
+        Err(_) => Status::ServiceUnavailable,
+    }
+}
+
+/// POST /ifb/volume?volume=0.8
+///
+/// Sets and saves the return audio volume, which applies right away.
+#[post("/volume?<volume>")]
+async fn set_ifb_volume(
+    volume: f32,
+    state: &State<SafeState>,
+    _jwt: Authenticated
+) -> Result<Json<AlasIfbConfig>, Status> {
+    let mut state = state.write().await;
+    let mut new_config = state.config.clone();
+    let Some(ifb) = new_config.ifb.as_mut() else {
+        return Err(Status::Conflict);
+    };
+    ifb.volume = volume;
+    if let Err(e) = ifb.validate() {
+        eprintln!("Invalid IFB volume: {}", e);
+        return Err(Status::BadRequest);
+    }
+    let ifb = ifb.clone();
+    state.update_config(new_config);
+    Ok(Json(ifb))
+}
+
+pub(crate) fn routes() -> Vec<Route> {
+    routes![
+        get_ifb_status,
+        set_ifb_enabled,
+        set_ifb_volume,
+    ]
+}
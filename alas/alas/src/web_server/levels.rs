@@ -0,0 +1,100 @@
+use std::path::Path;
+use chrono::{DateTime, Duration, Utc};
+use rocket::{get, routes, Route, State};
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::Json;
+use tokio::task::spawn_blocking;
+use alas_lib::level_history::{
+    dead_air_csv,
+    downsample,
+    find_dead_air,
+    read_level_history,
+    LevelSample,
+    LEVEL_HISTORY_DIRECTORY,
+};
+use alas_lib::state::SafeState;
+use crate::web_server::auth::Authenticated;
+
+/// Longer ranges are downsampled to stay under this many samples
+const MAX_SAMPLES: i64 = 3_600;
+const MAX_RANGE_DAYS: i64 = 31;
+const DEFAULT_DEAD_AIR_SECS: i64 = 10;
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, Status> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|_| Status::BadRequest)
+}
+
+/// Works out the range asked for, ending now and going back `default_span` by default
+fn parse_range(
+    from: Option<&str>,
+    to: Option<&str>,
+    default_span: Duration
+) -> Result<(DateTime<Utc>, DateTime<Utc>), Status> {
+    let to = to.map(parse_timestamp).transpose()?.unwrap_or_else(Utc::now);
+    let from = from.map(parse_timestamp).transpose()?.unwrap_or(to - default_span);
+    if from >= to || to - from > Duration::days(MAX_RANGE_DAYS) {
+        eprintln!("Invalid level history range: {} to {}", from, to);
+        return Err(Status::BadRequest);
+    }
+    Ok((from, to))
+}
+
+async fn read_range(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<LevelSample>, Status> {
+    spawn_blocking(move || read_level_history(Path::new(LEVEL_HISTORY_DIRECTORY), from, to))
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// GET /levels?from=...&to=...&step=60
+///
+/// The input levels and activation state for a range, by default the last
+/// hour. `from` and `to` are RFC 3339 timestamps. Samples are a second apart
+/// unless `step` asks for coarser ones; long ranges are coarsened anyway.
+#[get("/?<from>&<to>&<step>")]
+async fn get_levels(
+    from: Option<&str>,
+    to: Option<&str>,
+    step: Option<i64>,
+    _jwt: Authenticated
+) -> Result<Json<Vec<LevelSample>>, Status> {
+    let (from, to) = parse_range(from, to, Duration::hours(1))?;
+    let min_step = ((to - from).num_seconds() + MAX_SAMPLES - 1) / MAX_SAMPLES;
+    let step = step.unwrap_or(1).max(min_step);
+
+    Ok(Json(downsample(read_range(from, to).await?, step)))
+}
+
+/// GET /levels/dead_air.csv?from=...&to=...&min_secs=10&threshold_db=-50
+///
+/// Every silence of at least `min_secs` while the stream was expected on air,
+/// by default over the last week. Silence is anything below `threshold_db`,
+/// which defaults to the configured stop threshold.
+#[get("/dead_air.csv?<from>&<to>&<min_secs>&<threshold_db>")]
+async fn get_dead_air_report(
+    from: Option<&str>,
+    to: Option<&str>,
+    min_secs: Option<i64>,
+    threshold_db: Option<f32>,
+    state: &State<SafeState>,
+    _jwt: Authenticated
+) -> Result<(ContentType, String), Status> {
+    let (from, to) = parse_range(from, to, Duration::days(7))?;
+    let threshold_db = match threshold_db {
+        Some(threshold_db) => threshold_db,
+        None => state.read().await.config.audio.stop_threshold(),
+    };
+    let min_secs = min_secs.unwrap_or(DEFAULT_DEAD_AIR_SECS).max(1);
+
+    let samples = read_range(from, to).await?;
+    let periods = find_dead_air(&samples, threshold_db, min_secs);
+    Ok((ContentType::CSV, dead_air_csv(&periods)))
+}
+
+pub(crate) fn routes() -> Vec<Route> {
+    routes![
+        get_levels,
+        get_dead_air_report,
+    ]
+}
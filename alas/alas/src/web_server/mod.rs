@@ -6,6 +6,8 @@ use rocket_cors::AllowedOrigins;
 use tokio::sync::broadcast::{Receiver, Sender };
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
+use alas_lib::catalog::SafeCatalog;
+use alas_lib::meter::MeterFeed;
 use alas_lib::do_things;
 use alas_lib::state::{AlasMessage, SafeState};
 use crate::redundancy::RedundancyManager;
@@ -13,6 +15,10 @@ use crate::redundancy::RedundancyManager;
 mod auth;
 mod status;
 mod config;
+mod ifb;
+mod levels;
+mod recordings;
+mod stream;
 
 #[post("/")]
 async fn go() -> &'static str {
@@ -22,10 +28,14 @@ async fn go() -> &'static str {
 
 pub async fn run_rocket_server(
     bus: Sender<AlasMessage>,
-    alas_state: &SafeState
+    alas_state: &SafeState,
+    catalog: &SafeCatalog,
+    meter_feed: &MeterFeed
 ) -> JoinHandle<Rocket<Ignite>> {
     println!("Starting web server...");
     let tokio_state = alas_state.clone();
+    let catalog = catalog.clone();
+    let meter_feed = meter_feed.clone();
     tokio::spawn(async move {
         // Initialize RedundancyManager
         let redundancy_manager = RedundancyManager::new();
@@ -50,6 +60,8 @@ pub async fn run_rocket_server(
             .manage(bus)
             .manage(tokio_state.clone())
             .manage(redundancy_manager)
+            .manage(catalog)
+            .manage(meter_feed)
             .manage(cors.clone()) // Ensure Cors is managed
             .configure(Config {
                 address: Ipv4Addr::new(0, 0, 0, 0).into(),
@@ -59,6 +71,10 @@ pub async fn run_rocket_server(
             .mount("/auth", auth::routes())
             .mount("/config", config::routes())
             .mount("/status", status::routes())
+            .mount("/recordings", recordings::routes())
+            .mount("/stream", stream::routes())
+            .mount("/levels", levels::routes())
+            .mount("/ifb", ifb::routes())
             .mount(
                 "/",
                 routes![
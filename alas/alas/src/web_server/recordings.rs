@@ -0,0 +1,383 @@
+use std::convert::Infallible;
+use std::io;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use rocket::{delete, get, post, routes, Request, Response, Route, State};
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::{self, Responder};
+use rocket::serde::de::DeserializeOwned;
+use rocket::serde::Deserialize;
+use rocket::serde::json::{serde_json, Json};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, ReadBuf};
+use tokio::sync::broadcast::Sender;
+use tokio::time::timeout;
+use alas_lib::catalog::{RecordingEntry, RecordingFilter, SafeCatalog};
+use alas_lib::markers::{remove_sidecar, RecordingMarker};
+use alas_lib::podcast::{build_feed, episodes};
+use alas_lib::state::{AlasMessage, SafeState};
+use alas_lib::transcode::TranscodeJob;
+use alas_lib::waveform::{remove_waveform, waveform_path, Waveform};
+use crate::web_server::auth::Authenticated;
+
+/// The raw `Range` header, if the client sent one
+struct RangeHeader(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RangeHeader(request.headers().get_one("range").map(|r| r.to_string())))
+    }
+}
+
+/// Parses a single `bytes=` range against a file of `length` bytes, returning the
+/// inclusive start and end offsets. Returns `None` if the range cannot be satisfied.
+fn parse_range(header: &str, length: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    // We only serve a single range; players never ask for more than one
+    if spec.contains(',') || length == 0 {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // "bytes=-500" is the last 500 bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 {
+            return None;
+        }
+        return Some((length.saturating_sub(suffix), length - 1));
+    }
+
+    let start: u64 = start.parse().ok()?;
+    let end = if end.is_empty() { length - 1 } else { end.parse::<u64>().ok()?.min(length - 1) };
+    if start > end {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// A file that only yields `remaining` bytes from its current position
+struct FileRange {
+    file: File,
+    remaining: u64,
+}
+
+impl AsyncRead for FileRange {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.remaining == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let limit = (buf.remaining() as u64).min(self.remaining) as usize;
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(limit));
+        ready!(Pin::new(&mut self.file).poll_read(cx, &mut limited))?;
+        let read = limited.filled().len();
+        buf.advance(read);
+        self.remaining -= read as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Rocket only seeks to measure bodies of unknown size; ours always has a preset size.
+impl AsyncSeek for FileRange {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.file).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.file).poll_complete(cx)
+    }
+}
+
+struct RecordingDownload {
+    body: FileRange,
+    content_type: ContentType,
+    file_name: String,
+    start: u64,
+    length: u64,
+    total: u64,
+    partial: bool,
+}
+
+impl<'r> Responder<'r, 'static> for RecordingDownload {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .header(self.content_type)
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("Content-Disposition", format!("inline; filename=\"{}\"", self.file_name));
+
+        if self.partial {
+            response
+                .status(Status::PartialContent)
+                .raw_header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", self.start, self.start + self.length - 1, self.total)
+                );
+        }
+
+        response.sized_body(Some(self.length as usize), self.body).ok()
+    }
+}
+
+fn parse_query<T: DeserializeOwned>(value: &str) -> Result<T, Status> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| Status::BadRequest)
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, Status> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|_| Status::BadRequest)
+}
+
+/// GET /recordings
+///
+/// Lists recordings newest first. `from` and `to` are RFC 3339 timestamps
+/// compared against the recording start time.
+#[get("/?<show>&<kind>&<upload_status>&<from>&<to>")]
+async fn list_recordings(
+    show: Option<&str>,
+    kind: Option<&str>,
+    upload_status: Option<&str>,
+    from: Option<&str>,
+    to: Option<&str>,
+    catalog: &State<SafeCatalog>,
+    _jwt: Authenticated
+) -> Result<Json<Vec<RecordingEntry>>, Status> {
+    let filter = RecordingFilter {
+        show_name: show.map(|s| s.to_string()),
+        kind: kind.map(parse_query).transpose()?,
+        upload_status: upload_status.map(parse_query).transpose()?,
+        from: from.map(parse_timestamp).transpose()?,
+        to: to.map(parse_timestamp).transpose()?,
+    };
+
+    Ok(Json(catalog.read().await.list(&filter)))
+}
+
+/// GET /recordings/transcodes
+///
+/// Queued, running and recently finished transcode jobs, oldest first.
+#[get("/transcodes")]
+async fn list_transcodes(state: &State<SafeState>, _jwt: Authenticated) -> Json<Vec<TranscodeJob>> {
+    Json(state.read().await.transcodes.clone())
+}
+
+/// GET /recordings/feed.xml?show=Morning%20Show
+///
+/// The podcast feed for a show, as published to Dropbox. Recordings outside
+/// any scheduled show are in the feed without `show`.
+#[get("/feed.xml?<show>")]
+async fn get_podcast_feed(
+    show: Option<&str>,
+    state: &State<SafeState>,
+    catalog: &State<SafeCatalog>,
+    _jwt: Authenticated
+) -> Result<(ContentType, String), Status> {
+    let config = state.read().await.config.podcast.clone().ok_or(Status::NotFound)?;
+    let recordings = catalog.read().await.list(&Default::default());
+    let episodes = episodes(&recordings, show, &config);
+    Ok((ContentType::XML, build_feed(&config, show, &episodes)))
+}
+
+#[get("/<id>/metadata")]
+async fn get_recording_metadata(
+    id: &str,
+    catalog: &State<SafeCatalog>,
+    _jwt: Authenticated
+) -> Result<Json<RecordingEntry>, Status> {
+    catalog.read().await.get(id).cloned().map(Json).ok_or(Status::NotFound)
+}
+
+/// GET /recordings/<id>/waveform?format=json&zoom=4
+///
+/// Min/max peaks of a recording, also while it is still being recorded.
+/// `format` is `json` (the default) or `dat`, both as produced by BBC's
+/// audiowaveform. Each pixel covers 256 samples; `zoom` merges that many
+/// pixels into one for a coarser view.
+#[get("/<id>/waveform?<format>&<zoom>")]
+async fn get_recording_waveform(
+    id: &str,
+    format: Option<&str>,
+    zoom: Option<u32>,
+    catalog: &State<SafeCatalog>,
+    _jwt: Authenticated
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let path = catalog.read().await.get(id).map(|entry| entry.path.clone()).ok_or(Status::NotFound)?;
+    let zoom = zoom.unwrap_or(1);
+    if zoom == 0 {
+        return Err(Status::BadRequest);
+    }
+
+    let waveform = tokio::task::spawn_blocking(move || Waveform::read(&waveform_path(&path)))
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .map_err(|_| Status::NotFound)?
+        .zoom_out(zoom);
+    match format.unwrap_or("json") {
+        "json" => {
+            let body = serde_json::to_vec(&waveform).map_err(|_| Status::InternalServerError)?;
+            Ok((ContentType::JSON, body))
+        }
+        "dat" => Ok((ContentType::Binary, waveform.to_dat())),
+        _ => Err(Status::BadRequest),
+    }
+}
+
+/// GET /recordings/<id>
+///
+/// Downloads the local copy of a recording. Supports single `Range` requests so
+/// that browsers can seek while playing.
+#[get("/<id>")]
+async fn download_recording(
+    id: &str,
+    range: RangeHeader,
+    catalog: &State<SafeCatalog>,
+    _jwt: Authenticated
+) -> Result<RecordingDownload, Status> {
+    let entry = catalog.read().await.get(id).cloned().ok_or(Status::NotFound)?;
+
+    let mut file = File::open(&entry.path).await.map_err(|_| Status::NotFound)?;
+    let total = file.metadata().await.map_err(|_| Status::InternalServerError)?.len();
+
+    let (start, end, partial) = match range.0 {
+        Some(header) => {
+            let (start, end) = parse_range(&header, total).ok_or(Status::RangeNotSatisfiable)?;
+            (start, end, true)
+        }
+        None => (0, total.saturating_sub(1), false),
+    };
+    let length = if total == 0 { 0 } else { end - start + 1 };
+
+    file.seek(SeekFrom::Start(start)).await.map_err(|_| Status::InternalServerError)?;
+
+    let content_type = Path::new(&entry.path)
+        .extension()
+        .and_then(|extension| ContentType::from_extension(&extension.to_string_lossy()))
+        .unwrap_or(ContentType::Binary);
+
+    Ok(RecordingDownload {
+        body: FileRange { file, remaining: length },
+        content_type,
+        file_name: entry.file_name,
+        start,
+        length,
+        total,
+        partial,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct MarkerRequest {
+    label: Option<String>,
+}
+
+/// POST /recordings/markers
+///
+/// Marks the current moment in the recording that is in progress. The body is
+/// optional; send `{"label": "..."}` to name the marker.
+#[post("/markers", data = "<request>")]
+async fn add_marker(
+    request: Option<Json<MarkerRequest>>,
+    bus: &State<Sender<AlasMessage>>,
+    _jwt: Authenticated
+) -> Result<Json<RecordingMarker>, Status> {
+    let label = request.and_then(|request| request.into_inner().label);
+
+    // Subscribe before asking so that we cannot miss the answer
+    let mut receiver = bus.subscribe();
+    bus.send(AlasMessage::AddMarker { label }).map_err(|_| Status::ServiceUnavailable)?;
+
+    let answer = timeout(Duration::from_secs(2), async {
+        loop {
+            match receiver.recv().await {
+                Ok(AlasMessage::MarkerAdded { marker, .. }) => return Ok(Json(marker)),
+                Ok(AlasMessage::MarkerRejected) => return Err(Status::Conflict),
+                Ok(_) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(_) => return Err(Status::ServiceUnavailable),
+            }
+        }
+    }).await;
+
+    answer.unwrap_or(Err(Status::ServiceUnavailable))
+}
+
+/// DELETE /recordings/<id>
+///
+/// Removes the local file and forgets the recording. Recordings that are still
+/// being written cannot be deleted.
+#[delete("/<id>")]
+async fn delete_recording(
+    id: &str,
+    catalog: &State<SafeCatalog>,
+    _jwt: Authenticated
+) -> Status {
+    // The catalog is not locked while files are deleted, so that recording can carry on
+    let Some(entry) = catalog.read().await.get(id).cloned() else {
+        return Status::NotFound;
+    };
+    if entry.ended_at.is_none() {
+        return Status::Conflict;
+    }
+
+    match tokio::fs::remove_file(&entry.path).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            eprintln!("Could not delete recording {}: {}", entry.path, e);
+            return Status::InternalServerError;
+        }
+    }
+
+    remove_sidecar(&entry.path);
+    remove_waveform(&entry.path);
+    for derivative in &entry.derivatives {
+        let _ = tokio::fs::remove_file(&derivative.path).await;
+    }
+    catalog.write().await.remove(id);
+    Status::NoContent
+}
+
+pub(crate) fn routes() -> Vec<Route> {
+    routes![
+        list_recordings,
+        list_transcodes,
+        get_podcast_feed,
+        get_recording_metadata,
+        get_recording_waveform,
+        download_recording,
+        delete_recording,
+        add_marker,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 999)));
+
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=50-10", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+}
@@ -5,9 +5,16 @@ use rocket::serde::json::Json;
 use rocket::serde::Serialize;
 use tokio::select;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::time::Instant;
 use alas_lib::cellular::get_imei;
 use alas_lib::state::{AlasMessage, SafeState};
+use alas_lib::listeners::ListenerStats;
+use alas_lib::meter::{MeterFeed, MeterReading, MAX_RATE_HZ};
+use alas_lib::mixer::InputLevel;
+use alas_lib::icecast::IcecastConnectionState;
+use alas_lib::stream_stats::StreamSessionReport;
+use alas_lib::verifier::OffAirStatus;
 use crate::web_server::auth::Authenticated;
 
 #[derive(Serialize)]
@@ -36,8 +43,13 @@ async fn get_network_status(alas_state: &State<SafeState>) -> Json<NetworkStatus
 #[serde(crate = "rocket::serde")]
 struct AudioStatus {
     audio_present: bool,
+    on_fallback: bool,
     is_streaming: bool,
     is_recording: bool,
+    disk_free_mb: Option<u64>,
+    disk_low: bool,
+    off_air: OffAirStatus,
+    listeners: ListenerStats,
 }
 
 #[get("/audio")]
@@ -45,34 +57,101 @@ async fn get_audio_state(state: &State<SafeState>, jwt: Authenticated) -> Json<A
     let state = state.read().await;
     Json(AudioStatus {
         audio_present: state.is_audio_present,
+        on_fallback: state.is_on_fallback,
         is_streaming: state.is_streaming,
         is_recording: state.is_recording,
+        disk_free_mb: state.disk_free_mb,
+        disk_low: state.disk_low,
+        off_air: state.off_air.clone(),
+        listeners: state.listeners.clone(),
     })
 }
 
-#[get("/meter")]
-async fn volume_meter(broadcast: &State<Sender<AlasMessage>>, mut end: Shutdown) -> EventStream![] {
-    const THROTTLE_MS: u64 = 100;
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct StreamStatus {
+    is_streaming: bool,
+    connection: IcecastConnectionState,
+    current: Option<StreamSessionReport>,
+    /// Finished sessions, newest first
+    sessions: Vec<StreamSessionReport>,
+}
+
+#[get("/stream")]
+async fn get_stream_status(state: &State<SafeState>, _jwt: Authenticated) -> Json<StreamStatus> {
+    let state = state.read().await;
+    Json(StreamStatus {
+        is_streaming: state.is_streaming,
+        connection: state.stream_connection.clone(),
+        current: state.stream.current.as_ref().map(|session| session.report()),
+        sessions: state.stream.history.iter().map(|session| session.report()).collect(),
+    })
+}
+
+/// GET /status/inputs
+///
+/// The latest level of each input in the program mix. Empty unless inputs are
+/// configured in the audio config.
+#[get("/inputs")]
+async fn get_input_levels(state: &State<SafeState>, _jwt: Authenticated) -> Json<Vec<InputLevel>> {
+    Json(state.read().await.input_levels.clone())
+}
+
+const DEFAULT_METER_RATE_HZ: u32 = 10;
+
+/// GET /status/meter?rate=10&spectrum=true
+///
+/// A stream of JSON meter readings for both channels: RMS, peak and
+/// peak-hold in dBFS, plus the band spectrum when `spectrum` is set. `rate` is
+/// how many readings a second the client wants, up to 20; peaks in between are
+/// folded into the next reading so none are missed. When inputs are mixed,
+/// each reading also carries the latest level of every input.
+#[get("/meter?<rate>&<spectrum>")]
+async fn volume_meter(
+    rate: Option<u32>,
+    spectrum: Option<bool>,
+    broadcast: &State<Sender<AlasMessage>>,
+    feed: &State<MeterFeed>,
+    mut end: Shutdown
+) -> EventStream![] {
+    let rate = rate.unwrap_or(DEFAULT_METER_RATE_HZ).clamp(1, MAX_RATE_HZ);
+    let spectrum = spectrum.unwrap_or(false);
+    let interval = Duration::from_secs(1) / rate;
     let mut broadcast = broadcast.subscribe();
-    let mut last_message_sent = Instant::now() - Duration::from_millis(THROTTLE_MS);
+    let mut readings = feed.subscribe();
+    let mut last_message_sent = Instant::now() - interval;
+    let mut pending: Option<MeterReading> = None;
+    let feed = feed.inner().clone();
     EventStream! {
         loop {
             select! {
-                Ok(msg) = broadcast.recv() => {
-                    match msg {
-                        AlasMessage::VolumeChange { left, right } => {
+                reading = readings.recv() => {
+                    match reading {
+                        Ok(mut reading) => {
+                            if !spectrum {
+                                reading.spectrum = None;
+                            }
+                            match pending.as_mut() {
+                                Some(pending) => pending.merge(reading),
+                                None => pending = Some(reading),
+                            }
                             let now = Instant::now();
-                            if now.duration_since(last_message_sent) >= Duration::from_millis(THROTTLE_MS) {
+                            // Readings arrive every 50 ms, so allow a little jitter
+                            if now.duration_since(last_message_sent) + Duration::from_millis(10) >= interval &&
+                                let Some(mut reading) = pending.take() {
                                 last_message_sent = now;
-                                yield Event::data(left.to_string());
+                                reading.inputs = feed.input_levels();
+                                yield Event::json(&reading);
                             }
                         },
-                        AlasMessage::Exit => {
-                            break;
-                        }
-                        _ => {}
+                        // A slow client just skips some readings
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
                     }
                 },
+                Ok(AlasMessage::Exit) = broadcast.recv() => {
+                    break;
+                },
                 _ = &mut end => {
                     println!("Exiting WebSocket loop...");
                     break;
@@ -87,5 +166,7 @@ pub(crate) fn routes() -> Vec<Route> {
         volume_meter,
         get_network_status,
         get_audio_state,
+        get_stream_status,
+        get_input_levels,
     ]
 }
\ No newline at end of file
@@ -0,0 +1,59 @@
+use std::time::Duration;
+use rocket::{get, post, routes, Route, State};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use tokio::sync::broadcast::Sender;
+use tokio::time::timeout;
+use alas_lib::delay::DelayStatus;
+use alas_lib::state::{AlasMessage, SafeState};
+use crate::web_server::auth::Authenticated;
+
+/// GET /stream/delay
+///
+/// How deep the profanity delay currently is, or `null` when there is none.
+#[get("/delay")]
+async fn get_delay(state: &State<SafeState>, _jwt: Authenticated) -> Json<Option<DelayStatus>> {
+    Json(state.read().await.stream_delay)
+}
+
+/// POST /stream/delay/dump
+///
+/// Throws away the profanity delay, so that the stream jumps to the live input
+/// and then builds the delay back up. Answers with the delay after the dump.
+#[post("/delay/dump")]
+async fn dump_delay(
+    state: &State<SafeState>,
+    bus: &State<Sender<AlasMessage>>,
+    _jwt: Authenticated
+) -> Result<Json<DelayStatus>, Status> {
+    {
+        let state = state.read().await;
+        if state.config.delay.is_none() || !state.is_streaming {
+            return Err(Status::Conflict);
+        }
+    }
+
+    // Subscribe before asking so that we cannot miss the answer
+    let mut receiver = bus.subscribe();
+    bus.send(AlasMessage::DumpDelay).map_err(|_| Status::ServiceUnavailable)?;
+
+    let answer = timeout(Duration::from_secs(2), async {
+        loop {
+            match receiver.recv().await {
+                Ok(AlasMessage::DelayDumped { status }) => return Ok(Json(status)),
+                Ok(_) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(_) => return Err(Status::ServiceUnavailable),
+            }
+        }
+    }).await;
+
+    answer.unwrap_or(Err(Status::ServiceUnavailable))
+}
+
+pub(crate) fn routes() -> Vec<Route> {
+    routes![
+        get_delay,
+        dump_delay,
+    ]
+}
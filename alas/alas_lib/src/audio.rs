@@ -1,22 +1,43 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait };
 use cpal::{BufferSize, Sample, StreamConfig};
-use mp3lame_encoder::{ DualPcm, Encoder, FlushNoGap };
-use shout::{ ShoutConn };
+use mp3lame_encoder::{ Bitrate, DualPcm, Encoder, FlushNoGap };
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
 use std::sync::atomic::{ AtomicBool, Ordering };
 use std::sync::Arc;
-use std::time::{ SystemTime, UNIX_EPOCH };
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
 use tokio::runtime::Handle;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::broadcast::error::RecvError;
 
 use crate::state::AlasMessage::VolumeChange;
+use crate::config::{ AlasActivationMode, AlasDelayConfig, AlasEncryptionConfig, AlasLoggerConfig, AlasMonitorSource };
 use crate::state::{ AlasMessage, AlasState, SafeState };
 use bus::{Bus, BusReader};
 use tokio::task::JoinHandle;
 use tokio::{ select, task };
 use tokio::sync::RwLock;
+use chrono::Utc;
+use uuid::Uuid;
+use crate::catalog::{ RecordingEntry, RecordingKind, RecordingUploadStatus, SafeCatalog };
+use crate::delay::{ DelayStatus, ProfanityDelay };
 use crate::dropbox::upload_file_to_dropbox;
+use crate::encryption::{ encrypt_file, encrypted_path, parse_recipients };
+use crate::fallback::{ FallbackSource, FallbackSwitch };
+use crate::monitor::MonitorHandle;
+use crate::markers::{ take_marker_requests, write_chapters, write_sidecar, sidecar_path, RecordingMarker };
+use crate::schedule::{ sanitize_show_name, show_folder };
+use crate::storage::RECORDING_DIRECTORY;
+use crate::stream_stats::{ StreamSession, DEFAULT_BITRATE_KBPS };
+use crate::bitrate::{ AdaptiveBitrate, NetworkLinks };
+use crate::icecast::{ jitter_random, Backoff, IcecastConnectionState, IcecastSource };
+use crate::meter::{start_meter_thread, MeterFeed};
+use crate::mixer::{ DeviceBuffer, Mixer };
+use crate::tones::start_tone_thread;
+use crate::transcode::{ TranscodeQueue, TranscodeRequest };
+use crate::waveform::WaveformWriter;
 
 /// Starts the thread for handling audio.
 ///
@@ -26,30 +47,65 @@ use crate::dropbox::upload_file_to_dropbox;
 /// times.
 pub async fn start(
     bus: Sender<AlasMessage>,
-    alas_state: &SafeState
-) -> JoinHandle<(JoinHandle<()>, JoinHandle<&'static str>, JoinHandle<&'static str>)> {
+    alas_state: &SafeState,
+    catalog: &SafeCatalog,
+    monitor: &MonitorHandle,
+    meter_feed: &MeterFeed,
+    transcode_queue: &TranscodeQueue
+) -> JoinHandle<(
+    JoinHandle<()>,
+    JoinHandle<&'static str>,
+    JoinHandle<&'static str>,
+    JoinHandle<&'static str>,
+    JoinHandle<&'static str>,
+    JoinHandle<&'static str>
+)> {
     let handler = Handle::current();
     let alas_state = alas_state.clone();
+    let catalog = catalog.clone();
+    let monitor = monitor.clone();
+    let meter_feed = meter_feed.clone();
+    let transcode_queue = transcode_queue.clone();
 
     task::spawn_blocking(move || {
-        let mut desire_to_broadcast = Arc::new(AtomicBool::new(false));
+        // Each sink has its own activation state so that, for example, the
+        // recording can keep rolling through a longer silence than the stream.
+        let mut stream_activation = SinkActivation::new("stream");
+        let mut record_activation = SinkActivation::new("recording");
+        let stream_active = stream_activation.active.clone();
+        let record_active = record_activation.active.clone();
+        let mut fallback_switch = FallbackSwitch::new();
+        let fallback_active = fallback_switch.active.clone();
         let config_reset = Arc::new(AtomicBool::new(false));
-        let mut audio_last_seen = UNIX_EPOCH;
+        let dump_requested = Arc::new(AtomicBool::new(false));
+        let split_requested = Arc::new(AtomicBool::new(false));
 
         let mut audio_bus = Bus::<Vec<f32>>::new(2204 * 30);
 
         // Config watch
         let mut subscriber = bus.subscribe();
         let config_reset_watch = config_reset.clone();
+        let dump_requested_watch = dump_requested.clone();
+        let split_requested_watch = split_requested.clone();
         let config_thread = task::spawn(async move {
             loop {
                 let msg = subscriber.recv().await;
+                // Falling behind a busy bus is no reason to stop following the config
+                if let Err(RecvError::Lagged(_)) = msg {
+                    continue;
+                }
                 if let Ok(msg) = msg {
                     match msg {
                         AlasMessage::StreamingConfigUpdated => {
                             // Switch off the desire to broadcast to kill the loop
                             config_reset_watch.store(true, Ordering::Relaxed);
                         }
+                        AlasMessage::DumpDelay => {
+                            dump_requested_watch.store(true, Ordering::Relaxed);
+                        }
+                        AlasMessage::SplitRecording => {
+                            split_requested_watch.store(true, Ordering::Relaxed);
+                        }
                         AlasMessage::Exit => {
                             println!("✅ Exiting config thread!");
                             return;
@@ -68,20 +124,43 @@ pub async fn start(
         let icecast_rx = audio_bus.add_rx();
         let icecast = start_icecast_thread(
             icecast_rx,
-            desire_to_broadcast.clone(),
+            stream_active.clone(),
+            fallback_active,
             alas_state.clone(),
             bus.clone(),
-            config_reset.clone()
+            config_reset.clone(),
+            dump_requested
         );
         //
         // // File saving thread
         let file_rx = audio_bus.add_rx();
         let record = start_file_save_thread(
             file_rx,
-            desire_to_broadcast.clone(),
+            record_active.clone(),
+            split_requested,
+            alas_state.clone(),
+            catalog.clone(),
+            transcode_queue,
             bus.clone()
         );
 
+        // Compliance logger thread
+        let logger_rx = audio_bus.add_rx();
+        let logger = start_logger_thread(
+            logger_rx,
+            alas_state.clone(),
+            catalog.clone(),
+            bus.clone()
+        );
+
+        // Tone command thread
+        let tone_rx = audio_bus.add_rx();
+        let tones = start_tone_thread(tone_rx, alas_state.clone(), bus.clone());
+
+        // Meter feed thread
+        let meter_rx = audio_bus.add_rx();
+        let meter = start_meter_thread(meter_rx, meter_feed.clone());
+
         let host = cpal::default_host();
 
         // host.input_devices().expect("No input devices").for_each(|device| {
@@ -89,35 +168,68 @@ pub async fn start(
         //     println!("🔈 Found device: {}", name);
         // });
 
-        let device = host.input_devices()
-            .expect("No input devices")
-            .find(|device| {
-                let name = device.name().unwrap_or_else(|_| "Unknown".to_string());
-                name.contains("PCM1863") && name.contains("plughw:")
-            })
-            .expect("No device found containing 'PCM1863'");
+        let mixer = Mixer::new(&alas_state.blocking_read().config.audio.inputs);
+        let device = find_input_device(&host, &mixer.devices()[0])
+            .unwrap_or_else(|| panic!("No device found containing '{}'", mixer.devices()[0]));
 
         let err_fn = move |err| {
             eprintln!("an error occurred on stream: {}", err);
         };
 
+        // Any other devices are captured on their own clocks and mixed in as
+        // the primary device delivers audio
+        let mut buffers = Vec::new();
+        let mut secondary_streams = Vec::new();
+        for (index, name) in mixer.devices().iter().enumerate().skip(1) {
+            let buffer = DeviceBuffer::new(mixer.device_channels(index));
+            buffers.push(buffer.clone());
+            let Some(secondary) = find_input_device(&host, name) else {
+                eprintln!("🎚️ No device found containing '{}', its inputs will be silent", name);
+                continue;
+            };
+            let secondary_config = StreamConfig {
+                channels: mixer.device_channels(index) as u16,
+                sample_rate: cpal::SampleRate(48_000),
+                buffer_size: BufferSize::Default,
+            };
+            match secondary.build_input_stream(
+                &secondary_config,
+                move |data: &[f32], _: &_| buffer.push(data),
+                err_fn,
+                None
+            ) {
+                Ok(stream) => match stream.play() {
+                    Ok(_) => {
+                        println!("🎚️ Mixing in {}", name);
+                        secondary_streams.push(stream);
+                    }
+                    Err(e) => eprintln!("🎚️ Could not start {}: {}", name, e),
+                },
+                Err(e) => eprintln!("🎚️ Could not open {}: {}", name, e),
+            }
+        }
+
         let mut exit_bus = bus.subscribe();
 
         let stream_config = StreamConfig {
-            channels: 2,
+            channels: mixer.device_channels(0) as u16,
             sample_rate: cpal::SampleRate(48_000),
             buffer_size: BufferSize::Default,
         };
+        let mut input_mix = InputMix::new(mixer, buffers);
         let stream = match device
             .build_input_stream(
                 &stream_config,
-                move |data, _: &_| {
+                move |data: &[f32], _: &_| {
+                    let program = input_mix.mix(data, &alas_state, &meter_feed);
                     handle_samples::<f32>(
-                        data,
+                        &program,
                         &bus,
                         &alas_state,
-                        &mut desire_to_broadcast,
-                        &mut audio_last_seen,
+                        &mut stream_activation,
+                        &mut record_activation,
+                        &mut fallback_switch,
+                        &monitor,
                         &mut audio_bus
                     )
                 },
@@ -154,13 +266,17 @@ pub async fn start(
         });
         println!("Received exit message in audio thread...");
 
-        (config_thread, icecast, record)
+        (config_thread, icecast, record, logger, tones, meter)
     })
 }
 
 fn start_file_save_thread(
     mut file_rx: BusReader<Vec<f32>>,
-    desire_to_broadcast: Arc<AtomicBool>,
+    record_active: Arc<AtomicBool>,
+    split_requested: Arc<AtomicBool>,
+    state: SafeState,
+    catalog: SafeCatalog,
+    transcode_queue: TranscodeQueue,
     file_bus: Sender<AlasMessage>
 ) -> JoinHandle<&'static str> {
     let mut is_recording = false;
@@ -168,28 +284,91 @@ fn start_file_save_thread(
     task::spawn_blocking(move || {
         // return "Abandoned early!!";
         // TODO(config)
-        let mut mp3_encoder = mp3lame_encoder::Builder::new().expect("Could not create LAME");
-        mp3_encoder.set_num_channels(2).expect("set channels"); // TODO(config)
-        mp3_encoder
-            .set_sample_rate(48_000) // TODO(config)
-            .expect("set sample rate");
-        mp3_encoder
-            .set_brate(mp3lame_encoder::Bitrate::Kbps320) // TODO(config
-            .expect("set brate");
-        let mut mp3_encoder = mp3_encoder.build().expect("Could not init LAME");
+        let mut mp3_encoder = build_mp3_encoder(Bitrate::Kbps320);
+        // After a failure to open or write a file, wait before trying again
+        let mut retry_after: Option<Instant> = None;
+        let mut marker_rx = file_bus.subscribe();
+        // After a split, the audio that would have gone into the old file starts the new one
+        let mut carried: Option<Vec<f32>> = None;
 
         loop {
-            let mut input = match file_rx.recv() {
+            let mut input = match carried.take().map_or_else(|| file_rx.recv(), Ok) {
                 Ok(input) => input,
                 Err(_) => {
                     break;
                 }
             };
 
-            if desire_to_broadcast.load(Ordering::Relaxed) {
-                let (mut recording_file, file_path) = open_file_named_now();
+            // Nothing is being recorded, so there is nothing to mark
+            for _ in take_marker_requests(&mut marker_rx) {
+                let _ = file_bus.send(AlasMessage::MarkerRejected);
+            }
+
+            if retry_after.is_some_and(|retry_after| Instant::now() < retry_after) {
+                continue;
+            }
+
+            if record_active.load(Ordering::Relaxed) {
+                let show_name = state.blocking_read().schedule.show_name.clone();
+                let (mut recording_file, file_path) = match open_file_named_now(show_name.as_deref()) {
+                    Ok(opened) => opened,
+                    Err(err) => {
+                        eprintln!("Could not open a new recording file: {:?}", err);
+                        let _ = file_bus.send(AlasMessage::RecordingFailed {
+                            reason: format!("Could not open recording file: {}", err),
+                        });
+                        retry_after = Some(Instant::now() + RECORDING_RETRY_INTERVAL);
+                        continue;
+                    }
+                };
+                let recording_id = catalog_recording_started(
+                    &catalog,
+                    &file_path,
+                    RecordingKind::Show,
+                    show_name.clone()
+                );
+                let mut levels = RecordingLevels::default();
+                let mut markers: Vec<RecordingMarker> = Vec::new();
+                let mut waveform = WaveformWriter::create(&file_path)
+                    .inspect_err(|err| eprintln!("〰️ Could not start a waveform for {}: {:?}", file_path, err))
+                    .ok();
+                // A split asked for while nothing was recording does not apply to this file
+                split_requested.store(false, Ordering::Relaxed);
+
+                while record_active.load(Ordering::Relaxed) {
+                    if split_requested.swap(false, Ordering::Relaxed) {
+                        println!("✂️ Splitting the recording at {:.1}s", levels.duration_secs());
+                        carried = Some(input);
+                        break;
+                    }
+
+                    for label in take_marker_requests(&mut marker_rx) {
+                        let marker = RecordingMarker {
+                            offset_secs: levels.duration_secs(),
+                            created_at: Utc::now(),
+                            label,
+                        };
+                        println!("🔖 Marker at {:.1}s in {}", marker.offset_secs, file_path);
+                        markers.push(marker.clone());
+                        if let Err(err) = write_sidecar(&file_path, &markers) {
+                            eprintln!("🔖 Could not write markers for {}: {:?}", file_path, err);
+                        }
+                        catalog.blocking_write().update(&recording_id, |entry| entry.markers = markers.clone());
+                        let _ = file_bus.send(AlasMessage::MarkerAdded {
+                            recording_id: recording_id.clone(),
+                            marker,
+                            count: markers.len(),
+                        });
+                    }
 
-                while desire_to_broadcast.load(Ordering::Relaxed) {
+                    levels.add(&input);
+                    if let Some(writer) = waveform.as_mut()
+                        && let Err(err) = writer.add(&input)
+                    {
+                        // Not worth stopping the recording over
+                        eprintln!("〰️ Could not write the waveform for {}: {:?}", file_path, err);
+                        waveform = None;
+                    }
                     let mp3_buffer = make_mp3_samples(&mut mp3_encoder, &input);
                     match recording_file.write_all(&mp3_buffer) {
                         Ok(_) => {
@@ -201,9 +380,14 @@ fn start_file_save_thread(
                             }
                         }
                         Err(err) => {
+                            // Most likely the disk is full. Close out what we have
+                            // rather than keep writing into a broken file.
                             eprintln!("Error writing to file: {:?} 174", err);
-                            is_recording = false;
-                            let _ = &file_bus.send(AlasMessage::RecordingStopped).unwrap();
+                            let _ = file_bus.send(AlasMessage::RecordingFailed {
+                                reason: format!("Could not write recording file: {}", err),
+                            });
+                            retry_after = Some(Instant::now() + RECORDING_RETRY_INTERVAL);
+                            break;
                         }
                     }
 
@@ -218,9 +402,58 @@ fn start_file_save_thread(
                 is_recording = false;
                 let _ = &file_bus.send(AlasMessage::RecordingStopped);
                 println!("Stopped recording");
+                drop(recording_file);
+                if let Some(Err(err)) = waveform.map(WaveformWriter::finish) {
+                    eprintln!("〰️ Could not finish the waveform for {}: {:?}", file_path, err);
+                }
+                if let Err(err) = write_chapters(&file_path, &markers, levels.duration_secs()) {
+                    eprintln!("🔖 Could not write chapters to {}: {:?}", file_path, err);
+                }
+                catalog_recording_finished(
+                    &catalog,
+                    &recording_id,
+                    &file_path,
+                    &levels,
+                    RecordingUploadStatus::Pending
+                );
 
-                // Upload the file to Dropbox.
-                upload_file_to_dropbox(file_path, "".to_string(), file_bus.clone());
+                // Upload the file to Dropbox, filing scheduled shows in their own folder.
+                let destination_folder = show_folder(show_name.as_deref());
+                let (keep_local_copy, derivatives, encryption) = {
+                    let state = state.blocking_read();
+                    let keep_local_copy = state.config.storage
+                        .as_ref()
+                        .is_some_and(|storage| storage.keep_uploaded);
+                    let derivatives = state.config.transcode
+                        .as_ref()
+                        .map(|transcode| transcode.derivatives.clone())
+                        .unwrap_or_default();
+                    (keep_local_copy, derivatives, state.config.encryption.clone())
+                };
+                // Marker labels can be as sensitive as the recording, so with
+                // encryption on the sidecar stays here and in the catalog
+                if !markers.is_empty() && encryption.is_none() {
+                    upload_file_to_dropbox(
+                        sidecar_path(&file_path).to_string_lossy().to_string(),
+                        destination_folder.clone(),
+                        keep_local_copy,
+                        file_bus.clone()
+                    );
+                }
+                if derivatives.is_empty() && encryption.is_none() {
+                    upload_file_to_dropbox(file_path, destination_folder, keep_local_copy, file_bus.clone());
+                } else {
+                    // The transcoder uploads the recording once its derivatives
+                    // are made and it has been encrypted
+                    transcode_queue.submit(TranscodeRequest {
+                        recording_id,
+                        source: file_path,
+                        derivatives,
+                        encryption,
+                        destination_folder,
+                        keep_local_copy,
+                    });
+                }
             }
         }
 
@@ -229,94 +462,613 @@ fn start_file_save_thread(
     })
 }
 
+const RECORDING_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const LOGGER_DIRECTORY: &str = "/var/lib/alas/logger";
+const LOGGER_CONFIG_INTERVAL: Duration = Duration::from_secs(10);
+const STREAM_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An hour of compliance logging that is currently being written
+struct LoggerFile {
+    id: String,
+    path: String,
+    file: File,
+    encoder: Encoder,
+    levels: RecordingLevels,
+}
+
+/// Starts the compliance logger.
+///
+/// Unlike the show recording, the logger ignores the silence detector and the
+/// schedule entirely. It writes low-bitrate airchecks into one file per
+/// wall-clock hour under `/var/lib/alas/logger`, and prunes anything older than
+/// the configured retention. With encryption on, each hour is encrypted as
+/// soon as it is closed.
+fn start_logger_thread(
+    mut logger_rx: BusReader<Vec<f32>>,
+    state: SafeState,
+    catalog: SafeCatalog,
+    logger_bus: Sender<AlasMessage>
+) -> JoinHandle<&'static str> {
+    task::spawn_blocking(move || {
+        let mut logger_config: Option<AlasLoggerConfig> = None;
+        let mut encryption: Option<AlasEncryptionConfig> = None;
+        let mut last_config_check: Option<Instant> = None;
+        let mut current_hour = String::new();
+        let mut current_file: Option<LoggerFile> = None;
+        let mut last_open_attempt: Option<Instant> = None;
+
+        while let Ok(input) = logger_rx.recv() {
+
+            if last_config_check.is_none_or(|checked| checked.elapsed() >= LOGGER_CONFIG_INTERVAL) {
+                let state = state.blocking_read();
+                logger_config = state.config.logger.clone();
+                encryption = state.config.encryption.clone();
+                last_config_check = Some(Instant::now());
+            }
+
+            let Some(config) = &logger_config else {
+                // The logger has been switched off, so close out whatever we had
+                if let Some(logger_file) = current_file.take() {
+                    println!("📼 Logger disabled, closing {}", logger_file.path);
+                    finish_logger_file(logger_file, None, encryption.as_ref(), &catalog, &logger_bus);
+                }
+                current_hour.clear();
+                continue;
+            };
+
+            let hour = chrono::Local::now().format("%Y-%m-%dT%H0000").to_string();
+            if hour != current_hour {
+                if let Some(logger_file) = current_file.take() {
+                    finish_logger_file(logger_file, Some(config), encryption.as_ref(), &catalog, &logger_bus);
+                }
+                if last_open_attempt.is_none_or(|attempt| attempt.elapsed() >= RECORDING_RETRY_INTERVAL) {
+                    last_open_attempt = Some(Instant::now());
+                    prune_logger_files(Path::new(LOGGER_DIRECTORY), config.retention_hours);
+
+                    current_file = open_logger_file(&hour, config.bitrate, &catalog);
+                    // Until a file opens, the hour is tried again every little while
+                    if current_file.is_some() {
+                        current_hour = hour;
+                    }
+                }
+            }
+
+            if let Some(logger_file) = current_file.as_mut() {
+                logger_file.levels.add(&input);
+                let mp3_buffer = make_mp3_samples(&mut logger_file.encoder, &input);
+                if let Err(err) = logger_file.file.write_all(&mp3_buffer) {
+                    eprintln!("📼 Error writing to logger file {}: {:?}", logger_file.path, err);
+                    if let Some(logger_file) = current_file.take() {
+                        finish_logger_file(logger_file, None, encryption.as_ref(), &catalog, &logger_bus);
+                    }
+                    current_hour.clear();
+                }
+            }
+        }
+
+        if let Some(logger_file) = current_file.take() {
+            finish_logger_file(logger_file, None, encryption.as_ref(), &catalog, &logger_bus);
+        }
+
+        "✅ Exiting logger thread"
+    })
+}
+
+/// A path for the hour's logger file that is not taken yet, encrypted or not.
+/// After a restart the hour carries on in `<hour>-1.mp3`, `<hour>-2.mp3` and
+/// so on, rather than overwriting what was logged before.
+fn logger_file_path(directory: &Path, hour: &str) -> String {
+    (0..)
+        .map(|part| match part {
+            0 => directory.join(format!("{}.mp3", hour)),
+            part => directory.join(format!("{}-{}.mp3", hour, part)),
+        })
+        .map(|path| path.to_string_lossy().to_string())
+        .find(|path| !Path::new(path).exists() && !Path::new(&encrypted_path(path)).exists())
+        .expect("Ran out of logger file names")
+}
+
+fn open_logger_file(hour: &str, bitrate: u32, catalog: &SafeCatalog) -> Option<LoggerFile> {
+    if let Err(err) = std::fs::create_dir_all(LOGGER_DIRECTORY) {
+        eprintln!("📼 Could not create logger directory: {:?}", err);
+        return None;
+    }
+
+    let path = logger_file_path(Path::new(LOGGER_DIRECTORY), hour);
+    match File::options().write(true).create_new(true).open(&path) {
+        Ok(file) => {
+            println!("📼 Logging to {}", path);
+            Some(LoggerFile {
+                id: catalog_recording_started(catalog, &path, RecordingKind::Logger, None),
+                path,
+                file,
+                encoder: build_mp3_encoder(bitrate_from_kbps(bitrate)),
+                levels: RecordingLevels::default(),
+            })
+        }
+        Err(err) => {
+            eprintln!("📼 Could not open logger file {}: {:?}", path, err);
+            None
+        }
+    }
+}
+
+/// Closes out a logger file, uploading it if the logger's upload policy asks for it.
+/// Logger files are filed separately from show recordings and are always kept
+/// locally until they age out, since they are the compliance copy. Pass `None`
+/// for `config` to close the file without uploading it.
+fn finish_logger_file(
+    logger_file: LoggerFile,
+    config: Option<&AlasLoggerConfig>,
+    encryption: Option<&AlasEncryptionConfig>,
+    catalog: &SafeCatalog,
+    bus: &Sender<AlasMessage>
+) {
+    let LoggerFile { id, path, file, levels, .. } = logger_file;
+    drop(file);
+    println!("📼 Finished logger file {}", path);
+
+    let upload = config.is_some_and(|config| config.upload);
+    let upload_status = if upload {
+        RecordingUploadStatus::Pending
+    } else {
+        RecordingUploadStatus::Skipped
+    };
+    catalog_recording_finished(catalog, &id, &path, &levels, upload_status);
+
+    if let Some(encryption) = encryption {
+        encrypt_logger_file(catalog, id, path, encryption.clone(), upload, bus);
+    } else if upload {
+        upload_file_to_dropbox(path, "/Logger".to_string(), true, bus.clone());
+    }
+}
+
+/// Encrypts a closed logger file in place and then uploads it if asked to.
+/// This runs on its own thread so that the logger keeps up with the audio.
+/// Nothing is uploaded in the clear.
+fn encrypt_logger_file(
+    catalog: &SafeCatalog,
+    id: String,
+    path: String,
+    encryption: AlasEncryptionConfig,
+    upload: bool,
+    bus: &Sender<AlasMessage>
+) {
+    let catalog = catalog.clone();
+    let bus = bus.clone();
+    let spawned = std::thread::Builder::new().name("encrypt".to_string()).spawn(move || {
+        let result = parse_recipients(&encryption.recipients)
+            .and_then(|recipients| encrypt_file(&path, &recipients));
+        match result {
+            Ok(encrypted) => {
+                println!("🔐 Encrypted {}", path);
+                let size_bytes = std::fs::metadata(&encrypted).map(|m| m.len()).unwrap_or(0);
+                catalog.blocking_write().update(&id, |entry| {
+                    entry.path = encrypted.clone();
+                    entry.file_name = encrypted_path(&entry.file_name);
+                    entry.size_bytes = size_bytes;
+                    entry.encrypted = true;
+                });
+                if upload {
+                    upload_file_to_dropbox(encrypted, "/Logger".to_string(), true, bus);
+                }
+            }
+            Err(e) => {
+                eprintln!("🔐 Could not encrypt {}, so it will not be uploaded: {}", path, e);
+                if upload {
+                    catalog.blocking_write().update(&id, |entry| {
+                        entry.upload_status = RecordingUploadStatus::Failed;
+                    });
+                }
+            }
+        }
+    });
+    if let Err(e) = spawned {
+        eprintln!("🔐 Could not start encrypting a logger file: {}", e);
+    }
+}
+
+/// Deletes logger files, encrypted or not, whose last modification is older
+/// than `retention_hours`
+fn prune_logger_files(directory: &Path, retention_hours: u32) {
+    let retention = Duration::from_secs(retention_hours as u64 * 60 * 60);
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.ends_with(".mp3") && !name.ends_with(&encrypted_path(".mp3")) {
+            continue;
+        }
+        let age = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+
+        if let Some(age) = age && age > retention {
+            match std::fs::remove_file(&path) {
+                Ok(_) => println!("📼 Pruned old logger file {:?}", path),
+                Err(e) => eprintln!("📼 Could not prune {:?}: {:?}", path, e),
+            }
+        }
+    }
+}
+
+/// Adds a recording that has just been opened to the catalog and returns its id
+fn catalog_recording_started(
+    catalog: &SafeCatalog,
+    file_path: &str,
+    kind: RecordingKind,
+    show_name: Option<String>
+) -> String {
+    let id = Uuid::new_v4().to_string();
+    let file_name = Path::new(file_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    catalog.blocking_write().insert(RecordingEntry {
+        id: id.clone(),
+        path: file_path.to_string(),
+        file_name,
+        kind,
+        show_name,
+        started_at: Utc::now(),
+        ended_at: None,
+        duration_secs: 0.0,
+        size_bytes: 0,
+        peak_db: MIN_DB,
+        loudness_db: MIN_DB,
+        codec: "mp3".to_string(),
+        upload_status: RecordingUploadStatus::Pending,
+        local: true,
+        markers: vec![],
+        derivatives: vec![],
+        shared_url: None,
+        encrypted: false,
+    });
+    id
+}
+
+/// Fills in the final statistics for a recording that has just been closed
+fn catalog_recording_finished(
+    catalog: &SafeCatalog,
+    id: &str,
+    file_path: &str,
+    levels: &RecordingLevels,
+    upload_status: RecordingUploadStatus
+) {
+    let size_bytes = std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
+    catalog.blocking_write().update(id, |entry| {
+        entry.ended_at = Some(Utc::now());
+        entry.duration_secs = levels.duration_secs();
+        entry.size_bytes = size_bytes;
+        entry.peak_db = levels.peak_db();
+        entry.loudness_db = levels.loudness_db();
+        entry.upload_status = upload_status;
+    });
+}
+
+/// Moves the stream's connection to a new state, announcing the change
+fn set_connection_state(state: &SafeState, bus: &Sender<AlasMessage>, new_state: IcecastConnectionState) {
+    {
+        let mut state = state.blocking_write();
+        if state.stream_connection == new_state {
+            return;
+        }
+        state.stream_connection = new_state.clone();
+    }
+    let _ = bus.send(AlasMessage::StreamConnectionChanged { state: new_state });
+}
+
+fn set_streaming(state: &SafeState, bus: &Sender<AlasMessage>, is_streaming: bool) {
+    if state.blocking_read().is_streaming == is_streaming {
+        return;
+    }
+    state.blocking_write().is_streaming = is_streaming;
+    let _ = bus.send(if is_streaming { AlasMessage::StreamingStarted } else { AlasMessage::StreamingStopped });
+}
+
+/// Follows changes to the delay settings while streaming
+fn configure_delay(delay: &mut Option<ProfanityDelay>, config: Option<&AlasDelayConfig>) {
+    match (delay.as_mut(), config) {
+        (Some(delay), Some(config)) => delay.configure(config),
+        (None, Some(config)) => *delay = Some(ProfanityDelay::new(config)),
+        (_, None) => *delay = None,
+    }
+}
+
+/// Shares the delay's status, announcing it whenever what the LCD shows would change
+fn publish_delay_status(state: &SafeState, bus: &Sender<AlasMessage>, status: Option<DelayStatus>) {
+    let previous = std::mem::replace(&mut state.blocking_write().stream_delay, status);
+    let shown = |status: Option<DelayStatus>| {
+        status.map(|status| (status.target_secs, status.depth_secs.round() as u32, status.rebuilding))
+    };
+    if shown(previous) != shown(status) {
+        let _ = bus.send(AlasMessage::StreamDelayChanged { status });
+    }
+}
+
+/// Why we stopped waiting to reconnect
+enum StreamWait {
+    /// Time to try again
+    Elapsed,
+    /// The stream was switched off or its settings changed
+    Stopped,
+    /// The audio bus has gone away, so we are shutting down
+    Closed,
+}
+
+/// Waits for `duration`, or until the stream is stopped if there is none. The
+/// audio meant for the stream is thrown away meanwhile so that the audio bus
+/// never fills up and blocks the input.
+fn discard_audio_while_waiting(
+    icecast_rx: &mut BusReader<Vec<f32>>,
+    duration: Option<Duration>,
+    stream_active: &AtomicBool,
+    config_reset: &AtomicBool
+) -> StreamWait {
+    let until = duration.map(|duration| Instant::now() + duration);
+    loop {
+        if !stream_active.load(Ordering::Relaxed) || config_reset.load(Ordering::Relaxed) {
+            return StreamWait::Stopped;
+        }
+        let timeout = match until {
+            Some(until) if Instant::now() >= until => return StreamWait::Elapsed,
+            Some(until) => (until - Instant::now()).min(Duration::from_millis(100)),
+            None => Duration::from_millis(100),
+        };
+        if let Err(RecvTimeoutError::Disconnected) = icecast_rx.recv_timeout(timeout) {
+            return StreamWait::Closed;
+        }
+    }
+}
+
+/// Streams to Icecast whenever the stream is active.
+///
+/// Each session runs a small state machine: `Connecting` leads to `Live`, and
+/// any failure leads to `Backoff`, which waits (2, 4, 8... up to 60 seconds,
+/// with jitter) before `Connecting` again. Errors that retrying cannot fix,
+/// such as a wrong password, go to `Failed` until the settings change. The
+/// stream going quiet returns everything to `Idle`.
 fn start_icecast_thread(
     mut icecast_rx: BusReader<Vec<f32>>,
-    desire_to_broadcast: Arc<AtomicBool>,
+    stream_active: Arc<AtomicBool>,
+    fallback_active: Arc<AtomicBool>,
     state: Arc<RwLock<AlasState>>,
     message_bus: Sender<AlasMessage>,
-    config_reset: Arc<AtomicBool>
+    config_reset: Arc<AtomicBool>,
+    dump_requested: Arc<AtomicBool>
 ) -> JoinHandle<&'static str> {
     task::spawn_blocking(move || {
-        // return "Abandoned early!!";
-        // Set up the MP3 encoder.
-        let mut mp3_encoder = mp3lame_encoder::Builder::new().expect("Could not create LAME");
-        mp3_encoder.set_num_channels(2).expect("set channels"); // TODO(config)
-        mp3_encoder
-            .set_sample_rate(48_000) // TODO(config)
-            .expect("set sample rate");
-        mp3_encoder
-            .set_brate(mp3lame_encoder::Bitrate::Kbps128) // TODO(config)
-            .expect("set brate");
-        let mut mp3_encoder = mp3_encoder.build().expect("Could not init LAME");
+        let mut backoff = Backoff::default();
+        let mut fallback = FallbackSource::default();
+        let mut closed = false;
 
-        loop {
+        while !closed {
             let mut input = match icecast_rx.recv() {
                 Ok(input) => input,
                 Err(_) => {
                     break;
                 }
             };
+            if !stream_active.load(Ordering::Relaxed) {
+                continue;
+            }
 
-            if desire_to_broadcast.load(Ordering::Relaxed) {
-                let icecast_connection = connect_to_icecast(&state);
-                config_reset.store(false, Ordering::Relaxed);
+            let mut session = StreamSession::new();
+            config_reset.store(false, Ordering::Relaxed);
+            backoff.reset();
+            let mut last_published = Instant::now();
 
-                while
-                    desire_to_broadcast.load(Ordering::Relaxed) &&
-                    !config_reset.load(Ordering::Relaxed)
-                {
-                    let mp3_buffer = make_mp3_samples(&mut mp3_encoder, &input);
+            // Set up the MP3 encoder, at the top bitrate the links allow
+            let mut adaptive = {
+                let state = state.blocking_read();
+                state.config.adaptive_bitrate
+                    .as_ref()
+                    .map(|config| AdaptiveBitrate::new(config, NetworkLinks::from_state(&state)))
+            };
+            session.bitrate_kbps = adaptive.as_ref().map_or(DEFAULT_BITRATE_KBPS, AdaptiveBitrate::kbps);
+            let mut mp3_encoder = build_mp3_encoder(bitrate_from_kbps(session.bitrate_kbps));
+            let mut delay = state.blocking_read().config.delay.as_ref().map(ProfanityDelay::new);
+            // Refreshed along with the stream stats, to keep the state lock out of the send path
+            let (mut links, mut fallback_config) = {
+                let state = state.blocking_read();
+                (NetworkLinks::from_state(&state), state.config.fallback.clone())
+            };
 
-                    match icecast_connection.send(&mp3_buffer) {
-                        Ok(_) => {
-                            if !state.blocking_read().is_streaming {
-                                let mut mutable_state = state.blocking_write();
-                                mutable_state.is_streaming = true;
-                                let _ = message_bus.send(AlasMessage::StreamingStarted);
-                            }
+            // Each pass is one connection attempt
+            'session: loop {
+                let config = state.blocking_read().config.icecast.clone();
+                set_connection_state(&state, &message_bus, IcecastConnectionState::Connecting {
+                    attempt: backoff.attempt() + 1,
+                });
+                println!("Connecting to {:} {:}", config.hostname, config.mount);
+
+                let error = match IcecastSource::connect(&config) {
+                    Ok(mut icecast_connection) => {
+                        set_connection_state(&state, &message_bus, IcecastConnectionState::Live);
+                        backoff.reset();
+                        // Listeners hear silence until the delay has caught up with the input
+                        if let Some(delay) = delay.as_mut() {
+                            delay.fill_with_silence();
+                            publish_delay_status(&state, &message_bus, Some(delay.status()));
                         }
-                        Err(_err) => {
-                            let mut mutable_state = state.blocking_write();
-                            mutable_state.is_streaming = false;
-                            let _ = message_bus.send(AlasMessage::StreamingStopped);
-
-                            // Attempt to reconnect
-                            match icecast_connection.reconnect() {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    eprintln!("Icecast re-connect error: {:?}", e);
+                        let mut last_packet = Instant::now();
+
+                        loop {
+                            if !stream_active.load(Ordering::Relaxed) || config_reset.load(Ordering::Relaxed) {
+                                break 'session;
+                            }
+
+                            let replacement = if fallback_active.load(Ordering::Relaxed) {
+                                fallback.replace(&input, true, fallback_config.as_ref())
+                            } else {
+                                fallback.replace(&input, false, None)
+                            };
+                            if let Some(replacement) = replacement {
+                                input = replacement;
+                            }
+
+                            let dump = dump_requested.swap(false, Ordering::Relaxed);
+                            if let Some(delay) = delay.as_mut() {
+                                if dump {
+                                    delay.dump();
+                                    println!("🤐 Dumped the stream delay");
+                                    let status = delay.status();
+                                    state.blocking_write().stream_delay = Some(status);
+                                    let _ = message_bus.send(AlasMessage::DelayDumped { status });
                                 }
+                                input = delay.process(&input);
                             }
+
+                            let audio_secs = input.len() as f64 / (CHANNELS as f64 * SAMPLE_RATE as f64);
+                            let encode_started = Instant::now();
+                            let mp3_buffer = make_mp3_samples(&mut mp3_encoder, &input);
+                            session.record_encode(audio_secs, encode_started.elapsed());
+
+                            let send_started = Instant::now();
+                            if let Err(e) = icecast_connection.send(&mp3_buffer) {
+                                break e;
+                            }
+                            session.record_send(mp3_buffer.len(), send_started.elapsed());
+                            set_streaming(&state, &message_bus, true);
+
+                            if let Some(adaptive) = adaptive.as_mut() {
+                                adaptive.record_send(audio_secs, send_started.elapsed(), last_packet.elapsed());
+                                last_packet = Instant::now();
+
+                                if let Some(change) = adaptive.evaluate(links) {
+                                    println!("📶 Stream bitrate now {} kbps ({:?})", change.kbps, change.reason);
+                                    // Every packet is flushed, so the next one can start at the new rate
+                                    mp3_encoder = build_mp3_encoder(bitrate_from_kbps(change.kbps));
+                                    session.bitrate_kbps = change.kbps;
+                                    let _ = message_bus.send(AlasMessage::StreamBitrateChanged {
+                                        kbps: change.kbps,
+                                        reason: change.reason,
+                                    });
+                                }
+                            }
+
+                            // Publishing once a second keeps the state lock out of the send path
+                            if last_published.elapsed() >= STREAM_STATS_INTERVAL {
+                                let delay_config = {
+                                    let mut state = state.blocking_write();
+                                    state.stream.current = Some(session.clone());
+                                    links = NetworkLinks::from_state(&state);
+                                    fallback_config = state.config.fallback.clone();
+                                    state.config.delay.clone()
+                                };
+                                configure_delay(&mut delay, delay_config.as_ref());
+                                publish_delay_status(&state, &message_bus, delay.as_ref().map(ProfanityDelay::status));
+                                last_published = Instant::now();
+                            }
+
+                            input = match icecast_rx.recv() {
+                                Ok(input) => input,
+                                Err(_) => {
+                                    closed = true;
+                                    break 'session;
+                                }
+                            };
                         }
                     }
+                    Err(e) => e,
+                };
 
-                    input = match icecast_rx.recv() {
-                        Ok(input) => input,
-                        Err(_) => {
-                            break;
-                        }
-                    };
-                    // TODO: handle graceful exit
+                eprintln!("Icecast error: {}", error);
+                session.record_error(error.to_string());
+                set_streaming(&state, &message_bus, false);
+                state.blocking_write().stream.current = Some(session.clone());
+                // What was buffered before the failure is stale by the time we reconnect
+                if let Some(delay) = delay.as_mut() {
+                    delay.dump();
                 }
 
-                let mut mutable_state = state.blocking_write();
-                mutable_state.is_streaming = false;
-                let _ = message_bus.send(AlasMessage::StreamingStopped);
+                let delay = if error.is_fatal() {
+                    set_connection_state(&state, &message_bus, IcecastConnectionState::Failed {
+                        reason: error.to_string(),
+                    });
+                    None
+                } else {
+                    let delay = backoff.next_delay(jitter_random());
+                    println!("Reconnecting to Icecast in {:.1}s (attempt {})", delay.as_secs_f64(), backoff.attempt());
+                    set_connection_state(&state, &message_bus, IcecastConnectionState::Backoff {
+                        attempt: backoff.attempt(),
+                        retry_in_secs: delay.as_secs_f64().round() as u64,
+                        last_error: error.to_string(),
+                    });
+                    Some(delay)
+                };
+
+                match discard_audio_while_waiting(&mut icecast_rx, delay, &stream_active, &config_reset) {
+                    StreamWait::Elapsed => session.record_reconnect(),
+                    StreamWait::Stopped => break 'session,
+                    StreamWait::Closed => {
+                        closed = true;
+                        break 'session;
+                    }
+                }
             }
 
-            // TODO: handle graceful exit
+            state.blocking_write().stream.finish_session(session);
+            // The delay is filled with silence again when the next session connects
+            let idle_delay = delay.map(|mut delay| {
+                delay.dump();
+                delay.status()
+            });
+            publish_delay_status(&state, &message_bus, idle_delay);
+            set_streaming(&state, &message_bus, false);
+            set_connection_state(&state, &message_bus, IcecastConnectionState::Idle);
         }
 
-        let mut mutable_state = state.blocking_write();
-        mutable_state.is_streaming = false;
-        let _ = message_bus.send(AlasMessage::StreamingStopped);
+        set_streaming(&state, &message_bus, false);
+        set_connection_state(&state, &message_bus, IcecastConnectionState::Idle);
         println!("Closed Icecast streaming thread");
 
         "✅ Success! Returned out of Icecast thread!"
     })
 }
 
-fn make_mp3_samples<T>(mp3_encoder: &mut Encoder, input: &[T]) -> Vec<u8> where T: Sample {
+pub(crate) fn build_mp3_encoder(bitrate: Bitrate) -> Encoder {
+    let mut mp3_encoder = mp3lame_encoder::Builder::new().expect("Could not create LAME");
+    mp3_encoder.set_num_channels(2).expect("set channels"); // TODO(config)
+    mp3_encoder
+        .set_sample_rate(48_000) // TODO(config)
+        .expect("set sample rate");
+    mp3_encoder
+        .set_brate(bitrate)
+        .expect("set brate");
+    mp3_encoder.build().expect("Could not init LAME")
+}
+
+/// Picks the highest LAME bitrate that does not exceed `kbps`
+pub(crate) fn bitrate_from_kbps(kbps: u32) -> Bitrate {
+    match kbps {
+        0..=15 => Bitrate::Kbps8,
+        16..=23 => Bitrate::Kbps16,
+        24..=31 => Bitrate::Kbps24,
+        32..=39 => Bitrate::Kbps32,
+        40..=47 => Bitrate::Kbps40,
+        48..=63 => Bitrate::Kbps48,
+        64..=79 => Bitrate::Kbps64,
+        80..=95 => Bitrate::Kbps80,
+        96..=111 => Bitrate::Kbps96,
+        112..=127 => Bitrate::Kbps112,
+        128..=159 => Bitrate::Kbps128,
+        160..=191 => Bitrate::Kbps160,
+        192..=223 => Bitrate::Kbps192,
+        224..=255 => Bitrate::Kbps224,
+        256..=319 => Bitrate::Kbps256,
+        _ => Bitrate::Kbps320,
+    }
+}
+
+pub(crate) fn make_mp3_samples<T>(mp3_encoder: &mut Encoder, input: &[T]) -> Vec<u8> where T: Sample {
     let mut left_channel = Vec::new();
     let mut right_channel = Vec::new();
 
@@ -351,42 +1103,21 @@ fn make_mp3_samples<T>(mp3_encoder: &mut Encoder, input: &[T]) -> Vec<u8> where
     mp3_buffer
 }
 
-fn open_file_named_now() -> (File, String) {
-    let formatted_time = chrono::Local::now().format("/var/lib/alas/audio/%Y-%m-%dT%H%M%S.mp3").to_string();
-    (File::create(&formatted_time).expect("to open file"), formatted_time)
-}
-
-fn connect_to_icecast(state: &SafeState) -> ShoutConn {
-    println!("Connection attempt!");
-    loop {
-        let state_read_attempt = state.try_read();
-        if state_read_attempt.is_err() {
-            continue;
-        }
-        let state = state_read_attempt.unwrap().clone();
-        let config = &state.config.icecast;
-        println!("Connecting to {:} {:}", config.hostname, config.mount);
-        let connection = shout::ShoutConnBuilder
-            ::new()
-            .host(config.hostname.clone())
-            .port(config.port)
-            .user(String::from("source"))
-            .password(config.password.clone())
-            .mount(config.mount.clone())
-            .protocol(shout::ShoutProtocol::HTTP)
-            .format(shout::ShoutFormat::MP3)
-            .build();
-        if let Ok(connection) = connection {
-            return connection;
-        } else {
-            // Sleep for 3 seconds and try re-connecting
-            println!("Sleeping for 3 seconds and then re-trying our connection");
-            std::thread::sleep(std::time::Duration::from_secs(3));
-        }
-    }
+fn open_file_named_now(show_name: Option<&str>) -> std::io::Result<(File, String)> {
+    let timestamp = chrono::Local::now().format("%Y-%m-%dT%H%M%S");
+    let formatted_time = match show_name {
+        Some(show_name) => format!(
+            "{}/{}_{}.mp3",
+            RECORDING_DIRECTORY,
+            sanitize_show_name(show_name),
+            timestamp
+        ),
+        None => format!("{}/{}.mp3", RECORDING_DIRECTORY, timestamp),
+    };
+    Ok((File::create(&formatted_time)?, formatted_time))
 }
 
-fn float_to_i16(sample: f32) -> i16 {
+pub(crate) fn float_to_i16(sample: f32) -> i16 {
     // First clamp to the valid normalized range just in case
     let clamped = sample.clamp(-1.0, 1.0);
     // Map from [-1.0, 1.0] to [-32768, 32767] (i16 range)
@@ -395,57 +1126,239 @@ fn float_to_i16(sample: f32) -> i16 {
     (clamped * (i16::MAX as f32)) as i16
 }
 
+/// Finds the ALSA plug device whose name contains `pattern`
+fn find_input_device(host: &cpal::Host, pattern: &str) -> Option<cpal::Device> {
+    host.input_devices()
+        .expect("No input devices")
+        .find(|device| {
+            let name = device.name().unwrap_or_else(|_| "Unknown".to_string());
+            name.contains(pattern) && name.contains("plughw:")
+        })
+}
+
+const INPUT_CONFIG_INTERVAL: Duration = Duration::from_secs(1);
+const INPUT_LEVELS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Turns each packet from the capture devices into the program, keeping the
+/// mixer's settings and the per-input meters up to date along the way
+struct InputMix {
+    mixer: Mixer,
+    /// Audio from every device after the first, in the mixer's order
+    buffers: Vec<DeviceBuffer>,
+    channels: usize,
+    last_config_check: Option<Instant>,
+    last_levels: Instant,
+    restart_needed: bool,
+}
+
+impl InputMix {
+    fn new(mixer: Mixer, buffers: Vec<DeviceBuffer>) -> Self {
+        InputMix {
+            channels: mixer.device_channels(0),
+            mixer,
+            buffers,
+            last_config_check: None,
+            last_levels: Instant::now(),
+            restart_needed: false,
+        }
+    }
+
+    fn mix(&mut self, input: &[f32], state: &SafeState, meter_feed: &MeterFeed) -> Vec<f32> {
+        if self.last_config_check.is_none_or(|checked| checked.elapsed() >= INPUT_CONFIG_INTERVAL) &&
+            let Ok(state) = state.try_read()
+        {
+            self.last_config_check = Some(Instant::now());
+            if !self.mixer.configure(&state.config.audio.inputs) && !self.restart_needed {
+                println!("🎚️ The inputs' devices or channels changed; restart to apply them");
+                self.restart_needed = true;
+            }
+        }
+
+        let frames = input.len() / self.channels;
+        let secondary: Vec<Vec<f32>> = self.buffers.iter().map(|buffer| buffer.take(frames)).collect();
+        let mut sources = vec![input];
+        sources.extend(secondary.iter().map(Vec::as_slice));
+        let program = self.mixer.mix(&sources);
+
+        if self.last_levels.elapsed() >= INPUT_LEVELS_INTERVAL {
+            self.last_levels = Instant::now();
+            let levels = self.mixer.take_levels();
+            if !levels.is_empty() {
+                if let Ok(mut state) = state.try_write() {
+                    state.input_levels = levels.clone();
+                }
+                meter_feed.publish_input_levels(levels);
+            }
+        }
+        program
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn handle_samples<T>(
     input: &[T],
     bus: &Sender<AlasMessage>,
     state: &SafeState,
-    desire_to_broadcast: &AtomicBool,
-    audio_last_seen: &mut SystemTime,
-    sender: &mut Bus<Vec<T>>
+    stream_activation: &mut SinkActivation,
+    record_activation: &mut SinkActivation,
+    fallback_switch: &mut FallbackSwitch,
+    monitor: &MonitorHandle,
+    sender: &mut Bus<Vec<f32>>
 )
-    where T: Sample
+    where T: Sample<Float = f32>
 {
     let channels = 2;
+    let input: Vec<f32> = input.iter().map(|sample| sample.to_float_sample()).collect();
+    monitor.push(AlasMonitorSource::Input, &input);
+
     let (left, right) = calculate_rms_levels(&input, channels);
     let _ = &bus.send(VolumeChange { left, right }).expect("Could not update volume");
 
-    let read_state = match state.try_read() {
-        Ok(guard) => guard.clone(),
-        Err(_) => return, // Skip if can't acquire lock
+    // This runs for every buffer, so only the settings used here are read out
+    // of the state rather than cloning all of it
+    let Ok(read_state) = state.try_read() else {
+        return; // Skip if can't acquire lock
+    };
+    let audio_config = &read_state.config.audio;
+    let was_audio_present = read_state.is_audio_present;
+    let was_on_fallback = read_state.is_on_fallback;
+    // Once audio is present it has to drop below the lower stop threshold to
+    // count as silent, so levels hovering around one threshold do not flap
+    let threshold = if was_audio_present {
+        audio_config.stop_threshold()
+    } else {
+        audio_config.silence_threshold
+    };
+    // The schedule wins over the per-sink mode, which wins over the detector
+    let (stream_silence, stream_mode) = audio_config.stream_activation();
+    let stream_mode = read_state.schedule.stream.or(stream_mode);
+    let fallback_silence = read_state.config.fallback.as_ref().map(|fallback| fallback.silence_secs);
+    // ...except when the disk is nearly full, which stops the recording no matter what
+    let (record_silence, record_mode) = audio_config.record_activation();
+    let record_mode = if read_state.disk_low {
+        AlasActivationMode::ForceOff
+    } else {
+        read_state.record_override.unwrap_or(read_state.schedule.record.or(record_mode))
     };
+    drop(read_state);
+
+    let audio_present = left > threshold || right > threshold;
+    let now = SystemTime::now();
+    stream_activation.update(audio_present, now, stream_silence, stream_mode);
+
+    // A forced or scheduled stream plays the fallback audio through long silences
+    if let Some(on_fallback) = fallback_switch.update(audio_present, now, stream_mode, fallback_silence) {
+        let _ = bus.send(if on_fallback { AlasMessage::FallbackStarted } else { AlasMessage::FallbackStopped });
+    }
+
+    record_activation.update(audio_present, now, record_silence, record_mode);
 
-    if
-        left > read_state.config.audio.silence_threshold ||
-        right > read_state.config.audio.silence_threshold
+    let is_audio_present = stream_activation.detected || record_activation.detected;
+    let is_on_fallback = fallback_switch.active.load(Ordering::Relaxed);
+    if (is_audio_present != was_audio_present || is_on_fallback != was_on_fallback)
+        && let Ok(mut state) = state.try_write()
     {
-        // TODO(config)
-        if !desire_to_broadcast.load(Ordering::Relaxed) {
-            println!("Audio is now available!");
-            desire_to_broadcast.store(true, Ordering::Relaxed);
+        state.is_audio_present = is_audio_present;
+        state.is_on_fallback = is_on_fallback;
+    }
+
+    // Nothing is done to the audio yet, so the processed tap is the input as-is
+    monitor.push(AlasMonitorSource::Processed, &input);
+    sender.broadcast(input);
+}
 
-            if let Ok(mut state) = state.try_write() {
-                (*state).is_audio_present = true;
+/// Voice-activation state for a single sink
+struct SinkActivation {
+    name: &'static str,
+    /// Whether the silence detector thinks this sink should be running
+    detected: bool,
+    audio_last_seen: SystemTime,
+    /// Whether the sink is actually running, after the schedule and mode are applied
+    active: Arc<AtomicBool>,
+}
+
+impl SinkActivation {
+    fn new(name: &'static str) -> Self {
+        SinkActivation {
+            name,
+            detected: false,
+            audio_last_seen: UNIX_EPOCH,
+            active: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Feeds the detector with the latest audio level and returns whether the
+    /// sink should now be active.
+    fn update(
+        &mut self,
+        audio_present: bool,
+        now: SystemTime,
+        silence_duration: u32,
+        mode: AlasActivationMode
+    ) -> bool {
+        if audio_present {
+            if !self.detected {
+                println!("Audio is now available for the {}!", self.name);
             }
+            self.detected = true;
+            self.audio_last_seen = now;
+        } else if
+            self.detected &&
+            now.duration_since(self.audio_last_seen).map(|d| d.as_secs()).unwrap_or(0) >
+            (silence_duration as u64)
+        {
+            println!("There has been {} seconds of silence, stopping the {}!", silence_duration, self.name);
+            self.detected = false;
         }
-        *audio_last_seen = SystemTime::now();
-    } else if
-        // Before we do anything else, verify that we should still be recording/streaming
-        SystemTime::now().duration_since(*audio_last_seen).unwrap().as_secs() >
-        (read_state.config.audio.silence_duration_before_deactivation as u64)
-    {
-        if desire_to_broadcast.load(Ordering::Relaxed) {
-            println!("There has been 15 seconds of silence!");
+
+        let active = mode.resolve(self.detected);
+        self.active.store(active, Ordering::Relaxed);
+        active
+    }
+}
+
+const MIN_DB: f32 = -60.0;
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: usize = 2;
+
+fn amplitude_to_db(amplitude: f32) -> f32 {
+    if amplitude > 0.0 { (20.0 * amplitude.log10()).max(MIN_DB) } else { MIN_DB }
+}
+
+/// Running level statistics for a recording in progress
+#[derive(Default)]
+struct RecordingLevels {
+    peak: f32,
+    sum_squares: f64,
+    samples: u64,
+}
+
+impl RecordingLevels {
+    fn add(&mut self, input: &[f32]) {
+        for sample in input {
+            self.peak = self.peak.max(sample.abs());
+            self.sum_squares += (*sample as f64) * (*sample as f64);
         }
-        desire_to_broadcast.store(false, Ordering::Relaxed);
-        if let Ok(mut state) = state.try_write() {
-            (*state).is_audio_present = false;
+        self.samples += input.len() as u64;
+    }
+
+    fn peak_db(&self) -> f32 {
+        amplitude_to_db(self.peak)
+    }
+
+    fn loudness_db(&self) -> f32 {
+        if self.samples == 0 {
+            return MIN_DB;
         }
+        amplitude_to_db((self.sum_squares / self.samples as f64).sqrt() as f32)
     }
 
-    sender.broadcast(input.to_vec().clone());
+    fn duration_secs(&self) -> f64 {
+        self.samples as f64 / (CHANNELS as f64 * SAMPLE_RATE as f64)
+    }
 }
 
-fn calculate_rms_levels<T>(data: &[T], channels: usize) -> (f32, f32) where T: cpal::Sample {
+pub(crate) fn calculate_rms_levels<T>(data: &[T], channels: usize) -> (f32, f32) where T: cpal::Sample {
     let mut left_sum = 0.0;
     let mut right_sum = 0.0;
     let mut left_count = 0;
@@ -509,6 +1422,109 @@ mod test {
         assert!(loud_rms > -60.0);
         assert!(quiet_rms < loud_rms);
     }
+
+    #[test]
+    fn test_recording_levels() {
+        let mut levels = RecordingLevels::default();
+        assert_eq!(levels.peak_db(), MIN_DB);
+        assert_eq!(levels.loudness_db(), MIN_DB);
+
+        // One second of a full-scale square wave in both channels
+        let input: Vec<f32> = (0..SAMPLE_RATE as usize * CHANNELS)
+            .map(|i| if (i / 2) % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        levels.add(&input);
+
+        assert!(levels.peak_db().abs() < 0.01);
+        assert!(levels.loudness_db().abs() < 0.01);
+        assert!((levels.duration_secs() - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_bitrate_from_kbps() {
+        assert!(matches!(bitrate_from_kbps(64), Bitrate::Kbps64));
+        assert!(matches!(bitrate_from_kbps(100), Bitrate::Kbps96));
+        assert!(matches!(bitrate_from_kbps(1000), Bitrate::Kbps320));
+    }
+
+    #[test]
+    fn test_prune_logger_files() {
+        let directory = std::env::temp_dir().join(format!("alas-logger-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let old_file = directory.join("old.mp3");
+        let encrypted_file = directory.join("old.mp3.age");
+        let other_file = directory.join("notes.txt");
+        std::fs::write(&old_file, b"old").unwrap();
+        std::fs::write(&encrypted_file, b"old").unwrap();
+        std::fs::write(&other_file, b"keep").unwrap();
+
+        // Nothing is old enough to go yet
+        prune_logger_files(&directory, 1);
+        assert!(old_file.exists());
+
+        // With no retention at all, every logger file is stale
+        std::thread::sleep(Duration::from_millis(10));
+        prune_logger_files(&directory, 0);
+        assert!(!old_file.exists());
+        assert!(!encrypted_file.exists());
+        assert!(other_file.exists());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_logger_file_path_never_reuses_an_hour() {
+        let directory = std::env::temp_dir().join(format!("alas-logger-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let hour = "2024-05-01T140000";
+
+        let first = logger_file_path(&directory, hour);
+        assert_eq!(first, directory.join("2024-05-01T140000.mp3").to_string_lossy());
+        std::fs::write(&first, b"before the restart").unwrap();
+
+        let second = logger_file_path(&directory, hour);
+        assert_eq!(second, directory.join("2024-05-01T140000-1.mp3").to_string_lossy());
+        // An hour that has been encrypted is taken too
+        std::fs::write(encrypted_path(&second), b"encrypted").unwrap();
+        assert_eq!(
+            logger_file_path(&directory, hour),
+            directory.join("2024-05-01T140000-2.mp3").to_string_lossy()
+        );
+        assert_eq!(std::fs::read(&first).unwrap(), b"before the restart");
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_sink_activation_tails() {
+        let start = SystemTime::now();
+        let mut stream = SinkActivation::new("stream");
+        let mut record = SinkActivation::new("recording");
+
+        assert!(stream.update(true, start, 15, AlasActivationMode::Auto));
+        assert!(record.update(true, start, 60, AlasActivationMode::Auto));
+
+        // After 30 seconds of silence the stream stops but the recording carries on
+        let later = start + std::time::Duration::from_secs(30);
+        assert!(!stream.update(false, later, 15, AlasActivationMode::Auto));
+        assert!(record.update(false, later, 60, AlasActivationMode::Auto));
+
+        let much_later = start + std::time::Duration::from_secs(61);
+        assert!(!record.update(false, much_later, 60, AlasActivationMode::Auto));
+    }
+
+    #[test]
+    fn test_sink_activation_modes() {
+        let now = SystemTime::now();
+        let mut logger = SinkActivation::new("recording");
+
+        // A continuous aircheck runs without any audio at all
+        assert!(logger.update(false, now, 15, AlasActivationMode::ForceOn));
+        assert!(logger.active.load(Ordering::Relaxed));
+
+        assert!(!logger.update(true, now, 15, AlasActivationMode::ForceOff));
+        assert!(logger.detected);
+    }
 }
 
 /*
@@ -0,0 +1,256 @@
+use std::time::Duration;
+use serde::Serialize;
+
+use crate::config::AlasAdaptiveBitrateConfig;
+use crate::state::AlasState;
+
+/// How much audio is judged at a time
+const WINDOW_SECS: f64 = 5.0;
+/// Audio waiting to be sent beyond this means the uplink is not keeping up
+const CONGESTED_BACKLOG_SECS: f64 = 2.0;
+/// Spending more than this share of real time sending means we are close to the limit
+const CONGESTED_SEND_SHARE: f64 = 0.8;
+const CALM_BACKLOG_SECS: f64 = 0.5;
+const CALM_SEND_SHARE: f64 = 0.4;
+/// How long the link must look comfortable before trying a higher bitrate
+const STEP_UP_AFTER_SECS: f64 = 30.0;
+/// Windows to wait after a change before judging the new bitrate
+const SETTLE_WINDOWS: u32 = 2;
+
+/// Which of our network links are up
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetworkLinks {
+    pub wifi: bool,
+    pub cellular: bool,
+}
+
+impl NetworkLinks {
+    pub fn from_state(state: &AlasState) -> Self {
+        NetworkLinks {
+            wifi: state.wifi_on,
+            cellular: state.cell_on,
+        }
+    }
+
+    pub fn cellular_only(&self) -> bool {
+        self.cellular && !self.wifi
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BitrateChangeReason {
+    /// Audio was piling up faster than it could be sent
+    Congestion,
+    /// The link has had room to spare for a while
+    Headroom,
+    /// Only the cellular link is up, which is capped
+    CellularOnly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BitrateChange {
+    pub kbps: u32,
+    pub reason: BitrateChangeReason,
+}
+
+/// Steps the stream's bitrate through the configured tiers, based on how well
+/// the Icecast sink keeps up with the audio and which links are available.
+///
+/// The backlog is estimated from the sink itself: when sending is quick the
+/// sink waits on the audio, so wall-clock time and audio time match. When it
+/// is not, every packet takes longer than the audio it carries and the
+/// difference piles up.
+#[derive(Debug)]
+pub struct AdaptiveBitrate {
+    /// Highest first
+    tiers: Vec<u32>,
+    cellular_max_kbps: Option<u32>,
+    tier: usize,
+    backlog_secs: f64,
+    window_audio_secs: f64,
+    window_send_secs: f64,
+    calm_secs: f64,
+    settle_windows: u32,
+}
+
+impl AdaptiveBitrate {
+    /// Starts at the highest tier the current links allow
+    pub fn new(config: &AlasAdaptiveBitrateConfig, links: NetworkLinks) -> Self {
+        let mut tiers = config.tiers_kbps.clone();
+        tiers.sort_unstable_by(|a, b| b.cmp(a));
+        tiers.dedup();
+
+        let mut adaptive = AdaptiveBitrate {
+            tiers,
+            cellular_max_kbps: config.cellular_max_kbps,
+            tier: 0,
+            backlog_secs: 0.0,
+            window_audio_secs: 0.0,
+            window_send_secs: 0.0,
+            calm_secs: 0.0,
+            settle_windows: 0,
+        };
+        adaptive.tier = adaptive.highest_allowed_tier(links);
+        adaptive
+    }
+
+    pub fn kbps(&self) -> u32 {
+        self.tiers[self.tier]
+    }
+
+    /// Seconds of audio we think are waiting to be sent
+    pub fn backlog_secs(&self) -> f64 {
+        self.backlog_secs
+    }
+
+    /// The index of the best tier the links allow
+    fn highest_allowed_tier(&self, links: NetworkLinks) -> usize {
+        match self.cellular_max_kbps {
+            Some(max_kbps) if links.cellular_only() => self
+                .tiers
+                .iter()
+                .position(|&kbps| kbps <= max_kbps)
+                .unwrap_or(self.tiers.len() - 1),
+            _ => 0,
+        }
+    }
+
+    /// Notes one packet: how much audio it carried, how long sending it took,
+    /// and how much wall-clock time passed since the previous one.
+    pub fn record_send(&mut self, audio_secs: f64, send_took: Duration, since_last: Duration) {
+        self.backlog_secs = (self.backlog_secs + since_last.as_secs_f64() - audio_secs).max(0.0);
+        self.window_audio_secs += audio_secs;
+        self.window_send_secs += send_took.as_secs_f64();
+    }
+
+    fn change_to(&mut self, tier: usize, reason: BitrateChangeReason) -> Option<BitrateChange> {
+        self.tier = tier;
+        self.calm_secs = 0.0;
+        self.settle_windows = SETTLE_WINDOWS;
+        Some(BitrateChange {
+            kbps: self.kbps(),
+            reason,
+        })
+    }
+
+    /// Decides whether the bitrate should change. Losing Wi-Fi takes effect
+    /// straight away; everything else is judged once per window.
+    pub fn evaluate(&mut self, links: NetworkLinks) -> Option<BitrateChange> {
+        let highest_allowed = self.highest_allowed_tier(links);
+        if self.tier < highest_allowed {
+            return self.change_to(highest_allowed, BitrateChangeReason::CellularOnly);
+        }
+
+        if self.window_audio_secs < WINDOW_SECS {
+            return None;
+        }
+        let send_share = self.window_send_secs / self.window_audio_secs;
+        let window_secs = self.window_audio_secs;
+        self.window_audio_secs = 0.0;
+        self.window_send_secs = 0.0;
+
+        if self.settle_windows > 0 {
+            self.settle_windows -= 1;
+            return None;
+        }
+
+        if self.backlog_secs > CONGESTED_BACKLOG_SECS || send_share > CONGESTED_SEND_SHARE {
+            self.calm_secs = 0.0;
+            if self.tier + 1 < self.tiers.len() {
+                return self.change_to(self.tier + 1, BitrateChangeReason::Congestion);
+            }
+            return None;
+        }
+
+        if self.backlog_secs < CALM_BACKLOG_SECS && send_share < CALM_SEND_SHARE {
+            self.calm_secs += window_secs;
+            if self.calm_secs >= STEP_UP_AFTER_SECS && self.tier > highest_allowed {
+                return self.change_to(self.tier - 1, BitrateChangeReason::Headroom);
+            }
+        } else {
+            self.calm_secs = 0.0;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIFI: NetworkLinks = NetworkLinks { wifi: true, cellular: true };
+    const CELLULAR: NetworkLinks = NetworkLinks { wifi: false, cellular: true };
+
+    fn config() -> AlasAdaptiveBitrateConfig {
+        AlasAdaptiveBitrateConfig {
+            tiers_kbps: vec![64, 128, 96, 32],
+            cellular_max_kbps: Some(64),
+        }
+    }
+
+    /// Feeds a window of one-second packets and returns any change
+    fn run_window(adaptive: &mut AdaptiveBitrate, send_secs: f64, links: NetworkLinks) -> Option<BitrateChange> {
+        let mut change = None;
+        for _ in 0..WINDOW_SECS as usize {
+            let wall = Duration::from_secs_f64(send_secs.max(1.0));
+            adaptive.record_send(1.0, Duration::from_secs_f64(send_secs), wall);
+            change = change.or(adaptive.evaluate(links));
+        }
+        change
+    }
+
+    #[test]
+    fn test_steps_down_when_congested() {
+        let mut adaptive = AdaptiveBitrate::new(&config(), WIFI);
+        assert_eq!(adaptive.kbps(), 128);
+
+        // Each second of audio takes 1.5 seconds to send
+        let change = run_window(&mut adaptive, 1.5, WIFI);
+        assert_eq!(change, Some(BitrateChange { kbps: 96, reason: BitrateChangeReason::Congestion }));
+        assert!(adaptive.backlog_secs() > CONGESTED_BACKLOG_SECS);
+
+        // The new bitrate gets time to drain the backlog before it is judged
+        for _ in 0..SETTLE_WINDOWS {
+            assert_eq!(run_window(&mut adaptive, 1.5, WIFI), None);
+        }
+        assert_eq!(run_window(&mut adaptive, 1.5, WIFI).map(|change| change.kbps), Some(64));
+    }
+
+    #[test]
+    fn test_steps_up_after_headroom() {
+        let mut adaptive = AdaptiveBitrate::new(&config(), WIFI);
+        run_window(&mut adaptive, 1.5, WIFI);
+        assert_eq!(adaptive.kbps(), 96);
+
+        // Let the backlog drain, then stay comfortable
+        adaptive.backlog_secs = 0.0;
+        let mut changes = Vec::new();
+        for _ in 0..12 {
+            changes.extend(run_window(&mut adaptive, 0.1, WIFI));
+        }
+        assert_eq!(changes, vec![BitrateChange { kbps: 128, reason: BitrateChangeReason::Headroom }]);
+    }
+
+    #[test]
+    fn test_cellular_only_is_capped() {
+        let adaptive = AdaptiveBitrate::new(&config(), CELLULAR);
+        assert_eq!(adaptive.kbps(), 64);
+
+        let mut adaptive = AdaptiveBitrate::new(&config(), WIFI);
+        assert_eq!(
+            adaptive.evaluate(CELLULAR),
+            Some(BitrateChange { kbps: 64, reason: BitrateChangeReason::CellularOnly })
+        );
+
+        // Plenty of headroom, but still capped until Wi-Fi returns
+        for _ in 0..12 {
+            run_window(&mut adaptive, 0.1, CELLULAR);
+        }
+        assert_eq!(adaptive.kbps(), 64);
+        for _ in 0..12 {
+            run_window(&mut adaptive, 0.1, WIFI);
+        }
+        assert_eq!(adaptive.kbps(), 128);
+    }
+}
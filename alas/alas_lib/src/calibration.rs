@@ -0,0 +1,266 @@
+use std::time::Duration;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+use tokio::{select, spawn};
+
+use crate::config::AlasAudioConfig;
+use crate::state::{AlasMessage, AlasState, SafeState};
+
+pub const DEFAULT_WINDOW_SECS: u32 = 30;
+pub const MIN_WINDOW_SECS: u32 = 10;
+pub const MAX_WINDOW_SECS: u32 = 300;
+/// The quiet end of the window, taken as the noise floor
+const NOISE_PERCENTILE: f32 = 0.1;
+/// The loud end of the window, taken as the program level
+const PROGRAM_PERCENTILE: f32 = 0.9;
+/// Anything closer than this cannot be told apart reliably
+const MIN_RANGE_DB: f32 = 12.0;
+/// The start threshold sits this share of the way from the floor to the program...
+const START_SHARE: f32 = 0.4;
+/// ...but never further than this above the floor, so quiet passages still count
+const MAX_START_MARGIN_DB: f32 = 20.0;
+/// Readings come in roughly every 20 ms, so anything less means the input is not running
+const MIN_READINGS_PER_SEC: usize = 10;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Thresholds proposed from a calibration run
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct CalibrationProposal {
+    pub noise_floor_db: f32,
+    pub program_db: f32,
+    /// Audio must rise above this to count as present
+    pub start_threshold_db: f32,
+    /// ...and drop below this to count as silent again
+    pub stop_threshold_db: f32,
+}
+
+impl CalibrationProposal {
+    pub fn apply(&self, audio: &mut AlasAudioConfig) {
+        audio.silence_threshold = self.start_threshold_db;
+        audio.silence_stop_threshold = Some(self.stop_threshold_db);
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum CalibrationStatus {
+    #[default]
+    Idle,
+    Measuring {
+        window_secs: u32,
+        elapsed_secs: u32,
+    },
+    /// Waiting for someone to confirm the thresholds
+    Proposed {
+        proposal: CalibrationProposal,
+    },
+    Saved {
+        proposal: CalibrationProposal,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum CalibrationError {
+    #[error("Too little audio came in to calibrate ({0} readings)")]
+    TooFewReadings(usize),
+
+    #[error("Program ({program_db:.1} dB) is too close to the noise floor ({noise_floor_db:.1} dB)")]
+    NotEnoughRange {
+        noise_floor_db: f32,
+        program_db: f32,
+    },
+}
+
+fn round_to_half_db(level: f32) -> f32 {
+    (level * 2.0).round() / 2.0
+}
+
+fn percentile(sorted: &[f32], share: f32) -> f32 {
+    let index = ((sorted.len() - 1) as f32 * share).round() as usize;
+    sorted[index]
+}
+
+/// Works out start and stop thresholds from the levels heard during the window
+pub fn propose(mut levels: Vec<f32>, window_secs: u32) -> Result<CalibrationProposal, CalibrationError> {
+    if levels.len() < window_secs as usize * MIN_READINGS_PER_SEC {
+        return Err(CalibrationError::TooFewReadings(levels.len()));
+    }
+    levels.sort_unstable_by(f32::total_cmp);
+
+    let noise_floor_db = percentile(&levels, NOISE_PERCENTILE);
+    let program_db = percentile(&levels, PROGRAM_PERCENTILE);
+    let range = program_db - noise_floor_db;
+    if range < MIN_RANGE_DB {
+        return Err(CalibrationError::NotEnoughRange { noise_floor_db, program_db });
+    }
+
+    let start_margin = (range * START_SHARE).min(MAX_START_MARGIN_DB);
+    Ok(CalibrationProposal {
+        noise_floor_db: round_to_half_db(noise_floor_db),
+        program_db: round_to_half_db(program_db),
+        start_threshold_db: round_to_half_db(noise_floor_db + start_margin),
+        stop_threshold_db: round_to_half_db(noise_floor_db + start_margin / 2.0),
+    })
+}
+
+/// Saves the proposed thresholds to the audio config. Returns them, or `None`
+/// when there was nothing to confirm.
+pub fn confirm_calibration(state: &mut AlasState) -> Option<CalibrationProposal> {
+    let CalibrationStatus::Proposed { proposal } = state.calibration else {
+        return None;
+    };
+    let mut new_config = state.config.clone();
+    proposal.apply(&mut new_config.audio);
+    state.update_config(new_config);
+    state.calibration = CalibrationStatus::Saved { proposal };
+    Some(proposal)
+}
+
+async fn set_status(state: &SafeState, bus: &Sender<AlasMessage>, status: CalibrationStatus) {
+    state.write().await.calibration = status.clone();
+    let _ = bus.send(AlasMessage::CalibrationChanged { status });
+}
+
+/// Listens to the input levels for `window_secs` and proposes thresholds
+async fn measure(bus: Sender<AlasMessage>, state: SafeState, window_secs: u32) {
+    println!("🎚️ Calibrating levels over {}s", window_secs);
+    let mut receiver = bus.subscribe();
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(window_secs as u64);
+    let mut next_progress = started;
+    let mut levels = Vec::new();
+
+    loop {
+        if Instant::now() >= next_progress {
+            let elapsed_secs = started.elapsed().as_secs() as u32;
+            set_status(&state, &bus, CalibrationStatus::Measuring { window_secs, elapsed_secs }).await;
+            next_progress += PROGRESS_INTERVAL;
+        }
+        select! {
+            message = receiver.recv() => {
+                match message {
+                    Ok(AlasMessage::VolumeChange { left, right }) => levels.push(left.max(right)),
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return,
+                }
+            }
+            _ = sleep_until(deadline) => break,
+        }
+    }
+
+    let status = match propose(levels, window_secs) {
+        Ok(proposal) => {
+            println!("🎚️ Calibration proposes {:?}", proposal);
+            CalibrationStatus::Proposed { proposal }
+        }
+        Err(err) => {
+            eprintln!("🎚️ Calibration failed: {}", err);
+            CalibrationStatus::Failed { reason: err.to_string() }
+        }
+    };
+    set_status(&state, &bus, status).await;
+}
+
+/// Runs calibrations asked for from the API or the LCD
+pub fn start_calibration_listener(bus: Sender<AlasMessage>, state: &SafeState) -> JoinHandle<()> {
+    let state = state.clone();
+    let mut receiver = bus.subscribe();
+    spawn(async move {
+        let mut measuring: Option<JoinHandle<()>> = None;
+        loop {
+            match receiver.recv().await {
+                Ok(AlasMessage::StartCalibration { window_secs }) => {
+                    if measuring.as_ref().is_some_and(|task| !task.is_finished()) {
+                        println!("🎚️ Calibration already running");
+                        continue;
+                    }
+                    measuring = Some(spawn(measure(bus.clone(), state.clone(), window_secs)));
+                }
+                Ok(AlasMessage::ConfirmCalibration) => {
+                    let confirmed = {
+                        let mut state = state.write().await;
+                        confirm_calibration(&mut state).map(|_| state.calibration.clone())
+                    };
+                    if let Some(status) = confirmed {
+                        println!("🎚️ Saved calibrated thresholds");
+                        let _ = bus.send(AlasMessage::CalibrationChanged { status });
+                    }
+                }
+                Ok(AlasMessage::CancelCalibration) => {
+                    if let Some(task) = measuring.take() {
+                        task.abort();
+                    }
+                    set_status(&state, &bus, CalibrationStatus::Idle).await;
+                }
+                Ok(AlasMessage::Exit) | Err(RecvError::Closed) => {
+                    if let Some(task) = measuring.take() {
+                        task.abort();
+                    }
+                    println!("✅ Exiting calibration listener!");
+                    break;
+                }
+                _ => {}
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A window of readings, mostly at the floor with some program
+    fn readings(noise_floor_db: f32, program_db: f32, program_share: f32) -> Vec<f32> {
+        let count = DEFAULT_WINDOW_SECS as usize * 50;
+        let program = (count as f32 * program_share) as usize;
+        (0..count)
+            .map(|i| if i < program { program_db } else { noise_floor_db })
+            .collect()
+    }
+
+    #[test]
+    fn test_proposes_thresholds_between_floor_and_program() {
+        let proposal = propose(readings(-70.0, -20.0, 0.5), DEFAULT_WINDOW_SECS).unwrap();
+        assert_eq!(proposal.noise_floor_db, -70.0);
+        assert_eq!(proposal.program_db, -20.0);
+        // Capped at 20 dB above the floor, with the stop threshold halfway down
+        assert_eq!(proposal.start_threshold_db, -50.0);
+        assert_eq!(proposal.stop_threshold_db, -60.0);
+
+        let proposal = propose(readings(-60.0, -40.0, 0.3), DEFAULT_WINDOW_SECS).unwrap();
+        assert!(proposal.stop_threshold_db < proposal.start_threshold_db);
+        assert!(proposal.start_threshold_db < proposal.program_db);
+        assert!(proposal.stop_threshold_db > proposal.noise_floor_db);
+    }
+
+    #[test]
+    fn test_rejects_unusable_windows() {
+        assert!(matches!(
+            propose(readings(-50.0, -45.0, 0.5), DEFAULT_WINDOW_SECS),
+            Err(CalibrationError::NotEnoughRange { .. })
+        ));
+        assert!(matches!(
+            propose(vec![-60.0; 20], DEFAULT_WINDOW_SECS),
+            Err(CalibrationError::TooFewReadings(20))
+        ));
+    }
+
+    #[test]
+    fn test_apply_to_audio_config() {
+        let mut state = AlasState::test();
+        assert!(confirm_calibration(&mut state).is_none());
+        assert_eq!(state.calibration, CalibrationStatus::Idle);
+
+        let proposal = propose(readings(-70.0, -20.0, 0.5), DEFAULT_WINDOW_SECS).unwrap();
+        proposal.apply(&mut state.config.audio);
+        assert_eq!(state.config.audio.silence_threshold, -50.0);
+        assert_eq!(state.config.audio.stop_threshold(), -60.0);
+    }
+}
@@ -0,0 +1,392 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::spawn;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::RwLock;
+
+use crate::config::find_config_file;
+use crate::markers::RecordingMarker;
+use crate::state::AlasMessage;
+
+/// The oldest recordings are forgotten once the catalog holds this many when it is loaded
+const MAX_RECORDINGS: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingKind {
+    /// A show recording, started and stopped by the detector or the schedule
+    Show,
+    /// An hourly compliance logger file
+    Logger,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingUploadStatus {
+    /// Still recording, or waiting for the upload to start
+    Pending,
+    InProgress,
+    Uploaded,
+    Failed,
+    /// The upload policy (or a missing Dropbox link) means this file is never uploaded
+    Skipped,
+}
+
+/// Everything we know about a single recording
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordingEntry {
+    pub id: String,
+    pub path: String,
+    pub file_name: String,
+    pub kind: RecordingKind,
+    pub show_name: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub duration_secs: f64,
+    pub size_bytes: u64,
+    /// Highest sample peak, in dBFS
+    pub peak_db: f32,
+    /// Average RMS level over the whole recording, in dBFS
+    pub loudness_db: f32,
+    pub codec: String,
+    pub upload_status: RecordingUploadStatus,
+    /// False once the local file has been removed, e.g. after upload
+    pub local: bool,
+    #[serde(default)]
+    pub markers: Vec<RecordingMarker>,
+    /// Extra versions made by the transcoder, e.g. for the podcast
+    #[serde(default)]
+    pub derivatives: Vec<RecordingDerivative>,
+    /// Public download link to the uploaded copy, once the podcast feed has one
+    #[serde(default)]
+    pub shared_url: Option<String>,
+    /// The file at `path` has been encrypted with age, and the plaintext deleted
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+/// An extra version of a recording, made after it finished
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordingDerivative {
+    /// The derivative's name from the transcode config
+    pub name: String,
+    pub path: String,
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub bitrate: u32,
+    pub mono: bool,
+    /// Integrated loudness after normalization, when a target was set
+    pub loudness_lufs: Option<f32>,
+    /// Public download link to the uploaded copy, once the podcast feed has one
+    #[serde(default)]
+    pub shared_url: Option<String>,
+    /// The file at `path` has been encrypted with age, and the plaintext deleted
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+/// Filters for listing recordings. Every field that is set must match.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingFilter {
+    pub show_name: Option<String>,
+    pub kind: Option<RecordingKind>,
+    pub upload_status: Option<RecordingUploadStatus>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl RecordingFilter {
+    fn matches(&self, entry: &RecordingEntry) -> bool {
+        if let Some(show_name) = &self.show_name {
+            let entry_show = entry.show_name.as_deref().unwrap_or_default();
+            if !entry_show.eq_ignore_ascii_case(show_name) {
+                return false;
+            }
+        }
+        if self.kind.is_some_and(|kind| kind != entry.kind) {
+            return false;
+        }
+        if self.upload_status.is_some_and(|status| status != entry.upload_status) {
+            return false;
+        }
+        if self.from.is_some_and(|from| entry.started_at < from) {
+            return false;
+        }
+        if self.to.is_some_and(|to| entry.started_at > to) {
+            return false;
+        }
+        true
+    }
+}
+
+/// A persistent list of recordings, stored as JSON next to `config.json`
+pub struct RecordingCatalog {
+    path: PathBuf,
+    recordings: Vec<RecordingEntry>,
+}
+
+pub type SafeCatalog = Arc<RwLock<RecordingCatalog>>;
+
+/// The catalog lives alongside the configuration file
+pub fn find_catalog_file() -> PathBuf {
+    Path::new(&find_config_file()).with_file_name("recordings.json")
+}
+
+impl RecordingCatalog {
+    /// Loads the catalog from `path`, starting an empty one if it does not
+    /// exist yet. A catalog that cannot be parsed is moved aside, so that it
+    /// can be recovered by hand, rather than overwritten.
+    pub fn load(path: PathBuf) -> RecordingCatalog {
+        let recordings = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                let corrupt_path = path.with_extension(format!("json.corrupt-{}", Utc::now().format("%Y%m%dT%H%M%S")));
+                match fs::rename(&path, &corrupt_path) {
+                    Ok(_) => eprintln!("🗂️ Could not parse recording catalog, moved it to {:?} and starting fresh: {}", corrupt_path, e),
+                    Err(rename_error) => eprintln!("🗂️ Could not parse recording catalog ({}) or move it aside: {}", e, rename_error),
+                }
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        let mut catalog = RecordingCatalog { path, recordings };
+        if catalog.prune() {
+            catalog.save();
+        }
+        // Files may have come and gone while we were not running
+        catalog.refresh_local();
+        catalog
+    }
+
+    /// Forgets the oldest recordings past [`MAX_RECORDINGS`]. Returns whether
+    /// anything was forgotten.
+    fn prune(&mut self) -> bool {
+        if self.recordings.len() <= MAX_RECORDINGS {
+            return false;
+        }
+        self.recordings.sort_by_key(|entry| entry.started_at);
+        let excess = self.recordings.len() - MAX_RECORDINGS;
+        self.recordings.drain(..excess);
+        println!("🗂️ Forgot the {} oldest recording(s)", excess);
+        true
+    }
+
+    /// Checks which recordings still have their files here, for when upload
+    /// changes may have been missed. Saves only if anything changed.
+    pub fn refresh_local(&mut self) {
+        let mut changed = false;
+        for entry in self.recordings.iter_mut() {
+            let local = Path::new(&entry.path).exists();
+            changed |= entry.local != local;
+            entry.local = local;
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        let serialized = serde_json::to_vec(&self.recordings)
+            .expect("Could not stringify recording catalog");
+
+        // Write to a temporary file first so that a power cut cannot truncate the catalog
+        let temp_path = self.path.with_extension("tmp");
+        if let Err(e) = fs::write(&temp_path, serialized).and_then(|_| fs::rename(&temp_path, &self.path)) {
+            eprintln!("🗂️ Could not save recording catalog: {}", e);
+        }
+    }
+
+    pub fn insert(&mut self, entry: RecordingEntry) {
+        self.recordings.push(entry);
+        self.save();
+    }
+
+    /// Applies `change` to the recording with the given id, saving the catalog
+    /// only if the recording changed
+    pub fn update<F>(&mut self, id: &str, change: F) -> Option<RecordingEntry>
+        where F: FnOnce(&mut RecordingEntry)
+    {
+        let entry = self.recordings.iter_mut().find(|entry| entry.id == id)?;
+        let before = entry.clone();
+        change(entry);
+        let updated = entry.clone();
+        if updated != before {
+            self.save();
+        }
+        Some(updated)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&RecordingEntry> {
+        self.recordings.iter().find(|entry| entry.id == id)
+    }
+
+    pub fn find_by_path(&self, path: &str) -> Option<&RecordingEntry> {
+        self.recordings.iter().find(|entry| entry.path == path)
+    }
+
+    /// Lists matching recordings, newest first
+    pub fn list(&self, filter: &RecordingFilter) -> Vec<RecordingEntry> {
+        let mut results: Vec<RecordingEntry> = self.recordings
+            .iter()
+            .filter(|entry| filter.matches(entry))
+            .cloned()
+            .collect();
+        results.sort_by_key(|entry| std::cmp::Reverse(entry.started_at));
+        results
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<RecordingEntry> {
+        let index = self.recordings.iter().position(|entry| entry.id == id)?;
+        let removed = self.recordings.remove(index);
+        self.save();
+        Some(removed)
+    }
+}
+
+/// Keeps upload statuses in the catalog in step with the uploader
+pub async fn start_catalog_listener(mut receiver: Receiver<AlasMessage>, catalog: SafeCatalog) {
+    spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(AlasMessage::RecordingUploadChange { file_path, status }) => {
+                    let mut catalog = catalog.write().await;
+                    let id = catalog.find_by_path(&file_path).map(|entry| entry.id.clone());
+                    if let Some(id) = id {
+                        catalog.update(&id, |entry| {
+                            entry.upload_status = status;
+                            entry.local = Path::new(&entry.path).exists();
+                        });
+                    }
+                }
+                Ok(AlasMessage::Exit) => {
+                    println!("✅ Exiting catalog listener!");
+                    break;
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    // Upload statuses cannot be recovered, but where the files are can
+                    eprintln!("🗂️ Catalog listener missed {} messages, some upload statuses may be out of date", skipped);
+                    catalog.write().await.refresh_local();
+                }
+                Err(RecvError::Closed) => break,
+                Ok(_) => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn entry(id: &str, show_name: Option<&str>, kind: RecordingKind, started_at: DateTime<Utc>) -> RecordingEntry {
+        RecordingEntry {
+            id: id.to_string(),
+            path: format!("/var/lib/alas/audio/{}.mp3", id),
+            file_name: format!("{}.mp3", id),
+            kind,
+            show_name: show_name.map(|s| s.to_string()),
+            started_at,
+            ended_at: None,
+            duration_secs: 0.0,
+            size_bytes: 0,
+            peak_db: -60.0,
+            loudness_db: -60.0,
+            codec: "mp3".to_string(),
+            upload_status: RecordingUploadStatus::Pending,
+            local: true,
+            markers: vec![],
+            derivatives: vec![],
+            shared_url: None,
+            encrypted: false,
+        }
+    }
+
+    fn temp_catalog_path() -> PathBuf {
+        std::env::temp_dir().join(format!("alas-catalog-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_catalog_persists() {
+        let path = temp_catalog_path();
+        let now = Utc::now();
+
+        let mut catalog = RecordingCatalog::load(path.clone());
+        catalog.insert(entry("one", Some("Jazz Night"), RecordingKind::Show, now));
+        catalog.update("one", |entry| entry.upload_status = RecordingUploadStatus::Uploaded);
+
+        let reloaded = RecordingCatalog::load(path.clone());
+        let one = reloaded.get("one").expect("recording should have been saved");
+        assert_eq!(one.upload_status, RecordingUploadStatus::Uploaded);
+        assert_eq!(one.show_name.as_deref(), Some("Jazz Night"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_catalog_filters() {
+        let path = temp_catalog_path();
+        let now = Utc::now();
+
+        let mut catalog = RecordingCatalog::load(path.clone());
+        catalog.insert(entry("old", Some("Jazz Night"), RecordingKind::Show, now - Duration::days(7)));
+        catalog.insert(entry("new", Some("Jazz Night"), RecordingKind::Show, now));
+        catalog.insert(entry("hour", None, RecordingKind::Logger, now));
+
+        let everything = catalog.list(&RecordingFilter::default());
+        assert_eq!(everything.len(), 3);
+        assert_eq!(everything[0].started_at, now);
+        assert_eq!(everything[2].id, "old");
+
+        let jazz = catalog.list(&RecordingFilter {
+            show_name: Some("jazz night".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(jazz.len(), 2);
+
+        let recent_shows = catalog.list(&RecordingFilter {
+            kind: Some(RecordingKind::Show),
+            from: Some(now - Duration::days(1)),
+            ..Default::default()
+        });
+        assert_eq!(recent_shows.len(), 1);
+        assert_eq!(recent_shows[0].id, "new");
+
+        assert!(catalog.remove("hour").is_some());
+        assert!(catalog.remove("hour").is_none());
+        assert_eq!(catalog.list(&RecordingFilter::default()).len(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_catalog_is_moved_aside() {
+        let path = temp_catalog_path();
+        fs::write(&path, b"[{\"id\": ").unwrap();
+
+        let mut catalog = RecordingCatalog::load(path.clone());
+        assert!(catalog.list(&RecordingFilter::default()).is_empty());
+        assert!(!path.exists());
+        let corrupt_prefix = format!("{}.corrupt-", path.file_name().unwrap().to_string_lossy());
+        let corrupt_path = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.path())
+            .find(|moved| moved.file_name().unwrap().to_string_lossy().starts_with(&corrupt_prefix))
+            .expect("the corrupt catalog should have been kept");
+        assert_eq!(fs::read(&corrupt_path).unwrap(), b"[{\"id\": ");
+
+        // Recordings whose files are gone are kept, but no longer marked local
+        catalog.insert(entry("missing", None, RecordingKind::Show, Utc::now()));
+        let reloaded = RecordingCatalog::load(path.clone());
+        let missing = reloaded.get("missing").expect("the recording should have been kept");
+        assert!(!missing.local);
+
+        fs::remove_file(corrupt_path).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+}
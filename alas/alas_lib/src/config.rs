@@ -4,6 +4,18 @@ use std::io::Write;
 use base64::{Engine as _, engine::general_purpose};
 use std::net::IpAddr;
 use thiserror::Error;
+use crate::fallback::{audio_file_secs, MAX_FALLBACK_SECS};
+
+/// How we talk to the server as a source
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AlasIcecastProtocol {
+    /// Icecast 2.4+ HTTP `PUT`
+    #[default]
+    Http,
+    /// The legacy SHOUTcast v1 protocol, sent to the port after `port`
+    Icy,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AlasIcecastConfig {
@@ -11,6 +23,96 @@ pub struct AlasIcecastConfig {
     pub port: u16,
     pub mount: String,
     pub password: String,
+    /// The source user name. Ignored by the ICY protocol, which only has a password.
+    #[serde(default = "default_source_user")]
+    pub user: String,
+    #[serde(default)]
+    pub protocol: AlasIcecastProtocol,
+    /// Connect to the server over TLS
+    #[serde(default)]
+    pub tls: bool,
+    /// Stream name, shown by players and directories
+    pub name: Option<String>,
+    pub genre: Option<String>,
+    pub description: Option<String>,
+    /// The station's website
+    pub url: Option<String>,
+    /// List the stream in public directories
+    #[serde(default)]
+    pub public: bool,
+}
+
+fn default_source_user() -> String {
+    "source".to_string()
+}
+
+#[derive(Error, Debug)]
+pub enum IcecastConfigError {
+    #[error("Hostname must not be empty")]
+    MissingHostname,
+
+    #[error("Invalid port: {0} (must be 1-65535)")]
+    InvalidPort(u16),
+
+    #[error("Invalid mount: {0} (must start with /)")]
+    InvalidMount(String),
+
+    #[error("Source user and password must not be empty")]
+    MissingCredentials,
+
+    #[error("The ICY protocol does not support TLS")]
+    IcyOverTls,
+
+    #[error("Invalid station URL: {0} (must start with http:// or https://)")]
+    InvalidUrl(String),
+
+    #[error("{0} must not contain line breaks")]
+    InvalidHeader(&'static str),
+}
+
+impl AlasIcecastConfig {
+    /// Validates that the settings can be used to connect
+    pub fn validate(&self) -> Result<(), IcecastConfigError> {
+        if self.hostname.trim().is_empty() {
+            return Err(IcecastConfigError::MissingHostname);
+        }
+        // ICY sources connect to the next port up
+        if self.port == 0 || (self.protocol == AlasIcecastProtocol::Icy && self.port == u16::MAX) {
+            return Err(IcecastConfigError::InvalidPort(self.port));
+        }
+        if !self.mount.starts_with('/') || self.mount.chars().any(char::is_whitespace) {
+            return Err(IcecastConfigError::InvalidMount(self.mount.clone()));
+        }
+        if self.password.is_empty() || self.user.is_empty() {
+            return Err(IcecastConfigError::MissingCredentials);
+        }
+        if self.protocol == AlasIcecastProtocol::Icy && self.tls {
+            return Err(IcecastConfigError::IcyOverTls);
+        }
+        if let Some(url) = &self.url
+            && !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(IcecastConfigError::InvalidUrl(url.clone()));
+        }
+
+        // These all end up in request headers
+        let headers = [
+            ("Hostname", Some(&self.hostname)),
+            ("Mount", Some(&self.mount)),
+            ("User", Some(&self.user)),
+            ("Password", Some(&self.password)),
+            ("Name", self.name.as_ref()),
+            ("Genre", self.genre.as_ref()),
+            ("Description", self.description.as_ref()),
+            ("URL", self.url.as_ref()),
+        ];
+        for (field, value) in headers {
+            if value.is_some_and(|value| value.contains(['\r', '\n'])) {
+                return Err(IcecastConfigError::InvalidHeader(field));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -24,10 +126,124 @@ pub struct AlasWiFiConfig {
     pub password: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasSinkActivationConfig {
+    /// Overrides the shared `silence_duration_before_deactivation` for this sink
+    pub silence_duration_before_deactivation: Option<u32>,
+    /// Use `force_on` to keep this sink running continuously, e.g. as an aircheck logger
+    #[serde(default)]
+    pub mode: AlasActivationMode,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AlasAudioConfig {
     pub silence_duration_before_deactivation: u32,
+    /// Audio must rise above this level, in dB, to count as present
     pub silence_threshold: f32,
+    /// Once present, audio must drop below this level to count as silent.
+    /// Defaults to `silence_threshold`.
+    #[serde(default)]
+    pub silence_stop_threshold: Option<f32>,
+    pub stream: Option<AlasSinkActivationConfig>,
+    pub record: Option<AlasSinkActivationConfig>,
+    /// Inputs mixed into the program. Without any, the first two channels of
+    /// the PCM1863 are the program, as before.
+    #[serde(default)]
+    pub inputs: Vec<AlasInputConfig>,
+}
+
+/// One source in the program mix, such as a host mic or a line feed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasInputConfig {
+    pub name: String,
+    /// Part of the capture device's name; defaults to the PCM1863. Changing
+    /// the devices or channels takes effect when ALAS restarts.
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Zero-based channels on the device: one for a mono input, two for stereo
+    pub channels: Vec<u16>,
+    #[serde(default)]
+    pub gain_db: f32,
+    /// From -1 (left) to 1 (right)
+    #[serde(default)]
+    pub pan: f32,
+    #[serde(default)]
+    pub mute: bool,
+}
+
+/// Enough for a host mic, guests and a couple of line feeds
+const MAX_INPUTS: usize = 8;
+
+#[derive(Error, Debug)]
+pub enum InputConfigError {
+    #[error("At most {MAX_INPUTS} inputs can be mixed")]
+    TooManyInputs,
+
+    #[error("Every input needs a name")]
+    MissingName,
+
+    #[error("More than one input is named {0}")]
+    DuplicateName(String),
+
+    #[error("Input {0} must use one or two channels")]
+    InvalidChannels(String),
+
+    #[error("Invalid gain for {0}: {1} dB (must be -60 to 24)")]
+    InvalidGain(String, f32),
+
+    #[error("Invalid pan for {0}: {1} (must be -1 to 1)")]
+    InvalidPan(String, f32),
+}
+
+impl AlasAudioConfig {
+    pub fn validate(&self) -> Result<(), InputConfigError> {
+        if self.inputs.len() > MAX_INPUTS {
+            return Err(InputConfigError::TooManyInputs);
+        }
+        for (i, input) in self.inputs.iter().enumerate() {
+            if input.name.trim().is_empty() {
+                return Err(InputConfigError::MissingName);
+            }
+            if self.inputs[..i].iter().any(|other| other.name == input.name) {
+                return Err(InputConfigError::DuplicateName(input.name.clone()));
+            }
+            if !(1..=2).contains(&input.channels.len()) {
+                return Err(InputConfigError::InvalidChannels(input.name.clone()));
+            }
+            if !(-60.0..=24.0).contains(&input.gain_db) {
+                return Err(InputConfigError::InvalidGain(input.name.clone(), input.gain_db));
+            }
+            if !(-1.0..=1.0).contains(&input.pan) {
+                return Err(InputConfigError::InvalidPan(input.name.clone(), input.pan));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stop_threshold(&self) -> f32 {
+        self.silence_stop_threshold.unwrap_or(self.silence_threshold)
+    }
+
+    /// The silence tail and default mode for the Icecast stream
+    pub fn stream_activation(&self) -> (u32, AlasActivationMode) {
+        self.sink_activation(&self.stream)
+    }
+
+    /// The silence tail and default mode for the archive recording
+    pub fn record_activation(&self) -> (u32, AlasActivationMode) {
+        self.sink_activation(&self.record)
+    }
+
+    fn sink_activation(&self, sink: &Option<AlasSinkActivationConfig>) -> (u32, AlasActivationMode) {
+        match sink {
+            Some(sink) => (
+                sink.silence_duration_before_deactivation
+                    .unwrap_or(self.silence_duration_before_deactivation),
+                sink.mode,
+            ),
+            None => (self.silence_duration_before_deactivation, AlasActivationMode::Auto),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -47,6 +263,662 @@ pub struct AlasWebhookConfig {
     pub url: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasLoggerConfig {
+    /// MP3 bitrate of the hourly logger files, in kbps
+    pub bitrate: u32,
+    /// Logger files older than this are deleted automatically
+    pub retention_hours: u32,
+    /// Also upload each finished hour to the "/Logger" folder in Dropbox
+    #[serde(default)]
+    pub upload: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum LoggerConfigError {
+    #[error("Unsupported MP3 bitrate: {0} kbps")]
+    UnsupportedBitrate(u32),
+
+    #[error("Invalid retention: {0} hours (must be at least 1)")]
+    InvalidRetention(u32),
+}
+
+impl AlasLoggerConfig {
+    pub fn validate(&self) -> Result<(), LoggerConfigError> {
+        if !MP3_BITRATES_KBPS.contains(&self.bitrate) {
+            return Err(LoggerConfigError::UnsupportedBitrate(self.bitrate));
+        }
+        // No retention would delete every hour as soon as it is closed
+        if self.retention_hours == 0 {
+            return Err(LoggerConfigError::InvalidRetention(self.retention_hours));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasStorageConfig {
+    /// Archival recording stops when free space drops below this, in MB
+    pub min_free_mb: u64,
+    /// The oldest uploaded recordings are deleted until this much space is free, in MB
+    pub target_free_mb: u64,
+    /// Uploaded recordings older than this are deleted even when there is room to spare
+    pub retention_days: Option<u32>,
+    /// Keep show recordings on the device after they have been uploaded, leaving
+    /// them for the retention policy instead of deleting them straight away
+    #[serde(default)]
+    pub keep_uploaded: bool,
+    /// When deleting uploaded recordings does not free enough space, also delete
+    /// the oldest logger files that have been uploaded or never will be, even
+    /// before the logger's own retention is up
+    #[serde(default)]
+    pub prune_logger_files: bool,
+}
+
+impl Default for AlasStorageConfig {
+    fn default() -> Self {
+        AlasStorageConfig {
+            min_free_mb: 256,
+            target_free_mb: 1024,
+            retention_days: None,
+            keep_uploaded: false,
+            prune_logger_files: false,
+        }
+    }
+}
+
+/// Which point in the signal chain the confidence monitor plays
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AlasMonitorSource {
+    /// The raw audio from the input device
+    Input,
+    /// The audio as it is handed to the encoders
+    #[default]
+    Processed,
+    /// Our own stream, pulled back from the Icecast server
+    OffAir,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasMonitorConfig {
+    /// Name, or part of the name, of the output device, e.g. "Headphones"
+    pub device: String,
+    #[serde(default)]
+    pub source: AlasMonitorSource,
+    /// Linear gain from 0.0 (muted) to 1.0 (unity)
+    pub volume: f32,
+}
+
+/// Settings for listening back to our own stream
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasOffAirConfig {
+    /// Where listeners hear the stream. Defaults to the Icecast mount we stream to.
+    pub listen_url: Option<String>,
+    /// How far, on average, the return feed may drift from the input before
+    /// raising an alarm, in dB
+    pub tolerance_db: f32,
+    /// The longest delay between input and return feed that is searched for
+    pub max_latency_secs: u32,
+}
+
+impl Default for AlasOffAirConfig {
+    fn default() -> Self {
+        AlasOffAirConfig {
+            listen_url: None,
+            tolerance_db: 6.0,
+            max_latency_secs: 15,
+        }
+    }
+}
+
+/// Settings for stepping the stream's bitrate down when the uplink struggles
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasAdaptiveBitrateConfig {
+    /// The bitrates to move between, in kbps. The stream starts at the highest.
+    pub tiers_kbps: Vec<u32>,
+    /// The highest bitrate used while cellular is the only link up
+    pub cellular_max_kbps: Option<u32>,
+}
+
+impl Default for AlasAdaptiveBitrateConfig {
+    fn default() -> Self {
+        AlasAdaptiveBitrateConfig {
+            tiers_kbps: vec![128, 96, 64, 48, 32],
+            cellular_max_kbps: Some(64),
+        }
+    }
+}
+
+/// Bitrates the MP3 encoder can produce
+const MP3_BITRATES_KBPS: [u32; 16] = [8, 16, 24, 32, 40, 48, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+
+#[derive(Error, Debug)]
+pub enum BitrateConfigError {
+    #[error("At least one bitrate tier is required")]
+    NoTiers,
+
+    #[error("Unsupported MP3 bitrate: {0} kbps")]
+    UnsupportedBitrate(u32),
+}
+
+impl AlasAdaptiveBitrateConfig {
+    pub fn validate(&self) -> Result<(), BitrateConfigError> {
+        if self.tiers_kbps.is_empty() {
+            return Err(BitrateConfigError::NoTiers);
+        }
+        match self.tiers_kbps.iter().find(|kbps| !MP3_BITRATES_KBPS.contains(kbps)) {
+            Some(&kbps) => Err(BitrateConfigError::UnsupportedBitrate(kbps)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Audio to stream when the input goes quiet during a forced or scheduled broadcast
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasFallbackConfig {
+    /// MP3 or WAV files to loop, in order, e.g. a single station ID
+    pub files: Vec<String>,
+    /// Seconds of silence before switching to the fallback
+    pub silence_secs: u32,
+    /// Seconds of silence between plays. Zero loops the files back to back.
+    #[serde(default)]
+    pub gap_secs: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum FallbackConfigError {
+    #[error("At least one fallback file is required")]
+    NoFiles,
+
+    #[error("Fallback file not found: {0}")]
+    MissingFile(String),
+
+    #[error("The silence before switching to the fallback must be at least one second")]
+    NoSilence,
+
+    #[error("Could not read fallback file {0}: {1}")]
+    Unreadable(String, String),
+
+    #[error("The fallback files play for {0:.0} seconds, but may not play for more than {1}")]
+    TooLong(f64, u32),
+}
+
+impl AlasFallbackConfig {
+    pub fn validate(&self) -> Result<(), FallbackConfigError> {
+        if self.files.is_empty() {
+            return Err(FallbackConfigError::NoFiles);
+        }
+        if self.silence_secs == 0 {
+            return Err(FallbackConfigError::NoSilence);
+        }
+        let mut total_secs = 0.0;
+        for file in &self.files {
+            let path = std::path::Path::new(file);
+            if !path.is_file() {
+                return Err(FallbackConfigError::MissingFile(file.clone()));
+            }
+            // A file that does not say how long it is gets cut short when it is loaded
+            match audio_file_secs(path) {
+                Ok(secs) => total_secs += secs.unwrap_or(0.0),
+                Err(e) => return Err(FallbackConfigError::Unreadable(file.clone(), e.to_string())),
+            }
+        }
+        if total_secs > MAX_FALLBACK_SECS as f64 {
+            return Err(FallbackConfigError::TooLong(total_secs, MAX_FALLBACK_SECS));
+        }
+        Ok(())
+    }
+}
+
+/// A profanity delay on the stream. The recording is never delayed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasDelayConfig {
+    /// How far behind the input the stream runs, from 5 to 30 seconds
+    pub delay_secs: u32,
+    /// How much the stream is slowed down while the delay builds back up
+    /// after a dump, in percent. Higher rebuilds faster but is more audible.
+    #[serde(default = "default_rebuild_percent")]
+    pub rebuild_percent: u32,
+}
+
+fn default_rebuild_percent() -> u32 {
+    5
+}
+
+#[derive(Error, Debug)]
+pub enum DelayConfigError {
+    #[error("Invalid delay: {0} seconds (must be 5-30)")]
+    InvalidDelay(u32),
+
+    #[error("Invalid rebuild speed: {0}% (must be 1-10)")]
+    InvalidRebuildPercent(u32),
+}
+
+impl AlasDelayConfig {
+    pub fn validate(&self) -> Result<(), DelayConfigError> {
+        if !(5..=30).contains(&self.delay_secs) {
+            return Err(DelayConfigError::InvalidDelay(self.delay_secs));
+        }
+        if !(1..=10).contains(&self.rebuild_percent) {
+            return Err(DelayConfigError::InvalidRebuildPercent(self.rebuild_percent));
+        }
+        Ok(())
+    }
+}
+
+/// A per-second history of input levels on disk, for dead-air reports
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasLevelHistoryConfig {
+    /// Days of history kept before the oldest are deleted
+    pub retention_days: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum LevelHistoryConfigError {
+    #[error("Invalid retention: {0} days (must be 1-366)")]
+    InvalidRetention(u32),
+}
+
+impl AlasLevelHistoryConfig {
+    pub fn validate(&self) -> Result<(), LevelHistoryConfigError> {
+        if !(1..=366).contains(&self.retention_days) {
+            return Err(LevelHistoryConfigError::InvalidRetention(self.retention_days));
+        }
+        Ok(())
+    }
+}
+
+/// Return audio (IFB) from the studio, played to the talent's headphones
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasIfbConfig {
+    /// The studio's feed, e.g. an Icecast mount, as MP3 or Ogg Opus
+    pub url: String,
+    /// Name, or part of the name, of the output device, e.g. "Headphones"
+    pub device: String,
+    /// Linear gain from 0.0 (muted) to 1.0 (unity)
+    #[serde(default = "default_ifb_volume")]
+    pub volume: f32,
+    /// Audio held back to ride out network hiccups, in milliseconds
+    #[serde(default = "default_ifb_buffer_ms")]
+    pub buffer_ms: u32,
+    /// Play the return audio as soon as ALAS starts
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+fn default_ifb_volume() -> f32 {
+    1.0
+}
+
+fn default_ifb_buffer_ms() -> u32 {
+    500
+}
+
+#[derive(Error, Debug)]
+pub enum IfbConfigError {
+    #[error("Invalid return audio URL: {0} (expected http(s)://...)")]
+    InvalidUrl(String),
+
+    #[error("An output device is required")]
+    NoDevice,
+
+    #[error("Invalid volume: {0} (must be 0.0-1.0)")]
+    InvalidVolume(f32),
+
+    #[error("Invalid buffer: {0} ms (must be 100-5000)")]
+    InvalidBuffer(u32),
+}
+
+impl AlasIfbConfig {
+    pub fn validate(&self) -> Result<(), IfbConfigError> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(IfbConfigError::InvalidUrl(self.url.clone()));
+        }
+        if self.device.trim().is_empty() {
+            return Err(IfbConfigError::NoDevice);
+        }
+        if !(0.0..=1.0).contains(&self.volume) {
+            return Err(IfbConfigError::InvalidVolume(self.volume));
+        }
+        if !(100..=5_000).contains(&self.buffer_ms) {
+            return Err(IfbConfigError::InvalidBuffer(self.buffer_ms));
+        }
+        Ok(())
+    }
+}
+
+/// Extra versions of each finished show recording, made before it is uploaded
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasTranscodeConfig {
+    pub derivatives: Vec<AlasDerivativeConfig>,
+}
+
+/// One extra version of a recording, e.g. a 64 kbps mono MP3 for the podcast
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasDerivativeConfig {
+    /// Added to the recording's file name, e.g. "podcast" makes "Show_...-podcast.mp3"
+    pub name: String,
+    /// MP3 bitrate in kbps
+    pub bitrate: u32,
+    /// Mix down to a single channel
+    #[serde(default)]
+    pub mono: bool,
+    /// Integrated loudness to normalize to, e.g. -16.0. Left as recorded when unset.
+    pub loudness_lufs: Option<f32>,
+}
+
+#[derive(Error, Debug)]
+pub enum TranscodeConfigError {
+    #[error("At least one derivative is required")]
+    NoDerivatives,
+
+    #[error("Invalid derivative name: {0:?} (letters, digits, - and _ only)")]
+    InvalidName(String),
+
+    #[error("Derivative name {0:?} is used more than once")]
+    DuplicateName(String),
+
+    #[error("Invalid bitrate: {0} kbps (must be 8-320)")]
+    InvalidBitrate(u32),
+
+    #[error("Invalid loudness target: {0} LUFS (must be -36 to -6)")]
+    InvalidLoudness(f32),
+}
+
+impl AlasTranscodeConfig {
+    pub fn validate(&self) -> Result<(), TranscodeConfigError> {
+        if self.derivatives.is_empty() {
+            return Err(TranscodeConfigError::NoDerivatives);
+        }
+        let mut names = std::collections::HashSet::new();
+        for derivative in &self.derivatives {
+            let name = &derivative.name;
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(TranscodeConfigError::InvalidName(name.clone()));
+            }
+            if !names.insert(name.as_str()) {
+                return Err(TranscodeConfigError::DuplicateName(name.clone()));
+            }
+            if !(8..=320).contains(&derivative.bitrate) {
+                return Err(TranscodeConfigError::InvalidBitrate(derivative.bitrate));
+            }
+            if let Some(lufs) = derivative.loudness_lufs
+                && !(-36.0..=-6.0).contains(&lufs)
+            {
+                return Err(TranscodeConfigError::InvalidLoudness(lufs));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A podcast feed of each show's uploaded recordings, published next to them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasPodcastConfig {
+    /// The feed's title. In this and the other text fields, {show} is
+    /// replaced with the show name.
+    #[serde(default = "default_podcast_title")]
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    pub author: Option<String>,
+    /// The station's website
+    pub link: Option<String>,
+    /// Cover art, at least 1400x1400 for Apple Podcasts
+    pub image_url: Option<String>,
+    #[serde(default = "default_podcast_language")]
+    pub language: String,
+    /// An Apple Podcasts category, e.g. "News"
+    pub category: Option<String>,
+    #[serde(default)]
+    pub explicit: bool,
+    /// Title of each episode; {date} and {time} are when it was recorded
+    #[serde(default = "default_episode_title")]
+    pub episode_title: String,
+    #[serde(default)]
+    pub episode_description: String,
+    /// Publish this transcode derivative, e.g. "podcast", instead of the recording
+    pub derivative: Option<String>,
+    /// The newest episodes to keep in the feed
+    #[serde(default = "default_max_episodes")]
+    pub max_episodes: usize,
+}
+
+fn default_podcast_title() -> String {
+    "{show}".to_string()
+}
+
+fn default_podcast_language() -> String {
+    "en".to_string()
+}
+
+fn default_episode_title() -> String {
+    "{show}, {date}".to_string()
+}
+
+fn default_max_episodes() -> usize {
+    50
+}
+
+#[derive(Error, Debug)]
+pub enum PodcastConfigError {
+    #[error("A title is required")]
+    NoTitle,
+
+    #[error("Invalid URL: {0} (expected http(s)://...)")]
+    InvalidUrl(String),
+
+    #[error("Invalid episode count: {0} (must be 1-1000)")]
+    InvalidMaxEpisodes(usize),
+}
+
+impl AlasPodcastConfig {
+    pub fn validate(&self) -> Result<(), PodcastConfigError> {
+        if self.title.trim().is_empty() || self.episode_title.trim().is_empty() {
+            return Err(PodcastConfigError::NoTitle);
+        }
+        for url in [&self.link, &self.image_url].into_iter().flatten() {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(PodcastConfigError::InvalidUrl(url.clone()));
+            }
+        }
+        if !(1..=1_000).contains(&self.max_episodes) {
+            return Err(PodcastConfigError::InvalidMaxEpisodes(self.max_episodes));
+        }
+        Ok(())
+    }
+}
+
+/// Encrypts show recordings before they are uploaded, and each hour of the
+/// logger once it is closed, so that only whoever holds the private key can
+/// listen to them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasEncryptionConfig {
+    /// age public keys ("age1...") that can decrypt the recordings
+    pub recipients: Vec<String>,
+}
+
+impl AlasEncryptionConfig {
+    /// Validates that there is at least one recipient and that every one is an age public key
+    pub fn validate(&self) -> Result<(), crate::encryption::EncryptionError> {
+        crate::encryption::parse_recipients(&self.recipients).map(|_| ())
+    }
+}
+
+/// What a tone command does
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlasToneAction {
+    /// Records regardless of the silence detector, until stopped or the schedule changes
+    StartRecording,
+    /// Stops recording, until started again or the schedule changes
+    StopRecording,
+    /// Closes the current recording and carries on in a new file
+    SplitRecording,
+    Marker,
+    /// Fires the webhook with the state "tone:<sequence or frequency>"
+    Webhook,
+}
+
+/// A DTMF sequence or single tone that the remote end can send to control us
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasToneCommand {
+    /// DTMF keys to listen for, e.g. "*1"
+    pub dtmf: Option<String>,
+    /// Or a single tone, in Hz
+    pub tone_hz: Option<f32>,
+    /// How long a single tone must be held, in milliseconds
+    #[serde(default = "default_tone_ms")]
+    pub tone_ms: u32,
+    pub action: AlasToneAction,
+}
+
+fn default_tone_ms() -> u32 {
+    1_000
+}
+
+impl AlasToneCommand {
+    /// How the command is referred to in logs and webhooks, e.g. "*1" or "1000Hz"
+    pub fn trigger(&self) -> String {
+        match (&self.dtmf, self.tone_hz) {
+            (Some(sequence), _) => sequence.clone(),
+            (None, Some(frequency)) => format!("{}Hz", frequency),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlasToneConfig {
+    pub commands: Vec<AlasToneCommand>,
+}
+
+#[derive(Error, Debug)]
+pub enum ToneConfigError {
+    #[error("Each tone command needs either a DTMF sequence or a tone frequency, not both")]
+    AmbiguousTrigger,
+
+    #[error("Invalid DTMF sequence: {0:?} (use 0-9, *, # and A-D)")]
+    InvalidSequence(String),
+
+    #[error("Invalid tone frequency: {0} Hz (must be 300-3400)")]
+    InvalidFrequency(f32),
+}
+
+impl AlasToneConfig {
+    pub fn validate(&self) -> Result<(), ToneConfigError> {
+        for command in &self.commands {
+            match (&command.dtmf, command.tone_hz) {
+                (Some(sequence), None) => {
+                    if sequence.is_empty() || !sequence.chars().all(|key| "0123456789*#ABCD".contains(key)) {
+                        return Err(ToneConfigError::InvalidSequence(sequence.clone()));
+                    }
+                }
+                (None, Some(frequency)) => {
+                    if !(300.0..=3400.0).contains(&frequency) {
+                        return Err(ToneConfigError::InvalidFrequency(frequency));
+                    }
+                }
+                _ => return Err(ToneConfigError::AmbiguousTrigger),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a sink (stream or recording) decides whether it should be running
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AlasActivationMode {
+    /// Follow the silence detector
+    #[default]
+    Auto,
+    /// Stay on, even if the input is quiet
+    ForceOn,
+    /// Stay off, even if there is audio
+    ForceOff,
+}
+
+impl AlasActivationMode {
+    /// Combine this mode with what the silence detector would like to do
+    pub fn resolve(&self, detected: bool) -> bool {
+        match self {
+            AlasActivationMode::Auto => detected,
+            AlasActivationMode::ForceOn => true,
+            AlasActivationMode::ForceOff => false,
+        }
+    }
+
+    /// Returns this mode, unless it is `Auto`, in which case `fallback` applies
+    pub fn or(self, fallback: AlasActivationMode) -> AlasActivationMode {
+        match self {
+            AlasActivationMode::Auto => fallback,
+            _ => self,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasScheduleEntry {
+    /// Days of the week in cron-like notation, e.g. `["mon-fri"]` or `["sat", "sun"]`.
+    /// An empty list matches every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Start of the window as "HH:MM"
+    pub start: String,
+    /// End of the window as "HH:MM". An end at or before the start runs past midnight.
+    pub end: String,
+    #[serde(default)]
+    pub stream: AlasActivationMode,
+    #[serde(default)]
+    pub record: AlasActivationMode,
+    pub show_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlasScheduleConfig {
+    /// IANA timezone name, e.g. "America/New_York". Uses the system timezone when unset.
+    pub timezone: Option<String>,
+    pub entries: Vec<AlasScheduleEntry>,
+}
+
+#[derive(Error, Debug)]
+pub enum ScheduleError {
+    #[error("Invalid timezone: {0}")]
+    InvalidTimezone(String),
+
+    #[error("Invalid time: {0} (expected HH:MM)")]
+    InvalidTime(String),
+
+    #[error("Invalid day of week: {0}")]
+    InvalidDay(String),
+
+    #[error("Show names cannot be blank")]
+    BlankShowName,
+}
+
+impl AlasScheduleConfig {
+    /// Validates that the timezone and every entry can be understood
+    pub fn validate(&self) -> Result<(), ScheduleError> {
+        if let Some(timezone) = &self.timezone {
+            crate::schedule::parse_timezone(timezone)?;
+        }
+
+        for entry in &self.entries {
+            crate::schedule::parse_time(&entry.start)?;
+            crate::schedule::parse_time(&entry.end)?;
+            crate::schedule::parse_days(&entry.days)?;
+            // A blank name would put recordings at the root with no prefix
+            if entry.show_name.as_ref().is_some_and(|show_name| show_name.trim().is_empty()) {
+                return Err(ScheduleError::BlankShowName);
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AlasConfig {
     pub audio: AlasAudioConfig,
@@ -57,6 +929,21 @@ pub struct AlasConfig {
     pub dropbox: Option<AlasDropboxConfig>,
     pub redundancy: Option<AlasRedundancyConfig>,
     pub webhook: Option<AlasWebhookConfig>,
+    pub schedule: Option<AlasScheduleConfig>,
+    pub logger: Option<AlasLoggerConfig>,
+    pub storage: Option<AlasStorageConfig>,
+    pub monitor: Option<AlasMonitorConfig>,
+    pub off_air: Option<AlasOffAirConfig>,
+    /// Fixed 128 kbps when unset
+    pub adaptive_bitrate: Option<AlasAdaptiveBitrateConfig>,
+    pub fallback: Option<AlasFallbackConfig>,
+    pub delay: Option<AlasDelayConfig>,
+    pub tones: Option<AlasToneConfig>,
+    pub level_history: Option<AlasLevelHistoryConfig>,
+    pub ifb: Option<AlasIfbConfig>,
+    pub transcode: Option<AlasTranscodeConfig>,
+    pub podcast: Option<AlasPodcastConfig>,
+    pub encryption: Option<AlasEncryptionConfig>,
 }
 
 pub fn find_config_file() -> String {
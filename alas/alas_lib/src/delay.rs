@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use serde::Serialize;
+
+use crate::config::AlasDelayConfig;
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: usize = 2;
+
+/// How the profanity delay is doing, for the API and the LCD
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct DelayStatus {
+    pub target_secs: u32,
+    /// How far behind the input the stream currently is
+    pub depth_secs: f32,
+    /// Whether the delay is still growing back towards the target
+    pub rebuilding: bool,
+}
+
+/// A profanity delay for the stream.
+///
+/// Audio comes out exactly as fast as it goes in, so the sink never notices.
+/// Each connection starts with the delay filled with silence. While the buffer
+/// is shorter than the target, which is the case after a dump or when the
+/// delay is made longer, the buffered audio is played back slightly slowed
+/// down so that the delay grows back a little with every packet.
+pub struct ProfanityDelay {
+    /// Interleaved stereo
+    buffer: VecDeque<f32>,
+    target_frames: usize,
+    target_secs: u32,
+    /// Frames of buffer consumed for every frame played while rebuilding
+    rebuild_step: f64,
+    /// Where playback is between the first two frames of the buffer
+    position: f64,
+}
+
+impl ProfanityDelay {
+    pub fn new(config: &AlasDelayConfig) -> Self {
+        let mut delay = ProfanityDelay {
+            buffer: VecDeque::new(),
+            target_frames: 0,
+            target_secs: 0,
+            rebuild_step: 1.0,
+            position: 0.0,
+        };
+        delay.configure(config);
+        delay
+    }
+
+    /// Applies new settings. A shorter delay takes effect straight away by
+    /// skipping ahead; a longer one is rebuilt gradually.
+    pub fn configure(&mut self, config: &AlasDelayConfig) {
+        self.target_secs = config.delay_secs;
+        self.target_frames = config.delay_secs as usize * SAMPLE_RATE as usize;
+        self.rebuild_step = 1.0 - config.rebuild_percent as f64 / 100.0;
+
+        let excess = self.buffered_frames().saturating_sub(self.target_frames);
+        self.buffer.drain(..excess * CHANNELS);
+    }
+
+    fn buffered_frames(&self) -> usize {
+        self.buffer.len() / CHANNELS
+    }
+
+    pub fn depth_secs(&self) -> f32 {
+        self.buffered_frames() as f32 / SAMPLE_RATE as f32
+    }
+
+    pub fn is_rebuilding(&self) -> bool {
+        self.buffered_frames() < self.target_frames
+    }
+
+    pub fn status(&self) -> DelayStatus {
+        DelayStatus {
+            target_secs: self.target_secs,
+            depth_secs: self.depth_secs(),
+            rebuilding: self.is_rebuilding(),
+        }
+    }
+
+    /// Throws away everything in the delay, so the stream jumps to the live input
+    pub fn dump(&mut self) {
+        self.buffer.clear();
+        self.position = 0.0;
+    }
+
+    /// Tops the delay up to its target with silence, played before anything
+    /// already buffered, so that listeners are protected from the first packet
+    pub fn fill_with_silence(&mut self) {
+        let missing = self.target_frames.saturating_sub(self.buffered_frames());
+        let mut buffer = VecDeque::with_capacity(self.target_frames * CHANNELS);
+        buffer.resize(missing * CHANNELS, 0.0);
+        buffer.append(&mut self.buffer);
+        self.buffer = buffer;
+        self.position = 0.0;
+    }
+
+    /// Takes a packet of input and returns a packet of the same length from
+    /// further back
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.buffer.extend(input);
+        let frames = input.len() / CHANNELS;
+        if frames == 0 {
+            return Vec::new();
+        }
+
+        let available = self.buffered_frames();
+        let missing = (self.target_frames + frames).saturating_sub(available);
+        if missing == 0 {
+            self.position = 0.0;
+            return self.buffer.drain(..frames * CHANNELS).collect();
+        }
+
+        // Play the buffer a little slower than real time, without overshooting the target
+        let step = (1.0 - missing as f64 / frames as f64).max(self.rebuild_step);
+        let mut output = Vec::with_capacity(frames * CHANNELS);
+        for _ in 0..frames {
+            let index = (self.position as usize).min(available - 1);
+            let next = (index + 1).min(available - 1);
+            let fraction = (self.position - index as f64).clamp(0.0, 1.0) as f32;
+            for channel in 0..CHANNELS {
+                let a = self.buffer[index * CHANNELS + channel];
+                let b = self.buffer[next * CHANNELS + channel];
+                output.push(a + (b - a) * fraction);
+            }
+            self.position += step;
+        }
+
+        let consumed = (self.position as usize).min(available);
+        self.buffer.drain(..consumed * CHANNELS);
+        self.position -= consumed as f64;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(delay_secs: u32) -> AlasDelayConfig {
+        AlasDelayConfig {
+            delay_secs,
+            rebuild_percent: 5,
+        }
+    }
+
+    /// One second of stereo audio whose samples count up from `start`
+    fn second(start: usize) -> Vec<f32> {
+        (0..SAMPLE_RATE as usize).flat_map(|frame| [(start + frame) as f32; 2]).collect()
+    }
+
+    #[test]
+    fn test_delay_rebuilds_then_holds() {
+        let mut delay = ProfanityDelay::new(&config(5));
+        assert!(delay.is_rebuilding());
+
+        // Each second of input adds a twentieth of a second of delay
+        let output = delay.process(&second(0));
+        assert_eq!(output.len(), SAMPLE_RATE as usize * CHANNELS);
+        assert!((delay.depth_secs() - 0.05).abs() < 0.001);
+        assert!((output[2] - 0.95).abs() < 1e-3);
+
+        for i in 1..200 {
+            delay.process(&second(i * SAMPLE_RATE as usize));
+        }
+        assert!(!delay.is_rebuilding());
+        assert!((delay.depth_secs() - 5.0).abs() < 0.001);
+
+        // Once full, audio comes out exactly five seconds late
+        let start = 300 * SAMPLE_RATE as usize;
+        for i in 0..6 {
+            let output = delay.process(&second(start + i * SAMPLE_RATE as usize));
+            if i == 5 {
+                assert!((output[0] - start as f32).abs() < 2.0);
+            }
+        }
+        assert!((delay.depth_secs() - 5.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_dump_and_shorten() {
+        let mut delay = ProfanityDelay::new(&config(10));
+        for i in 0..400 {
+            delay.process(&second(i * SAMPLE_RATE as usize));
+        }
+        assert!((delay.depth_secs() - 10.0).abs() < 0.001);
+
+        delay.configure(&config(5));
+        assert!((delay.depth_secs() - 5.0).abs() < 0.001);
+        assert!(!delay.status().rebuilding);
+
+        delay.dump();
+        assert_eq!(delay.depth_secs(), 0.0);
+        let live = 1_000 * SAMPLE_RATE as usize;
+        let output = delay.process(&second(live));
+        assert_eq!(output[0], live as f32);
+        assert!(delay.status().rebuilding);
+    }
+
+    #[test]
+    fn test_starts_full_of_silence() {
+        let mut delay = ProfanityDelay::new(&config(5));
+        delay.fill_with_silence();
+        assert!(!delay.is_rebuilding());
+        assert!((delay.depth_secs() - 5.0).abs() < 0.001);
+
+        // Listeners hear five seconds of silence, then the input at normal speed
+        for i in 0..5 {
+            let output = delay.process(&second(1 + i * SAMPLE_RATE as usize));
+            assert!(output.iter().all(|sample| *sample == 0.0));
+        }
+        let output = delay.process(&second(5 * SAMPLE_RATE as usize));
+        assert_eq!(output[0], 1.0);
+        assert_eq!(output[2], 2.0);
+        assert!((delay.depth_secs() - 5.0).abs() < 0.001);
+    }
+}
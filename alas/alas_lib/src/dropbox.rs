@@ -3,10 +3,11 @@ use tokio::task;
 use tokio::sync::broadcast::Sender;
 use bytes::Bytes;
 
+use crate::catalog::RecordingUploadStatus;
 use crate::config::load_config_async;
 use crate::state::{AlasMessage, AlasUploadState, AlasUploadStatus};
 use dropbox_sdk::default_client::UserAuthDefaultClient;
-use dropbox_sdk::async_routes::files;
+use dropbox_sdk::async_routes::{files, sharing};
 use dropbox_sdk::oauth2::Authorization;
 use tokio::runtime::Builder;
 use tokio::task::JoinHandle;
@@ -44,11 +45,13 @@ pub async fn get_dropbox_access_token() -> Result<Authorization, String> {
 
 async fn do_upload(file_path: String,
                    destination_folder: String,
+                   keep_local_copy: bool,
                    message_bus: Sender<AlasMessage>,) {
     // Get Dropbox access token
     let token = get_dropbox_access_token().await;
     if token.is_err() {
         println!("📦 No Dropbox token, exiting..");
+        send_recording_update(&message_bus, &file_path, RecordingUploadStatus::Skipped);
         return;
     }
 
@@ -74,6 +77,8 @@ async fn do_upload(file_path: String,
         queue: vec![file_name.clone()],
     });
 
+    send_recording_update(&message_bus, &file_path, RecordingUploadStatus::InProgress);
+
     println!("📦 Reported progress...");
 
     // Load file
@@ -130,20 +135,24 @@ async fn do_upload(file_path: String,
                                         progress: 100,
                                         queue: vec![],
                                     });
-                                    // Delete the file
-                                    match tokio::fs::remove_file(&file_path).await {
-                                        Ok(_) => {
-                                            println!("📦 Deleted file {}", file_path);
-                                        },
-                                        Err(e) => {
-                                            println!("📦 Failed to delete file: {}", e);
+                                    // Delete the file, unless its owner wants to keep it around
+                                    if !keep_local_copy {
+                                        match tokio::fs::remove_file(&file_path).await {
+                                            Ok(_) => {
+                                                println!("📦 Deleted file {}", file_path);
+                                            },
+                                            Err(e) => {
+                                                println!("📦 Failed to delete file: {}", e);
+                                            }
                                         }
                                     }
+                                    send_recording_update(&message_bus, &file_path, RecordingUploadStatus::Uploaded);
                                 },
                                 Err(e) => {
                                     println!("📦 Failed to complete upload: {:?}", e);
                                     // Reset upload state
                                     reset_upload_state(&message_bus);
+                                    send_recording_update(&message_bus, &file_path, RecordingUploadStatus::Failed);
                                 }
                             }
                         } else {
@@ -179,6 +188,7 @@ async fn do_upload(file_path: String,
                                     println!("📦 Failed to upload chunk: {:?}", e);
                                     // Reset upload state
                                     reset_upload_state(&message_bus);
+                                    send_recording_update(&message_bus, &file_path, RecordingUploadStatus::Failed);
                                     break;
                                 }
                             }
@@ -188,12 +198,14 @@ async fn do_upload(file_path: String,
                 Err(e) => {
                     println!("📦 Failed to start upload session: {:?}", e);
                     reset_upload_state(&message_bus);
+                    send_recording_update(&message_bus, &file_path, RecordingUploadStatus::Failed);
                 }
             }
         },
         Err(e) => {
             println!("📦 Failed to read file for upload: {:?}", e);
             reset_upload_state(&message_bus);
+            send_recording_update(&message_bus, &file_path, RecordingUploadStatus::Failed);
         }
     }
 }
@@ -206,8 +218,14 @@ async fn do_upload(file_path: String,
 /// # Arguments
 /// * `file_path` - Path to the file to upload
 /// * `destination_folder` - Folder path in Dropbox where the file should be stored
+/// * `keep_local_copy` - Keep the local file after a successful upload instead of deleting it
 /// * `message_bus` - Broadcast sender for AlasMessages to report progress
-pub fn upload_file_to_dropbox(file_path: String, destination_folder: String, message_bus: Sender<AlasMessage>) -> JoinHandle<()> {
+pub fn upload_file_to_dropbox(
+    file_path: String,
+    destination_folder: String,
+    keep_local_copy: bool,
+    message_bus: Sender<AlasMessage>
+) -> JoinHandle<()> {
     println!("📦 Uploading file to Dropbox");
     // Spawn a new task to handle the upload asynchronously
     task::spawn_blocking(move || {
@@ -218,10 +236,54 @@ pub fn upload_file_to_dropbox(file_path: String, destination_folder: String, mes
             .expect("failed to build runtime");
 
         // Run the async upload logic to completion
-        rt.block_on(do_upload(file_path, destination_folder, message_bus));
+        rt.block_on(do_upload(file_path, destination_folder, keep_local_copy, message_bus));
     })
 }
 
+/// Writes a small file straight to `destination_path` in Dropbox, replacing
+/// whatever is there. Unlike `upload_file_to_dropbox`, this waits for the
+/// upload and does not report progress.
+pub async fn upload_bytes_to_dropbox(destination_path: String, contents: Vec<u8>) -> Result<(), String> {
+    let client = UserAuthDefaultClient::new(get_dropbox_access_token().await?);
+    let session = files::upload_session_start(&client, &files::UploadSessionStartArg::default(), Bytes::new())
+        .await
+        .map_err(|e| format!("Could not start the upload: {:?}", e))?;
+    let finish_args = files::UploadSessionFinishArg::new(
+        files::UploadSessionCursor::new(session.session_id, 0),
+        files::CommitInfo::new(destination_path).with_mode(files::WriteMode::Overwrite)
+    );
+    files::upload_session_finish(&client, &finish_args, Bytes::from(contents))
+        .await
+        .map_err(|e| format!("Could not finish the upload: {:?}", e))?;
+    Ok(())
+}
+
+/// Returns a public link to a file that has been uploaded, sharing it if it
+/// has not been shared before.
+pub async fn create_shared_link(dropbox_path: &str) -> Result<String, String> {
+    let client = UserAuthDefaultClient::new(get_dropbox_access_token().await?);
+    let create_args = sharing::CreateSharedLinkWithSettingsArg::new(dropbox_path.to_string());
+    let link = match sharing::create_shared_link_with_settings(&client, &create_args).await {
+        Ok(link) => Some(link),
+        // Most likely it is shared already, so look for the existing link
+        Err(_) => {
+            let list_args = sharing::ListSharedLinksArg::default()
+                .with_path(dropbox_path.to_string())
+                .with_direct_only(true);
+            sharing::list_shared_links(&client, &list_args)
+                .await
+                .map_err(|e| format!("Could not share {}: {:?}", dropbox_path, e))?
+                .links
+                .into_iter()
+                .next()
+        }
+    };
+    // Files and folders have different metadata, but both have a URL
+    link.and_then(|link| serde_json::to_value(link).ok())
+        .and_then(|link| link.get("url").and_then(|url| url.as_str()).map(str::to_string))
+        .ok_or_else(|| format!("Dropbox did not return a link for {}", dropbox_path))
+}
+
 /// Helper function to reset the upload state to idle
 fn reset_upload_state(bus: &Sender<AlasMessage>) {
     send_state_update(bus, AlasUploadState {
@@ -242,3 +304,11 @@ fn send_state_update(bus: &Sender<AlasMessage>, state: AlasUploadState) {
         println!("📦 UploadStateChange sent successfully");
     }
 }
+
+/// Helper function to let the recording catalog know how an upload is going
+fn send_recording_update(bus: &Sender<AlasMessage>, file_path: &str, status: RecordingUploadStatus) {
+    let _ = bus.send(AlasMessage::RecordingUploadChange {
+        file_path: file_path.to_string(),
+        status,
+    });
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use age::x25519::Recipient;
+use thiserror::Error;
+
+use crate::markers::sidecar_path;
+use crate::waveform::waveform_path;
+
+#[derive(Error, Debug)]
+pub enum EncryptionError {
+    #[error("At least one recipient is required")]
+    NoRecipients,
+
+    #[error("Invalid age public key {0:?}: {1}")]
+    InvalidRecipient(String, &'static str),
+
+    #[error("Could not encrypt: {0}")]
+    Encrypt(String),
+
+    #[error("Could not read or write a file: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Where the encrypted copy of `path` goes, next to it
+pub fn encrypted_path(path: &str) -> String {
+    format!("{}.age", path)
+}
+
+pub fn parse_recipients(recipients: &[String]) -> Result<Vec<Recipient>, EncryptionError> {
+    if recipients.is_empty() {
+        return Err(EncryptionError::NoRecipients);
+    }
+    recipients
+        .iter()
+        .map(|recipient| {
+            recipient
+                .trim()
+                .parse::<Recipient>()
+                .map_err(|e| EncryptionError::InvalidRecipient(recipient.clone(), e))
+        })
+        .collect()
+}
+
+/// Encrypts `path` with age to `<path>.age` and deletes the plaintext,
+/// returning the encrypted file's path. The plaintext is left alone if
+/// anything goes wrong.
+pub fn encrypt_file(path: &str, recipients: &[Recipient]) -> Result<String, EncryptionError> {
+    let output_path = encrypted_path(path);
+    let encrypt = || -> Result<(), EncryptionError> {
+        let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+            .map_err(|e| EncryptionError::Encrypt(e.to_string()))?;
+        let mut input = BufReader::new(File::open(path)?);
+        let mut writer = encryptor.wrap_output(BufWriter::new(File::create(&output_path)?))?;
+        io::copy(&mut input, &mut writer)?;
+        writer.finish()?.flush()?;
+        Ok(())
+    };
+    if let Err(e) = encrypt() {
+        let _ = std::fs::remove_file(&output_path);
+        return Err(e);
+    }
+
+    std::fs::remove_file(path)?;
+    Ok(output_path)
+}
+
+/// Encrypts a show recording, moving its waveform and marker sidecar along so
+/// that they still sit next to it
+pub fn encrypt_recording(path: &str, recipients: &[Recipient]) -> Result<String, EncryptionError> {
+    let output_path = encrypt_file(path, recipients)?;
+    for (from, to) in [
+        (waveform_path(path), waveform_path(&output_path)),
+        (sidecar_path(path), sidecar_path(&output_path)),
+    ] {
+        if Path::new(&from).exists()
+            && let Err(e) = std::fs::rename(&from, &to)
+        {
+            eprintln!("🔐 Could not move {} along with its recording: {}", from.display(), e);
+        }
+    }
+    Ok(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("alas-encryption-{}-{}", uuid::Uuid::new_v4(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn test_parse_recipients() {
+        let identity = age::x25519::Identity::generate();
+        let public_key = identity.to_public().to_string();
+
+        assert_eq!(parse_recipients(&[format!(" {} ", public_key)]).unwrap().len(), 1);
+        assert!(matches!(parse_recipients(&[]), Err(EncryptionError::NoRecipients)));
+        assert!(matches!(
+            parse_recipients(&[public_key, "not-a-key".to_string()]),
+            Err(EncryptionError::InvalidRecipient(key, _)) if key == "not-a-key"
+        ));
+    }
+
+    #[test]
+    fn test_encrypted_file_decrypts_with_the_private_key() {
+        let identity = age::x25519::Identity::generate();
+        let path = temp_path("show.mp3");
+        let plaintext: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &plaintext).unwrap();
+
+        let encrypted = encrypt_file(&path, &[identity.to_public()]).unwrap();
+        assert_eq!(encrypted, encrypted_path(&path));
+        assert!(!Path::new(&path).exists());
+
+        let ciphertext = std::fs::read(&encrypted).unwrap();
+        assert_ne!(ciphertext, plaintext);
+        assert_eq!(age::decrypt(&identity, &ciphertext).unwrap(), plaintext);
+        std::fs::remove_file(&encrypted).unwrap();
+    }
+
+    #[test]
+    fn test_encrypt_recording_moves_companions() {
+        let identity = age::x25519::Identity::generate();
+        let path = temp_path("show.mp3");
+        std::fs::write(&path, b"mp3").unwrap();
+        std::fs::write(waveform_path(&path), b"peaks").unwrap();
+
+        let encrypted = encrypt_recording(&path, &[identity.to_public()]).unwrap();
+        assert!(!waveform_path(&path).exists());
+        assert_eq!(std::fs::read(waveform_path(&encrypted)).unwrap(), b"peaks");
+
+        std::fs::remove_file(waveform_path(&encrypted)).unwrap();
+        std::fs::remove_file(&encrypted).unwrap();
+
+        // A missing file leaves nothing half-written behind
+        let missing = temp_path("missing.mp3");
+        assert!(encrypt_file(&missing, &[identity.to_public()]).is_err());
+        assert!(!Path::new(&encrypted_path(&missing)).exists());
+    }
+}
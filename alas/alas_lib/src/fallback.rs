@@ -0,0 +1,452 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use thiserror::Error;
+
+use crate::config::{AlasActivationMode, AlasFallbackConfig};
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: usize = 2;
+/// The fallback files are held in memory, about 23 MB a minute, so together
+/// they may not play for longer than this
+pub const MAX_FALLBACK_SECS: u32 = 120;
+
+#[derive(Error, Debug)]
+pub enum FallbackError {
+    #[error("Could not read fallback file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Could not decode fallback file: {0}")]
+    Decode(String),
+
+    #[error("None of the fallback files could be loaded")]
+    NothingToPlay,
+
+    #[error("Fallback audio is longer than {0} seconds")]
+    TooLong(u32),
+}
+
+impl From<SymphoniaError> for FallbackError {
+    fn from(error: SymphoniaError) -> Self {
+        match error {
+            SymphoniaError::IoError(e) => FallbackError::Io(e),
+            e => FallbackError::Decode(e.to_string()),
+        }
+    }
+}
+
+/// Mixes or copies interleaved audio down (or up) to stereo
+fn to_stereo(samples: &[f32], channels: usize) -> Vec<f32> {
+    match channels {
+        2 => samples.to_vec(),
+        1 => samples.iter().flat_map(|&sample| [sample, sample]).collect(),
+        _ => samples.chunks_exact(channels).flat_map(|frame| [frame[0], frame[1]]).collect(),
+    }
+}
+
+/// Linear interpolation is plenty for a station ID
+fn resample_stereo(samples: &[f32], from_rate: u32) -> Vec<f32> {
+    if from_rate == SAMPLE_RATE {
+        return samples.to_vec();
+    }
+    let frames = samples.len() / CHANNELS;
+    if frames == 0 {
+        return Vec::new();
+    }
+    let output_frames = (frames as u64 * SAMPLE_RATE as u64 / from_rate as u64) as usize;
+    let step = from_rate as f64 / SAMPLE_RATE as f64;
+
+    let mut output = Vec::with_capacity(output_frames * CHANNELS);
+    for frame in 0..output_frames {
+        let position = frame as f64 * step;
+        let index = position as usize;
+        let fraction = (position - index as f64) as f32;
+        let next = (index + 1).min(frames - 1);
+        for channel in 0..CHANNELS {
+            let a = samples[index * CHANNELS + channel];
+            let b = samples[next * CHANNELS + channel];
+            output.push(a + (b - a) * fraction);
+        }
+    }
+    output
+}
+
+fn open_audio_file(path: &Path) -> Result<Box<dyn FormatReader>, FallbackError> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default()
+    )?;
+    Ok(probed.format)
+}
+
+/// How long a file plays for, from its headers without decoding it. Some
+/// MP3 files do not say.
+pub fn audio_file_secs(path: &Path) -> Result<Option<f64>, FallbackError> {
+    let format = open_audio_file(path)?;
+    let track = format
+        .default_track()
+        .ok_or_else(|| FallbackError::Decode("No audio track".to_string()))?;
+    let params = &track.codec_params;
+    Ok(params.n_frames.zip(params.sample_rate).map(|(frames, rate)| frames as f64 / rate as f64))
+}
+
+/// Decodes a whole audio file into 48 kHz interleaved stereo, giving up if it
+/// plays for longer than `max_secs`
+pub fn load_audio_file(path: &Path, max_secs: u32) -> Result<Vec<f32>, FallbackError> {
+    let mut format = open_audio_file(path)?;
+    let track = format
+        .default_track()
+        .ok_or_else(|| FallbackError::Decode("No audio track".to_string()))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut samples = Vec::new();
+    let mut sample_rate = SAMPLE_RATE;
+    loop {
+        if samples.len() > max_secs as usize * sample_rate as usize * CHANNELS {
+            return Err(FallbackError::TooLong(max_secs));
+        }
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+                sample_rate = spec.rate;
+                samples.extend(to_stereo(buffer.samples(), spec.channels.count()));
+            }
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(resample_stereo(&samples, sample_rate))
+}
+
+/// Plays the fallback clips one after another, forever, with an optional gap
+/// of silence between them
+pub struct FallbackPlayer {
+    clips: Vec<Vec<f32>>,
+    clip: usize,
+    position: usize,
+    gap_samples: usize,
+    gap_remaining: usize,
+}
+
+impl FallbackPlayer {
+    pub fn new(clips: Vec<Vec<f32>>, gap_secs: u32) -> Result<Self, FallbackError> {
+        let clips: Vec<Vec<f32>> = clips.into_iter().filter(|clip| !clip.is_empty()).collect();
+        if clips.is_empty() {
+            return Err(FallbackError::NothingToPlay);
+        }
+        Ok(FallbackPlayer {
+            clips,
+            clip: 0,
+            position: 0,
+            gap_samples: gap_secs as usize * SAMPLE_RATE as usize * CHANNELS,
+            gap_remaining: 0,
+        })
+    }
+
+    /// Loads every configured file. A file that cannot be read is skipped, as
+    /// long as at least one can. Files past [`MAX_FALLBACK_SECS`] in total are
+    /// skipped too.
+    pub fn load(config: &AlasFallbackConfig) -> Result<Self, FallbackError> {
+        let mut remaining_secs = MAX_FALLBACK_SECS;
+        let clips = config.files
+            .iter()
+            .filter_map(|file| match load_audio_file(Path::new(file), remaining_secs) {
+                Ok(clip) => {
+                    let secs = (clip.len() / CHANNELS) as u32 / SAMPLE_RATE;
+                    remaining_secs = remaining_secs.saturating_sub(secs);
+                    Some(clip)
+                }
+                Err(e) => {
+                    eprintln!("📼 Skipping fallback file {}: {}", file, e);
+                    None
+                }
+            })
+            .collect();
+        FallbackPlayer::new(clips, config.gap_secs)
+    }
+
+    /// Goes back to the start of the first clip
+    pub fn restart(&mut self) {
+        self.clip = 0;
+        self.position = 0;
+        self.gap_remaining = 0;
+    }
+
+    /// The next `len` interleaved samples of fallback audio
+    pub fn next_chunk(&mut self, len: usize) -> Vec<f32> {
+        let mut chunk = Vec::with_capacity(len);
+        while chunk.len() < len {
+            if self.gap_remaining > 0 {
+                let silence = self.gap_remaining.min(len - chunk.len());
+                chunk.resize(chunk.len() + silence, 0.0);
+                self.gap_remaining -= silence;
+                continue;
+            }
+
+            let clip = &self.clips[self.clip];
+            let take = (clip.len() - self.position).min(len - chunk.len());
+            chunk.extend_from_slice(&clip[self.position..self.position + take]);
+            self.position += take;
+            if self.position == clip.len() {
+                self.clip = (self.clip + 1) % self.clips.len();
+                self.position = 0;
+                self.gap_remaining = self.gap_samples;
+            }
+        }
+        chunk
+    }
+}
+
+/// Feeds the stream from the fallback player while the switch is on. The files
+/// are loaded the first time they are needed, and again if the settings change.
+/// Loading happens on its own thread so that the stream never waits for it.
+#[derive(Default)]
+pub struct FallbackSource {
+    loaded: Option<AlasFallbackConfig>,
+    loading: Option<JoinHandle<Result<FallbackPlayer, FallbackError>>>,
+    player: Option<FallbackPlayer>,
+    playing: bool,
+}
+
+impl FallbackSource {
+    /// Returns the audio to stream in place of `input`, if the fallback is
+    /// playing and there is something to play
+    pub fn replace(&mut self, input: &[f32], active: bool, config: Option<&AlasFallbackConfig>) -> Option<Vec<f32>> {
+        let config = match config {
+            Some(config) if active => config,
+            _ => {
+                if self.playing {
+                    println!("📼 Input is back, leaving the fallback audio");
+                    self.playing = false;
+                    // A station ID should start from the top next time
+                    if let Some(player) = self.player.as_mut() {
+                        player.restart();
+                    }
+                }
+                return None;
+            }
+        };
+
+        if self.loaded.as_ref() != Some(config) {
+            let loading = config.clone();
+            self.player = None;
+            // A load still running for older settings is left to finish and thrown away
+            self.loading = Some(std::thread::spawn(move || FallbackPlayer::load(&loading)));
+            // Remember failures too, so that a broken file is not decoded on every packet
+            self.loaded = Some(config.clone());
+        }
+        if let Some(loading) = self.loading.take_if(|loading| loading.is_finished()) {
+            self.player = match loading.join() {
+                Ok(Ok(player)) => Some(player),
+                Ok(Err(e)) => {
+                    eprintln!("📼 Could not load the fallback audio: {}", e);
+                    None
+                }
+                Err(_) => {
+                    eprintln!("📼 Loading the fallback audio panicked");
+                    None
+                }
+            };
+        }
+
+        // The input goes out as it is until the files are ready
+        let player = self.player.as_mut()?;
+        if !self.playing {
+            println!("📼 Input is silent, streaming the fallback audio");
+            self.playing = true;
+        }
+        Some(player.next_chunk(input.len()))
+    }
+}
+
+/// Decides when the stream should switch to the fallback audio: only while it
+/// is forced or scheduled on, after the input has been silent long enough.
+pub struct FallbackSwitch {
+    pub active: Arc<AtomicBool>,
+    silent_since: Option<SystemTime>,
+}
+
+impl FallbackSwitch {
+    pub fn new() -> Self {
+        FallbackSwitch {
+            active: Arc::new(AtomicBool::new(false)),
+            silent_since: None,
+        }
+    }
+
+    /// Feeds the switch with the latest audio level. Returns the new position
+    /// of the switch when it changes.
+    pub fn update(
+        &mut self,
+        audio_present: bool,
+        now: SystemTime,
+        mode: AlasActivationMode,
+        silence_secs: Option<u32>
+    ) -> Option<bool> {
+        if audio_present {
+            self.silent_since = None;
+        } else {
+            self.silent_since.get_or_insert(now);
+        }
+
+        let silent_for = self.silent_since
+            .and_then(|since| now.duration_since(since).ok())
+            .map(|silence| silence.as_secs())
+            .unwrap_or(0);
+        let wanted = mode == AlasActivationMode::ForceOn &&
+            silence_secs.is_some_and(|silence_secs| silent_for >= silence_secs as u64);
+
+        if wanted == self.active.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.active.store(wanted, Ordering::Relaxed);
+        Some(wanted)
+    }
+}
+
+impl Default for FallbackSwitch {
+    fn default() -> Self {
+        FallbackSwitch::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[test]
+    fn test_player_loops_with_gap() {
+        let clips = vec![vec![1.0; 4], vec![], vec![2.0; 2]];
+        let mut player = FallbackPlayer::new(clips, 0).unwrap();
+        assert_eq!(player.next_chunk(8), vec![1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 1.0, 1.0]);
+
+        let mut player = FallbackPlayer::new(vec![vec![1.0; 4]], 1).unwrap();
+        let chunk = player.next_chunk(SAMPLE_RATE as usize * CHANNELS + 8);
+        assert_eq!(&chunk[..4], &[1.0; 4]);
+        assert!(chunk[4..chunk.len() - 4].iter().all(|&sample| sample == 0.0));
+        assert_eq!(&chunk[chunk.len() - 4..], &[1.0; 4]);
+
+        assert!(matches!(FallbackPlayer::new(vec![vec![]], 0), Err(FallbackError::NothingToPlay)));
+    }
+
+    #[test]
+    fn test_switch_needs_forced_mode_and_silence() {
+        let start = SystemTime::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut switch = FallbackSwitch::new();
+
+        assert_eq!(switch.update(false, at(0), AlasActivationMode::ForceOn, Some(5)), None);
+        assert_eq!(switch.update(false, at(4), AlasActivationMode::ForceOn, Some(5)), None);
+        assert_eq!(switch.update(false, at(5), AlasActivationMode::ForceOn, Some(5)), Some(true));
+        assert_eq!(switch.update(false, at(6), AlasActivationMode::ForceOn, Some(5)), None);
+        assert_eq!(switch.update(true, at(7), AlasActivationMode::ForceOn, Some(5)), Some(false));
+
+        // Without a forced or scheduled broadcast, silence simply stops the stream
+        let mut switch = FallbackSwitch::new();
+        switch.update(false, at(0), AlasActivationMode::Auto, Some(5));
+        assert_eq!(switch.update(false, at(60), AlasActivationMode::Auto, Some(5)), None);
+        assert_eq!(switch.update(false, at(60), AlasActivationMode::ForceOn, None), None);
+    }
+
+    /// Writes half a second of mono 24 kHz audio to a temporary WAV file
+    fn write_wav() -> std::path::PathBuf {
+        let samples: Vec<i16> = (0..12_000).map(|i| if i % 2 == 0 { 8_000 } else { -8_000 }).collect();
+        let data_len = samples.len() as u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+        wav.extend_from_slice(&24_000u32.to_le_bytes());
+        wav.extend_from_slice(&48_000u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let path = std::env::temp_dir().join(format!("alas-fallback-{}.wav", uuid::Uuid::new_v4()));
+        File::create(&path).unwrap().write_all(&wav).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_wav_file() {
+        let path = write_wav();
+        let secs = audio_file_secs(&path);
+        let audio = load_audio_file(&path, MAX_FALLBACK_SECS);
+        let too_long = load_audio_file(&path, 0);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(secs.unwrap(), Some(0.5));
+        let audio = audio.unwrap();
+        assert_eq!(audio.len(), 24_000 * CHANNELS);
+        assert!((audio[0] - 8_000.0 / 32_768.0).abs() < 1e-3);
+        assert_eq!(audio[0], audio[1]);
+        assert!(matches!(too_long, Err(FallbackError::TooLong(0))));
+    }
+
+    #[test]
+    fn test_source_loads_in_the_background() {
+        let path = write_wav();
+        let config = AlasFallbackConfig {
+            files: vec![path.to_string_lossy().into_owned()],
+            silence_secs: 5,
+            gap_secs: 0,
+        };
+        let mut source = FallbackSource::default();
+        let input = vec![0.0; 960];
+
+        // The input carries on until the file has been decoded
+        let mut replacement = source.replace(&input, true, Some(&config));
+        for _ in 0..100 {
+            if replacement.is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+            replacement = source.replace(&input, true, Some(&config));
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let replacement = replacement.unwrap();
+        assert_eq!(replacement.len(), input.len());
+        assert!((replacement[0] - 8_000.0 / 32_768.0).abs() < 1e-3);
+        assert_eq!(source.replace(&input, false, None), None);
+    }
+}
@@ -0,0 +1,495 @@
+use std::fmt;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use base64::{Engine as _, engine::general_purpose};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::config::{AlasIcecastConfig, AlasIcecastProtocol, IcecastConfigError};
+use crate::libshout::{ShoutConn, ShoutError, ShoutSettings, SHOUT_PROTOCOL_HTTP, SHOUT_PROTOCOL_ICY};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// A send that stalls for this long is treated as a dropped connection
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+#[derive(Error, Debug)]
+pub enum IcecastError {
+    #[error("Invalid configuration: {0}")]
+    Config(#[from] IcecastConfigError),
+
+    #[error("Connection error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("TLS error: {0}")]
+    Tls(String),
+
+    #[error("The server rejected the source user or password")]
+    Unauthorized,
+
+    #[error("The mount is already in use by another source")]
+    MountInUse,
+
+    #[error("The server refused the stream: {0}")]
+    Rejected(String),
+
+    #[error("Could not stream: {0}")]
+    Stream(String),
+}
+
+impl IcecastError {
+    /// Errors that retrying will not fix until the settings change
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, IcecastError::Config(_) | IcecastError::Unauthorized)
+    }
+}
+
+/// Where the stream's connection to Icecast is at
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum IcecastConnectionState {
+    /// The stream is switched off
+    #[default]
+    Idle,
+    Connecting {
+        attempt: u32,
+    },
+    Live,
+    /// Waiting before the next attempt
+    Backoff {
+        attempt: u32,
+        retry_in_secs: u64,
+        last_error: String,
+    },
+    /// Retrying will not help, e.g. the password is wrong. Waits for new settings.
+    Failed {
+        reason: String,
+    },
+}
+
+/// A short description for displays, e.g. "reconnecting (attempt 4, next in 16s)"
+impl fmt::Display for IcecastConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IcecastConnectionState::Idle => write!(f, "idle"),
+            IcecastConnectionState::Connecting { attempt } => write!(f, "connecting (attempt {})", attempt),
+            IcecastConnectionState::Live => write!(f, "live"),
+            IcecastConnectionState::Backoff { attempt, retry_in_secs, .. } => {
+                write!(f, "reconnecting (attempt {}, next in {}s)", attempt, retry_in_secs)
+            }
+            IcecastConnectionState::Failed { reason } => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+const BACKOFF_BASE_SECS: f64 = 2.0;
+const BACKOFF_MAX_SECS: f64 = 60.0;
+/// Delays are spread by up to this fraction either way, so that a room full of
+/// encoders does not hammer the server in lockstep after an outage
+const BACKOFF_JITTER: f64 = 0.2;
+
+/// Capped exponential backoff with jitter: 2, 4, 8, 16, 32, 60, 60... seconds
+#[derive(Debug, Default)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    /// The number of failed attempts so far
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Counts a failed attempt and returns how long to wait before the next
+    /// one. `random` is a number in `0.0..1.0` used for the jitter.
+    pub fn next_delay(&mut self, random: f64) -> Duration {
+        self.attempt = self.attempt.saturating_add(1);
+        let exponent = (self.attempt - 1).min(16) as i32;
+        let delay = (BACKOFF_BASE_SECS * 2f64.powi(exponent)).min(BACKOFF_MAX_SECS);
+        let jitter = 1.0 + BACKOFF_JITTER * (2.0 * random.clamp(0.0, 1.0) - 1.0);
+        Duration::from_secs_f64(delay * jitter)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// A number in `0.0..1.0` that is good enough for jitter
+pub fn jitter_random() -> f64 {
+    let hash = RandomState::new().build_hasher().finish();
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A connection to the server, with or without TLS
+pub(crate) enum Transport {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.read(buf),
+            Transport::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.write(buf),
+            Transport::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.flush(),
+            Transport::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Transport {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Transport::Plain(stream) => stream,
+            Transport::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_read_timeout(timeout)
+    }
+}
+
+fn tls_config() -> Result<Arc<ClientConfig>, IcecastError> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| IcecastError::Tls(e.to_string()))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Opens a connection to `host:port`, negotiating TLS if asked to
+pub(crate) fn open_transport(host: &str, port: u16, tls: bool) -> Result<Transport, IcecastError> {
+    let address = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Could not resolve {}", host)))?;
+    let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    if !tls {
+        return Ok(Transport::Plain(stream));
+    }
+    let server_name = ServerName::try_from(host.to_string()).map_err(|e| IcecastError::Tls(e.to_string()))?;
+    let connection = ClientConnection::new(tls_config()?, server_name).map_err(|e| IcecastError::Tls(e.to_string()))?;
+    Ok(Transport::Tls(Box::new(StreamOwned::new(connection, stream))))
+}
+
+/// Reads up to and including the first `terminator`, one byte at a time so
+/// that nothing after it is consumed
+fn read_until(reader: &mut impl Read, terminator: &[u8]) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    let mut byte = [0u8; 1];
+    while !bytes.ends_with(terminator) {
+        if reader.read(&mut byte)? == 0 || bytes.len() > MAX_HEADER_BYTES {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Incomplete response from server"));
+        }
+        bytes.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Reads an HTTP response head, returning its status line
+pub(crate) fn read_status_line(reader: &mut impl Read) -> io::Result<String> {
+    let head = read_response_head(reader)?;
+    Ok(head.lines().next().unwrap_or_default().to_string())
+}
+
+/// The status line and headers of a response, leaving the body unread
+pub(crate) fn read_response_head(reader: &mut impl Read) -> io::Result<String> {
+    read_until(reader, b"\r\n\r\n")
+}
+
+fn status_code(status_line: &str) -> Option<u16> {
+    status_line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Percent-encodes a query string value. Slashes are left alone, since mounts
+/// are full of them and they are allowed in a query.
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Whether anyone is streaming to a mount, as far as the server's admin API says
+#[derive(Debug, PartialEq)]
+enum MountStatus {
+    Free,
+    InUse,
+}
+
+/// Logs in to the admin API with the source credentials and asks for the
+/// mount's listeners. Icecast accepts source credentials for admin requests
+/// about their own mount, and checks them before looking the mount up, so
+/// this tells a wrong password from a busy mount without going on air.
+fn check_source_login(config: &AlasIcecastConfig) -> Result<MountStatus, IcecastError> {
+    let mut transport = open_transport(&config.hostname, config.port, config.tls)?;
+    let credentials = general_purpose::STANDARD.encode(format!("{}:{}", config.user, config.password));
+    write!(
+        transport,
+        "GET /admin/listclients?mount={} HTTP/1.0\r\nHost: {}:{}\r\nAuthorization: Basic {}\r\nUser-Agent: alas\r\n\r\n",
+        encode_query_value(&config.mount),
+        config.hostname,
+        config.port,
+        credentials
+    )?;
+    transport.flush()?;
+
+    let status_line = read_status_line(&mut transport)?;
+    match status_code(&status_line) {
+        Some(200) => Ok(MountStatus::InUse),
+        // "Source does not exist"
+        Some(400) | Some(404) => Ok(MountStatus::Free),
+        Some(401) | Some(403) => Err(IcecastError::Unauthorized),
+        _ => Err(IcecastError::Rejected(status_line)),
+    }
+}
+
+/// The legacy SHOUTcast v1 login: the password on its own line. We hang up
+/// after the answer, before any stream headers, so nothing goes on air.
+fn check_icy_login(config: &AlasIcecastConfig) -> Result<(), IcecastError> {
+    let mut transport = open_transport(&config.hostname, config.port + 1, false)?;
+    write!(transport, "{}\r\n", config.password)?;
+    transport.flush()?;
+
+    let answer = read_until(&mut transport, b"\n")?;
+    if answer.trim().starts_with("OK") {
+        Ok(())
+    } else if answer.to_lowercase().contains("password") {
+        Err(IcecastError::Unauthorized)
+    } else {
+        Err(IcecastError::Rejected(answer.trim().to_string()))
+    }
+}
+
+/// Turns a libshout error into one of ours. libshout reports a wrong password
+/// and a busy mount alike, so we ask the server which it was.
+fn classify(config: &AlasIcecastConfig, error: ShoutError) -> IcecastError {
+    match error {
+        ShoutError::NoLogin(message) if config.protocol == AlasIcecastProtocol::Http => match check_source_login(config) {
+            Err(IcecastError::Unauthorized) => IcecastError::Unauthorized,
+            Ok(MountStatus::InUse) => IcecastError::MountInUse,
+            _ => IcecastError::Rejected(message),
+        },
+        ShoutError::NoLogin(_) => IcecastError::Unauthorized,
+        ShoutError::Tls(message) => IcecastError::Tls(message),
+        ShoutError::Other(message) => IcecastError::Stream(message),
+    }
+}
+
+/// A source connection to an Icecast server, through libshout. libshout
+/// speaks `PUT` to servers that take it and `SOURCE` to older ones, and ICY
+/// to SHOUTcast.
+pub struct IcecastSource {
+    connection: ShoutConn,
+}
+
+impl IcecastSource {
+    pub fn connect(config: &AlasIcecastConfig) -> Result<Self, IcecastError> {
+        config.validate()?;
+        let settings = ShoutSettings {
+            host: &config.hostname,
+            port: config.port,
+            user: &config.user,
+            password: &config.password,
+            mount: &config.mount,
+            protocol: match config.protocol {
+                AlasIcecastProtocol::Http => SHOUT_PROTOCOL_HTTP,
+                AlasIcecastProtocol::Icy => SHOUT_PROTOCOL_ICY,
+            },
+            tls: config.tls,
+            name: config.name.as_deref(),
+            genre: config.genre.as_deref(),
+            description: config.description.as_deref(),
+            url: config.url.as_deref(),
+            public: config.public,
+        };
+        ShoutConn::open(&settings)
+            .map(|connection| IcecastSource { connection })
+            .map_err(|e| classify(config, e))
+    }
+
+    pub fn send(&mut self, data: &[u8]) -> Result<(), IcecastError> {
+        self.connection.send(data).map_err(|e| IcecastError::Stream(e.to_string()))
+    }
+}
+
+/// Checks that we could stream with these settings, without taking the mount
+/// or announcing anything: the credentials are checked against the admin
+/// API, and the mount must not already have a source on it.
+pub fn test_connection(config: &AlasIcecastConfig) -> Result<(), IcecastError> {
+    config.validate()?;
+    match config.protocol {
+        AlasIcecastProtocol::Http => match check_source_login(config)? {
+            MountStatus::Free => Ok(()),
+            MountStatus::InUse => Err(IcecastError::MountInUse),
+        },
+        AlasIcecastProtocol::Icy => check_icy_login(config),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use crate::state::AlasState;
+
+    fn config(port: u16) -> AlasIcecastConfig {
+        let mut config = AlasState::test().config.icecast;
+        config.hostname = "127.0.0.1".to_string();
+        config.port = port;
+        config.user = "dj".to_string();
+        config.name = Some("Ridgeline Radio".to_string());
+        config.public = true;
+        config
+    }
+
+    /// A stand-in for Icecast that answers one request with `response`, and
+    /// hands back the request along with anything sent after it
+    fn start_server(response: &'static str) -> (u16, mpsc::Receiver<(String, Vec<u8>)>) {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let (mut client, _) = server.accept().unwrap();
+            let request = read_until(&mut client, b"\r\n\r\n").unwrap();
+            client.write_all(response.as_bytes()).unwrap();
+            let mut rest = Vec::new();
+            let _ = client.read_to_end(&mut rest);
+            sender.send((request, rest)).unwrap();
+        });
+        (port, receiver)
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = config(8000);
+        assert!(config.validate().is_ok());
+
+        config.mount = "live.mp3".to_string();
+        assert!(matches!(config.validate(), Err(IcecastConfigError::InvalidMount(_))));
+
+        config.mount = "/live.mp3".to_string();
+        config.protocol = AlasIcecastProtocol::Icy;
+        config.tls = true;
+        assert!(matches!(config.validate(), Err(IcecastConfigError::IcyOverTls)));
+
+        config.tls = false;
+        config.name = Some("Evil\r\nHeader: yes".to_string());
+        assert!(matches!(config.validate(), Err(IcecastConfigError::InvalidHeader("Name"))));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<u64> = (0..8).map(|_| backoff.next_delay(0.5).as_secs()).collect();
+        assert_eq!(delays, vec![2, 4, 8, 16, 32, 60, 60, 60]);
+        assert_eq!(backoff.attempt(), 8);
+
+        backoff.reset();
+        let shortest = backoff.next_delay(0.0).as_secs_f64();
+        backoff.reset();
+        let longest = backoff.next_delay(1.0).as_secs_f64();
+        assert!((shortest - 1.6).abs() < 1e-9);
+        assert!((longest - 2.4).abs() < 1e-9);
+
+        assert!((0..100).map(|_| jitter_random()).all(|random| (0.0..1.0).contains(&random)));
+
+        let state = IcecastConnectionState::Backoff {
+            attempt: 4,
+            retry_in_secs: 16,
+            last_error: "Connection refused".to_string(),
+        };
+        assert_eq!(state.to_string(), "reconnecting (attempt 4, next in 16s)");
+    }
+
+    #[test]
+    fn test_connection_does_not_take_the_mount() {
+        let (port, requests) = start_server("HTTP/1.0 400 Source does not exist\r\n\r\n");
+        assert!(test_connection(&config(port)).is_ok());
+
+        let (request, rest) = requests.recv().unwrap();
+        assert!(request.starts_with("GET /admin/listclients?mount=/hello.mp3 HTTP/1.0\r\n"));
+        // "dj:password"
+        assert!(request.contains("Authorization: Basic ZGo6cGFzc3dvcmQ=\r\n"));
+        assert!(!request.to_lowercase().contains("ice-"));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_encode_query_value() {
+        assert_eq!(encode_query_value("/hello.mp3"), "/hello.mp3");
+        assert_eq!(encode_query_value("/rock&roll#1?50%"), "/rock%26roll%231%3F50%25");
+        assert_eq!(encode_query_value("/caf\u{e9}"), "/caf%C3%A9");
+    }
+
+    #[test]
+    fn test_connection_results() {
+        let (port, _) = start_server("HTTP/1.0 401 Authentication Required\r\n\r\n");
+        assert!(matches!(test_connection(&config(port)), Err(IcecastError::Unauthorized)));
+
+        // Someone is already streaming to the mount
+        let (port, _) = start_server("HTTP/1.0 200 OK\r\n\r\n<icestats/>");
+        assert!(matches!(test_connection(&config(port)), Err(IcecastError::MountInUse)));
+
+        let (port, _) = start_server("HTTP/1.0 500 Internal Server Error\r\n\r\n");
+        assert!(matches!(test_connection(&config(port)), Err(IcecastError::Rejected(_))));
+    }
+
+    #[test]
+    fn test_icy_login() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut client, _) = server.accept().unwrap();
+            let password = read_until(&mut client, b"\r\n").unwrap();
+            client.write_all(b"OK2\r\n").unwrap();
+            let mut rest = Vec::new();
+            let _ = client.read_to_end(&mut rest);
+            sender.send((password, rest)).unwrap();
+        });
+
+        // ICY sources connect one port up from the configured one
+        let mut config = config(port - 1);
+        config.protocol = AlasIcecastProtocol::Icy;
+        assert!(test_connection(&config).is_ok());
+
+        // No stream headers follow the password, so nothing is announced
+        let (password, rest) = receiver.recv().unwrap();
+        assert_eq!(password, "password\r\n");
+        assert!(rest.is_empty());
+    }
+}
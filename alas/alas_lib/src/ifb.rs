@@ -0,0 +1,614 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, Stream, StreamConfig};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::task;
+use tokio::task::JoinHandle;
+
+use crate::config::AlasIfbConfig;
+use crate::icecast::{open_transport, read_response_head, IcecastError, Transport};
+use crate::state::{AlasMessage, SafeState};
+use crate::verifier::{parse_listen_url, DecodedAudio, OffAirListener, VerifierError};
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: usize = 2;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+/// The longest Opus packet is 120 ms
+const MAX_OPUS_FRAMES: usize = 5_760;
+
+#[derive(Error, Debug)]
+pub enum IfbError {
+    #[error(transparent)]
+    Listen(#[from] VerifierError),
+
+    #[error("Could not reach the return feed: {0}")]
+    Connection(#[from] IcecastError),
+
+    #[error("Connection error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Unexpected response from the return feed: {0}")]
+    BadResponse(String),
+
+    #[error("Unsupported return feed format: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("Could not decode the return feed: {0}")]
+    Decode(String),
+
+    #[error("No output device found containing '{0}'")]
+    NoDevice(String),
+
+    #[error("Could not open the output: {0}")]
+    Output(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IfbCodec {
+    Mp3,
+    Opus,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IfbPlayback {
+    #[default]
+    Off,
+    Connecting,
+    /// Filling the jitter buffer, at the start or after running dry
+    Buffering,
+    Playing,
+    /// Could not connect or play; retried every few seconds
+    Failed,
+}
+
+/// Where the return audio player is at
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct IfbStatus {
+    pub enabled: bool,
+    pub playback: IfbPlayback,
+    pub codec: Option<IfbCodec>,
+    /// Audio waiting in the jitter buffer
+    pub buffered_ms: u32,
+    /// Times the buffer ran dry since connecting
+    pub underruns: u64,
+    pub error: Option<String>,
+}
+
+struct JitterState {
+    samples: VecDeque<f32>,
+    /// Samples to gather before playing
+    target: usize,
+    playing: bool,
+    underruns: u64,
+    volume: f32,
+}
+
+/// Holds the return audio back by the configured buffer so that it plays
+/// smoothly through network hiccups. Playback waits for the buffer to fill, and
+/// goes back to waiting whenever it runs dry.
+#[derive(Clone)]
+pub struct JitterBuffer {
+    state: Arc<Mutex<JitterState>>,
+}
+
+impl JitterBuffer {
+    pub fn new() -> Self {
+        JitterBuffer {
+            state: Arc::new(Mutex::new(JitterState {
+                samples: VecDeque::new(),
+                target: 0,
+                playing: false,
+                underruns: 0,
+                volume: 1.0,
+            })),
+        }
+    }
+
+    pub fn reset(&self, buffer_ms: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.samples.clear();
+        state.target = buffer_ms as usize * SAMPLE_RATE as usize / 1_000 * CHANNELS;
+        state.playing = false;
+        state.underruns = 0;
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.state.lock().unwrap().volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Queues interleaved stereo audio at 48 kHz. Anything more than twice the
+    /// buffer behind is dropped, so a burst after a stall does not add delay.
+    pub fn push(&self, samples: &[f32]) {
+        let mut state = self.state.lock().unwrap();
+        state.samples.extend(samples);
+        let excess = state.samples.len().saturating_sub(state.target * 2);
+        state.samples.drain(..excess);
+    }
+
+    fn fill_output(&self, output: &mut [f32]) {
+        let mut state = self.state.lock().unwrap();
+        if !state.playing && state.samples.len() >= state.target.max(1) {
+            state.playing = true;
+        }
+        if !state.playing {
+            output.fill(0.0);
+            return;
+        }
+        let volume = state.volume;
+        let available = output.len().min(state.samples.len());
+        for (sample, queued) in output.iter_mut().zip(state.samples.drain(..available)) {
+            *sample = queued * volume;
+        }
+        if available < output.len() {
+            output[available..].fill(0.0);
+            state.playing = false;
+            state.underruns += 1;
+        }
+    }
+
+    fn playback(&self) -> (IfbPlayback, u32, u64) {
+        let state = self.state.lock().unwrap();
+        let playback = if state.playing { IfbPlayback::Playing } else { IfbPlayback::Buffering };
+        let buffered_ms = (state.samples.len() / CHANNELS * 1_000 / SAMPLE_RATE as usize) as u32;
+        (playback, buffered_ms, state.underruns)
+    }
+}
+
+impl Default for JitterBuffer {
+    fn default() -> Self {
+        JitterBuffer::new()
+    }
+}
+
+/// Turns decoded audio of any rate and channel count into 48 kHz stereo, by
+/// linear interpolation. That is plenty for talkback.
+#[derive(Default)]
+struct Resampler {
+    rate: u32,
+    /// Where the next output frame falls, in input frames after `last`
+    position: f64,
+    /// The final frame of the previous packet
+    last: [f32; CHANNELS],
+}
+
+impl Resampler {
+    fn process(&mut self, audio: &DecodedAudio) -> Vec<f32> {
+        let frames: Vec<[f32; CHANNELS]> = audio.samples
+            .chunks_exact(audio.channels)
+            .map(|frame| [frame[0], frame[frame.len().min(CHANNELS) - 1]])
+            .collect();
+        if audio.sample_rate == SAMPLE_RATE {
+            return frames.into_iter().flatten().collect();
+        }
+        if audio.sample_rate != self.rate {
+            *self = Resampler { rate: audio.sample_rate, ..Default::default() };
+        }
+
+        let step = audio.sample_rate as f64 / SAMPLE_RATE as f64;
+        let mut output = Vec::with_capacity((frames.len() as f64 / step) as usize * CHANNELS + CHANNELS);
+        // Position 0 is `last`, and position 1 the first frame of this packet
+        while self.position < frames.len() as f64 {
+            let index = self.position.floor() as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let from = if index == 0 { self.last } else { frames[index - 1] };
+            let to = frames[index];
+            for channel in 0..CHANNELS {
+                output.push(from[channel] + (to[channel] - from[channel]) * fraction);
+            }
+            self.position += step;
+        }
+        self.position -= frames.len() as f64;
+        if let Some(last) = frames.last() {
+            self.last = *last;
+        }
+        output
+    }
+}
+
+/// Reassembles packets from an Ogg stream, one page at a time
+struct OggPackets<R> {
+    reader: R,
+    partial: Vec<u8>,
+    packets: VecDeque<Vec<u8>>,
+}
+
+impl<R: Read> OggPackets<R> {
+    fn new(reader: R) -> Self {
+        OggPackets {
+            reader,
+            partial: Vec::new(),
+            packets: VecDeque::new(),
+        }
+    }
+
+    fn next_packet(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(packet) = self.packets.pop_front() {
+                return Ok(packet);
+            }
+            self.read_page()?;
+        }
+    }
+
+    fn read_page(&mut self) -> io::Result<()> {
+        let mut header = [0u8; 27];
+        self.reader.read_exact(&mut header)?;
+        if &header[..4] != b"OggS" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Lost sync with the Ogg stream"));
+        }
+        let mut lacing = vec![0u8; header[26] as usize];
+        self.reader.read_exact(&mut lacing)?;
+        let mut body = vec![0u8; lacing.iter().map(|&size| size as usize).sum()];
+        self.reader.read_exact(&mut body)?;
+
+        // A segment shorter than 255 bytes ends a packet; a packet still open
+        // at the end of the page carries on in the next one
+        let mut offset = 0;
+        for size in lacing {
+            self.partial.extend_from_slice(&body[offset..offset + size as usize]);
+            offset += size as usize;
+            if size < 255 {
+                self.packets.push_back(std::mem::take(&mut self.partial));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The return feed, decoded as it arrives
+enum ReturnFeed {
+    Mp3(OffAirListener),
+    Opus {
+        packets: OggPackets<Transport>,
+        decoder: Option<(opus::Decoder, usize)>,
+    },
+}
+
+impl ReturnFeed {
+    fn connect(url: &str) -> Result<(Self, IfbCodec), IfbError> {
+        let url = parse_listen_url(url)?;
+        let mut stream = open_transport(&url.host, url.port, url.tls)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        write!(
+            stream,
+            "GET {} HTTP/1.0\r\nHost: {}:{}\r\nUser-Agent: alas-ifb\r\nIcy-MetaData: 0\r\n\r\n",
+            url.path, url.host, url.port
+        )?;
+        stream.flush()?;
+
+        let head = read_response_head(&mut stream)?;
+        let status_line = head.lines().next().unwrap_or_default();
+        if !status_line.contains(" 200") {
+            return Err(IfbError::BadResponse(status_line.to_string()));
+        }
+        let content_type = head
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match content_type.as_str() {
+            "audio/mpeg" | "audio/mp3" => Ok((ReturnFeed::Mp3(OffAirListener::from_stream(stream)?), IfbCodec::Mp3)),
+            "audio/ogg" | "application/ogg" | "audio/opus" => Ok((
+                ReturnFeed::Opus { packets: OggPackets::new(stream), decoder: None },
+                IfbCodec::Opus,
+            )),
+            other => Err(IfbError::UnsupportedFormat(other.to_string())),
+        }
+    }
+
+    fn next_audio(&mut self) -> Result<DecodedAudio, IfbError> {
+        match self {
+            ReturnFeed::Mp3(listener) => Ok(listener.next_audio()?),
+            ReturnFeed::Opus { packets, decoder } => loop {
+                let packet = packets.next_packet()?;
+                // Each new stream in the feed, e.g. at a track change, starts with its header
+                if packet.starts_with(b"OpusHead") {
+                    let channels = packet.get(9).copied().unwrap_or(2).clamp(1, 2) as usize;
+                    let layout = if channels == 1 { opus::Channels::Mono } else { opus::Channels::Stereo };
+                    let opened = opus::Decoder::new(SAMPLE_RATE, layout)
+                        .map_err(|e| IfbError::Decode(e.to_string()))?;
+                    *decoder = Some((opened, channels));
+                    continue;
+                }
+                if packet.starts_with(b"OpusTags") {
+                    continue;
+                }
+                let Some((decoder, channels)) = decoder.as_mut() else {
+                    continue;
+                };
+                let mut samples = vec![0.0f32; MAX_OPUS_FRAMES * *channels];
+                match decoder.decode_float(&packet, &mut samples, false) {
+                    Ok(frames) => {
+                        samples.truncate(frames * *channels);
+                        return Ok(DecodedAudio {
+                            samples,
+                            sample_rate: SAMPLE_RATE,
+                            channels: *channels,
+                        });
+                    }
+                    // A corrupt packet is not fatal, just skip it
+                    Err(e) => eprintln!("🎙️ Skipping a bad Opus packet: {}", e),
+                }
+            },
+        }
+    }
+}
+
+fn build_output_stream(config: &AlasIfbConfig, buffer: &JitterBuffer) -> Result<Stream, IfbError> {
+    let host = cpal::default_host();
+    let device = host.output_devices()
+        .map_err(|e| IfbError::Output(e.to_string()))?
+        .find(|device| device.name().is_ok_and(|name| name.contains(&config.device)))
+        .ok_or_else(|| IfbError::NoDevice(config.device.clone()))?;
+
+    let stream_config = StreamConfig {
+        channels: CHANNELS as u16,
+        sample_rate: cpal::SampleRate(SAMPLE_RATE),
+        buffer_size: BufferSize::Default,
+    };
+    let callback_buffer = buffer.clone();
+    let stream = device
+        .build_output_stream(
+            &stream_config,
+            move |data: &mut [f32], _: &_| callback_buffer.fill_output(data),
+            |err| eprintln!("🎙️ An error occurred on the return audio output: {}", err),
+            None
+        )
+        .map_err(|e| IfbError::Output(e.to_string()))?;
+    stream.play().map_err(|e| IfbError::Output(e.to_string()))?;
+    Ok(stream)
+}
+
+/// Publishes the player's status when it changes. The buffer level alone
+/// only updates the state, since it changes all the time.
+fn report(state: &SafeState, bus: &Sender<AlasMessage>, update: impl FnOnce(&mut IfbStatus)) {
+    let (previous, status) = {
+        let mut state = state.blocking_write();
+        let previous = state.ifb.clone();
+        update(&mut state.ifb);
+        (previous, state.ifb.clone())
+    };
+    let changed = IfbStatus { buffered_ms: status.buffered_ms, ..previous } != status;
+    if changed {
+        let _ = bus.send(AlasMessage::IfbChanged { status });
+    }
+}
+
+/// Handles requests to switch the player on or off. Returns true if we should exit.
+fn drain_bus(receiver: &mut Receiver<AlasMessage>, state: &SafeState, bus: &Sender<AlasMessage>) -> bool {
+    loop {
+        match receiver.try_recv() {
+            Ok(AlasMessage::SetIfb { enabled }) => {
+                println!("🎙️ Return audio {}", if enabled { "on" } else { "off" });
+                report(state, bus, |status| status.enabled = enabled);
+            }
+            Ok(AlasMessage::Exit) | Err(TryRecvError::Closed) => return true,
+            Ok(_) | Err(TryRecvError::Lagged(_)) => continue,
+            Err(TryRecvError::Empty) => return false,
+        }
+    }
+}
+
+/// Waits for `duration` while keeping up with the bus. Returns true if we should exit.
+fn wait(duration: Duration, receiver: &mut Receiver<AlasMessage>, state: &SafeState, bus: &Sender<AlasMessage>) -> bool {
+    let until = Instant::now() + duration;
+    while Instant::now() < until {
+        if drain_bus(receiver, state, bus) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    false
+}
+
+/// The config to play with, if the player is switched on
+fn wanted_config(state: &SafeState) -> Option<AlasIfbConfig> {
+    let state = state.blocking_read();
+    state.config.ifb.clone().filter(|_| state.ifb.enabled)
+}
+
+/// Starts the return audio (IFB) player.
+///
+/// While switched on, this pulls the configured feed, decodes it and plays it
+/// through the jitter buffer to the configured output device. It starts
+/// switched on if the config says so, and `SetIfb` turns it on and off. A
+/// change of URL, device or buffer reconnects; the volume applies right away.
+pub fn start_ifb_player(bus: Sender<AlasMessage>, state: &SafeState) -> JoinHandle<&'static str> {
+    let state = state.clone();
+    let mut receiver = bus.subscribe();
+
+    task::spawn_blocking(move || {
+        let enabled = state.blocking_read().config.ifb.as_ref().is_some_and(|ifb| ifb.enabled);
+        report(&state, &bus, |status| status.enabled = enabled);
+        let buffer = JitterBuffer::new();
+
+        loop {
+            if drain_bus(&mut receiver, &state, &bus) {
+                break;
+            }
+            let Some(config) = wanted_config(&state) else {
+                report(&state, &bus, |status| *status = IfbStatus { enabled: status.enabled, ..Default::default() });
+                if wait(Duration::from_millis(500), &mut receiver, &state, &bus) {
+                    break;
+                }
+                continue;
+            };
+
+            report(&state, &bus, |status| {
+                status.playback = IfbPlayback::Connecting;
+                status.error = None;
+            });
+            let connected = ReturnFeed::connect(&config.url).and_then(|(feed, codec)| {
+                buffer.reset(config.buffer_ms);
+                buffer.set_volume(config.volume);
+                Ok((feed, codec, build_output_stream(&config, &buffer)?))
+            });
+            // cpal streams cannot move between threads, so this one lives here
+            // until the player stops or reconnects
+            let (mut feed, codec, _stream) = match connected {
+                Ok(connected) => connected,
+                Err(e) => {
+                    eprintln!("🎙️ Could not start the return audio: {}", e);
+                    report(&state, &bus, |status| {
+                        status.playback = IfbPlayback::Failed;
+                        status.error = Some(e.to_string());
+                    });
+                    if wait(RETRY_INTERVAL, &mut receiver, &state, &bus) {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            println!("🎙️ Playing the return audio from {} on '{}'", config.url, config.device);
+            let mut resampler = Resampler::default();
+            let mut last_status: Option<Instant> = None;
+
+            loop {
+                if drain_bus(&mut receiver, &state, &bus) {
+                    return "✅ Exiting IFB player";
+                }
+                let Some(current) = wanted_config(&state) else {
+                    break;
+                };
+                if (&current.url, &current.device, current.buffer_ms) != (&config.url, &config.device, config.buffer_ms) {
+                    break;
+                }
+                buffer.set_volume(current.volume);
+
+                match feed.next_audio() {
+                    Ok(audio) if audio.channels > 0 && audio.sample_rate > 0 => buffer.push(&resampler.process(&audio)),
+                    Ok(_) => continue,
+                    Err(e) => {
+                        eprintln!("🎙️ Lost the return audio: {}", e);
+                        report(&state, &bus, |status| {
+                            status.playback = IfbPlayback::Failed;
+                            status.error = Some(e.to_string());
+                        });
+                        if wait(RETRY_INTERVAL, &mut receiver, &state, &bus) {
+                            return "✅ Exiting IFB player";
+                        }
+                        break;
+                    }
+                }
+
+                let (playback, buffered_ms, underruns) = buffer.playback();
+                let playback_changed = state.blocking_read().ifb.playback != playback;
+                if playback_changed || last_status.is_none_or(|last| last.elapsed() >= STATUS_INTERVAL) {
+                    last_status = Some(Instant::now());
+                    report(&state, &bus, |status| {
+                        status.playback = playback;
+                        status.codec = Some(codec);
+                        status.buffered_ms = buffered_ms;
+                        status.underruns = underruns;
+                    });
+                }
+            }
+        }
+
+        "✅ Exiting IFB player"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_buffer_waits_to_fill() {
+        let buffer = JitterBuffer::new();
+        buffer.reset(100);
+        buffer.set_volume(0.5);
+        let target = 4_800 * CHANNELS;
+
+        // Nothing plays until the whole buffer has arrived
+        buffer.push(&vec![1.0; target - 2]);
+        let mut output = [1.0; 4];
+        buffer.fill_output(&mut output);
+        assert_eq!(output, [0.0; 4]);
+        assert_eq!(buffer.playback().0, IfbPlayback::Buffering);
+
+        buffer.push(&[1.0; 2]);
+        buffer.fill_output(&mut output);
+        assert_eq!(output, [0.5; 4]);
+        assert_eq!(buffer.playback(), (IfbPlayback::Playing, 99, 0));
+
+        // Running dry counts an underrun and goes back to buffering
+        let mut rest = vec![0.0; target];
+        buffer.fill_output(&mut rest);
+        assert_eq!(rest[target - 5], 0.5);
+        assert_eq!(rest[target - 4], 0.0);
+        assert_eq!(buffer.playback().0, IfbPlayback::Buffering);
+        assert_eq!(buffer.playback().2, 1);
+
+        // A burst larger than twice the buffer is trimmed to it
+        buffer.push(&vec![1.0; target * 3]);
+        assert_eq!(buffer.playback().1, 200);
+    }
+
+    #[test]
+    fn test_resampler() {
+        let mut resampler = Resampler::default();
+        let mono = |sample_rate, samples: Vec<f32>| DecodedAudio { samples, sample_rate, channels: 1 };
+
+        // 48 kHz passes through, with mono copied to both sides
+        assert_eq!(resampler.process(&mono(48_000, vec![0.25, 0.5])), vec![0.25, 0.25, 0.5, 0.5]);
+
+        // 24 kHz doubles up, interpolating between frames and across packets
+        let first = resampler.process(&mono(24_000, vec![1.0, 1.0]));
+        assert_eq!(first, vec![0.0, 0.0, 0.5, 0.5, 1.0, 1.0, 1.0, 1.0]);
+        let second = resampler.process(&mono(24_000, vec![0.0, 0.0]));
+        assert_eq!(second, vec![1.0, 1.0, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0]);
+
+        // 44.1 kHz comes out at about the right length
+        let output = resampler.process(&mono(44_100, vec![0.1; 44_100]));
+        assert!((output.len() as i64 / 2 - 48_000).abs() <= 1);
+    }
+
+    /// An Ogg page holding `packets`, the last of which may continue on the next page
+    fn ogg_page(packets: &[&[u8]], continued: bool) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        let mut body = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            lacing.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+            if !(continued && i == packets.len() - 1) {
+                lacing.push((packet.len() % 255) as u8);
+            }
+            body.extend_from_slice(packet);
+        }
+        let mut page = b"OggS".to_vec();
+        page.extend([0u8; 22]);
+        page.push(lacing.len() as u8);
+        page.extend(lacing);
+        page.extend(body);
+        page
+    }
+
+    #[test]
+    fn test_ogg_packets_span_pages() {
+        let long = vec![7u8; 510];
+        let mut stream = ogg_page(&[b"OpusHead", &long], true);
+        stream.extend(ogg_page(&[b"tail"], false));
+
+        let mut packets = OggPackets::new(io::Cursor::new(stream));
+        assert_eq!(packets.next_packet().unwrap(), b"OpusHead");
+        let joined = packets.next_packet().unwrap();
+        assert_eq!(joined.len(), 514);
+        assert_eq!(&joined[510..], b"tail");
+        assert!(packets.next_packet().is_err());
+
+        let mut packets = OggPackets::new(io::Cursor::new(b"NotOgg".repeat(10)));
+        assert_eq!(packets.next_packet().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,373 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Days, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+
+use crate::config::AlasActivationMode;
+use crate::state::{AlasMessage, AlasState, SafeState};
+
+pub const LEVEL_HISTORY_DIRECTORY: &str = "/var/lib/alas/levels";
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Matches the floor of the level messages on the bus
+const MIN_DB: f32 = -60.0;
+/// Samples further apart than this mean ALAS was not running in between, so
+/// a silence does not carry across the gap
+const MAX_SAMPLE_GAP_SECS: i64 = 2;
+
+/// One second of input levels, and what ALAS was doing with them
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct LevelSample {
+    pub timestamp: DateTime<Utc>,
+    /// The loudest RMS level during the second, in dB
+    pub left_db: f32,
+    pub right_db: f32,
+    pub streaming: bool,
+    pub recording: bool,
+    /// Whether the stream should have been on air: it was running, or the
+    /// schedule or its mode forced it on
+    pub on_air_expected: bool,
+}
+
+impl LevelSample {
+    pub fn from_state(state: &AlasState, timestamp: DateTime<Utc>, left_db: f32, right_db: f32) -> Self {
+        let (_, stream_mode) = state.config.audio.stream_activation();
+        let stream_mode = state.schedule.stream.or(stream_mode);
+        LevelSample {
+            timestamp,
+            left_db,
+            right_db,
+            streaming: state.is_streaming,
+            recording: state.is_recording,
+            on_air_expected: state.is_streaming || stream_mode == AlasActivationMode::ForceOn,
+        }
+    }
+
+    pub fn level_db(&self) -> f32 {
+        self.left_db.max(self.right_db)
+    }
+
+    /// One line of a day file: `unix seconds,left,right,streaming,recording,expected`
+    fn to_line(self) -> String {
+        format!(
+            "{},{:.1},{:.1},{},{},{}\n",
+            self.timestamp.timestamp(),
+            self.left_db,
+            self.right_db,
+            self.streaming as u8,
+            self.recording as u8,
+            self.on_air_expected as u8
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.trim().split(',');
+        let timestamp = Utc.timestamp_opt(fields.next()?.parse().ok()?, 0).single()?;
+        let left_db = fields.next()?.parse().ok()?;
+        let right_db = fields.next()?.parse().ok()?;
+        let mut flag = || fields.next().map(|field| field == "1");
+        Some(LevelSample {
+            timestamp,
+            left_db,
+            right_db,
+            streaming: flag()?,
+            recording: flag()?,
+            on_air_expected: flag()?,
+        })
+    }
+
+    /// Folds a later sample into this one when downsampling
+    fn merge(&mut self, later: &LevelSample) {
+        self.left_db = self.left_db.max(later.left_db);
+        self.right_db = self.right_db.max(later.right_db);
+        self.streaming |= later.streaming;
+        self.recording |= later.recording;
+        self.on_air_expected |= later.on_air_expected;
+    }
+}
+
+fn day_file(directory: &Path, day: NaiveDate) -> PathBuf {
+    directory.join(format!("{}.csv", day.format("%Y-%m-%d")))
+}
+
+/// Appends samples to one file per UTC day, deleting days past the retention
+pub struct LevelHistoryWriter {
+    directory: PathBuf,
+    current: Option<(NaiveDate, File)>,
+}
+
+impl LevelHistoryWriter {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        LevelHistoryWriter {
+            directory: directory.into(),
+            current: None,
+        }
+    }
+
+    pub fn append(&mut self, sample: &LevelSample, retention_days: u32) -> std::io::Result<()> {
+        let day = sample.timestamp.date_naive();
+        if self.current.as_ref().is_none_or(|(current_day, _)| *current_day != day) {
+            self.current = None;
+            std::fs::create_dir_all(&self.directory)?;
+            prune_level_history(&self.directory, day, retention_days);
+            let file = OpenOptions::new().create(true).append(true).open(day_file(&self.directory, day))?;
+            self.current = Some((day, file));
+        }
+        if let Some((_, file)) = self.current.as_mut() {
+            file.write_all(sample.to_line().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Deletes day files older than `retention_days` before `today`
+fn prune_level_history(directory: &Path, today: NaiveDate, retention_days: u32) {
+    let Some(oldest_kept) = today.checked_sub_days(Days::new(retention_days as u64)) else {
+        return;
+    };
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let day = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+        if day.is_some_and(|day| day < oldest_kept) {
+            match std::fs::remove_file(&path) {
+                Ok(_) => println!("📈 Pruned old level history {:?}", path),
+                Err(e) => eprintln!("📈 Could not prune {:?}: {:?}", path, e),
+            }
+        }
+    }
+}
+
+/// Reads the samples between `from` and `to`, oldest first
+pub fn read_level_history(directory: &Path, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<LevelSample> {
+    let mut samples = Vec::new();
+    let mut day = from.date_naive();
+    while day <= to.date_naive() {
+        if let Ok(contents) = std::fs::read_to_string(day_file(directory, day)) {
+            samples.extend(
+                contents
+                    .lines()
+                    .filter_map(LevelSample::from_line)
+                    .filter(|sample| sample.timestamp >= from && sample.timestamp < to)
+            );
+        }
+        let Some(next) = day.succ_opt() else {
+            break;
+        };
+        day = next;
+    }
+    samples
+}
+
+/// Combines samples into buckets of `step_secs`, keeping the loudest level
+/// and any activity within each bucket
+pub fn downsample(samples: Vec<LevelSample>, step_secs: i64) -> Vec<LevelSample> {
+    if step_secs <= 1 {
+        return samples;
+    }
+    let mut buckets: Vec<LevelSample> = Vec::new();
+    for sample in samples {
+        let bucket = sample.timestamp.timestamp().div_euclid(step_secs);
+        match buckets.last_mut() {
+            Some(last) if last.timestamp.timestamp().div_euclid(step_secs) == bucket => last.merge(&sample),
+            _ => buckets.push(sample),
+        }
+    }
+    buckets
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeadAirPeriod {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub duration_secs: i64,
+}
+
+/// Finds silences of at least `min_secs` while the stream was expected on air
+pub fn find_dead_air(samples: &[LevelSample], threshold_db: f32, min_secs: i64) -> Vec<DeadAirPeriod> {
+    let mut periods = Vec::new();
+    let mut current: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+
+    let mut close = |current: &mut Option<(DateTime<Utc>, DateTime<Utc>)>| {
+        if let Some((start, last)) = current.take() {
+            let end = last + chrono::Duration::seconds(1);
+            let duration_secs = (end - start).num_seconds();
+            if duration_secs >= min_secs {
+                periods.push(DeadAirPeriod { start, end, duration_secs });
+            }
+        }
+    };
+
+    for sample in samples {
+        let silent = sample.on_air_expected && sample.level_db() < threshold_db;
+        let continues = current
+            .is_some_and(|(_, last)| (sample.timestamp - last).num_seconds() <= MAX_SAMPLE_GAP_SECS);
+        if !silent || !continues {
+            close(&mut current);
+        }
+        if silent {
+            let start = current.map_or(sample.timestamp, |(start, _)| start);
+            current = Some((start, sample.timestamp));
+        }
+    }
+    close(&mut current);
+    periods
+}
+
+/// The dead-air report as CSV, one period per line
+pub fn dead_air_csv(periods: &[DeadAirPeriod]) -> String {
+    let mut csv = String::from("start,end,duration_secs\n");
+    for period in periods {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            period.start.to_rfc3339(),
+            period.end.to_rfc3339(),
+            period.duration_secs
+        ));
+    }
+    csv
+}
+
+/// Writes a sample of the input levels every second, while the level history
+/// is switched on
+pub fn start_level_history(bus: Sender<AlasMessage>, state: &SafeState) -> JoinHandle<()> {
+    let state = state.clone();
+    let mut receiver = bus.subscribe();
+
+    tokio::spawn(async move {
+        let mut ticker = time::interval(SAMPLE_INTERVAL);
+        let mut writer = LevelHistoryWriter::new(LEVEL_HISTORY_DIRECTORY);
+        // A second without any levels means the input has stopped, which is dead air too
+        let mut levels = (MIN_DB, MIN_DB);
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    let (left_db, right_db) = std::mem::replace(&mut levels, (MIN_DB, MIN_DB));
+                    let (sample, config) = {
+                        let state = state.read().await;
+                        let sample = LevelSample::from_state(&state, Utc::now(), left_db, right_db);
+                        (sample, state.config.level_history.clone())
+                    };
+                    let Some(config) = config else {
+                        continue;
+                    };
+                    if let Err(e) = writer.append(&sample, config.retention_days) {
+                        eprintln!("📈 Could not write level history: {:?}", e);
+                    }
+                }
+                message = receiver.recv() => {
+                    match message {
+                        Ok(AlasMessage::VolumeChange { left, right }) => {
+                            levels = (levels.0.max(left), levels.1.max(right));
+                        }
+                        Ok(AlasMessage::Exit) | Err(RecvError::Closed) => {
+                            println!("✅ Exiting level history!");
+                            return;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc)
+    }
+
+    fn sample(timestamp: DateTime<Utc>, level_db: f32, on_air_expected: bool) -> LevelSample {
+        LevelSample {
+            timestamp,
+            left_db: level_db,
+            right_db: level_db - 3.0,
+            streaming: on_air_expected,
+            recording: false,
+            on_air_expected,
+        }
+    }
+
+    /// `levels` one second apart, starting at `start`
+    fn seconds(start: DateTime<Utc>, levels: &[(f32, bool)]) -> Vec<LevelSample> {
+        levels
+            .iter()
+            .enumerate()
+            .map(|(i, &(level_db, expected))| sample(start + chrono::Duration::seconds(i as i64), level_db, expected))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_read_and_prune() {
+        let directory = std::env::temp_dir().join(format!("alas-levels-{}", uuid::Uuid::new_v4()));
+        let mut writer = LevelHistoryWriter::new(&directory);
+
+        let old = sample(at("2025-01-01T12:00:00Z"), -20.0, true);
+        writer.append(&old, 7).unwrap();
+        let before_midnight = seconds(at("2025-01-09T23:59:58Z"), &[(-20.0, true), (-55.5, false)]);
+        let after_midnight = seconds(at("2025-01-10T00:00:00Z"), &[(-30.0, true), (-40.0, true)]);
+        for sample in before_midnight.iter().chain(&after_midnight) {
+            writer.append(sample, 7).unwrap();
+        }
+
+        // The first day is more than a week before the latest, so it is gone
+        assert!(!day_file(&directory, old.timestamp.date_naive()).exists());
+        let read = read_level_history(&directory, at("2025-01-09T23:59:59Z"), at("2025-01-10T00:00:01Z"));
+        assert_eq!(read, vec![before_midnight[1], after_midnight[0]]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_dead_air_periods() {
+        let start = at("2025-01-06T09:00:00Z");
+        let mut levels = vec![(-20.0, true); 5];
+        levels.extend(vec![(-58.0, true); 12]);
+        levels.extend(vec![(-20.0, true); 5]);
+        // Too short to report
+        levels.extend(vec![(-58.0, true); 3]);
+        // Silent, but nobody expected us on air
+        levels.extend(vec![(-58.0, false); 30]);
+        let samples = seconds(start, &levels);
+
+        let periods = find_dead_air(&samples, -50.0, 10);
+        assert_eq!(periods, vec![DeadAirPeriod {
+            start: at("2025-01-06T09:00:05Z"),
+            end: at("2025-01-06T09:00:17Z"),
+            duration_secs: 12,
+        }]);
+        assert_eq!(
+            dead_air_csv(&periods),
+            "start,end,duration_secs\n2025-01-06T09:00:05+00:00,2025-01-06T09:00:17+00:00,12\n"
+        );
+
+        // A gap in the history, such as a reboot, splits a silence
+        let mut gapped = seconds(start, &[(-58.0, true); 8]);
+        gapped.extend(seconds(start + chrono::Duration::seconds(60), &[(-58.0, true); 8]));
+        assert!(find_dead_air(&gapped, -50.0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_downsample_keeps_loudest() {
+        let samples = seconds(at("2025-01-06T09:00:00Z"), &[(-40.0, false), (-10.0, true), (-50.0, false)]);
+        let downsampled = downsample(samples, 60);
+        assert_eq!(downsampled.len(), 1);
+        assert_eq!(downsampled[0].left_db, -10.0);
+        assert!(downsampled[0].on_air_expected);
+    }
+}
@@ -1,6 +1,22 @@
 pub mod audio;
+pub mod bitrate;
+pub mod calibration;
+pub mod catalog;
 pub mod config;
+pub mod delay;
 pub mod dropbox;
+pub mod encryption;
+pub mod fallback;
+pub mod icecast;
+pub mod ifb;
+pub mod level_history;
+mod libshout;
+pub mod listeners;
+pub mod markers;
+pub mod meter;
+pub mod mixer;
+pub mod monitor;
+pub mod podcast;
 mod modem_manager;
 mod network_manager;
 pub mod state;
@@ -8,6 +24,13 @@ mod utils;
 pub mod wifi;
 pub mod cellular;
 pub mod redundancy;
+pub mod schedule;
+pub mod storage;
+pub mod stream_stats;
+pub mod tones;
+pub mod transcode;
+pub mod verifier;
+pub mod waveform;
 pub mod webhook;
 
 use crate::modem_manager::ModemSimpleProxy;
@@ -0,0 +1,150 @@
+use std::ffi::{c_char, c_int, c_uint, c_ushort, c_void, CStr, CString};
+use std::sync::Once;
+use thiserror::Error;
+
+// From shout.h. libshout 2.4 or newer is needed for TLS.
+const SHOUTERR_SUCCESS: c_int = 0;
+const SHOUTERR_NOLOGIN: c_int = -3;
+const SHOUTERR_NOTLS: c_int = -11;
+const SHOUTERR_TLSBADCERT: c_int = -12;
+
+pub(crate) const SHOUT_PROTOCOL_HTTP: c_uint = 0;
+pub(crate) const SHOUT_PROTOCOL_ICY: c_uint = 2;
+const SHOUT_FORMAT_MP3: c_uint = 1;
+/// TLS from the first byte, as for https://
+const SHOUT_TLS_RFC2818: c_int = 11;
+
+#[allow(non_camel_case_types)]
+type shout_t = c_void;
+
+#[link(name = "shout")]
+unsafe extern "C" {
+    fn shout_init();
+    fn shout_new() -> *mut shout_t;
+    fn shout_free(shout: *mut shout_t);
+    fn shout_get_error(shout: *mut shout_t) -> *const c_char;
+    fn shout_set_host(shout: *mut shout_t, host: *const c_char) -> c_int;
+    fn shout_set_port(shout: *mut shout_t, port: c_ushort) -> c_int;
+    fn shout_set_user(shout: *mut shout_t, user: *const c_char) -> c_int;
+    fn shout_set_password(shout: *mut shout_t, password: *const c_char) -> c_int;
+    fn shout_set_mount(shout: *mut shout_t, mount: *const c_char) -> c_int;
+    fn shout_set_protocol(shout: *mut shout_t, protocol: c_uint) -> c_int;
+    fn shout_set_format(shout: *mut shout_t, format: c_uint) -> c_int;
+    fn shout_set_tls(shout: *mut shout_t, mode: c_int) -> c_int;
+    fn shout_set_agent(shout: *mut shout_t, agent: *const c_char) -> c_int;
+    fn shout_set_name(shout: *mut shout_t, name: *const c_char) -> c_int;
+    fn shout_set_genre(shout: *mut shout_t, genre: *const c_char) -> c_int;
+    fn shout_set_description(shout: *mut shout_t, description: *const c_char) -> c_int;
+    fn shout_set_url(shout: *mut shout_t, url: *const c_char) -> c_int;
+    fn shout_set_public(shout: *mut shout_t, public: c_uint) -> c_int;
+    fn shout_open(shout: *mut shout_t) -> c_int;
+    fn shout_close(shout: *mut shout_t) -> c_int;
+    fn shout_send(shout: *mut shout_t, data: *const u8, len: usize) -> c_int;
+}
+
+static INIT: Once = Once::new();
+
+/// What went wrong, by libshout's error code
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum ShoutError {
+    /// The server turned down the login, or the mount
+    #[error("{0}")]
+    NoLogin(String),
+    /// TLS could not be set up, or the server's certificate is bad
+    #[error("{0}")]
+    Tls(String),
+    /// Anything else, which is worth retrying
+    #[error("{0}")]
+    Other(String),
+}
+
+/// The settings libshout needs to open a source connection
+pub(crate) struct ShoutSettings<'a> {
+    pub(crate) host: &'a str,
+    pub(crate) port: u16,
+    pub(crate) user: &'a str,
+    pub(crate) password: &'a str,
+    pub(crate) mount: &'a str,
+    pub(crate) protocol: c_uint,
+    pub(crate) tls: bool,
+    pub(crate) name: Option<&'a str>,
+    pub(crate) genre: Option<&'a str>,
+    pub(crate) description: Option<&'a str>,
+    pub(crate) url: Option<&'a str>,
+    pub(crate) public: bool,
+}
+
+/// An open libshout source connection, closed when dropped
+pub(crate) struct ShoutConn {
+    shout: *mut shout_t,
+}
+
+// libshout connections are not shared, only moved between threads
+unsafe impl Send for ShoutConn {}
+
+impl ShoutConn {
+    pub(crate) fn open(settings: &ShoutSettings) -> Result<Self, ShoutError> {
+        INIT.call_once(|| unsafe { shout_init() });
+        let shout = unsafe { shout_new() };
+        if shout.is_null() {
+            return Err(ShoutError::Other("Could not allocate a libshout connection".to_string()));
+        }
+        // Freed on any error from here on
+        let conn = ShoutConn { shout };
+
+        let string = |value: &str| CString::new(value).map_err(|_| ShoutError::Other(format!("{:?} contains a NUL byte", value)));
+        conn.check(unsafe { shout_set_host(shout, string(settings.host)?.as_ptr()) })?;
+        conn.check(unsafe { shout_set_port(shout, settings.port) })?;
+        conn.check(unsafe { shout_set_user(shout, string(settings.user)?.as_ptr()) })?;
+        conn.check(unsafe { shout_set_password(shout, string(settings.password)?.as_ptr()) })?;
+        conn.check(unsafe { shout_set_mount(shout, string(settings.mount)?.as_ptr()) })?;
+        conn.check(unsafe { shout_set_protocol(shout, settings.protocol) })?;
+        conn.check(unsafe { shout_set_format(shout, SHOUT_FORMAT_MP3) })?;
+        conn.check(unsafe { shout_set_agent(shout, c"alas".as_ptr()) })?;
+        // Fails if this libshout was built without TLS
+        if settings.tls && let Err(e) = conn.check(unsafe { shout_set_tls(shout, SHOUT_TLS_RFC2818) }) {
+            return Err(ShoutError::Tls(e.to_string()));
+        }
+        let fields: [(unsafe extern "C" fn(*mut shout_t, *const c_char) -> c_int, Option<&str>); 4] = [
+            (shout_set_name, settings.name),
+            (shout_set_genre, settings.genre),
+            (shout_set_description, settings.description),
+            (shout_set_url, settings.url),
+        ];
+        for (set, value) in fields {
+            if let Some(value) = value {
+                conn.check(unsafe { set(shout, string(value)?.as_ptr()) })?;
+            }
+        }
+        conn.check(unsafe { shout_set_public(shout, settings.public as c_uint) })?;
+
+        conn.check(unsafe { shout_open(shout) })?;
+        Ok(conn)
+    }
+
+    pub(crate) fn send(&mut self, data: &[u8]) -> Result<(), ShoutError> {
+        self.check(unsafe { shout_send(self.shout, data.as_ptr(), data.len()) })
+    }
+
+    fn check(&self, code: c_int) -> Result<(), ShoutError> {
+        if code == SHOUTERR_SUCCESS {
+            return Ok(());
+        }
+        let message = unsafe { CStr::from_ptr(shout_get_error(self.shout)) }.to_string_lossy().into_owned();
+        Err(match code {
+            SHOUTERR_NOLOGIN => ShoutError::NoLogin(message),
+            SHOUTERR_NOTLS | SHOUTERR_TLSBADCERT => ShoutError::Tls(message),
+            _ => ShoutError::Other(message),
+        })
+    }
+}
+
+impl Drop for ShoutConn {
+    fn drop(&mut self) {
+        unsafe {
+            // Closing a connection that never opened does no harm
+            shout_close(self.shout);
+            shout_free(self.shout);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+
+use crate::config::AlasConfig;
+use crate::state::{AlasMessage, SafeState};
+use crate::verifier::USER_AGENT as VERIFIER_USER_AGENT;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the Icecast server reports for one of our mounts
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MountStats {
+    pub mount: String,
+    /// Whether the server currently has a source on this mount
+    pub online: bool,
+    pub listeners: u32,
+    /// The most listeners at once since the source connected
+    pub listener_peak: u32,
+    pub stream_start: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ListenerStats {
+    pub mounts: Vec<MountStats>,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+impl ListenerStats {
+    /// Listeners across all of our mounts, once the server has been asked
+    pub fn total_listeners(&self) -> Option<u32> {
+        self.checked_at?;
+        Some(self.mounts.iter().map(|mount| mount.listeners).sum())
+    }
+
+    pub fn total_peak(&self) -> Option<u32> {
+        self.checked_at?;
+        Some(self.mounts.iter().map(|mount| mount.listener_peak).sum())
+    }
+}
+
+/// The mounts we publish to, as paths on the Icecast server
+fn configured_mounts(config: &AlasConfig) -> Vec<String> {
+    vec![config.icecast.mount.clone()]
+}
+
+fn parse_stream_start(value: &str) -> Option<DateTime<Utc>> {
+    // Icecast writes e.g. "2025-03-01T18:00:05+0000"
+    DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%z")
+        .or_else(|_| DateTime::parse_from_rfc3339(value))
+        .ok()
+        .map(|start| start.with_timezone(&Utc))
+}
+
+fn as_count(value: Option<&Value>) -> u32 {
+    match value {
+        Some(Value::Number(number)) => number.as_u64().unwrap_or(0) as u32,
+        // Older servers send numbers as strings
+        Some(Value::String(text)) => text.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Picks our mounts out of the server's `status-json.xsl`. The server reports
+/// a single source as an object and several as an array; mounts without a
+/// source are not listed at all, so they come back offline.
+pub fn parse_status_json(status: &Value, mounts: &[String]) -> Vec<MountStats> {
+    let sources: Vec<&Value> = match status.pointer("/icestats/source") {
+        Some(Value::Array(sources)) => sources.iter().collect(),
+        Some(source @ Value::Object(_)) => vec![source],
+        _ => vec![],
+    };
+
+    mounts
+        .iter()
+        .map(|mount| {
+            let source = sources.iter().find(|source| {
+                source
+                    .get("listenurl")
+                    .and_then(Value::as_str)
+                    .is_some_and(|url| url.ends_with(mount.as_str()))
+            });
+            match source {
+                Some(source) => MountStats {
+                    mount: mount.clone(),
+                    online: true,
+                    listeners: as_count(source.get("listeners")),
+                    listener_peak: as_count(source.get("listener_peak")),
+                    stream_start: source
+                        .get("stream_start_iso8601")
+                        .and_then(Value::as_str)
+                        .and_then(parse_stream_start),
+                },
+                None => MountStats {
+                    mount: mount.clone(),
+                    ..Default::default()
+                },
+            }
+        })
+        .collect()
+}
+
+fn server_url(config: &AlasConfig, path: &str) -> String {
+    let scheme = if config.icecast.tls { "https" } else { "http" };
+    format!("{}://{}:{}{}", scheme, config.icecast.hostname, config.icecast.port, path)
+}
+
+/// How many of a mount's listeners are our own off-air verifier, going by the
+/// user agents in the server's listener list. The source login is allowed to
+/// read the list for its own mount.
+async fn count_verifier_listeners(config: &AlasConfig, mount: &str) -> Result<u32, reqwest::Error> {
+    let listeners = reqwest::Client::new()
+        .get(server_url(config, "/admin/listclients"))
+        .query(&[("mount", mount)])
+        .basic_auth(&config.icecast.user, Some(&config.icecast.password))
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(listeners.matches(&format!("<UserAgent>{}</UserAgent>", VERIFIER_USER_AGENT)).count() as u32)
+}
+
+/// Asks the Icecast server how our mounts are doing. With the off-air
+/// verifier on, its own connection is left out of the listener numbers.
+pub async fn fetch_listener_stats(config: &AlasConfig) -> Result<Vec<MountStats>, reqwest::Error> {
+    let status: Value = reqwest::Client::new()
+        .get(server_url(config, "/status-json.xsl"))
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let mut mounts = parse_status_json(&status, &configured_mounts(config));
+    if config.off_air.is_some() {
+        for mount in mounts.iter_mut().filter(|mount| mount.listeners > 0) {
+            match count_verifier_listeners(config, &mount.mount).await {
+                Ok(verifiers) => mount.listeners = mount.listeners.saturating_sub(verifiers),
+                Err(e) => eprintln!("👂 Could not list the listeners on {}: {}", mount.mount, e),
+            }
+        }
+    }
+    Ok(mounts)
+}
+
+/// Starts a task that polls the Icecast server for listener numbers, keeping
+/// `AlasState::listeners` up to date and announcing `ListenerCountChanged`
+/// whenever the total changes.
+pub fn start_listener_stats_poller(bus: Sender<AlasMessage>, state: &SafeState) -> JoinHandle<()> {
+    let state = state.clone();
+    let mut exit_bus = bus.subscribe();
+
+    tokio::spawn(async move {
+        let mut ticker = time::interval(POLL_INTERVAL);
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    let config = state.read().await.config.clone();
+                    let stats = match fetch_listener_stats(&config).await {
+                        Ok(mounts) => ListenerStats {
+                            mounts,
+                            checked_at: Some(Utc::now()),
+                            error: None,
+                        },
+                        Err(e) => {
+                            eprintln!("👂 Could not fetch Icecast stats: {}", e);
+                            ListenerStats {
+                                error: Some(e.to_string()),
+                                ..Default::default()
+                            }
+                        }
+                    };
+
+                    let listeners = stats.total_listeners();
+                    let previous = {
+                        let mut state = state.write().await;
+                        std::mem::replace(&mut state.listeners, stats)
+                    };
+                    if previous.total_listeners() != listeners {
+                        let _ = bus.send(AlasMessage::ListenerCountChanged { listeners });
+                    }
+                }
+                message = exit_bus.recv() => {
+                    match message {
+                        Ok(AlasMessage::Exit) | Err(RecvError::Closed) => {
+                            println!("✅ Exiting listener stats poller!");
+                            return;
+                        }
+                        // Falling behind while a poll was in flight is expected
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::AlasState;
+    use serde_json::json;
+    use wiremock::matchers::{header_exists, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_parse_status_json() {
+        let status = json!({
+            "icestats": {
+                "source": [
+                    {
+                        "listenurl": "http://radio.example.org:8000/other.mp3",
+                        "listeners": 40,
+                        "listener_peak": 41
+                    },
+                    {
+                        "listenurl": "http://radio.example.org:8000/live.mp3",
+                        "listeners": 12,
+                        "listener_peak": "30",
+                        "stream_start_iso8601": "2025-03-01T18:00:05+0000"
+                    }
+                ]
+            }
+        });
+        let mounts = vec!["/live.mp3".to_string(), "/backup.mp3".to_string()];
+
+        let stats = parse_status_json(&status, &mounts);
+        assert_eq!(stats[0], MountStats {
+            mount: "/live.mp3".to_string(),
+            online: true,
+            listeners: 12,
+            listener_peak: 30,
+            stream_start: Some("2025-03-01T18:00:05Z".parse().unwrap()),
+        });
+        assert!(!stats[1].online);
+        assert_eq!(stats[1].listeners, 0);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_listener_stats() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status-json.xsl"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "icestats": {
+                    "source": {
+                        "listenurl": "http://localhost:8000/hello.mp3",
+                        "listeners": 3,
+                        "listener_peak": 5
+                    }
+                }
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let mut config = AlasState::test().config;
+        let address = mock_server.address();
+        config.icecast.hostname = address.ip().to_string();
+        config.icecast.port = address.port();
+
+        let stats = fetch_listener_stats(&config).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].listeners, stats[0].listener_peak), (3, 5));
+
+        // The off-air verifier is one of the three, and does not count
+        Mock::given(method("GET"))
+            .and(path("/admin/listclients"))
+            .and(query_param("mount", "/hello.mp3"))
+            .and(header_exists("authorization"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<icestats><source mount=\"/hello.mp3\"><Listeners>3</Listeners>\
+                 <listener><UserAgent>VLC/3.0.20</UserAgent></listener>\
+                 <listener><UserAgent>alas-verifier</UserAgent></listener>\
+                 <listener><UserAgent>Mozilla/5.0</UserAgent></listener>\
+                 </source></icestats>"
+            ))
+            .mount(&mock_server)
+            .await;
+        config.off_air = Some(Default::default());
+        let stats = fetch_listener_stats(&config).await.unwrap();
+        assert_eq!(stats[0].listeners, 2);
+    }
+}
@@ -0,0 +1,148 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use id3::frame::{Chapter, TableOfContents};
+use id3::{Frame, Tag, TagLike, Version};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::broadcast::Receiver;
+
+use crate::state::AlasMessage;
+
+/// A moment of interest in a recording, e.g. a goal or the start of a segment
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordingMarker {
+    /// Seconds from the start of the recording
+    pub offset_secs: f64,
+    pub created_at: DateTime<Utc>,
+    pub label: Option<String>,
+}
+
+/// ID3 uses this value to say that a chapter has no byte offsets
+const NO_OFFSET: u32 = 0xFFFFFFFF;
+
+/// Markers are kept in a JSON file next to the recording, e.g. `show.mp3.markers.json`
+pub fn sidecar_path(recording_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.markers.json", recording_path))
+}
+
+pub fn write_sidecar(recording_path: &str, markers: &[RecordingMarker]) -> io::Result<()> {
+    let serialized = serde_json::to_string_pretty(markers)?;
+    std::fs::write(sidecar_path(recording_path), serialized)
+}
+
+/// Removes the sidecar for a recording, if it has one
+pub fn remove_sidecar(recording_path: &str) {
+    let path = sidecar_path(recording_path);
+    if path.exists() && let Err(e) = std::fs::remove_file(&path) {
+        eprintln!("🔖 Could not delete {}: {}", path.display(), e);
+    }
+}
+
+fn marker_title(marker: &RecordingMarker, index: usize) -> String {
+    marker.label.clone().unwrap_or_else(|| format!("Marker {}", index + 1))
+}
+
+/// Builds ID3v2 CHAP frames, plus the CTOC frame that lists them, for a
+/// recording. Each chapter runs from its marker to the next one, with an extra
+/// opening chapter if the first marker is not at the very start.
+fn chapter_tag(markers: &[RecordingMarker], duration_secs: f64) -> Tag {
+    let to_ms = |secs: f64| (secs * 1000.0).round().clamp(0.0, u32::MAX as f64) as u32;
+    let end_ms = to_ms(duration_secs);
+
+    let mut starts: Vec<(u32, String)> = markers
+        .iter()
+        .enumerate()
+        .map(|(index, marker)| (to_ms(marker.offset_secs).min(end_ms), marker_title(marker, index)))
+        .collect();
+    if starts.first().is_some_and(|(start, _)| *start > 0) {
+        starts.insert(0, (0, "Start".to_string()));
+    }
+
+    let mut tag = Tag::new();
+    let mut elements = Vec::new();
+    for (index, (start, title)) in starts.iter().enumerate() {
+        let end = starts.get(index + 1).map(|(next, _)| *next).unwrap_or(end_ms);
+        let element_id = format!("chp{}", index);
+        tag.add_frame(Chapter {
+            element_id: element_id.clone(),
+            start_time: *start,
+            end_time: end,
+            start_offset: NO_OFFSET,
+            end_offset: NO_OFFSET,
+            frames: vec![Frame::text("TIT2", title.clone())],
+        });
+        elements.push(element_id);
+    }
+    tag.add_frame(TableOfContents {
+        element_id: "toc".to_string(),
+        top_level: true,
+        ordered: true,
+        elements,
+        frames: vec![],
+    });
+    tag
+}
+
+/// Writes the markers into the recording as ID3v2 chapters
+pub fn write_chapters(recording_path: &str, markers: &[RecordingMarker], duration_secs: f64) -> id3::Result<()> {
+    if markers.is_empty() {
+        return Ok(());
+    }
+    chapter_tag(markers, duration_secs).write_to_path(Path::new(recording_path), Version::Id3v24)
+}
+
+/// Collects any marker requests waiting on the bus, returning their labels.
+/// Everything else on the bus is skipped over.
+pub fn take_marker_requests(receiver: &mut Receiver<AlasMessage>) -> Vec<Option<String>> {
+    let mut requests = Vec::new();
+    loop {
+        match receiver.try_recv() {
+            Ok(AlasMessage::AddMarker { label }) => requests.push(label),
+            Ok(_) | Err(TryRecvError::Lagged(_)) => continue,
+            Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+        }
+    }
+    requests
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(offset_secs: f64, label: Option<&str>) -> RecordingMarker {
+        RecordingMarker {
+            offset_secs,
+            created_at: Utc::now(),
+            label: label.map(|l| l.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_chapter_tag() {
+        let markers = vec![marker(30.0, Some("Interview")), marker(95.5, None)];
+        let tag = chapter_tag(&markers, 120.0);
+
+        let chapters: Vec<&Chapter> = tag.chapters().collect();
+        assert_eq!(chapters.len(), 3);
+        assert_eq!((chapters[0].start_time, chapters[0].end_time), (0, 30_000));
+        assert_eq!((chapters[1].start_time, chapters[1].end_time), (30_000, 95_500));
+        assert_eq!((chapters[2].start_time, chapters[2].end_time), (95_500, 120_000));
+        assert_eq!(chapters[1].frames[0].content().text(), Some("Interview"));
+        assert_eq!(chapters[2].frames[0].content().text(), Some("Marker 2"));
+
+        let toc = tag.tables_of_contents().next().expect("should have a table of contents");
+        assert_eq!(toc.elements, vec!["chp0", "chp1", "chp2"]);
+    }
+
+    #[test]
+    fn test_take_marker_requests() {
+        let (sender, mut receiver) = tokio::sync::broadcast::channel(16);
+        sender.send(AlasMessage::AddMarker { label: Some("Goal".to_string()) }).unwrap();
+        sender.send(AlasMessage::RecordingStarted).unwrap();
+        sender.send(AlasMessage::AddMarker { label: None }).unwrap();
+
+        assert_eq!(take_marker_requests(&mut receiver), vec![Some("Goal".to_string()), None]);
+        assert!(take_marker_requests(&mut receiver).is_empty());
+    }
+}
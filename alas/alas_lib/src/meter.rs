@@ -0,0 +1,349 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use bus::BusReader;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task;
+use tokio::task::JoinHandle;
+
+use crate::mixer::InputLevel;
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const CHANNELS: usize = 2;
+/// Readings are taken every 50 ms, which is as fast as any client may ask for
+const READING_FRAMES: usize = 2_400;
+pub const MAX_RATE_HZ: u32 = (SAMPLE_RATE as usize / READING_FRAMES) as u32;
+/// Matches the floor of the level messages on the bus
+const MIN_DB: f32 = -60.0;
+/// A peak is held this long before it starts to fall
+const PEAK_HOLD_SECS: f32 = 1.5;
+const PEAK_FALL_DB_PER_SEC: f32 = 20.0;
+/// About 43 ms of audio, which resolves 23 Hz
+const FFT_SIZE: usize = 2_048;
+/// The spectrum is split into this many bands, evenly spaced on a log scale
+/// from 31.5 Hz to 16 kHz
+pub const SPECTRUM_BANDS: usize = 16;
+const SPECTRUM_LOW_HZ: f32 = 31.5;
+const SPECTRUM_HIGH_HZ: f32 = 16_000.0;
+/// Readings a slow client can fall behind by before it skips ahead
+const FEED_CAPACITY: usize = 16;
+
+fn to_db(level: f32) -> f32 {
+    if level > 0.0 { (20.0 * level.log10()).max(MIN_DB) } else { MIN_DB }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct MeterChannel {
+    pub rms_db: f32,
+    /// The loudest sample since the previous reading
+    pub peak_db: f32,
+    /// The loudest recent peak, held for a moment and then falling slowly
+    pub peak_hold_db: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MeterReading {
+    pub left: MeterChannel,
+    pub right: MeterChannel,
+    /// Band levels in dBFS, lowest band first. See [`SPECTRUM_BANDS`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spectrum: Option<Vec<f32>>,
+    /// Levels of each mixed input, filled in by the meter feed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<InputLevel>,
+}
+
+impl MeterReading {
+    /// Folds a later reading into this one, for clients that want fewer
+    /// readings. Peaks are kept so that none are missed in between.
+    pub fn merge(&mut self, later: MeterReading) {
+        let left_peak = self.left.peak_db.max(later.left.peak_db);
+        let right_peak = self.right.peak_db.max(later.right.peak_db);
+        *self = later;
+        self.left.peak_db = left_peak;
+        self.right.peak_db = right_peak;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct PeakHold {
+    level_db: f32,
+    held_secs: f32,
+}
+
+impl PeakHold {
+    fn update(&mut self, peak_db: f32, elapsed_secs: f32) -> f32 {
+        if peak_db >= self.level_db {
+            self.level_db = peak_db;
+            self.held_secs = 0.0;
+        } else {
+            self.held_secs += elapsed_secs;
+            if self.held_secs > PEAK_HOLD_SECS {
+                self.level_db = (self.level_db - PEAK_FALL_DB_PER_SEC * elapsed_secs).max(peak_db);
+            }
+        }
+        self.level_db
+    }
+}
+
+/// Turns the raw input into meter readings
+pub struct Meter {
+    frames: usize,
+    sums: [f32; CHANNELS],
+    peaks: [f32; CHANNELS],
+    holds: [PeakHold; CHANNELS],
+    /// The latest mono audio, for the spectrum
+    history: VecDeque<f32>,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// The FFT bins that make up each band
+    bands: Vec<std::ops::Range<usize>>,
+}
+
+impl Meter {
+    pub fn new() -> Self {
+        let window: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        let bin_hz = SAMPLE_RATE / FFT_SIZE as f32;
+        let ratio = SPECTRUM_HIGH_HZ / SPECTRUM_LOW_HZ;
+        let bands = (0..SPECTRUM_BANDS)
+            .map(|band| {
+                let low = SPECTRUM_LOW_HZ * ratio.powf(band as f32 / SPECTRUM_BANDS as f32);
+                let high = SPECTRUM_LOW_HZ * ratio.powf((band + 1) as f32 / SPECTRUM_BANDS as f32);
+                // The lowest bands are narrower than a bin, so they get at least one
+                let first = (low / bin_hz).round() as usize;
+                let last = ((high / bin_hz).round() as usize).max(first + 1);
+                first..last
+            })
+            .collect();
+
+        Meter {
+            frames: 0,
+            sums: [0.0; CHANNELS],
+            peaks: [0.0; CHANNELS],
+            holds: [PeakHold { level_db: MIN_DB, held_secs: 0.0 }; CHANNELS],
+            history: VecDeque::with_capacity(FFT_SIZE),
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            bands,
+        }
+    }
+
+    /// Feeds interleaved stereo audio and returns any readings it completed
+    pub fn process(&mut self, input: &[f32]) -> Vec<MeterReading> {
+        let mut readings = Vec::new();
+        for frame in input.chunks_exact(CHANNELS) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                self.sums[channel] += sample * sample;
+                self.peaks[channel] = self.peaks[channel].max(sample.abs());
+            }
+            if self.history.len() == FFT_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back((frame[0] + frame[1]) / 2.0);
+
+            self.frames += 1;
+            if self.frames == READING_FRAMES {
+                readings.push(self.take_reading());
+            }
+        }
+        readings
+    }
+
+    fn take_reading(&mut self) -> MeterReading {
+        let elapsed_secs = self.frames as f32 / SAMPLE_RATE;
+        let mut channels = [MeterChannel { rms_db: MIN_DB, peak_db: MIN_DB, peak_hold_db: MIN_DB }; CHANNELS];
+        for (channel, meter) in channels.iter_mut().enumerate() {
+            meter.rms_db = to_db((self.sums[channel] / self.frames as f32).sqrt());
+            meter.peak_db = to_db(self.peaks[channel]);
+            meter.peak_hold_db = self.holds[channel].update(meter.peak_db, elapsed_secs);
+        }
+        self.frames = 0;
+        self.sums = [0.0; CHANNELS];
+        self.peaks = [0.0; CHANNELS];
+
+        MeterReading {
+            left: channels[0],
+            right: channels[1],
+            spectrum: self.spectrum(),
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Band levels in dBFS, where a full-scale sine reads 0 dB in its band
+    fn spectrum(&self) -> Option<Vec<f32>> {
+        if self.history.len() < FFT_SIZE {
+            return None;
+        }
+        let mut buffer: Vec<Complex<f32>> = self.history
+            .iter()
+            .zip(&self.window)
+            .map(|(sample, weight)| Complex::new(sample * weight, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+
+        // A windowed sine of amplitude A puts A^2 * N * sum(w^2) / 4 into the
+        // positive bins, so this scale makes a full-scale sine come out at 1
+        let window_energy: f32 = self.window.iter().map(|weight| weight * weight).sum();
+        let scale = 4.0 / (FFT_SIZE as f32 * window_energy);
+        let spectrum = self.bands
+            .iter()
+            .map(|bins| {
+                let power: f32 = buffer[bins.clone()].iter().map(|bin| bin.norm_sqr()).sum();
+                (10.0 * (power * scale).max(1e-10).log10()).max(MIN_DB)
+            })
+            .collect();
+        Some(spectrum)
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Meter::new()
+    }
+}
+
+/// Hands meter readings and input levels to API clients on their own
+/// channels, keeping them off the message bus
+#[derive(Clone)]
+pub struct MeterFeed {
+    readings: Sender<MeterReading>,
+    /// Only the latest levels matter, so a slow client never falls behind
+    inputs: watch::Sender<Vec<InputLevel>>,
+}
+
+impl MeterFeed {
+    pub fn new() -> Self {
+        let (readings, _) = broadcast::channel(FEED_CAPACITY);
+        let (inputs, _) = watch::channel(Vec::new());
+        MeterFeed { readings, inputs }
+    }
+
+    pub fn subscribe(&self) -> Receiver<MeterReading> {
+        self.readings.subscribe()
+    }
+
+    /// The latest level of each mixed input
+    pub fn input_levels(&self) -> Vec<InputLevel> {
+        self.inputs.borrow().clone()
+    }
+
+    pub(crate) fn publish_input_levels(&self, levels: Vec<InputLevel>) {
+        self.inputs.send_replace(levels);
+    }
+}
+
+impl Default for MeterFeed {
+    fn default() -> Self {
+        MeterFeed::new()
+    }
+}
+
+/// Starts the thread that turns the input into meter readings. Nothing is
+/// measured while no client is subscribed to the feed.
+pub(crate) fn start_meter_thread(
+    mut meter_rx: BusReader<Vec<f32>>,
+    feed: MeterFeed
+) -> JoinHandle<&'static str> {
+    task::spawn_blocking(move || {
+        let mut meter: Option<Meter> = None;
+        // The audio bus still has to be drained, or it would block the input
+        while let Ok(input) = meter_rx.recv() {
+            if feed.readings.receiver_count() == 0 {
+                meter = None;
+                continue;
+            }
+            // Starts afresh for each client, rather than from stale peaks
+            let meter = meter.get_or_insert_with(Meter::new);
+            for reading in meter.process(&input) {
+                let _ = feed.readings.send(reading);
+            }
+        }
+
+        "✅ Exiting meter thread"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f32, amplitude: f32, secs: f32) -> Vec<f32> {
+        let frames = (secs * SAMPLE_RATE) as usize;
+        (0..frames)
+            .flat_map(|frame| {
+                let sample = amplitude * (2.0 * std::f32::consts::PI * frequency * frame as f32 / SAMPLE_RATE).sin();
+                [sample, sample * 0.1]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_levels_and_peak_hold() {
+        let mut meter = Meter::new();
+        let readings = meter.process(&sine(1_000.0, 0.5, 0.5));
+        assert_eq!(readings.len(), 10);
+
+        let reading = readings.last().unwrap();
+        // A sine's RMS sits 3 dB below its peak
+        assert!((reading.left.peak_db - -6.0).abs() < 0.1);
+        assert!((reading.left.rms_db - -9.0).abs() < 0.1);
+        assert!((reading.right.peak_db - -26.0).abs() < 0.1);
+
+        // The peak is held through a short silence, then falls
+        let silence = vec![0.0; READING_FRAMES * CHANNELS];
+        let held = meter.process(&silence.repeat(50));
+        assert_eq!(held[0].left.peak_db, MIN_DB);
+        assert!((held[25].left.peak_hold_db - -6.0).abs() < 0.1);
+        assert!((held[49].left.peak_hold_db - -26.0).abs() < 1.5);
+    }
+
+    #[test]
+    fn test_spectrum_finds_the_tone() {
+        let mut meter = Meter::new();
+        let reading = meter.process(&sine(1_000.0, 1.0, 0.2)).pop().unwrap();
+        let spectrum = reading.spectrum.unwrap();
+        assert_eq!(spectrum.len(), SPECTRUM_BANDS);
+
+        let (loudest, level) = spectrum
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        assert!(meter.bands[loudest].contains(&((1_000.0 / (SAMPLE_RATE / FFT_SIZE as f32)).round() as usize)));
+        // The right channel is a tenth as loud, so the mono mix peaks at 0.55
+        assert!((level - 20.0 * 0.55f32.log10()).abs() < 1.5);
+        assert!(spectrum[2] < -40.0);
+        assert!(spectrum[SPECTRUM_BANDS - 1] < -40.0);
+    }
+
+    #[tokio::test]
+    async fn test_feed_measures_for_subscribers() {
+        let mut audio = bus::Bus::new(8);
+        let feed = MeterFeed::new();
+        let mut readings = feed.subscribe();
+        let thread = start_meter_thread(audio.add_rx(), feed.clone());
+
+        audio.broadcast(vec![0.5; READING_FRAMES * CHANNELS]);
+        let reading = readings.recv().await.unwrap();
+        assert!((reading.left.peak_db - -6.0).abs() < 0.1);
+
+        drop(audio);
+        assert_eq!(thread.await.unwrap(), "✅ Exiting meter thread");
+    }
+
+    #[test]
+    fn test_merge_keeps_peaks() {
+        let channel = |peak_db| MeterChannel { rms_db: -20.0, peak_db, peak_hold_db: peak_db };
+        let mut reading = MeterReading { left: channel(-3.0), right: channel(-30.0), spectrum: None, inputs: Vec::new() };
+        reading.merge(MeterReading { left: channel(-12.0), right: channel(-10.0), spectrum: Some(vec![]), inputs: Vec::new() });
+        assert_eq!(reading.left.peak_db, -3.0);
+        assert_eq!(reading.left.peak_hold_db, -12.0);
+        assert_eq!(reading.right.peak_db, -10.0);
+        assert_eq!(reading.spectrum, Some(vec![]));
+    }
+}
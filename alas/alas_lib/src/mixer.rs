@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use serde::Serialize;
+
+use crate::config::AlasInputConfig;
+
+/// The capture device used when an input does not name one
+pub const DEFAULT_INPUT_DEVICE: &str = "PCM1863";
+const SAMPLE_RATE: usize = 48_000;
+/// A secondary device may run this far ahead before its oldest audio is
+/// dropped, which also absorbs the drift between the devices' clocks
+const MAX_DEVICE_BACKLOG_FRAMES: usize = SAMPLE_RATE / 10;
+/// Matches the floor of the level messages on the bus
+const MIN_DB: f32 = -60.0;
+
+/// The level of one input after its gain, for the meters
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct InputLevel {
+    pub name: String,
+    pub left_db: f32,
+    pub right_db: f32,
+    pub muted: bool,
+}
+
+/// One input's settings, worked out from its config
+#[derive(Clone, Debug)]
+struct Strip {
+    name: String,
+    /// Index into the mixer's devices
+    device: usize,
+    channels: Vec<usize>,
+    gain: f32,
+    /// How much of the input goes to the left and right of the program
+    pan: [f32; 2],
+    muted: bool,
+    /// Sum of squares since the levels were last taken, per side
+    sums: [f32; 2],
+}
+
+impl Strip {
+    fn new(config: &AlasInputConfig, device: usize) -> Self {
+        let pan = config.pan.clamp(-1.0, 1.0);
+        let pan = if config.channels.len() == 1 {
+            // Constant power, so a mono input sounds as loud wherever it sits
+            let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+            [angle.cos(), angle.sin()]
+        } else {
+            // Balance, so a centred stereo input passes through untouched
+            [(1.0 - pan).min(1.0), (1.0 + pan).min(1.0)]
+        };
+        Strip {
+            name: config.name.clone(),
+            device,
+            channels: config.channels.iter().map(|&channel| channel as usize).collect(),
+            gain: 10f32.powf(config.gain_db / 20.0),
+            pan,
+            muted: config.mute,
+            sums: [0.0; 2],
+        }
+    }
+}
+
+/// Mixes the configured inputs, from one or more capture devices, into the
+/// stereo program. Without any inputs configured the first two channels of
+/// the default device pass straight through, as they always have.
+pub struct Mixer {
+    /// Device name patterns; the first one drives the capture
+    devices: Vec<String>,
+    /// Channels to open on each device
+    device_channels: Vec<usize>,
+    strips: Vec<Strip>,
+    configured: bool,
+    level_frames: usize,
+}
+
+impl Mixer {
+    pub fn new(inputs: &[AlasInputConfig]) -> Self {
+        let configured = !inputs.is_empty();
+        let program = [AlasInputConfig {
+            name: "Program".to_string(),
+            device: None,
+            channels: vec![0, 1],
+            gain_db: 0.0,
+            pan: 0.0,
+            mute: false,
+        }];
+        let inputs = if configured { inputs } else { &program };
+
+        let mut devices: Vec<String> = Vec::new();
+        let mut device_channels: Vec<usize> = Vec::new();
+        let mut strips = Vec::new();
+        for input in inputs {
+            let name = input.device.clone().unwrap_or_else(|| DEFAULT_INPUT_DEVICE.to_string());
+            let device = match devices.iter().position(|device| *device == name) {
+                Some(device) => device,
+                None => {
+                    devices.push(name);
+                    device_channels.push(0);
+                    devices.len() - 1
+                }
+            };
+            let highest = input.channels.iter().copied().max().unwrap_or(0) as usize;
+            device_channels[device] = device_channels[device].max(highest + 1);
+            strips.push(Strip::new(input, device));
+        }
+
+        Mixer {
+            devices,
+            device_channels,
+            strips,
+            configured,
+            level_frames: 0,
+        }
+    }
+
+    /// Device name patterns to open, the one driving the capture first
+    pub fn devices(&self) -> &[String] {
+        &self.devices
+    }
+
+    pub fn device_channels(&self, device: usize) -> usize {
+        self.device_channels[device]
+    }
+
+    /// Whether `inputs` can be applied without reopening any devices
+    fn same_routing(&self, inputs: &[AlasInputConfig]) -> bool {
+        let other = Mixer::new(inputs);
+        other.devices == self.devices &&
+            other.device_channels == self.device_channels &&
+            other.strips.len() == self.strips.len() &&
+            other.strips.iter().zip(&self.strips).all(|(a, b)| a.device == b.device && a.channels == b.channels)
+    }
+
+    /// Applies new gain, pan and mute settings while the capture runs. The
+    /// routing is only read when the capture starts, so this returns false if
+    /// `inputs` needs a restart.
+    pub fn configure(&mut self, inputs: &[AlasInputConfig]) -> bool {
+        if !self.same_routing(inputs) {
+            return false;
+        }
+        for (strip, input) in self.strips.iter_mut().zip(inputs) {
+            let sums = strip.sums;
+            *strip = Strip { sums, ..Strip::new(input, strip.device) };
+        }
+        true
+    }
+
+    /// Mixes one packet. `sources` holds interleaved audio from each device,
+    /// in the order of [`Mixer::devices`], all with the same number of frames.
+    pub fn mix(&mut self, sources: &[&[f32]]) -> Vec<f32> {
+        let frames = sources[0].len() / self.device_channels[0];
+        let mut program = vec![0.0f32; frames * 2];
+
+        for strip in self.strips.iter_mut() {
+            let source = sources[strip.device];
+            let channels = self.device_channels[strip.device];
+            for frame in 0..frames.min(source.len() / channels) {
+                let sample = |channel: usize| source[frame * channels + channel] * strip.gain;
+                let (left, right) = match strip.channels.as_slice() {
+                    [mono] => (sample(*mono), sample(*mono)),
+                    [left, right, ..] => (sample(*left), sample(*right)),
+                    [] => continue,
+                };
+                strip.sums[0] += left * left;
+                strip.sums[1] += right * right;
+                if !strip.muted {
+                    program[frame * 2] += left * strip.pan[0];
+                    program[frame * 2 + 1] += right * strip.pan[1];
+                }
+            }
+        }
+        self.level_frames += frames;
+        program
+    }
+
+    /// Each input's level since the last call. Empty when no inputs are
+    /// configured, since the program meters already cover the only input.
+    pub fn take_levels(&mut self) -> Vec<InputLevel> {
+        let frames = std::mem::take(&mut self.level_frames).max(1) as f32;
+        let to_db = |sum: f32| {
+            let rms = (sum / frames).sqrt();
+            if rms > 0.0 { (20.0 * rms.log10()).max(MIN_DB) } else { MIN_DB }
+        };
+        let levels = self.strips
+            .iter_mut()
+            .map(|strip| {
+                let sums = std::mem::take(&mut strip.sums);
+                InputLevel {
+                    name: strip.name.clone(),
+                    left_db: to_db(sums[0]),
+                    right_db: to_db(sums[1]),
+                    muted: strip.muted,
+                }
+            })
+            .collect();
+        if self.configured { levels } else { Vec::new() }
+    }
+}
+
+/// Audio from a secondary capture device, waiting to be mixed in on the
+/// primary device's clock
+#[derive(Clone)]
+pub struct DeviceBuffer {
+    channels: usize,
+    samples: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl DeviceBuffer {
+    pub fn new(channels: usize) -> Self {
+        DeviceBuffer {
+            channels,
+            samples: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Called from the device's own capture callback
+    pub fn push(&self, input: &[f32]) {
+        let Ok(mut samples) = self.samples.lock() else {
+            return;
+        };
+        samples.extend(input);
+        let excess = samples.len().saturating_sub(MAX_DEVICE_BACKLOG_FRAMES * self.channels);
+        samples.drain(..excess);
+    }
+
+    /// Takes `frames` frames, padding with silence if the device has fallen behind
+    pub fn take(&self, frames: usize) -> Vec<f32> {
+        let wanted = frames * self.channels;
+        let mut output: Vec<f32> = match self.samples.lock() {
+            Ok(mut samples) => {
+                let available = wanted.min(samples.len() / self.channels * self.channels);
+                samples.drain(..available).collect()
+            }
+            Err(_) => Vec::new(),
+        };
+        output.resize(wanted, 0.0);
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(name: &str, device: Option<&str>, channels: &[u16], gain_db: f32, pan: f32) -> AlasInputConfig {
+        AlasInputConfig {
+            name: name.to_string(),
+            device: device.map(str::to_string),
+            channels: channels.to_vec(),
+            gain_db,
+            pan,
+            mute: false,
+        }
+    }
+
+    #[test]
+    fn test_passthrough_without_inputs() {
+        let mut mixer = Mixer::new(&[]);
+        assert_eq!(mixer.devices(), &[DEFAULT_INPUT_DEVICE.to_string()]);
+        assert_eq!(mixer.device_channels(0), 2);
+
+        let packet = [0.1, -0.2, 0.3, -0.4];
+        assert_eq!(mixer.mix(&[&packet]), packet.to_vec());
+        assert!(mixer.take_levels().is_empty());
+    }
+
+    #[test]
+    fn test_mixes_mic_and_line() {
+        let inputs = [
+            // A mic on the third channel, panned hard left and 6 dB down
+            input("Mic", None, &[2], -6.0206, -1.0),
+            input("Line", Some("USB"), &[0, 1], 0.0, 0.0),
+        ];
+        let mut mixer = Mixer::new(&inputs);
+        assert_eq!(mixer.devices(), &[DEFAULT_INPUT_DEVICE.to_string(), "USB".to_string()]);
+        assert_eq!(mixer.device_channels(0), 3);
+        assert_eq!(mixer.device_channels(1), 2);
+
+        let onboard = [0.9, 0.9, 0.5, 0.9, 0.9, 0.5];
+        let usb = [0.1, 0.2, 0.1, 0.2];
+        let program = mixer.mix(&[&onboard, &usb]);
+        assert!((program[0] - 0.35).abs() < 1e-4);
+        assert!((program[1] - 0.2).abs() < 1e-4);
+
+        let levels = mixer.take_levels();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[0].name, "Mic");
+        assert!((levels[0].left_db - -12.04).abs() < 0.05);
+        assert!((levels[1].right_db - -13.98).abs() < 0.05);
+
+        // Muting takes the mic out of the program, but it is still metered
+        let mut muted = inputs.clone();
+        muted[0].mute = true;
+        assert!(mixer.configure(&muted));
+        let program = mixer.mix(&[&onboard, &usb]);
+        assert!((program[0] - 0.1).abs() < 1e-4);
+        let levels = mixer.take_levels();
+        assert!(levels[0].muted && levels[0].left_db > -13.0);
+
+        // Moving the mic to another channel has to wait for a restart
+        let mut moved = inputs.clone();
+        moved[0].channels = vec![3];
+        assert!(!mixer.configure(&moved));
+    }
+
+    #[test]
+    fn test_device_buffer_pads_and_drops() {
+        let buffer = DeviceBuffer::new(2);
+        buffer.push(&[0.5; 4]);
+        assert_eq!(buffer.take(3), vec![0.5, 0.5, 0.5, 0.5, 0.0, 0.0]);
+
+        // A device running ahead loses its oldest audio
+        buffer.push(&vec![0.1; MAX_DEVICE_BACKLOG_FRAMES * 2]);
+        buffer.push(&[0.7; 2]);
+        let taken = buffer.take(MAX_DEVICE_BACKLOG_FRAMES);
+        assert_eq!(taken.len(), MAX_DEVICE_BACKLOG_FRAMES * 2);
+        assert_eq!(taken[taken.len() - 1], 0.7);
+    }
+}
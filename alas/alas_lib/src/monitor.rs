@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, RwLock as StdRwLock};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, Stream, StreamConfig};
+use tokio::sync::broadcast::Sender;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task;
+use tokio::task::JoinHandle;
+
+use crate::config::{AlasMonitorConfig, AlasMonitorSource};
+use crate::state::{AlasMessage, SafeState};
+
+/// At most a quarter of a second of stereo audio is queued for the monitor, so
+/// that what you hear stays close to what is happening.
+const MAX_BUFFERED_SAMPLES: usize = 48_000 / 4 * 2;
+
+struct MonitorShared {
+    /// The source and volume currently being monitored, or `None` when off
+    settings: StdRwLock<Option<(AlasMonitorSource, f32)>>,
+    buffer: Mutex<VecDeque<f32>>,
+}
+
+/// A handle for feeding audio to the confidence monitor. Every tap point pushes
+/// its audio here, and only the one matching the configured source is played.
+#[derive(Clone)]
+pub struct MonitorHandle {
+    shared: Arc<MonitorShared>,
+}
+
+impl MonitorHandle {
+    pub fn new() -> Self {
+        MonitorHandle {
+            shared: Arc::new(MonitorShared {
+                settings: StdRwLock::new(None),
+                buffer: Mutex::new(VecDeque::with_capacity(MAX_BUFFERED_SAMPLES)),
+            }),
+        }
+    }
+
+    fn configure(&self, config: Option<&AlasMonitorConfig>) {
+        let settings = config.map(|config| (config.source, config.volume.clamp(0.0, 1.0)));
+        *self.shared.settings.write().unwrap() = settings;
+        self.shared.buffer.lock().unwrap().clear();
+    }
+
+    /// Queues interleaved stereo samples from `source`. This never blocks, so it
+    /// is safe to call from the audio callback; if the monitor is busy the
+    /// samples are simply dropped.
+    pub fn push(&self, source: AlasMonitorSource, samples: &[f32]) {
+        let Ok(settings) = self.shared.settings.try_read() else {
+            return;
+        };
+        if !settings.is_some_and(|(monitored, _)| monitored == source) {
+            return;
+        }
+        if let Ok(mut buffer) = self.shared.buffer.try_lock() {
+            buffer.extend(samples);
+            let excess = buffer.len().saturating_sub(MAX_BUFFERED_SAMPLES);
+            buffer.drain(..excess);
+        }
+    }
+
+    /// Fills an output buffer with queued audio at the configured volume,
+    /// padding with silence if we have run dry.
+    fn fill_output(&self, output: &mut [f32]) {
+        let volume = self.shared.settings.read().unwrap().map(|(_, volume)| volume).unwrap_or(0.0);
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        for sample in output.iter_mut() {
+            *sample = buffer.pop_front().unwrap_or(0.0) * volume;
+        }
+    }
+}
+
+impl Default for MonitorHandle {
+    fn default() -> Self {
+        MonitorHandle::new()
+    }
+}
+
+fn build_output_stream(config: &AlasMonitorConfig, monitor: &MonitorHandle) -> Option<Stream> {
+    let host = cpal::default_host();
+    let device = host.output_devices()
+        .ok()?
+        .find(|device| device.name().is_ok_and(|name| name.contains(&config.device)));
+    let Some(device) = device else {
+        eprintln!("🎧 No output device found containing '{}'", config.device);
+        return None;
+    };
+
+    let stream_config = StreamConfig {
+        channels: 2,
+        sample_rate: cpal::SampleRate(48_000),
+        buffer_size: BufferSize::Default,
+    };
+    let callback_monitor = monitor.clone();
+    let stream = device.build_output_stream(
+        &stream_config,
+        move |data: &mut [f32], _: &_| callback_monitor.fill_output(data),
+        |err| eprintln!("🎧 An error occurred on the monitor stream: {}", err),
+        None
+    );
+
+    match stream {
+        Ok(stream) => match stream.play() {
+            Ok(_) => Some(stream),
+            Err(e) => {
+                eprintln!("🎧 Could not start the monitor output: {}", e);
+                None
+            }
+        },
+        Err(e) => {
+            eprintln!("🎧 Could not open the monitor output: {}", e);
+            None
+        }
+    }
+}
+
+/// Starts the confidence monitor, which plays one of the tap points (the raw
+/// input, the processed audio sent to the encoders, or the off-air return) to a
+/// local output device such as headphones. The output is rebuilt whenever
+/// `MonitorConfigUpdated` is announced.
+pub fn start_monitor(bus: Sender<AlasMessage>, state: &SafeState, monitor: &MonitorHandle) -> JoinHandle<&'static str> {
+    let state = state.clone();
+    let monitor = monitor.clone();
+    let mut receiver = bus.subscribe();
+
+    task::spawn_blocking(move || {
+        loop {
+            let config = state.blocking_read().config.monitor.clone();
+            monitor.configure(config.as_ref());
+
+            // cpal streams cannot move between threads, so this one lives here
+            // until the configuration changes.
+            let _stream = config.as_ref().and_then(|config| {
+                println!("🎧 Monitoring {:?} on '{}'", config.source, config.device);
+                build_output_stream(config, &monitor)
+            });
+
+            loop {
+                match receiver.blocking_recv() {
+                    Ok(AlasMessage::MonitorConfigUpdated) => break,
+                    Ok(AlasMessage::Exit) | Err(RecvError::Closed) => {
+                        monitor.configure(None);
+                        return "✅ Exiting monitor thread";
+                    }
+                    _ => {}
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(source: AlasMonitorSource, volume: f32) -> AlasMonitorConfig {
+        AlasMonitorConfig {
+            device: "Headphones".to_string(),
+            source,
+            volume,
+        }
+    }
+
+    #[test]
+    fn test_only_monitored_source_is_played() {
+        let monitor = MonitorHandle::new();
+        monitor.configure(Some(&config(AlasMonitorSource::Processed, 0.5)));
+
+        monitor.push(AlasMonitorSource::Input, &[1.0, 1.0]);
+        monitor.push(AlasMonitorSource::Processed, &[0.8, -0.8]);
+
+        let mut output = [1.0; 4];
+        monitor.fill_output(&mut output);
+        assert_eq!(output, [0.4, -0.4, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_buffer_is_bounded() {
+        let monitor = MonitorHandle::new();
+        monitor.configure(Some(&config(AlasMonitorSource::Input, 1.0)));
+
+        let samples: Vec<f32> = (0..MAX_BUFFERED_SAMPLES + 10).map(|i| i as f32).collect();
+        monitor.push(AlasMonitorSource::Input, &samples);
+
+        // The oldest samples are dropped to keep the monitor close to real time
+        let mut output = [0.0; 1];
+        monitor.fill_output(&mut output);
+        assert_eq!(output[0], 10.0);
+    }
+
+    #[test]
+    fn test_switched_off() {
+        let monitor = MonitorHandle::new();
+        monitor.push(AlasMonitorSource::Processed, &[1.0, 1.0]);
+
+        let mut output = [1.0; 2];
+        monitor.fill_output(&mut output);
+        assert_eq!(output, [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,351 @@
+use chrono::Local;
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+
+use crate::catalog::{RecordingEntry, RecordingKind, RecordingUploadStatus, SafeCatalog};
+use crate::config::AlasPodcastConfig;
+use crate::dropbox::{create_shared_link, upload_bytes_to_dropbox};
+use crate::schedule::show_folder;
+use crate::state::{AlasMessage, SafeState};
+
+/// Published in the same folder as the show's recordings
+pub const FEED_FILE_NAME: &str = "feed.xml";
+/// Stands in for {show} when a recording was not part of a scheduled show
+const UNSCHEDULED_SHOW_NAME: &str = "Recordings";
+
+#[derive(Error, Debug)]
+pub enum PodcastError {
+    #[error("Dropbox: {0}")]
+    Dropbox(String),
+}
+
+/// One item in the feed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Episode {
+    pub guid: String,
+    pub title: String,
+    pub description: String,
+    /// RFC 2822, as RSS wants it
+    pub pub_date: String,
+    pub url: String,
+    pub size_bytes: u64,
+    pub duration_secs: f64,
+}
+
+/// Turns a Dropbox shared link into one that downloads the file itself rather
+/// than showing Dropbox's preview page, which podcast apps cannot play
+pub fn direct_download_url(url: &str) -> String {
+    if url.contains("dl=0") {
+        url.replacen("dl=0", "dl=1", 1)
+    } else if url.contains("dl=1") {
+        url.to_string()
+    } else if url.contains('?') {
+        format!("{}&dl=1", url)
+    } else {
+        format!("{}?dl=1", url)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Fills in {show}, {date} and {time}. The date and time are left as they
+/// are in the channel's fields, which have no recording to take them from.
+fn fill_template(template: &str, show_name: Option<&str>, recording: Option<&RecordingEntry>) -> String {
+    let text = template.replace("{show}", show_name.unwrap_or(UNSCHEDULED_SHOW_NAME));
+    match recording {
+        Some(recording) => {
+            let started_at = recording.started_at.with_timezone(&Local);
+            text.replace("{date}", &started_at.format("%Y-%m-%d").to_string())
+                .replace("{time}", &started_at.format("%H:%M").to_string())
+        }
+        None => text,
+    }
+}
+
+fn format_duration(duration_secs: f64) -> String {
+    let secs = duration_secs.round() as u64;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// The show's finished recordings that have a public link, newest first
+pub fn episodes(recordings: &[RecordingEntry], show_name: Option<&str>, config: &AlasPodcastConfig) -> Vec<Episode> {
+    let mut recordings: Vec<&RecordingEntry> = recordings
+        .iter()
+        .filter(|entry| entry.kind == RecordingKind::Show && entry.show_name.as_deref() == show_name)
+        .filter(|entry| entry.ended_at.is_some())
+        // Encrypted recordings are only for the station's ears
+        .filter(|entry| !entry.encrypted)
+        .collect();
+    recordings.sort_by_key(|entry| std::cmp::Reverse(entry.started_at));
+
+    recordings
+        .into_iter()
+        .filter_map(|entry| {
+            let (url, size_bytes) = match &config.derivative {
+                Some(name) => entry.derivatives
+                    .iter()
+                    .find(|derivative| &derivative.name == name)
+                    .and_then(|derivative| Some((derivative.shared_url.clone()?, derivative.size_bytes)))?,
+                None => (entry.shared_url.clone()?, entry.size_bytes),
+            };
+            Some(Episode {
+                guid: entry.id.clone(),
+                title: fill_template(&config.episode_title, show_name, Some(entry)),
+                description: fill_template(&config.episode_description, show_name, Some(entry)),
+                pub_date: entry.started_at.to_rfc2822(),
+                url,
+                size_bytes,
+                duration_secs: entry.duration_secs,
+            })
+        })
+        .take(config.max_episodes)
+        .collect()
+}
+
+/// Renders an RSS 2.0 feed with the iTunes tags that Apple Podcasts and most
+/// other apps look for
+pub fn build_feed(config: &AlasPodcastConfig, show_name: Option<&str>, episodes: &[Episode]) -> String {
+    let text = |template: &str| escape_xml(&fill_template(template, show_name, None));
+    let mut feed = String::new();
+    feed.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    feed.push_str("<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\n");
+    feed.push_str("  <channel>\n");
+    feed.push_str(&format!("    <title>{}</title>\n", text(&config.title)));
+    feed.push_str(&format!("    <description>{}</description>\n", text(&config.description)));
+    feed.push_str(&format!("    <language>{}</language>\n", escape_xml(&config.language)));
+    if let Some(link) = &config.link {
+        feed.push_str(&format!("    <link>{}</link>\n", escape_xml(link)));
+    }
+    if let Some(author) = &config.author {
+        feed.push_str(&format!("    <itunes:author>{}</itunes:author>\n", text(author)));
+    }
+    if let Some(image_url) = &config.image_url {
+        feed.push_str(&format!("    <itunes:image href=\"{}\"/>\n", escape_xml(image_url)));
+    }
+    if let Some(category) = &config.category {
+        feed.push_str(&format!("    <itunes:category text=\"{}\"/>\n", escape_xml(category)));
+    }
+    feed.push_str(&format!("    <itunes:explicit>{}</itunes:explicit>\n", config.explicit));
+
+    for episode in episodes {
+        feed.push_str("    <item>\n");
+        feed.push_str(&format!("      <title>{}</title>\n", escape_xml(&episode.title)));
+        feed.push_str(&format!("      <description>{}</description>\n", escape_xml(&episode.description)));
+        feed.push_str(&format!("      <guid isPermaLink=\"false\">{}</guid>\n", escape_xml(&episode.guid)));
+        feed.push_str(&format!("      <pubDate>{}</pubDate>\n", episode.pub_date));
+        feed.push_str(&format!(
+            "      <enclosure url=\"{}\" length=\"{}\" type=\"audio/mpeg\"/>\n",
+            escape_xml(&episode.url),
+            episode.size_bytes
+        ));
+        feed.push_str(&format!("      <itunes:duration>{}</itunes:duration>\n", format_duration(episode.duration_secs)));
+        feed.push_str("    </item>\n");
+    }
+
+    feed.push_str("  </channel>\n");
+    feed.push_str("</rss>\n");
+    feed
+}
+
+/// Which recording an uploaded file is the podcast episode of, if any, along
+/// with the episode's file name
+fn find_episode(recordings: &[RecordingEntry], file_path: &str, config: &AlasPodcastConfig) -> Option<(String, Option<String>, String)> {
+    recordings
+        .iter()
+        .filter(|entry| entry.kind == RecordingKind::Show && !entry.encrypted)
+        .find_map(|entry| {
+            let file_name = match &config.derivative {
+                Some(name) => entry.derivatives
+                    .iter()
+                    .find(|derivative| &derivative.name == name && derivative.path == file_path)?
+                    .file_name
+                    .clone(),
+                None if entry.path == file_path => entry.file_name.clone(),
+                None => return None,
+            };
+            Some((entry.id.clone(), entry.show_name.clone(), file_name))
+        })
+}
+
+/// Shares a newly uploaded episode and publishes its show's feed again
+async fn publish_episode(config: &AlasPodcastConfig, catalog: &SafeCatalog, file_path: &str) -> Result<(), PodcastError> {
+    let recordings = catalog.read().await.list(&Default::default());
+    let Some((id, show_name, file_name)) = find_episode(&recordings, file_path, config) else {
+        return Ok(());
+    };
+    let folder = show_folder(show_name.as_deref());
+
+    let url = create_shared_link(&format!("{}/{}", folder, file_name))
+        .await
+        .map_err(PodcastError::Dropbox)?;
+    let url = direct_download_url(&url);
+    let recordings = {
+        let mut catalog = catalog.write().await;
+        catalog.update(&id, |entry| match &config.derivative {
+            Some(name) => entry.derivatives
+                .iter_mut()
+                .filter(|derivative| &derivative.name == name)
+                .for_each(|derivative| derivative.shared_url = Some(url.clone())),
+            None => entry.shared_url = Some(url.clone()),
+        });
+        catalog.list(&Default::default())
+    };
+
+    let episodes = episodes(&recordings, show_name.as_deref(), config);
+    let feed = build_feed(config, show_name.as_deref(), &episodes);
+    let feed_path = format!("{}/{}", folder, FEED_FILE_NAME);
+    upload_bytes_to_dropbox(feed_path.clone(), feed.into_bytes())
+        .await
+        .map_err(PodcastError::Dropbox)?;
+    println!("📻 Published {} with {} episode(s)", feed_path, episodes.len());
+    Ok(())
+}
+
+/// Starts a task that keeps a podcast feed for each show in Dropbox.
+///
+/// When a show recording (or the configured derivative of one) finishes
+/// uploading, it is shared, and the feed in the show's folder is rebuilt from
+/// the catalog and uploaded over the old one. Nothing happens without a
+/// podcast config.
+pub fn start_podcast_publisher(bus: Sender<AlasMessage>, state: &SafeState, catalog: &SafeCatalog) -> JoinHandle<()> {
+    let state = state.clone();
+    let catalog = catalog.clone();
+    let mut receiver = bus.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(AlasMessage::RecordingUploadChange { file_path, status: RecordingUploadStatus::Uploaded }) => {
+                    let Some(config) = state.read().await.config.podcast.clone() else {
+                        continue;
+                    };
+                    if let Err(e) = publish_episode(&config, &catalog, &file_path).await {
+                        eprintln!("📻 Could not publish {} to the podcast feed: {}", file_path, e);
+                    }
+                }
+                Ok(AlasMessage::Exit) | Err(RecvError::Closed) => break,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+    use crate::catalog::RecordingDerivative;
+
+    fn config() -> AlasPodcastConfig {
+        serde_json::from_value(serde_json::json!({
+            "title": "{show} on ALAS",
+            "description": "Every {show}, as broadcast",
+            "author": "Radio & Friends",
+        })).unwrap()
+    }
+
+    fn recording(id: &str, show_name: Option<&str>, days_ago: i64, shared_url: Option<&str>) -> RecordingEntry {
+        let started_at = Utc.with_ymd_and_hms(2024, 6, 10, 12, 0, 0).unwrap() - Duration::days(days_ago);
+        RecordingEntry {
+            id: id.to_string(),
+            path: format!("/var/lib/alas/{}.mp3", id),
+            file_name: format!("{}.mp3", id),
+            kind: RecordingKind::Show,
+            show_name: show_name.map(str::to_string),
+            started_at,
+            ended_at: Some(started_at + Duration::hours(1)),
+            duration_secs: 3_725.4,
+            size_bytes: 144_000_000,
+            peak_db: -1.0,
+            loudness_db: -18.0,
+            codec: "mp3".to_string(),
+            upload_status: RecordingUploadStatus::Uploaded,
+            local: false,
+            markers: vec![],
+            derivatives: vec![],
+            shared_url: shared_url.map(str::to_string),
+            encrypted: false,
+        }
+    }
+
+    #[test]
+    fn test_episodes_of_a_show() {
+        let mut podcast = recording("c", Some("Morning Show"), 0, None);
+        podcast.derivatives.push(RecordingDerivative {
+            name: "podcast".to_string(),
+            path: "/var/lib/alas/c-podcast.mp3".to_string(),
+            file_name: "c-podcast.mp3".to_string(),
+            size_bytes: 29_000_000,
+            bitrate: 64,
+            mono: true,
+            loudness_lufs: Some(-16.0),
+            shared_url: Some("https://dl.example/c-podcast.mp3".to_string()),
+            encrypted: false,
+        });
+        let recordings = vec![
+            recording("a", Some("Morning Show"), 2, Some("https://dl.example/a.mp3")),
+            recording("b", Some("Morning Show"), 1, None),
+            recording("x", Some("Late Show"), 1, Some("https://dl.example/x.mp3")),
+            podcast,
+        ];
+
+        // Only recordings of the show that have been shared, newest first
+        let config = config();
+        let found = episodes(&recordings, Some("Morning Show"), &config);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].guid, "a");
+        assert_eq!(found[0].title, "Morning Show, 2024-06-08");
+
+        // With a derivative configured, it is the derivative that is published
+        let config = AlasPodcastConfig { derivative: Some("podcast".to_string()), ..config };
+        let found = episodes(&recordings, Some("Morning Show"), &config);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].guid, "c");
+        assert_eq!(found[0].size_bytes, 29_000_000);
+        assert_eq!(
+            find_episode(&recordings, "/var/lib/alas/c-podcast.mp3", &config),
+            Some(("c".to_string(), Some("Morning Show".to_string()), "c-podcast.mp3".to_string()))
+        );
+        assert_eq!(find_episode(&recordings, "/var/lib/alas/c.mp3", &config), None);
+
+        // Encrypted recordings are never published
+        let recordings: Vec<RecordingEntry> = recordings
+            .into_iter()
+            .map(|entry| RecordingEntry { encrypted: true, ..entry })
+            .collect();
+        assert!(episodes(&recordings, Some("Morning Show"), &config).is_empty());
+        assert_eq!(find_episode(&recordings, "/var/lib/alas/c-podcast.mp3", &config), None);
+    }
+
+    #[test]
+    fn test_build_feed() {
+        let config = config();
+        let recordings = vec![recording("a", Some("Morning Show"), 0, Some("https://dl.example/a.mp3?x=1&dl=1"))];
+        let feed = build_feed(&config, Some("Morning Show"), &episodes(&recordings, Some("Morning Show"), &config));
+
+        assert!(feed.contains("<title>Morning Show on ALAS</title>"));
+        assert!(feed.contains("<itunes:author>Radio &amp; Friends</itunes:author>"));
+        assert!(feed.contains("<enclosure url=\"https://dl.example/a.mp3?x=1&amp;dl=1\" length=\"144000000\" type=\"audio/mpeg\"/>"));
+        assert!(feed.contains("<itunes:duration>01:02:05</itunes:duration>"));
+        assert!(feed.contains("<pubDate>Mon, 10 Jun 2024 12:00:00 +0000</pubDate>"));
+        assert!(feed.ends_with("</rss>\n"));
+    }
+
+    #[test]
+    fn test_direct_download_url() {
+        assert_eq!(
+            direct_download_url("https://www.dropbox.com/scl/fi/abc/show.mp3?rlkey=xyz&dl=0"),
+            "https://www.dropbox.com/scl/fi/abc/show.mp3?rlkey=xyz&dl=1"
+        );
+        assert_eq!(direct_download_url("https://www.dropbox.com/s/abc/show.mp3"), "https://www.dropbox.com/s/abc/show.mp3?dl=1");
+        assert_eq!(direct_download_url("https://example.com/a.mp3?dl=1"), "https://example.com/a.mp3?dl=1");
+    }
+}
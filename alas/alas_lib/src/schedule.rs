@@ -0,0 +1,306 @@
+use std::time::Duration;
+use chrono::{DateTime, Datelike, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+
+use crate::config::{AlasActivationMode, AlasScheduleConfig, AlasScheduleEntry, ScheduleError};
+use crate::state::{AlasMessage, SafeState};
+
+const ALL_DAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// What the schedule wants the sinks to do right now
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ScheduleDecision {
+    pub stream: AlasActivationMode,
+    pub record: AlasActivationMode,
+    pub show_name: Option<String>,
+}
+
+pub(crate) fn parse_timezone(timezone: &str) -> Result<Tz, ScheduleError> {
+    timezone.parse::<Tz>().map_err(|_| ScheduleError::InvalidTimezone(timezone.to_string()))
+}
+
+pub(crate) fn parse_time(time: &str) -> Result<NaiveTime, ScheduleError> {
+    NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| ScheduleError::InvalidTime(time.to_string()))
+}
+
+fn parse_day(day: &str) -> Result<Weekday, ScheduleError> {
+    day.trim().parse::<Weekday>().map_err(|_| ScheduleError::InvalidDay(day.to_string()))
+}
+
+/// Parses cron-like day lists such as `["mon-fri", "sun"]`. Ranges may wrap
+/// around the end of the week (`"fri-mon"`). An empty list means every day.
+pub(crate) fn parse_days(days: &[String]) -> Result<Vec<Weekday>, ScheduleError> {
+    if days.is_empty() {
+        return Ok(ALL_DAYS.to_vec());
+    }
+
+    let mut result = Vec::new();
+    for day in days {
+        if day.trim() == "*" {
+            return Ok(ALL_DAYS.to_vec());
+        }
+        match day.split_once('-') {
+            Some((first, last)) => {
+                let mut current = parse_day(first)?;
+                let last = parse_day(last)?;
+                result.push(current);
+                while current != last {
+                    current = current.succ();
+                    result.push(current);
+                }
+            }
+            None => result.push(parse_day(day)?),
+        }
+    }
+    Ok(result)
+}
+
+/// Returns true if the entry covers the given local day and time
+fn entry_matches(entry: &AlasScheduleEntry, weekday: Weekday, time: NaiveTime) -> bool {
+    let (Ok(start), Ok(end), Ok(days)) = (
+        parse_time(&entry.start),
+        parse_time(&entry.end),
+        parse_days(&entry.days),
+    ) else {
+        return false;
+    };
+
+    if start < end {
+        days.contains(&weekday) && time >= start && time < end
+    } else {
+        // The window runs past midnight, so the early morning part belongs to
+        // the previous day's entry.
+        (days.contains(&weekday) && time >= start) ||
+            (days.contains(&weekday.pred()) && time < end)
+    }
+}
+
+fn evaluate_local<T: TimeZone>(schedule: &AlasScheduleConfig, now: DateTime<T>) -> ScheduleDecision {
+    let weekday = now.weekday();
+    let time = now.time();
+
+    schedule.entries
+        .iter()
+        .find(|entry| entry_matches(entry, weekday, time))
+        .map(|entry| ScheduleDecision {
+            stream: entry.stream,
+            record: entry.record,
+            show_name: entry.show_name.clone(),
+        })
+        .unwrap_or_default()
+}
+
+/// Works out which schedule entry applies at `now`. The first matching entry wins;
+/// if nothing matches, both sinks are left to the silence detector. A timezone
+/// that cannot be understood is an error rather than a guess.
+pub fn evaluate(schedule: &AlasScheduleConfig, now: DateTime<Utc>) -> Result<ScheduleDecision, ScheduleError> {
+    Ok(match schedule.timezone.as_deref().map(parse_timezone).transpose()? {
+        Some(timezone) => evaluate_local(schedule, now.with_timezone(&timezone)),
+        None => evaluate_local(schedule, now.with_timezone(&chrono::Local)),
+    })
+}
+
+/// Turns a show name into something that is safe to use in file and folder names
+pub fn sanitize_show_name(show_name: &str) -> String {
+    show_name
+        .trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect()
+}
+
+/// The upload folder for a show's recordings, or the root for unscheduled ones
+pub fn show_folder(show_name: Option<&str>) -> String {
+    show_name
+        .map(|show_name| format!("/{}", sanitize_show_name(show_name)))
+        .unwrap_or_default()
+}
+
+/// Starts a task that re-evaluates the schedule every second and publishes the
+/// result to `AlasState`, announcing any change on the bus. A schedule that
+/// cannot be evaluated is ignored, and the problem is logged once.
+pub fn start_schedule_watcher(bus: Sender<AlasMessage>, state: &SafeState) -> JoinHandle<()> {
+    let state = state.clone();
+    let mut exit_bus = bus.subscribe();
+
+    tokio::spawn(async move {
+        let mut ticker = time::interval(Duration::from_secs(1));
+        let mut last_error: Option<String> = None;
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    let (schedule, current) = {
+                        let read_state = state.read().await;
+                        (read_state.config.schedule.clone(), read_state.schedule.clone())
+                    };
+                    let decision = match schedule.map(|schedule| evaluate(&schedule, Utc::now())).transpose() {
+                        Ok(decision) => {
+                            if last_error.take().is_some() {
+                                println!("🗓️ The schedule can be followed again");
+                            }
+                            decision.unwrap_or_default()
+                        }
+                        Err(e) => {
+                            if last_error.as_ref() != Some(&e.to_string()) {
+                                eprintln!("🗓️ Ignoring the schedule: {}", e);
+                                last_error = Some(e.to_string());
+                            }
+                            ScheduleDecision::default()
+                        }
+                    };
+
+                    if current != decision {
+                        println!("🗓️ Schedule changed: {:?}", decision);
+                        {
+                            let mut state = state.write().await;
+                            state.schedule = decision.clone();
+                            // A new show starts from the schedule, not from the last tone command
+                            state.record_override = None;
+                        }
+                        let _ = bus.send(AlasMessage::ScheduleChanged { decision });
+                    }
+                }
+                message = exit_bus.recv() => {
+                    match message {
+                        Ok(AlasMessage::Exit) | Err(RecvError::Closed) => {
+                            println!("✅ Exiting schedule watcher!");
+                            return;
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(days: &[&str], start: &str, end: &str, show_name: &str) -> AlasScheduleEntry {
+        AlasScheduleEntry {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+            stream: AlasActivationMode::ForceOn,
+            record: AlasActivationMode::Auto,
+            show_name: Some(show_name.to_string()),
+        }
+    }
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parse_days() {
+        assert_eq!(parse_days(&[]).unwrap().len(), 7);
+        assert_eq!(
+            parse_days(&["mon-wed".to_string()]).unwrap(),
+            vec![Weekday::Mon, Weekday::Tue, Weekday::Wed]
+        );
+        assert_eq!(
+            parse_days(&["sat-mon".to_string()]).unwrap(),
+            vec![Weekday::Sat, Weekday::Sun, Weekday::Mon]
+        );
+        assert!(parse_days(&["someday".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_weekly_entry() {
+        let schedule = AlasScheduleConfig {
+            timezone: Some("UTC".to_string()),
+            entries: vec![entry(&["tue"], "19:00", "21:00", "Jazz Night")],
+        };
+
+        // 2025-01-07 is a Tuesday
+        let during = evaluate(&schedule, at("2025-01-07T20:00:00Z")).unwrap();
+        assert_eq!(during.stream, AlasActivationMode::ForceOn);
+        assert_eq!(during.record, AlasActivationMode::Auto);
+        assert_eq!(during.show_name.as_deref(), Some("Jazz Night"));
+
+        assert_eq!(evaluate(&schedule, at("2025-01-07T21:00:00Z")).unwrap(), ScheduleDecision::default());
+        assert_eq!(evaluate(&schedule, at("2025-01-08T20:00:00Z")).unwrap(), ScheduleDecision::default());
+    }
+
+    #[test]
+    fn test_overnight_entry() {
+        let mut quiet = entry(&["fri"], "23:00", "06:00", "Overnight");
+        quiet.stream = AlasActivationMode::ForceOff;
+        quiet.record = AlasActivationMode::ForceOff;
+        let schedule = AlasScheduleConfig {
+            timezone: Some("UTC".to_string()),
+            entries: vec![quiet],
+        };
+
+        // Friday night and the following Saturday morning are both covered
+        assert_eq!(evaluate(&schedule, at("2025-01-10T23:30:00Z")).unwrap().record, AlasActivationMode::ForceOff);
+        assert_eq!(evaluate(&schedule, at("2025-01-11T05:59:00Z")).unwrap().record, AlasActivationMode::ForceOff);
+        assert_eq!(evaluate(&schedule, at("2025-01-11T06:00:00Z")).unwrap().record, AlasActivationMode::Auto);
+        // Thursday night is not
+        assert_eq!(evaluate(&schedule, at("2025-01-09T23:30:00Z")).unwrap().record, AlasActivationMode::Auto);
+    }
+
+    #[test]
+    fn test_timezone() {
+        let schedule = AlasScheduleConfig {
+            timezone: Some("America/New_York".to_string()),
+            entries: vec![entry(&["mon"], "08:00", "09:00", "Morning")],
+        };
+
+        // 13:30 UTC is 08:30 in New York during winter
+        assert_eq!(evaluate(&schedule, at("2025-01-06T13:30:00Z")).unwrap().show_name.as_deref(), Some("Morning"));
+        assert_eq!(evaluate(&schedule, at("2025-01-06T08:30:00Z")).unwrap().show_name, None);
+    }
+
+    #[test]
+    fn test_first_entry_wins() {
+        let schedule = AlasScheduleConfig {
+            timezone: Some("UTC".to_string()),
+            entries: vec![
+                entry(&[], "10:00", "11:00", "First"),
+                entry(&[], "10:30", "12:00", "Second"),
+            ],
+        };
+
+        assert_eq!(evaluate(&schedule, at("2025-01-06T10:45:00Z")).unwrap().show_name.as_deref(), Some("First"));
+        assert_eq!(evaluate(&schedule, at("2025-01-06T11:15:00Z")).unwrap().show_name.as_deref(), Some("Second"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut schedule = AlasScheduleConfig {
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+            entries: vec![],
+        };
+        assert!(matches!(schedule.validate(), Err(ScheduleError::InvalidTimezone(_))));
+        // Evaluating it does not quietly fall back to another timezone either
+        assert!(matches!(evaluate(&schedule, at("2025-01-06T10:45:00Z")), Err(ScheduleError::InvalidTimezone(_))));
+
+        schedule.timezone = None;
+        schedule.entries = vec![entry(&["mon"], "25:00", "09:00", "Bad")];
+        assert!(matches!(schedule.validate(), Err(ScheduleError::InvalidTime(_))));
+
+        schedule.entries = vec![entry(&["mon"], "08:00", "09:00", "  ")];
+        assert!(matches!(schedule.validate(), Err(ScheduleError::BlankShowName)));
+    }
+
+    #[test]
+    fn test_sanitize_show_name() {
+        assert_eq!(sanitize_show_name(" Jazz Night / Live "), "Jazz-Night---Live");
+    }
+}
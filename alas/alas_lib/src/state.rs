@@ -1,15 +1,30 @@
 use crate::config::{
     load_config,
     save_config,
+    AlasActivationMode,
     AlasAudioConfig,
     AlasCellularConfig,
     AlasConfig,
     AlasIcecastConfig,
+    AlasToneAction,
     AlasWiFiConfig,
 };
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use crate::wifi::AlasWiFiState;
+use crate::schedule::ScheduleDecision;
+use crate::catalog::RecordingUploadStatus;
+use crate::markers::RecordingMarker;
+use crate::verifier::OffAirStatus;
+use crate::listeners::ListenerStats;
+use crate::stream_stats::StreamStats;
+use crate::icecast::IcecastConnectionState;
+use crate::bitrate::BitrateChangeReason;
+use crate::delay::DelayStatus;
+use crate::calibration::CalibrationStatus;
+use crate::mixer::InputLevel;
+use crate::ifb::IfbStatus;
+use crate::transcode::TranscodeJob;
 
 #[derive(Clone)]
 pub struct AlasState {
@@ -19,9 +34,36 @@ pub struct AlasState {
     pub is_streaming: bool,
     pub is_recording: bool,
     pub is_audio_present: bool,
+    /// The stream is playing the fallback audio because the input went quiet
+    pub is_on_fallback: bool,
     pub audio_last_seen: u64,
     pub config: AlasConfig,
     pub upload_state: AlasUploadState,
+    pub schedule: ScheduleDecision,
+    /// Free space on the recording volume, once it has been checked
+    pub disk_free_mb: Option<u64>,
+    /// Recording is paused because the recording volume is nearly full
+    pub disk_low: bool,
+    /// How our stream sounds to a listener, according to the off-air verifier
+    pub off_air: OffAirStatus,
+    /// Listener numbers from the Icecast server
+    pub listeners: ListenerStats,
+    /// Counters for the current and recent stream sessions
+    pub stream: StreamStats,
+    /// Where the stream's connection to Icecast is at
+    pub stream_connection: IcecastConnectionState,
+    /// The profanity delay on the stream, when one is configured
+    pub stream_delay: Option<DelayStatus>,
+    /// Recording forced on or off by a tone command, until the schedule next changes
+    pub record_override: Option<AlasActivationMode>,
+    /// Where the silence-threshold calibration is at
+    pub calibration: CalibrationStatus,
+    /// The latest level of each mixed input, when inputs are configured
+    pub input_levels: Vec<InputLevel>,
+    /// The return audio (IFB) player
+    pub ifb: IfbStatus,
+    /// Queued, running and recently finished transcode jobs, oldest first
+    pub transcodes: Vec<TranscodeJob>,
 }
 
 impl AlasState {
@@ -33,6 +75,7 @@ impl AlasState {
             is_streaming: false,
             is_recording: false,
             is_audio_present: false,
+            is_on_fallback: false,
             audio_last_seen: 0,
             config: load_config(),
             upload_state: AlasUploadState {
@@ -40,6 +83,19 @@ impl AlasState {
                 progress: 0,
                 queue: Vec::new(),
             },
+            schedule: ScheduleDecision::default(),
+            disk_free_mb: None,
+            disk_low: false,
+            off_air: OffAirStatus::default(),
+            listeners: ListenerStats::default(),
+            stream: StreamStats::default(),
+            stream_connection: IcecastConnectionState::default(),
+            stream_delay: None,
+            record_override: None,
+            calibration: CalibrationStatus::Idle,
+            input_levels: Vec::new(),
+            ifb: IfbStatus::default(),
+            transcodes: Vec::new(),
         }
     }
 
@@ -57,17 +113,30 @@ impl AlasState {
             is_streaming: false,
             is_recording: false,
             is_audio_present: false,
+            is_on_fallback: false,
             audio_last_seen: 0,
             config: AlasConfig {
                 audio: AlasAudioConfig {
                     silence_duration_before_deactivation: 15,
                     silence_threshold: -55.0,
+                    silence_stop_threshold: None,
+                    stream: None,
+                    record: None,
+                    inputs: Vec::new(),
                 },
                 icecast: AlasIcecastConfig {
                     hostname: "localhost".to_string(),
                     port: 8000,
                     mount: "/hello.mp3".to_string(),
                     password: "password".to_string(),
+                    user: "source".to_string(),
+                    protocol: Default::default(),
+                    tls: false,
+                    name: None,
+                    genre: None,
+                    description: None,
+                    url: None,
+                    public: false,
                 },
                 cellular: AlasCellularConfig {
                     apn: "broadband".to_string(),
@@ -80,12 +149,39 @@ impl AlasState {
                 dropbox: None,
                 redundancy: None,
                 webhook: None,
+                schedule: None,
+                logger: None,
+                storage: None,
+                monitor: None,
+                off_air: None,
+                adaptive_bitrate: None,
+                fallback: None,
+                delay: None,
+                tones: None,
+                level_history: None,
+                ifb: None,
+                transcode: None,
+                podcast: None,
+                encryption: None,
             },
             upload_state: AlasUploadState {
                 state: AlasUploadStatus::Idle,
                 progress: 0,
                 queue: Vec::new(),
-            }
+            },
+            schedule: ScheduleDecision::default(),
+            disk_free_mb: None,
+            disk_low: false,
+            off_air: OffAirStatus::default(),
+            listeners: ListenerStats::default(),
+            stream: StreamStats::default(),
+            stream_connection: IcecastConnectionState::default(),
+            stream_delay: None,
+            record_override: None,
+            calibration: CalibrationStatus::Idle,
+            input_levels: Vec::new(),
+            ifb: IfbStatus::default(),
+            transcodes: Vec::new(),
         }
     }
 }
@@ -125,9 +221,94 @@ pub enum AlasMessage {
     StreamingStarted,
     StreamingStopped,
     StreamingConfigUpdated,
+    StreamConnectionChanged {
+        state: IcecastConnectionState,
+    },
+    StreamBitrateChanged {
+        kbps: u32,
+        reason: BitrateChangeReason,
+    },
+    MonitorConfigUpdated,
     UploadStateChange {
         new_state: AlasUploadState,
-    }
+    },
+    ScheduleChanged {
+        decision: ScheduleDecision,
+    },
+    RecordingUploadChange {
+        file_path: String,
+        status: RecordingUploadStatus,
+    },
+    DiskSpaceLow {
+        free_mb: u64,
+    },
+    DiskSpaceRecovered {
+        free_mb: u64,
+    },
+    RecordingFailed {
+        reason: String,
+    },
+    AddMarker {
+        label: Option<String>,
+    },
+    MarkerAdded {
+        recording_id: String,
+        marker: RecordingMarker,
+        count: usize,
+    },
+    /// A marker was requested while nothing was being recorded
+    MarkerRejected,
+    /// The return feed stopped matching the input, or could not be heard at all
+    OffAirAlarm {
+        status: OffAirStatus,
+    },
+    OffAirRecovered {
+        status: OffAirStatus,
+    },
+    /// The number of people listening across our mounts, if known
+    ListenerCountChanged {
+        listeners: Option<u32>,
+    },
+    /// The stream switched to the fallback audio after the input went silent
+    FallbackStarted,
+    /// The input came back, so the stream switched back to it
+    FallbackStopped,
+    /// Asks the stream to throw away its profanity delay
+    DumpDelay,
+    DelayDumped {
+        status: DelayStatus,
+    },
+    StreamDelayChanged {
+        status: Option<DelayStatus>,
+    },
+    /// Asks the recording to carry on in a new file
+    SplitRecording,
+    /// A DTMF sequence or tone on the input triggered a command
+    ToneCommand {
+        trigger: String,
+        action: AlasToneAction,
+    },
+    /// Asks for the input levels to be measured so thresholds can be proposed
+    StartCalibration {
+        window_secs: u32,
+    },
+    /// Saves the proposed thresholds to the audio config
+    ConfirmCalibration,
+    CancelCalibration,
+    CalibrationChanged {
+        status: CalibrationStatus,
+    },
+    /// Switches the return audio (IFB) player on or off
+    SetIfb {
+        enabled: bool,
+    },
+    IfbChanged {
+        status: IfbStatus,
+    },
+    /// A transcode job was queued, made progress or finished
+    TranscodeChanged {
+        job: TranscodeJob,
+    },
 }
 
 pub type UnsafeState = AlasState;
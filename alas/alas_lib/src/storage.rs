@@ -0,0 +1,301 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+
+use crate::catalog::{RecordingEntry, RecordingKind, RecordingUploadStatus, SafeCatalog};
+use crate::config::AlasStorageConfig;
+use crate::markers::remove_sidecar;
+use crate::state::{AlasMessage, SafeState};
+
+/// Where show recordings are written
+pub const RECORDING_DIRECTORY: &str = "/var/lib/alas/audio";
+
+const STORAGE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Recording only resumes once this much space above the minimum is free again,
+/// so that we do not flap on and off around the threshold.
+const RECOVERY_MARGIN_MB: u64 = 64;
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Returns the space available to unprivileged users on the volume holding `path`
+pub fn free_space_bytes(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Picks which local recordings to delete, oldest first. Only finished show
+/// recordings that have already been uploaded are considered.
+///
+/// Anything older than `retention` goes regardless of free space; after that,
+/// recordings are removed until `free_bytes` would reach `target_free_bytes`.
+/// Logger files keep to their own retention, unless `prune_logger_files` lets
+/// the oldest of them go when deleting every uploaded recording is not enough.
+/// A logger file waiting to upload, or whose upload failed, is never picked.
+pub fn recordings_to_prune(
+    recordings: &[RecordingEntry],
+    free_bytes: u64,
+    target_free_bytes: u64,
+    retention: Option<chrono::Duration>,
+    prune_logger_files: bool,
+    now: DateTime<Utc>
+) -> Vec<String> {
+    let mut candidates: Vec<&RecordingEntry> = recordings
+        .iter()
+        .filter(|entry| entry.local && entry.ended_at.is_some())
+        .collect();
+    candidates.sort_by_key(|entry| entry.started_at);
+    let (loggers, shows): (Vec<&RecordingEntry>, Vec<&RecordingEntry>) = candidates
+        .into_iter()
+        .partition(|entry| entry.kind == RecordingKind::Logger);
+    let uploaded = shows
+        .into_iter()
+        .filter(|entry| entry.upload_status == RecordingUploadStatus::Uploaded);
+    let loggers = loggers
+        .into_iter()
+        .filter(|_| prune_logger_files)
+        .filter(|entry| matches!(entry.upload_status, RecordingUploadStatus::Uploaded | RecordingUploadStatus::Skipped));
+
+    let mut free_bytes = free_bytes;
+    let mut pruned = Vec::new();
+    for entry in uploaded {
+        let expired = retention.is_some_and(|retention| entry.started_at < now - retention);
+        if !expired && free_bytes >= target_free_bytes {
+            continue;
+        }
+        free_bytes += entry.size_bytes;
+        pruned.push(entry.id.clone());
+    }
+    for entry in loggers {
+        if free_bytes >= target_free_bytes {
+            break;
+        }
+        free_bytes += entry.size_bytes;
+        pruned.push(entry.id.clone());
+    }
+    pruned
+}
+
+/// Deletes the chosen recordings from disk, keeping their catalog entries
+/// around since the uploaded copy may still exist. The catalog is only locked
+/// to pick the recordings and to mark them afterwards, not while deleting.
+async fn prune_recordings(catalog: &SafeCatalog, free_bytes: u64, storage: &AlasStorageConfig) {
+    let recordings = catalog.read().await.list(&Default::default());
+    let retention = storage.retention_days.map(|days| chrono::Duration::days(days as i64));
+    let chosen = recordings_to_prune(
+        &recordings,
+        free_bytes,
+        storage.target_free_mb * MEGABYTE,
+        retention,
+        storage.prune_logger_files,
+        Utc::now()
+    );
+
+    let mut deleted = Vec::new();
+    for entry in recordings.iter().filter(|entry| chosen.contains(&entry.id)) {
+        match tokio::fs::remove_file(&entry.path).await {
+            Ok(_) if entry.kind == RecordingKind::Logger => {
+                println!("💾 Deleted logger file {} to make space", entry.path);
+            }
+            Ok(_) => println!("💾 Deleted uploaded recording {}", entry.path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                eprintln!("💾 Could not delete {}: {}", entry.path, e);
+                continue;
+            }
+        }
+        // The markers are in the catalog, so their sidecar can go. The waveform
+        // is small, and is kept so that the web UI can still show the recording.
+        remove_sidecar(&entry.path);
+        for derivative in &entry.derivatives {
+            let _ = tokio::fs::remove_file(&derivative.path).await;
+        }
+        deleted.push(entry.id.clone());
+    }
+
+    // Files can also disappear behind our back, e.g. when the logger prunes itself
+    deleted.extend(recordings
+        .iter()
+        .filter(|entry| entry.local && entry.ended_at.is_some() && !Path::new(&entry.path).exists())
+        .map(|entry| entry.id.clone()));
+    if deleted.is_empty() {
+        return;
+    }
+    let mut catalog = catalog.write().await;
+    for id in deleted {
+        catalog.update(&id, |entry| entry.local = false);
+    }
+}
+
+/// Starts a task that keeps an eye on free space on the recording volume.
+///
+/// Every check applies the retention policy first. If free space is still below
+/// the configured minimum, `disk_low` is raised in `AlasState`, which stops the
+/// archival recording until enough space has been recovered.
+pub fn start_storage_watcher(bus: Sender<AlasMessage>, state: &SafeState, catalog: &SafeCatalog) -> JoinHandle<()> {
+    let state = state.clone();
+    let catalog = catalog.clone();
+    let mut exit_bus = bus.subscribe();
+
+    tokio::spawn(async move {
+        let mut ticker = time::interval(STORAGE_CHECK_INTERVAL);
+        let directory = Path::new(RECORDING_DIRECTORY);
+        loop {
+            select! {
+                _ = ticker.tick() => {
+                    let storage = state.read().await.config.storage.clone().unwrap_or_default();
+
+                    let free_bytes = match free_space_bytes(directory) {
+                        Ok(free_bytes) => free_bytes,
+                        Err(e) => {
+                            eprintln!("💾 Could not check free space on {}: {}", RECORDING_DIRECTORY, e);
+                            continue;
+                        }
+                    };
+                    prune_recordings(&catalog, free_bytes, &storage).await;
+
+                    let free_mb = free_space_bytes(directory).unwrap_or(free_bytes) / MEGABYTE;
+                    let was_low = state.read().await.disk_low;
+                    let is_low = if was_low {
+                        free_mb < storage.min_free_mb + RECOVERY_MARGIN_MB
+                    } else {
+                        free_mb < storage.min_free_mb
+                    };
+
+                    {
+                        let mut state = state.write().await;
+                        state.disk_free_mb = Some(free_mb);
+                        state.disk_low = is_low;
+                    }
+
+                    if is_low && !was_low {
+                        eprintln!("💾 Only {} MB free, stopping archival recording", free_mb);
+                        let _ = bus.send(AlasMessage::DiskSpaceLow { free_mb });
+                    } else if was_low && !is_low {
+                        println!("💾 {} MB free again, recording can resume", free_mb);
+                        let _ = bus.send(AlasMessage::DiskSpaceRecovered { free_mb });
+                    }
+                }
+                message = exit_bus.recv() => {
+                    match message {
+                        Ok(AlasMessage::Exit) | Err(RecvError::Closed) => {
+                            println!("✅ Exiting storage watcher!");
+                            return;
+                        }
+                        // Falling behind while a check was running is expected
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(id: &str, days_old: i64, size_mb: u64, upload_status: RecordingUploadStatus, now: DateTime<Utc>) -> RecordingEntry {
+        RecordingEntry {
+            id: id.to_string(),
+            path: format!("{}/{}.mp3", RECORDING_DIRECTORY, id),
+            file_name: format!("{}.mp3", id),
+            kind: RecordingKind::Show,
+            show_name: None,
+            started_at: now - chrono::Duration::days(days_old),
+            ended_at: Some(now - chrono::Duration::days(days_old)),
+            duration_secs: 3600.0,
+            size_bytes: size_mb * MEGABYTE,
+            peak_db: -1.0,
+            loudness_db: -18.0,
+            codec: "mp3".to_string(),
+            upload_status,
+            local: true,
+            markers: vec![],
+            derivatives: vec![],
+            shared_url: None,
+            encrypted: false,
+        }
+    }
+
+    #[test]
+    fn test_prunes_oldest_uploaded_first() {
+        let now = Utc::now();
+        let recordings = vec![
+            recording("newest", 1, 100, RecordingUploadStatus::Uploaded, now),
+            recording("oldest", 5, 100, RecordingUploadStatus::Uploaded, now),
+            recording("not-uploaded", 9, 100, RecordingUploadStatus::Failed, now),
+            recording("middle", 3, 100, RecordingUploadStatus::Uploaded, now),
+        ];
+
+        // Plenty of space, nothing to do
+        assert!(recordings_to_prune(&recordings, 1000 * MEGABYTE, 500 * MEGABYTE, None, false, now).is_empty());
+
+        // Need 150 MB more, so the two oldest uploaded recordings go
+        assert_eq!(
+            recordings_to_prune(&recordings, 350 * MEGABYTE, 500 * MEGABYTE, None, false, now),
+            vec!["oldest".to_string(), "middle".to_string()]
+        );
+
+        // Never deletes anything that has not been uploaded
+        let everything = recordings_to_prune(&recordings, 0, u64::MAX, None, false, now);
+        assert!(!everything.contains(&"not-uploaded".to_string()));
+    }
+
+    #[test]
+    fn test_prunes_expired_recordings() {
+        let now = Utc::now();
+        let recordings = vec![
+            recording("old", 10, 100, RecordingUploadStatus::Uploaded, now),
+            recording("recent", 1, 100, RecordingUploadStatus::Uploaded, now),
+        ];
+
+        assert_eq!(
+            recordings_to_prune(&recordings, 1000 * MEGABYTE, 500 * MEGABYTE, Some(chrono::Duration::days(7)), false, now),
+            vec!["old".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_prunes_logger_files_last() {
+        let now = Utc::now();
+        let logger = |id: &str, days_old: i64, upload_status: RecordingUploadStatus| RecordingEntry {
+            kind: RecordingKind::Logger,
+            ..recording(id, days_old, 100, upload_status, now)
+        };
+        let recordings = vec![
+            logger("pending-hour", 4, RecordingUploadStatus::Pending),
+            logger("failed-hour", 3, RecordingUploadStatus::Failed),
+            logger("old-hour", 2, RecordingUploadStatus::Uploaded),
+            logger("new-hour", 1, RecordingUploadStatus::Skipped),
+            recording("show", 1, 100, RecordingUploadStatus::Uploaded, now),
+        ];
+
+        // Logger files are left to their own retention, even once uploaded
+        assert_eq!(recordings_to_prune(&recordings, 0, 0, Some(chrono::Duration::hours(1)), true, now), vec!["show".to_string()]);
+
+        // Unless asked to, logger files are kept however little space there is
+        assert_eq!(recordings_to_prune(&recordings, 0, u64::MAX, None, false, now), vec!["show".to_string()]);
+
+        // Uploaded recordings go before any logger file does, and hours still
+        // waiting for their upload are never deleted
+        assert_eq!(
+            recordings_to_prune(&recordings, 350 * MEGABYTE, 500 * MEGABYTE, None, true, now),
+            vec!["show".to_string(), "old-hour".to_string()]
+        );
+        assert_eq!(
+            recordings_to_prune(&recordings, 0, u64::MAX, None, true, now),
+            vec!["show".to_string(), "old-hour".to_string(), "new-hour".to_string()]
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// How many finished sessions are remembered for troubleshooting
+pub const MAX_STREAM_SESSIONS: usize = 20;
+
+/// The bitrate used unless adaptive bitrate is configured
+pub const DEFAULT_BITRATE_KBPS: u32 = 128;
+
+/// Counters for one stream session, from the moment the stream was switched on
+/// until it was switched off again
+#[derive(Clone, Debug, Serialize)]
+pub struct StreamSession {
+    pub id: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub bytes_sent: u64,
+    /// Seconds of audio handed to the encoder
+    pub audio_secs: f64,
+    /// Wall-clock seconds spent encoding that audio
+    pub encode_secs: f64,
+    /// Wall-clock seconds spent sending to Icecast
+    pub send_secs: f64,
+    pub reconnects: u32,
+    /// The encoder's current bitrate
+    pub bitrate_kbps: u32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Time spent without a working connection, including the current outage
+    pub disconnected_secs: f64,
+    #[serde(skip)]
+    disconnected_since: Option<DateTime<Utc>>,
+}
+
+/// A session along with figures derived from its counters
+#[derive(Clone, Debug, Serialize)]
+pub struct StreamSessionReport {
+    #[serde(flatten)]
+    pub session: StreamSession,
+    pub uptime_secs: f64,
+    /// How many times faster than real time the encoder is running
+    pub encoder_speed: Option<f64>,
+    pub send_kbps: Option<f64>,
+    pub connected: bool,
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_milliseconds().max(0) as f64 / 1000.0
+}
+
+impl StreamSession {
+    pub fn new() -> Self {
+        let now = Utc::now();
+        StreamSession {
+            id: Uuid::new_v4().to_string(),
+            started_at: now,
+            ended_at: None,
+            bytes_sent: 0,
+            audio_secs: 0.0,
+            encode_secs: 0.0,
+            send_secs: 0.0,
+            reconnects: 0,
+            bitrate_kbps: DEFAULT_BITRATE_KBPS,
+            last_error: None,
+            last_error_at: None,
+            disconnected_secs: 0.0,
+            // Nothing is connected until the first successful send
+            disconnected_since: Some(now),
+        }
+    }
+
+    pub fn record_encode(&mut self, audio_secs: f64, took: Duration) {
+        self.audio_secs += audio_secs;
+        self.encode_secs += took.as_secs_f64();
+    }
+
+    pub fn record_send(&mut self, bytes: usize, took: Duration) {
+        self.bytes_sent += bytes as u64;
+        self.send_secs += took.as_secs_f64();
+        if let Some(since) = self.disconnected_since.take() {
+            self.disconnected_secs += seconds_between(since, Utc::now());
+        }
+    }
+
+    /// Notes a failed connection or send. Disconnected time runs until the next
+    /// successful send.
+    pub fn record_error(&mut self, error: String) {
+        let now = Utc::now();
+        self.last_error = Some(error);
+        self.last_error_at = Some(now);
+        self.disconnected_since.get_or_insert(now);
+    }
+
+    pub fn record_reconnect(&mut self) {
+        self.reconnects += 1;
+    }
+
+    pub fn finish(&mut self) {
+        let now = Utc::now();
+        if let Some(since) = self.disconnected_since.take() {
+            self.disconnected_secs += seconds_between(since, now);
+        }
+        self.ended_at = Some(now);
+    }
+
+    pub fn report(&self) -> StreamSessionReport {
+        let now = Utc::now();
+        let mut session = self.clone();
+        if let Some(since) = session.disconnected_since {
+            session.disconnected_secs += seconds_between(since, now);
+        }
+
+        StreamSessionReport {
+            uptime_secs: seconds_between(self.started_at, self.ended_at.unwrap_or(now)),
+            encoder_speed: (self.encode_secs > 0.0).then(|| self.audio_secs / self.encode_secs),
+            send_kbps: (self.send_secs > 0.0).then(|| self.bytes_sent as f64 * 8.0 / 1000.0 / self.send_secs),
+            connected: self.ended_at.is_none() && self.disconnected_since.is_none(),
+            session,
+        }
+    }
+}
+
+impl Default for StreamSession {
+    fn default() -> Self {
+        StreamSession::new()
+    }
+}
+
+/// The current stream session, if any, and the ones before it
+#[derive(Clone, Debug, Default)]
+pub struct StreamStats {
+    pub current: Option<StreamSession>,
+    /// Finished sessions, newest first
+    pub history: VecDeque<StreamSession>,
+}
+
+impl StreamStats {
+    /// Moves a finished session into the history, forgetting the oldest ones
+    pub fn finish_session(&mut self, mut session: StreamSession) {
+        session.finish();
+        self.current = None;
+        self.history.push_front(session);
+        self.history.truncate(MAX_STREAM_SESSIONS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_report() {
+        let mut session = StreamSession::new();
+        assert!(!session.report().connected);
+
+        session.record_encode(10.0, Duration::from_millis(500));
+        session.record_send(16_000, Duration::from_millis(100));
+        let report = session.report();
+        assert!(report.connected);
+        assert_eq!(report.encoder_speed, Some(20.0));
+        assert_eq!(report.send_kbps, Some(1280.0));
+
+        session.record_error("Socket error".to_string());
+        session.record_reconnect();
+        let report = session.report();
+        assert!(!report.connected);
+        assert_eq!(report.session.reconnects, 1);
+        assert_eq!(report.session.last_error.as_deref(), Some("Socket error"));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut stats = StreamStats::default();
+        for _ in 0..MAX_STREAM_SESSIONS + 5 {
+            stats.current = Some(StreamSession::new());
+            let session = stats.current.clone().unwrap();
+            stats.finish_session(session);
+        }
+
+        assert!(stats.current.is_none());
+        assert_eq!(stats.history.len(), MAX_STREAM_SESSIONS);
+        assert!(stats.history.iter().all(|session| session.ended_at.is_some()));
+    }
+}
@@ -0,0 +1,339 @@
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+use bus::BusReader;
+use tokio::sync::broadcast::Sender;
+use tokio::task;
+use tokio::task::JoinHandle;
+
+use crate::config::{AlasActivationMode, AlasToneAction, AlasToneCommand, AlasToneConfig};
+use crate::state::{AlasMessage, SafeState};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const CHANNELS: usize = 2;
+/// 25 ms blocks resolve 40 Hz, which separates the DTMF rows and columns
+const BLOCK_SIZE: usize = 1_200;
+const BLOCK_MS: u32 = 25;
+/// A digit must be heard for this many blocks in a row to count
+const MIN_DIGIT_BLOCKS: u32 = 2;
+/// Digits further apart than this start a new sequence
+const INTER_DIGIT_TIMEOUT_BLOCKS: u32 = 3_000 / BLOCK_MS;
+/// Blocks quieter than this are not searched for tones
+const MIN_TONE_DB: f32 = -45.0;
+/// How much of a block's energy the tone or tone pair must carry
+const MIN_DTMF_SHARE: f32 = 0.7;
+const MIN_SINGLE_TONE_SHARE: f32 = 0.8;
+const CONFIG_INTERVAL: Duration = Duration::from_secs(1);
+
+const DTMF_ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLUMNS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+/// The power of one frequency in a block, using the Goertzel algorithm
+fn goertzel_power(block: &[f32], frequency: f32) -> f32 {
+    let coefficient = 2.0 * (2.0 * PI * frequency / SAMPLE_RATE).cos();
+    let (mut previous, mut before_previous) = (0.0f32, 0.0f32);
+    for &sample in block {
+        let current = sample + coefficient * previous - before_previous;
+        before_previous = previous;
+        previous = current;
+    }
+    previous * previous + before_previous * before_previous - coefficient * previous * before_previous
+}
+
+/// How much of the block's energy sits at `frequency`, from 0.0 to about 1.0
+fn energy_share(block: &[f32], energy: f32, frequency: f32) -> f32 {
+    goertzel_power(block, frequency) / (energy * block.len() as f32 / 2.0)
+}
+
+/// The strongest of a group of frequencies, provided it stands at least 6 dB
+/// above the others
+fn dominant(shares: &[f32; 4]) -> Option<(usize, f32)> {
+    let (index, &strongest) = shares
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    let stands_out = shares
+        .iter()
+        .enumerate()
+        .all(|(other, &share)| other == index || share * 4.0 < strongest);
+    stands_out.then_some((index, strongest))
+}
+
+fn detect_dtmf(block: &[f32], energy: f32) -> Option<char> {
+    let rows = DTMF_ROWS.map(|frequency| energy_share(block, energy, frequency));
+    let columns = DTMF_COLUMNS.map(|frequency| energy_share(block, energy, frequency));
+    let (row, row_share) = dominant(&rows)?;
+    let (column, column_share) = dominant(&columns)?;
+
+    // Between them the pair must carry most of the energy, and neither tone
+    // may be drowned out by the other
+    if row_share + column_share < MIN_DTMF_SHARE || row_share < 0.1 || column_share < 0.1 {
+        return None;
+    }
+    Some(DTMF_KEYS[row][column])
+}
+
+/// Listens for the configured DTMF sequences and single tones
+pub struct ToneDetector {
+    commands: Vec<AlasToneCommand>,
+    block: Vec<f32>,
+    /// The digit heard in the latest blocks, and for how many in a row
+    current_digit: Option<(char, u32)>,
+    digits: String,
+    blocks_since_digit: u32,
+    /// For each command, how many blocks in a row its single tone has been heard
+    tone_blocks: Vec<u32>,
+}
+
+impl ToneDetector {
+    pub fn new(config: &AlasToneConfig) -> Self {
+        ToneDetector {
+            commands: config.commands.clone(),
+            block: Vec::with_capacity(BLOCK_SIZE),
+            current_digit: None,
+            digits: String::new(),
+            blocks_since_digit: 0,
+            tone_blocks: vec![0; config.commands.len()],
+        }
+    }
+
+    /// Feeds interleaved stereo audio and returns any commands it completed
+    pub fn process(&mut self, input: &[f32]) -> Vec<AlasToneCommand> {
+        let mut triggered = Vec::new();
+        for frame in input.chunks_exact(CHANNELS) {
+            self.block.push((frame[0] + frame[1]) / 2.0);
+            if self.block.len() == BLOCK_SIZE {
+                let block = std::mem::replace(&mut self.block, Vec::with_capacity(BLOCK_SIZE));
+                triggered.extend(self.process_block(&block));
+            }
+        }
+        triggered
+    }
+
+    fn process_block(&mut self, block: &[f32]) -> Vec<AlasToneCommand> {
+        let mut triggered = Vec::new();
+        let energy: f32 = block.iter().map(|sample| sample * sample).sum();
+        let level_db = 10.0 * (energy / block.len() as f32).max(1e-10).log10();
+        let loud_enough = level_db > MIN_TONE_DB;
+
+        let digit = if loud_enough { detect_dtmf(block, energy) } else { None };
+        self.blocks_since_digit = self.blocks_since_digit.saturating_add(1);
+        if self.blocks_since_digit > INTER_DIGIT_TIMEOUT_BLOCKS {
+            self.digits.clear();
+        }
+
+        self.current_digit = match (digit, self.current_digit) {
+            (Some(digit), Some((current, count))) if digit == current => Some((digit, count + 1)),
+            (Some(digit), _) => Some((digit, 1)),
+            (None, _) => None,
+        };
+        if let Some((digit, MIN_DIGIT_BLOCKS)) = self.current_digit {
+            self.digits.push(digit);
+            self.blocks_since_digit = 0;
+            let completed = self.commands.iter().find(|command| {
+                command.dtmf.as_ref().is_some_and(|sequence| self.digits.ends_with(sequence.as_str()))
+            });
+            if let Some(command) = completed {
+                triggered.push(command.clone());
+                self.digits.clear();
+            }
+        }
+
+        for (command, held) in self.commands.iter().zip(self.tone_blocks.iter_mut()) {
+            let Some(frequency) = command.tone_hz else {
+                continue;
+            };
+            let heard = loud_enough && digit.is_none() &&
+                energy_share(block, energy, frequency) > MIN_SINGLE_TONE_SHARE;
+            if !heard {
+                *held = 0;
+                continue;
+            }
+            *held += 1;
+            // Fires once per tone, however long it is held
+            if *held == command.tone_ms.div_ceil(BLOCK_MS).max(1) {
+                triggered.push(command.clone());
+            }
+        }
+
+        triggered
+    }
+}
+
+/// Carries out a command heard on the input
+fn run_command(command: &AlasToneCommand, state: &SafeState, bus: &Sender<AlasMessage>) {
+    let trigger = command.trigger();
+    println!("☎️ Heard {}, running {:?}", trigger, command.action);
+    match command.action {
+        AlasToneAction::StartRecording => {
+            state.blocking_write().record_override = Some(AlasActivationMode::ForceOn);
+        }
+        AlasToneAction::StopRecording => {
+            state.blocking_write().record_override = Some(AlasActivationMode::ForceOff);
+        }
+        AlasToneAction::SplitRecording => {
+            let _ = bus.send(AlasMessage::SplitRecording);
+        }
+        AlasToneAction::Marker => {
+            let _ = bus.send(AlasMessage::AddMarker { label: Some(format!("Tone {}", trigger)) });
+        }
+        // The webhook listener picks this up from the message below
+        AlasToneAction::Webhook => {}
+    }
+    let _ = bus.send(AlasMessage::ToneCommand {
+        trigger,
+        action: command.action,
+    });
+}
+
+/// Starts the thread that listens to the input for tone commands
+pub(crate) fn start_tone_thread(
+    mut tone_rx: BusReader<Vec<f32>>,
+    state: SafeState,
+    bus: Sender<AlasMessage>
+) -> JoinHandle<&'static str> {
+    task::spawn_blocking(move || {
+        let mut tone_config: Option<AlasToneConfig> = None;
+        let mut detector: Option<ToneDetector> = None;
+        let mut last_config_check: Option<Instant> = None;
+
+        while let Ok(input) = tone_rx.recv() {
+            if last_config_check.is_none_or(|checked| checked.elapsed() >= CONFIG_INTERVAL) {
+                let config = state.blocking_read().config.tones.clone();
+                if config != tone_config {
+                    detector = config.as_ref().map(ToneDetector::new);
+                    tone_config = config;
+                }
+                last_config_check = Some(Instant::now());
+            }
+
+            if let Some(detector) = detector.as_mut() {
+                for command in detector.process(&input) {
+                    run_command(&command, &state, &bus);
+                }
+            }
+        }
+
+        "✅ Exiting tone detector thread"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(dtmf: Option<&str>, tone_hz: Option<f32>, action: AlasToneAction) -> AlasToneCommand {
+        AlasToneCommand {
+            dtmf: dtmf.map(str::to_string),
+            tone_hz,
+            tone_ms: 1_000,
+            action,
+        }
+    }
+
+    /// Interleaved stereo made up of the given frequencies
+    fn tones(frequencies: &[f32], secs: f32) -> Vec<f32> {
+        let frames = (secs * SAMPLE_RATE) as usize;
+        (0..frames)
+            .flat_map(|frame| {
+                let time = frame as f32 / SAMPLE_RATE;
+                let sample: f32 = frequencies
+                    .iter()
+                    .map(|frequency| 0.3 * (2.0 * PI * frequency * time).sin())
+                    .sum();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn dtmf(key: char) -> [f32; 2] {
+        for (row, keys) in DTMF_KEYS.iter().enumerate() {
+            if let Some(column) = keys.iter().position(|&k| k == key) {
+                return [DTMF_ROWS[row], DTMF_COLUMNS[column]];
+            }
+        }
+        panic!("Not a DTMF key: {}", key);
+    }
+
+    /// Feeds audio in the small, uneven packets the sound card hands us
+    fn feed(detector: &mut ToneDetector, audio: &[f32]) -> Vec<AlasToneAction> {
+        audio
+            .chunks(882)
+            .flat_map(|packet| detector.process(packet))
+            .map(|command| command.action)
+            .collect()
+    }
+
+    fn dial(keys: &str) -> Vec<f32> {
+        keys.chars()
+            .flat_map(|key| {
+                let mut audio = tones(&dtmf(key), 0.08);
+                audio.extend(tones(&[], 0.08));
+                audio
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dtmf_sequences() {
+        let config = AlasToneConfig {
+            commands: vec![
+                command(Some("*1"), None, AlasToneAction::StartRecording),
+                command(Some("*2"), None, AlasToneAction::StopRecording),
+                command(Some("#9D"), None, AlasToneAction::Webhook),
+            ],
+        };
+        let mut detector = ToneDetector::new(&config);
+
+        assert_eq!(feed(&mut detector, &dial("5*1")), vec![AlasToneAction::StartRecording]);
+        assert_eq!(feed(&mut detector, &dial("#9D*2")), vec![AlasToneAction::Webhook, AlasToneAction::StopRecording]);
+
+        // Digits spread too far apart do not make a sequence
+        let mut audio = dial("*");
+        audio.extend(tones(&[], 3.5));
+        audio.extend(dial("1"));
+        assert!(feed(&mut detector, &audio).is_empty());
+    }
+
+    #[test]
+    fn test_single_tone_fires_once() {
+        let config = AlasToneConfig {
+            commands: vec![command(None, Some(1_000.0), AlasToneAction::SplitRecording)],
+        };
+        let mut detector = ToneDetector::new(&config);
+
+        assert!(feed(&mut detector, &tones(&[1_000.0], 0.5)).is_empty());
+        let mut audio = tones(&[1_000.0], 2.0);
+        audio.extend(tones(&[], 0.2));
+        assert_eq!(feed(&mut detector, &audio), vec![AlasToneAction::SplitRecording]);
+
+        // A different pitch, or one that is too quiet, does nothing
+        assert!(feed(&mut detector, &tones(&[1_500.0], 1.5)).is_empty());
+        let quiet: Vec<f32> = tones(&[1_000.0], 1.5).iter().map(|sample| sample * 0.001).collect();
+        assert!(feed(&mut detector, &quiet).is_empty());
+    }
+
+    #[test]
+    fn test_music_is_not_a_digit() {
+        let config = AlasToneConfig {
+            commands: vec![command(Some("1"), None, AlasToneAction::Marker)],
+        };
+        let mut detector = ToneDetector::new(&config);
+
+        // A chord with a third note on top, and plain noise
+        assert!(feed(&mut detector, &tones(&[697.0, 1209.0, 440.0], 1.0)).is_empty());
+        let mut seed = 1u32;
+        let noise: Vec<f32> = (0..96_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+            })
+            .collect();
+        assert!(feed(&mut detector, &noise).is_empty());
+    }
+}
@@ -0,0 +1,829 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use mp3lame_encoder::{BuildError, DualPcm, Encoder, FlushNoGap, MonoPcm};
+use serde::Serialize;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use thiserror::Error;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::task::{self, JoinHandle};
+use uuid::Uuid;
+
+use crate::audio::{bitrate_from_kbps, float_to_i16};
+use crate::catalog::{RecordingDerivative, RecordingUploadStatus, SafeCatalog};
+use crate::config::{AlasDerivativeConfig, AlasEncryptionConfig};
+use crate::dropbox::upload_file_to_dropbox;
+use crate::encryption::{encrypt_file, encrypt_recording, encrypted_path, parse_recipients, EncryptionError};
+use crate::state::{AlasMessage, SafeState};
+
+/// Finished jobs kept around for the API
+const MAX_FINISHED_JOBS: usize = 50;
+/// Normalization never pushes a sample peak above this, in dBFS
+const MAX_PEAK_DB: f32 = -1.0;
+/// The lowest priority there is, so a job only gets the CPU that capture,
+/// streaming and recording leave idle
+const JOB_NICENESS: libc::c_int = 19;
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Error, Debug)]
+pub enum TranscodeError {
+    #[error("Could not read or write a file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Could not decode the recording: {0}")]
+    Decode(String),
+
+    #[error("Could not encode the derivative: {0}")]
+    Encode(String),
+
+    #[error("Cancelled because ALAS is shutting down")]
+    Cancelled,
+}
+
+impl From<SymphoniaError> for TranscodeError {
+    fn from(error: SymphoniaError) -> Self {
+        match error {
+            SymphoniaError::IoError(e) => TranscodeError::Io(e),
+            e => TranscodeError::Decode(e.to_string()),
+        }
+    }
+}
+
+impl From<BuildError> for TranscodeError {
+    fn from(error: BuildError) -> Self {
+        TranscodeError::Encode(format!("{:?}", error))
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscodeJobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Making one derivative of one recording
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TranscodeJob {
+    pub id: String,
+    pub recording_id: String,
+    /// The recording being transcoded
+    pub source: String,
+    /// The derivative's name from the transcode config
+    pub derivative: String,
+    pub state: TranscodeJobState,
+    /// Percent done, while running
+    pub progress: u8,
+    /// The file written, once done
+    pub output: Option<String>,
+    /// Integrated loudness before normalization, in LUFS
+    pub measured_lufs: Option<f32>,
+    /// Gain applied to reach the loudness target, in dB
+    pub gain_db: Option<f32>,
+    pub error: Option<String>,
+    pub queued_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// A finished recording waiting for its derivatives, and then for its upload
+pub struct TranscodeRequest {
+    pub recording_id: String,
+    pub source: String,
+    pub derivatives: Vec<AlasDerivativeConfig>,
+    /// Encrypt the recording and its derivatives before they are uploaded
+    pub encryption: Option<AlasEncryptionConfig>,
+    /// Where the recording and its derivatives are uploaded to
+    pub destination_folder: String,
+    pub keep_local_copy: bool,
+}
+
+/// Hands finished recordings over to the transcoder
+#[derive(Clone)]
+pub struct TranscodeQueue {
+    sender: mpsc::Sender<TranscodeRequest>,
+    receiver: Arc<Mutex<mpsc::Receiver<TranscodeRequest>>>,
+}
+
+impl TranscodeQueue {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        TranscodeQueue {
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+        }
+    }
+
+    /// Queues a recording. This never blocks, so the recording thread can
+    /// carry straight on with the next file.
+    pub fn submit(&self, request: TranscodeRequest) {
+        if self.sender.send(request).is_err() {
+            eprintln!("🎚️ The transcoder has stopped, so nothing more can be queued");
+        }
+    }
+}
+
+impl Default for TranscodeQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One section of a filter, in direct form I
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad { b, a, x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1] -
+            self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The K-weighting from ITU-R BS.1770: a high shelf for the effect of the
+/// head, then a high pass. The coefficients are worked out for the sample
+/// rate, and match the ones tabulated in the standard at 48 kHz.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let k = (std::f64::consts::PI * 1681.974450955533 / rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]
+    );
+
+    let k = (std::f64::consts::PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]);
+
+    [shelf, high_pass]
+}
+
+fn energy_to_lufs(energy: f64) -> f32 {
+    (-0.691 + 10.0 * energy.log10()) as f32
+}
+
+/// Measures integrated loudness as in ITU-R BS.1770 and EBU R 128: 400 ms
+/// blocks overlapping by 75%, gated at -70 LUFS and then 10 LU below the
+/// loudness of what is left. Every channel counts the same, which is right
+/// for mono and stereo.
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    /// Frames in 100 ms, a quarter of a block
+    step_frames: usize,
+    frames_in_step: usize,
+    step_energy: f64,
+    /// Energy of the last four steps
+    steps: VecDeque<f64>,
+    /// Mean square of each block, summed over the channels
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        LoudnessMeter {
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            step_frames: (sample_rate as usize / 10).max(1),
+            frames_in_step: 0,
+            step_energy: 0.0,
+            steps: VecDeque::with_capacity(4),
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Adds interleaved audio
+    pub fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, [shelf, high_pass]) in frame.iter().zip(self.filters.iter_mut()) {
+                let weighted = high_pass.process(shelf.process(*sample as f64));
+                self.step_energy += weighted * weighted;
+                self.peak = self.peak.max(sample.abs());
+            }
+            self.frames_in_step += 1;
+            if self.frames_in_step == self.step_frames {
+                if self.steps.len() == 4 {
+                    self.steps.pop_front();
+                }
+                self.steps.push_back(std::mem::take(&mut self.step_energy));
+                self.frames_in_step = 0;
+                if self.steps.len() == 4 {
+                    self.blocks.push(self.steps.iter().sum::<f64>() / (4 * self.step_frames) as f64);
+                }
+            }
+        }
+    }
+
+    /// The integrated loudness, or None if nothing got past the gates
+    pub fn integrated_lufs(&self) -> Option<f32> {
+        let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+        let audible: Vec<f64> = self.blocks
+            .iter()
+            .copied()
+            .filter(|&energy| energy > 0.0 && energy_to_lufs(energy) > -70.0)
+            .collect();
+        if audible.is_empty() {
+            return None;
+        }
+        let relative_gate = energy_to_lufs(mean(&audible)) - 10.0;
+        let gated: Vec<f64> = audible.into_iter().filter(|&energy| energy_to_lufs(energy) > relative_gate).collect();
+        Some(energy_to_lufs(mean(&gated)))
+    }
+
+    /// The highest sample peak, in dBFS
+    pub fn peak_db(&self) -> f32 {
+        20.0 * self.peak.max(1e-6).log10()
+    }
+}
+
+/// Gain to bring `measured_lufs` up or down to `target_lufs`, held back so
+/// that the loudest sample stays at or under [`MAX_PEAK_DB`]
+fn normalization_gain_db(measured_lufs: f32, target_lufs: f32, peak_db: f32) -> f32 {
+    (target_lufs - measured_lufs).min(MAX_PEAK_DB - peak_db)
+}
+
+/// Mixes interleaved audio down to mono, or to (or up to) stereo
+fn to_output_channels(samples: &[f32], channels: usize, mono: bool) -> Vec<f32> {
+    match (mono, channels) {
+        (true, 1) | (false, 2) => samples.to_vec(),
+        (true, _) => samples.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32).collect(),
+        (false, 1) => samples.iter().flat_map(|&sample| [sample, sample]).collect(),
+        (false, _) => samples.chunks_exact(channels).flat_map(|frame| [frame[0], frame[1]]).collect(),
+    }
+}
+
+/// Decodes `path` a packet at a time, handing each to `handle` as interleaved
+/// samples along with the channel count and sample rate. Nothing is held in
+/// memory, since a recording can run for hours.
+fn decode_file(
+    path: &Path,
+    cancel: &AtomicBool,
+    mut handle: impl FnMut(&[f32], usize, u32) -> Result<(), TranscodeError>
+) -> Result<(), TranscodeError> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default()
+    )?;
+    let mut format = probed.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| TranscodeError::Decode("No audio track".to_string()))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(TranscodeError::Cancelled);
+        }
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+                handle(buffer.samples(), spec.channels.count(), spec.rate)?;
+            }
+            // A corrupt frame is not fatal, just skip it
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn build_encoder(derivative: &AlasDerivativeConfig, sample_rate: u32) -> Result<Encoder, TranscodeError> {
+    let mut builder = mp3lame_encoder::Builder::new()
+        .ok_or_else(|| TranscodeError::Encode("Could not create LAME".to_string()))?;
+    builder.set_num_channels(if derivative.mono { 1 } else { 2 })?;
+    builder.set_sample_rate(sample_rate)?;
+    builder.set_brate(bitrate_from_kbps(derivative.bitrate))?;
+    Ok(builder.build()?)
+}
+
+/// Encodes mono or interleaved stereo audio
+fn encode(encoder: &mut Encoder, samples: &[f32], mono: bool) -> Result<Vec<u8>, TranscodeError> {
+    let pcm: Vec<i16> = samples.iter().map(|&sample| float_to_i16(sample)).collect();
+    let mut mp3_buffer = Vec::new();
+    let encoded_size = if mono {
+        mp3_buffer.reserve(mp3lame_encoder::max_required_buffer_size(pcm.len()));
+        encoder.encode(MonoPcm(&pcm), mp3_buffer.spare_capacity_mut())
+    } else {
+        let left: Vec<i16> = pcm.iter().step_by(2).copied().collect();
+        let right: Vec<i16> = pcm.iter().skip(1).step_by(2).copied().collect();
+        mp3_buffer.reserve(mp3lame_encoder::max_required_buffer_size(left.len()));
+        encoder.encode(DualPcm { left: &left, right: &right }, mp3_buffer.spare_capacity_mut())
+    };
+    let encoded_size = encoded_size.map_err(|e| TranscodeError::Encode(format!("{:?}", e)))?;
+    unsafe {
+        mp3_buffer.set_len(encoded_size);
+    }
+    Ok(mp3_buffer)
+}
+
+fn flush(encoder: &mut Encoder) -> Result<Vec<u8>, TranscodeError> {
+    let mut mp3_buffer = Vec::with_capacity(7200);
+    let encoded_size = encoder
+        .flush::<FlushNoGap>(mp3_buffer.spare_capacity_mut())
+        .map_err(|e| TranscodeError::Encode(format!("{:?}", e)))?;
+    unsafe {
+        mp3_buffer.set_len(encoded_size);
+    }
+    Ok(mp3_buffer)
+}
+
+/// What came of a successful job
+struct TranscodeOutcome {
+    measured_lufs: Option<f32>,
+    gain_db: Option<f32>,
+}
+
+/// Writes one derivative of `source` to `output`. With a loudness target the
+/// recording is decoded twice: once to measure it, and once to encode it.
+fn transcode(
+    source: &Path,
+    output: &Path,
+    derivative: &AlasDerivativeConfig,
+    duration_secs: f64,
+    progress: &AtomicU8,
+    cancel: &AtomicBool
+) -> Result<TranscodeOutcome, TranscodeError> {
+    let channels = if derivative.mono { 1 } else { 2 };
+    let passes = if derivative.loudness_lufs.is_some() { 2.0 } else { 1.0 };
+    let set_progress = |pass: f64, decoded_secs: f64| {
+        let done = (pass + (decoded_secs / duration_secs.max(1.0)).min(1.0)) / passes;
+        progress.store((done * 100.0) as u8, Ordering::Relaxed);
+    };
+
+    let mut outcome = TranscodeOutcome { measured_lufs: None, gain_db: None };
+    if let Some(target_lufs) = derivative.loudness_lufs {
+        let mut meter: Option<LoudnessMeter> = None;
+        let mut decoded_secs = 0.0;
+        decode_file(source, cancel, |samples, source_channels, sample_rate| {
+            let samples = to_output_channels(samples, source_channels, derivative.mono);
+            meter.get_or_insert_with(|| LoudnessMeter::new(channels, sample_rate)).add(&samples);
+            decoded_secs += (samples.len() / channels) as f64 / sample_rate as f64;
+            set_progress(0.0, decoded_secs);
+            Ok(())
+        })?;
+        // A silent recording is left as it is
+        if let Some(meter) = meter
+            && let Some(measured_lufs) = meter.integrated_lufs()
+        {
+            outcome.measured_lufs = Some(measured_lufs);
+            outcome.gain_db = Some(normalization_gain_db(measured_lufs, target_lufs, meter.peak_db()));
+        }
+    }
+
+    let gain = 10f32.powf(outcome.gain_db.unwrap_or(0.0) / 20.0);
+    let mut file = File::create(output)?;
+    let mut encoder: Option<Encoder> = None;
+    let mut decoded_secs = 0.0;
+    decode_file(source, cancel, |samples, source_channels, sample_rate| {
+        let mut samples = to_output_channels(samples, source_channels, derivative.mono);
+        samples.iter_mut().for_each(|sample| *sample *= gain);
+        let encoder = match encoder.as_mut() {
+            Some(encoder) => encoder,
+            None => encoder.insert(build_encoder(derivative, sample_rate)?),
+        };
+        file.write_all(&encode(encoder, &samples, derivative.mono)?)?;
+        decoded_secs += (samples.len() / channels) as f64 / sample_rate as f64;
+        set_progress(passes - 1.0, decoded_secs);
+        Ok(())
+    })?;
+    if let Some(encoder) = encoder.as_mut() {
+        file.write_all(&flush(encoder)?)?;
+    }
+    Ok(outcome)
+}
+
+/// Where a derivative of `source` goes: next to it, with the derivative's name added
+fn derivative_path(source: &Path, name: &str) -> PathBuf {
+    let stem = source.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    source.with_file_name(format!("{}-{}.mp3", stem, name))
+}
+
+/// Updates a job, announcing the change. The progress alone only updates the
+/// state, since it changes all the time.
+fn report(state: &SafeState, bus: &Sender<AlasMessage>, id: &str, update: impl FnOnce(&mut TranscodeJob)) {
+    let (previous, job) = {
+        let mut state = state.blocking_write();
+        let Some(job) = state.transcodes.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        let previous = job.clone();
+        update(job);
+        (previous, job.clone())
+    };
+    if (TranscodeJob { progress: job.progress, ..previous }) != job {
+        let _ = bus.send(AlasMessage::TranscodeChanged { job });
+    }
+}
+
+/// Adds a queued job for each derivative of the recording, forgetting the
+/// oldest finished jobs once there are too many
+fn add_jobs(state: &SafeState, bus: &Sender<AlasMessage>, request: &TranscodeRequest) -> Vec<String> {
+    let jobs: Vec<TranscodeJob> = request.derivatives
+        .iter()
+        .map(|derivative| TranscodeJob {
+            id: Uuid::new_v4().to_string(),
+            recording_id: request.recording_id.clone(),
+            source: request.source.clone(),
+            derivative: derivative.name.clone(),
+            state: TranscodeJobState::Queued,
+            progress: 0,
+            output: None,
+            measured_lufs: None,
+            gain_db: None,
+            error: None,
+            queued_at: Utc::now(),
+            finished_at: None,
+        })
+        .collect();
+    {
+        let mut state = state.blocking_write();
+        state.transcodes.extend(jobs.iter().cloned());
+        let finished = state.transcodes.iter().filter(|job| job.finished_at.is_some()).count();
+        let mut excess = finished.saturating_sub(MAX_FINISHED_JOBS);
+        state.transcodes.retain(|job| {
+            let forget = excess > 0 && job.finished_at.is_some();
+            if forget {
+                excess -= 1;
+            }
+            !forget
+        });
+    }
+    jobs.into_iter()
+        .map(|job| {
+            let id = job.id.clone();
+            let _ = bus.send(AlasMessage::TranscodeChanged { job });
+            id
+        })
+        .collect()
+}
+
+/// A recording and the ids of its jobs
+type QueuedRecording = (TranscodeRequest, Vec<String>);
+
+/// Takes in newly finished recordings, waiting up to `timeout` for one if
+/// nothing is queued yet. Returns true if we should exit.
+fn accept(
+    requests: &mpsc::Receiver<TranscodeRequest>,
+    queue: &mut VecDeque<QueuedRecording>,
+    timeout: Duration,
+    receiver: &mut Receiver<AlasMessage>,
+    state: &SafeState,
+    bus: &Sender<AlasMessage>
+) -> bool {
+    loop {
+        match receiver.try_recv() {
+            Ok(AlasMessage::Exit) | Err(TryRecvError::Closed) => return true,
+            Ok(_) | Err(TryRecvError::Lagged(_)) => continue,
+            Err(TryRecvError::Empty) => break,
+        }
+    }
+    let request = if queue.is_empty() {
+        requests.recv_timeout(timeout).map_err(|e| e == RecvTimeoutError::Disconnected)
+    } else {
+        requests.try_recv().map_err(|e| e == mpsc::TryRecvError::Disconnected)
+    };
+    match request {
+        Ok(request) => {
+            println!("🎚️ Queued {} derivative(s) of {}", request.derivatives.len(), request.source);
+            let ids = add_jobs(state, bus, &request);
+            queue.push_back((request, ids));
+            false
+        }
+        Err(disconnected) => disconnected,
+    }
+}
+
+/// Lowers the priority of the calling thread only
+fn lower_priority() {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, JOB_NICENESS) } != 0 {
+        eprintln!("🎚️ Could not lower the transcoder's priority: {}", std::io::Error::last_os_error());
+    }
+}
+
+/// Encrypts a finished recording and the derivatives made of it, returning
+/// the files to upload in their place. The catalog is pointed at the
+/// encrypted files.
+fn encrypt_for_upload(
+    catalog: &SafeCatalog,
+    recording_id: &str,
+    source: &str,
+    outputs: &[String],
+    encryption: &AlasEncryptionConfig
+) -> Result<Vec<String>, EncryptionError> {
+    let recipients = parse_recipients(&encryption.recipients)?;
+    let mut encrypted = vec![encrypt_recording(source, &recipients)?];
+    let mut failed = Vec::new();
+    for output in outputs {
+        match encrypt_file(output, &recipients) {
+            Ok(path) => encrypted.push(path),
+            Err(e) => {
+                // A derivative can be made again, so it goes rather than staying in the clear
+                eprintln!("🔐 Could not encrypt {}, deleting it: {}", output, e);
+                let _ = std::fs::remove_file(output);
+                failed.push(output.clone());
+            }
+        }
+    }
+
+    let size_of = |path: &str| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    catalog.blocking_write().update(recording_id, |entry| {
+        entry.path = encrypted_path(&entry.path);
+        entry.file_name = encrypted_path(&entry.file_name);
+        entry.size_bytes = size_of(&entry.path);
+        entry.encrypted = true;
+        entry.derivatives.retain(|derivative| !failed.contains(&derivative.path));
+        for derivative in entry.derivatives.iter_mut().filter(|derivative| outputs.contains(&derivative.path)) {
+            derivative.path = encrypted_path(&derivative.path);
+            derivative.file_name = encrypted_path(&derivative.file_name);
+            derivative.size_bytes = size_of(&derivative.path);
+            derivative.encrypted = true;
+        }
+    });
+    Ok(encrypted)
+}
+
+/// Starts the transcoder, which makes the configured derivatives of each
+/// finished show recording, encrypts them if configured to, and then uploads
+/// the recording with them.
+///
+/// Each job runs on its own thread at the lowest priority, so that it only
+/// ever uses CPU the capture does not need. Jobs run one at a time, in the
+/// order the recordings finished; their status is kept in `AlasState` and
+/// announced with `TranscodeChanged`.
+pub fn start_transcoder(
+    bus: Sender<AlasMessage>,
+    state: &SafeState,
+    catalog: &SafeCatalog,
+    queue: &TranscodeQueue
+) -> JoinHandle<&'static str> {
+    let state = state.clone();
+    let catalog = catalog.clone();
+    let requests = queue.receiver.clone();
+    let mut receiver = bus.subscribe();
+
+    task::spawn_blocking(move || {
+        let Ok(requests) = requests.lock() else {
+            return "🎚️ Transcoder could not take the queue";
+        };
+        let mut queue: VecDeque<QueuedRecording> = VecDeque::new();
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        'recordings: loop {
+            // An exit seen while a job ran has already been taken off the bus
+            if cancel.load(Ordering::Relaxed) ||
+                accept(&requests, &mut queue, Duration::from_millis(500), &mut receiver, &state, &bus)
+            {
+                break;
+            }
+            let Some((request, ids)) = queue.pop_front() else {
+                continue;
+            };
+            let source = PathBuf::from(&request.source);
+            let duration_secs = catalog.blocking_read()
+                .get(&request.recording_id)
+                .map(|entry| entry.duration_secs)
+                .unwrap_or(0.0);
+            let mut outputs = Vec::new();
+
+            for (derivative, id) in request.derivatives.iter().zip(&ids) {
+                report(&state, &bus, id, |job| job.state = TranscodeJobState::Running);
+                let output = derivative_path(&source, &derivative.name);
+                let progress = Arc::new(AtomicU8::new(0));
+                let job = {
+                    let (source, output, derivative) = (source.clone(), output.clone(), derivative.clone());
+                    let (progress, cancel) = (progress.clone(), cancel.clone());
+                    std::thread::Builder::new().name("transcode".to_string()).spawn(move || {
+                        lower_priority();
+                        let result = transcode(&source, &output, &derivative, duration_secs, &progress, &cancel);
+                        if result.is_err() {
+                            let _ = std::fs::remove_file(&output);
+                        }
+                        result
+                    })
+                };
+                let job = match job {
+                    Ok(job) => job,
+                    Err(e) => {
+                        report(&state, &bus, id, |job| {
+                            job.state = TranscodeJobState::Failed;
+                            job.error = Some(format!("Could not start the job: {}", e));
+                            job.finished_at = Some(Utc::now());
+                        });
+                        continue;
+                    }
+                };
+
+                while !job.is_finished() {
+                    if accept(&requests, &mut queue, Duration::ZERO, &mut receiver, &state, &bus) {
+                        cancel.store(true, Ordering::Relaxed);
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                    let percent = progress.load(Ordering::Relaxed);
+                    report(&state, &bus, id, |job| job.progress = percent);
+                }
+                let result = job.join().unwrap_or_else(|_| Err(TranscodeError::Encode("The job panicked".to_string())));
+
+                match result {
+                    Ok(outcome) => {
+                        println!("🎚️ Made {}", output.display());
+                        let output = output.to_string_lossy().to_string();
+                        let size_bytes = std::fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+                        catalog.blocking_write().update(&request.recording_id, |entry| {
+                            entry.derivatives.retain(|existing| existing.name != derivative.name);
+                            entry.derivatives.push(RecordingDerivative {
+                                name: derivative.name.clone(),
+                                path: output.clone(),
+                                file_name: Path::new(&output)
+                                    .file_name()
+                                    .map(|n| n.to_string_lossy().to_string())
+                                    .unwrap_or_default(),
+                                size_bytes,
+                                bitrate: derivative.bitrate,
+                                mono: derivative.mono,
+                                loudness_lufs: outcome.measured_lufs.zip(outcome.gain_db).map(|(lufs, gain)| lufs + gain),
+                                shared_url: None,
+                                encrypted: false,
+                            });
+                        });
+                        report(&state, &bus, id, |job| {
+                            job.state = TranscodeJobState::Done;
+                            job.progress = 100;
+                            job.output = Some(output.clone());
+                            job.measured_lufs = outcome.measured_lufs;
+                            job.gain_db = outcome.gain_db;
+                            job.finished_at = Some(Utc::now());
+                        });
+                        outputs.push(output);
+                    }
+                    Err(TranscodeError::Cancelled) => {
+                        report(&state, &bus, id, |job| {
+                            job.state = TranscodeJobState::Failed;
+                            job.error = Some(TranscodeError::Cancelled.to_string());
+                            job.finished_at = Some(Utc::now());
+                        });
+                        break 'recordings;
+                    }
+                    Err(e) => {
+                        eprintln!("🎚️ Could not make {}: {}", output.display(), e);
+                        report(&state, &bus, id, |job| {
+                            job.state = TranscodeJobState::Failed;
+                            job.error = Some(e.to_string());
+                            job.finished_at = Some(Utc::now());
+                        });
+                    }
+                }
+            }
+
+            let mut uploads = vec![request.source.clone()];
+            uploads.extend(outputs);
+            if let Some(encryption) = request.encryption.clone() {
+                let job = {
+                    let (catalog, recording_id) = (catalog.clone(), request.recording_id.clone());
+                    std::thread::Builder::new().name("encrypt".to_string()).spawn(move || {
+                        lower_priority();
+                        encrypt_for_upload(&catalog, &recording_id, &uploads[0], &uploads[1..], &encryption)
+                    })
+                };
+                let result = job
+                    .map_err(EncryptionError::Io)
+                    .and_then(|job| job.join().unwrap_or_else(|_| Err(EncryptionError::Encrypt("The job panicked".to_string()))));
+                uploads = match result {
+                    Ok(encrypted) => {
+                        println!("🔐 Encrypted {}", request.source);
+                        encrypted
+                    }
+                    Err(e) => {
+                        // Nothing is uploaded in the clear
+                        eprintln!("🔐 Could not encrypt {}, so it will not be uploaded: {}", request.source, e);
+                        catalog.blocking_write().update(&request.recording_id, |entry| {
+                            entry.upload_status = RecordingUploadStatus::Failed;
+                        });
+                        continue;
+                    }
+                };
+            }
+
+            // The recording goes up even if a derivative failed
+            for upload in uploads {
+                upload_file_to_dropbox(upload, request.destination_folder.clone(), request.keep_local_copy, bus.clone());
+            }
+        }
+
+        if !queue.is_empty() {
+            println!("🎚️ {} recording(s) were still waiting to be transcoded", queue.len());
+        }
+        "🎚️ Exiting transcoder"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude_db: f32, channels: usize, secs: f32) -> Vec<f32> {
+        let amplitude = 10f32.powf(amplitude_db / 20.0);
+        (0..(48_000.0 * secs) as usize)
+            .flat_map(|i| {
+                let sample = amplitude * (2.0 * std::f32::consts::PI * 1_000.0 * i as f32 / 48_000.0).sin();
+                vec![sample; channels]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_loudness_of_reference_tone() {
+        // EBU Tech 3341: a 1 kHz tone at -23 dBFS in both channels reads -23 LUFS
+        let mut meter = LoudnessMeter::new(2, 48_000);
+        meter.add(&sine(-23.0, 2, 10.0));
+        let lufs = meter.integrated_lufs().unwrap();
+        assert!((lufs - -23.0).abs() < 0.1, "measured {}", lufs);
+        assert!((meter.peak_db() - -23.0).abs() < 0.01);
+
+        // The same tone in one channel is 3 LU quieter
+        let mut meter = LoudnessMeter::new(1, 48_000);
+        meter.add(&sine(-23.0, 1, 10.0));
+        assert!((meter.integrated_lufs().unwrap() - -26.0).abs() < 0.1);
+
+        // Silence is gated out entirely
+        let mut meter = LoudnessMeter::new(2, 48_000);
+        meter.add(&vec![0.0; 96_000]);
+        assert_eq!(meter.integrated_lufs(), None);
+    }
+
+    #[test]
+    fn test_relative_gate_ignores_quiet_passages() {
+        // Ten seconds of programme and twenty of room tone 30 dB down
+        let mut samples = sine(-20.0, 2, 10.0);
+        samples.extend(sine(-50.0, 2, 20.0));
+        let mut meter = LoudnessMeter::new(2, 48_000);
+        meter.add(&samples);
+        assert!((meter.integrated_lufs().unwrap() - -20.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_normalization_and_downmix() {
+        assert_eq!(normalization_gain_db(-23.0, -16.0, -12.0), 7.0);
+        // Held back so the peak lands on -1 dBFS
+        assert_eq!(normalization_gain_db(-23.0, -16.0, -3.0), 2.0);
+        assert_eq!(normalization_gain_db(-12.0, -16.0, -0.5), -4.0);
+
+        assert_eq!(to_output_channels(&[0.2, 0.4, -0.2, 0.0], 2, true), vec![0.3, -0.1]);
+        assert_eq!(to_output_channels(&[0.2, 0.4], 1, false), vec![0.2, 0.2, 0.4, 0.4]);
+        assert_eq!(
+            derivative_path(Path::new("/var/lib/alas/Show_2024-01-01T100000.mp3"), "podcast"),
+            PathBuf::from("/var/lib/alas/Show_2024-01-01T100000-podcast.mp3")
+        );
+    }
+}
@@ -0,0 +1,667 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSourceStream, ReadOnlySource};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use thiserror::Error;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::task;
+use tokio::task::JoinHandle;
+
+use crate::audio::calculate_rms_levels;
+use crate::config::{AlasMonitorSource, AlasOffAirConfig};
+use crate::icecast::{open_transport, read_status_line, IcecastError, Transport};
+use crate::monitor::MonitorHandle;
+use crate::state::{AlasMessage, SafeState};
+
+/// Levels on both sides are averaged into windows of this length before comparing
+const WINDOW_SECS: f64 = 0.1;
+/// How much off-air audio is compared at a time
+const EVALUATION_SECS: f64 = 10.0;
+const EVALUATION_INTERVAL: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_DB: f32 = -60.0;
+/// Input levels are never kept for longer than this, even when not listening
+const MAX_INPUT_HISTORY_SECS: f64 = 120.0;
+/// How the verifier introduces itself to Icecast, so it is not counted as a listener
+pub(crate) const USER_AGENT: &str = "alas-verifier";
+
+#[derive(Error, Debug)]
+pub enum VerifierError {
+    #[error("Invalid listen URL: {0} (expected http(s)://host:port/mount)")]
+    InvalidUrl(String),
+
+    #[error("Could not reach Icecast: {0}")]
+    Connection(#[from] IcecastError),
+
+    #[error("Connection error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Unexpected response from Icecast: {0}")]
+    BadResponse(String),
+
+    #[error("Could not decode the stream: {0}")]
+    Decode(String),
+}
+
+impl From<SymphoniaError> for VerifierError {
+    fn from(error: SymphoniaError) -> Self {
+        match error {
+            SymphoniaError::IoError(e) => VerifierError::Io(e),
+            e => VerifierError::Decode(e.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OffAirHealth {
+    /// Not streaming, or not enough audio heard back yet
+    #[default]
+    Unknown,
+    Healthy,
+    /// The return feed does not sound like what we are sending
+    Mismatch,
+    /// We could not listen to our own mount
+    Unreachable,
+}
+
+/// End-to-end health of the stream, as heard by a listener
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct OffAirStatus {
+    pub health: OffAirHealth,
+    /// Time from audio entering ALAS to a listener hearing it
+    pub latency_ms: Option<u64>,
+    /// Average level difference between the input and the return feed, in dB
+    pub level_difference_db: Option<f32>,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+/// Where to listen, split out of an `http://` or `https://` URL
+#[derive(Debug, PartialEq)]
+pub(crate) struct ListenUrl {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) path: String,
+    pub(crate) tls: bool,
+}
+
+pub(crate) fn parse_listen_url(url: &str) -> Result<ListenUrl, VerifierError> {
+    let invalid = || VerifierError::InvalidUrl(url.to_string());
+    let (rest, tls) = match url.strip_prefix("https://") {
+        Some(rest) => (rest, true),
+        None => (url.strip_prefix("http://").ok_or_else(invalid)?, false),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid())?),
+        None => (authority, if tls { 443 } else { 80 }),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok(ListenUrl {
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+        tls,
+    })
+}
+
+/// Connects to the mount like any other listener and skips past the HTTP headers
+fn open_listener_stream(url: &str) -> Result<Transport, VerifierError> {
+    let url = parse_listen_url(url)?;
+    let mut stream = open_transport(&url.host, url.port, url.tls)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}:{}\r\nUser-Agent: {}\r\nIcy-MetaData: 0\r\n\r\n",
+        url.path, url.host, url.port, USER_AGENT
+    )?;
+    stream.flush()?;
+
+    // The headers are read one byte at a time so that no audio is consumed
+    let status_line = read_status_line(&mut stream)?;
+    if !status_line.contains(" 200") {
+        return Err(VerifierError::BadResponse(status_line));
+    }
+    Ok(stream)
+}
+
+/// Decoded audio from one packet of the return feed
+pub(crate) struct DecodedAudio {
+    pub(crate) samples: Vec<f32>,
+    pub(crate) sample_rate: u32,
+    pub(crate) channels: usize,
+}
+
+/// A listener on our own mount, or on any other MP3 stream
+pub(crate) struct OffAirListener {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+}
+
+impl OffAirListener {
+    fn connect(url: &str) -> Result<Self, VerifierError> {
+        Self::from_stream(open_listener_stream(url)?)
+    }
+
+    /// Starts decoding a stream whose HTTP headers have already been read
+    pub(crate) fn from_stream(stream: Transport) -> Result<Self, VerifierError> {
+        let source = MediaSourceStream::new(Box::new(ReadOnlySource::new(stream)), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("mp3");
+
+        let probed = symphonia::default::get_probe().format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default()
+        )?;
+        let format = probed.format;
+        let track = format
+            .default_track()
+            .ok_or_else(|| VerifierError::Decode("No audio track".to_string()))?;
+        let track_id = track.id;
+        let decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+        Ok(OffAirListener { format, decoder, track_id })
+    }
+
+    pub(crate) fn next_audio(&mut self) -> Result<DecodedAudio, VerifierError> {
+        loop {
+            let packet = self.format.next_packet()?;
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                    buffer.copy_interleaved_ref(decoded);
+                    return Ok(DecodedAudio {
+                        samples: buffer.samples().to_vec(),
+                        sample_rate: spec.rate,
+                        channels: spec.channels.count(),
+                    });
+                }
+                // A corrupt frame is not fatal, just skip it
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Averages levels into fixed windows, producing `(window centre, level)` points
+#[derive(Default)]
+struct EnvelopeBuilder {
+    window: Option<i64>,
+    total: f32,
+    count: u32,
+}
+
+impl EnvelopeBuilder {
+    fn add(&mut self, time: f64, level: f32) -> Option<(f64, f32)> {
+        let window = (time / WINDOW_SECS).floor() as i64;
+        let mut finished = None;
+        if let Some(current) = self.window
+            && current != window
+            && self.count > 0
+        {
+            let centre = (current as f64 + 0.5) * WINDOW_SECS;
+            finished = Some((centre, self.total / self.count as f32));
+            self.total = 0.0;
+            self.count = 0;
+        }
+        self.window = Some(window);
+        self.total += level.max(MIN_DB);
+        self.count += 1;
+        finished
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct EnvelopeMatch {
+    latency_secs: f64,
+    difference_db: f32,
+}
+
+/// Finds the input level nearest to `time`, if there is one close enough
+fn level_at(envelope: &[(f64, f32)], time: f64) -> Option<f32> {
+    let index = envelope.partition_point(|(t, _)| *t < time);
+    [index.checked_sub(1), Some(index)]
+        .into_iter()
+        .flatten()
+        .filter_map(|i| envelope.get(i))
+        .filter(|(t, _)| (t - time).abs() <= WINDOW_SECS * 0.6)
+        .min_by(|a, b| (a.0 - time).abs().total_cmp(&(b.0 - time).abs()))
+        .map(|(_, level)| *level)
+}
+
+/// Slides the off-air envelope back in time against the input envelope, and
+/// returns the delay at which they line up best along with how far apart they
+/// still are. When the audio barely changes, every delay fits equally well and
+/// the shortest one is reported.
+fn compare_envelopes(input: &[(f64, f32)], off_air: &[(f64, f32)], max_latency_secs: f64) -> Option<EnvelopeMatch> {
+    let steps = (max_latency_secs / WINDOW_SECS).round() as usize;
+    let mut best: Option<EnvelopeMatch> = None;
+
+    for step in 0..=steps {
+        let latency_secs = step as f64 * WINDOW_SECS;
+        let differences: Vec<f32> = off_air
+            .iter()
+            .filter_map(|(time, level)| level_at(input, time - latency_secs).map(|input| (level - input).abs()))
+            .collect();
+
+        // Only trust a delay if most of the return feed overlaps the input we remember
+        if differences.is_empty() || differences.len() * 2 < off_air.len() {
+            continue;
+        }
+        let difference_db = differences.iter().sum::<f32>() / differences.len() as f32;
+        if best.as_ref().is_none_or(|best| difference_db < best.difference_db) {
+            best = Some(EnvelopeMatch { latency_secs, difference_db });
+        }
+    }
+    best
+}
+
+/// Collects input levels from the bus. Returns true once we have been asked to exit.
+fn drain_bus(
+    receiver: &mut Receiver<AlasMessage>,
+    started: Instant,
+    input: &mut EnvelopeBuilder,
+    input_history: &mut VecDeque<(f64, f32)>
+) -> bool {
+    loop {
+        match receiver.try_recv() {
+            Ok(AlasMessage::VolumeChange { left, right }) => {
+                let time = started.elapsed().as_secs_f64();
+                if let Some(point) = input.add(time, left.max(right)) {
+                    input_history.push_back(point);
+                }
+                while input_history.front().is_some_and(|(t, _)| *t < time - MAX_INPUT_HISTORY_SECS) {
+                    input_history.pop_front();
+                }
+            }
+            Ok(AlasMessage::Exit) | Err(TryRecvError::Closed) => return true,
+            Ok(_) | Err(TryRecvError::Lagged(_)) => continue,
+            Err(TryRecvError::Empty) => return false,
+        }
+    }
+}
+
+/// Waits for `duration` while keeping up with the bus. Returns true if we should exit.
+fn wait(
+    duration: Duration,
+    receiver: &mut Receiver<AlasMessage>,
+    started: Instant,
+    input: &mut EnvelopeBuilder,
+    input_history: &mut VecDeque<(f64, f32)>
+) -> bool {
+    let until = Instant::now() + duration;
+    while Instant::now() < until {
+        if drain_bus(receiver, started, input, input_history) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    false
+}
+
+/// Publishes the latest status, raising an alarm when the stream goes bad
+fn report(state: &SafeState, bus: &Sender<AlasMessage>, status: OffAirStatus) {
+    let previous = {
+        let mut state = state.blocking_write();
+        std::mem::replace(&mut state.off_air, status.clone())
+    };
+
+    let is_bad = |health| matches!(health, OffAirHealth::Mismatch | OffAirHealth::Unreachable);
+    if is_bad(status.health) && !is_bad(previous.health) {
+        eprintln!("📡 Off-air alarm: {:?}", status);
+        let _ = bus.send(AlasMessage::OffAirAlarm { status });
+    } else if status.health == OffAirHealth::Healthy && is_bad(previous.health) {
+        println!("📡 Off-air feed is healthy again");
+        let _ = bus.send(AlasMessage::OffAirRecovered { status });
+    }
+}
+
+/// The URL to listen on, defaulting to the mount we stream to
+fn listen_url(state: &SafeState) -> Option<(String, AlasOffAirConfig)> {
+    let state = state.blocking_read();
+    let off_air = state.config.off_air.clone()?;
+    if !state.is_streaming {
+        return None;
+    }
+    let icecast = &state.config.icecast;
+    let url = off_air.listen_url.clone().unwrap_or_else(|| {
+        let scheme = if icecast.tls { "https" } else { "http" };
+        format!("{}://{}:{}{}", scheme, icecast.hostname, icecast.port, icecast.mount)
+    });
+    Some((url, off_air))
+}
+
+/// How far back the input is searched for: the latency allowed for the trip
+/// through Icecast, plus the profanity delay the stream is held back by
+fn search_window_secs(state: &SafeState, config: &AlasOffAirConfig) -> f64 {
+    let delay_secs = state.blocking_read().config.delay.as_ref().map_or(0, |delay| delay.delay_secs);
+    (config.max_latency_secs + delay_secs) as f64
+}
+
+/// Starts the off-air verifier.
+///
+/// While we are streaming, this connects to our own Icecast mount as a listener,
+/// decodes what comes back and checks that its levels track the input. The
+/// result is kept in `AlasState::off_air`, and an `OffAirAlarm` is raised when
+/// the return feed stops matching or cannot be reached. Nothing is compared
+/// while the stream plays the fallback audio. The decoded audio also feeds the
+/// confidence monitor's off-air source.
+pub fn start_off_air_verifier(bus: Sender<AlasMessage>, state: &SafeState, monitor: &MonitorHandle) -> JoinHandle<&'static str> {
+    let state = state.clone();
+    let monitor = monitor.clone();
+    let mut receiver = bus.subscribe();
+
+    task::spawn_blocking(move || {
+        let started = Instant::now();
+        let mut input = EnvelopeBuilder::default();
+        let mut input_history: VecDeque<(f64, f32)> = VecDeque::new();
+
+        loop {
+            let Some((url, config)) = listen_url(&state) else {
+                if state.blocking_read().off_air.health != OffAirHealth::Unknown {
+                    report(&state, &bus, OffAirStatus::default());
+                }
+                if wait(Duration::from_secs(1), &mut receiver, started, &mut input, &mut input_history) {
+                    break;
+                }
+                continue;
+            };
+
+            let mut listener = match OffAirListener::connect(&url) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    report(&state, &bus, OffAirStatus {
+                        health: OffAirHealth::Unreachable,
+                        checked_at: Some(Utc::now()),
+                        error: Some(e.to_string()),
+                        ..Default::default()
+                    });
+                    if wait(RETRY_INTERVAL, &mut receiver, started, &mut input, &mut input_history) {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            println!("📡 Listening to the off-air feed at {}", url);
+
+            // Play the return feed back in real time from when we connected, just
+            // like a listener would, stalling if the network falls behind.
+            let connected = started.elapsed().as_secs_f64();
+            let mut heard_secs = 0.0;
+            let mut off_air = EnvelopeBuilder::default();
+            let mut off_air_history: VecDeque<(f64, f32)> = VecDeque::new();
+            let mut last_evaluation = Instant::now();
+            // Comparisons are held off until this time, in seconds since we started
+            let mut resume_at = 0.0;
+
+            loop {
+                if drain_bus(&mut receiver, started, &mut input, &mut input_history) {
+                    return "✅ Exiting off-air verifier";
+                }
+                if listen_url(&state).is_none_or(|(current, _)| current != url) {
+                    break;
+                }
+
+                let audio = match listener.next_audio() {
+                    Ok(audio) => audio,
+                    Err(e) => {
+                        report(&state, &bus, OffAirStatus {
+                            health: OffAirHealth::Unreachable,
+                            checked_at: Some(Utc::now()),
+                            error: Some(e.to_string()),
+                            ..Default::default()
+                        });
+                        break;
+                    }
+                };
+                if audio.channels == 0 || audio.sample_rate == 0 {
+                    continue;
+                }
+
+                if audio.channels == 2 && audio.sample_rate == 48_000 {
+                    monitor.push(AlasMonitorSource::OffAir, &audio.samples);
+                }
+
+                let (left, right) = calculate_rms_levels(&audio.samples, audio.channels);
+                let now = started.elapsed().as_secs_f64();
+                let time = (connected + heard_secs).max(now);
+                heard_secs = time - connected + audio.samples.len() as f64 / audio.channels as f64 / audio.sample_rate as f64;
+                if let Some(point) = off_air.add(time, left.max(right)) {
+                    off_air_history.push_back(point);
+                }
+
+                // Only remember as much as we can compare
+                while off_air_history.front().is_some_and(|(t, _)| *t < time - EVALUATION_SECS) {
+                    off_air_history.pop_front();
+                }
+                let window_secs = search_window_secs(&state, &config);
+                let keep_input = EVALUATION_SECS + window_secs + 1.0;
+                while input_history.front().is_some_and(|(t, _)| *t < now - keep_input) {
+                    input_history.pop_front();
+                }
+                // The fallback audio is not the input, so it is not compared
+                // until it has made its way out of the return feed
+                if state.blocking_read().is_on_fallback {
+                    resume_at = now + window_secs + EVALUATION_SECS;
+                }
+
+                let covered = off_air_history.back().zip(off_air_history.front()).map(|(b, f)| b.0 - f.0);
+                if last_evaluation.elapsed() < EVALUATION_INTERVAL || covered.unwrap_or(0.0) < EVALUATION_SECS * 0.9 {
+                    continue;
+                }
+                last_evaluation = Instant::now();
+                if now < resume_at {
+                    report(&state, &bus, OffAirStatus {
+                        health: OffAirHealth::Unknown,
+                        checked_at: Some(Utc::now()),
+                        error: Some("Not checked while the fallback audio plays".to_string()),
+                        ..Default::default()
+                    });
+                    continue;
+                }
+
+                let comparison = compare_envelopes(
+                    input_history.make_contiguous(),
+                    off_air_history.make_contiguous(),
+                    window_secs
+                );
+                let status = match comparison {
+                    Some(comparison) => OffAirStatus {
+                        health: if comparison.difference_db <= config.tolerance_db {
+                            OffAirHealth::Healthy
+                        } else {
+                            OffAirHealth::Mismatch
+                        },
+                        latency_ms: Some((comparison.latency_secs * 1000.0).round() as u64),
+                        level_difference_db: Some(comparison.difference_db),
+                        checked_at: Some(Utc::now()),
+                        error: None,
+                    },
+                    None => OffAirStatus {
+                        health: OffAirHealth::Mismatch,
+                        checked_at: Some(Utc::now()),
+                        error: Some("The return feed does not line up with the input".to_string()),
+                        ..Default::default()
+                    },
+                };
+                report(&state, &bus, status);
+            }
+        }
+
+        "✅ Exiting off-air verifier"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use crate::audio::{build_mp3_encoder, make_mp3_samples};
+    use mp3lame_encoder::Bitrate;
+
+    /// A repeatable envelope that changes enough to line up unambiguously
+    fn varying_envelope(start: f64, seconds: f64, offset: f64) -> Vec<(f64, f32)> {
+        let mut seed: u32 = 7;
+        (0..(seconds / WINDOW_SECS) as usize)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let level = -40.0 + (seed >> 16) as f32 % 35.0;
+                (start + (i as f64 + 0.5) * WINDOW_SECS + offset, level)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_listen_url() {
+        assert_eq!(parse_listen_url("http://localhost:8000/live.mp3").unwrap(), ListenUrl {
+            host: "localhost".to_string(),
+            port: 8000,
+            path: "/live.mp3".to_string(),
+            tls: false,
+        });
+        assert_eq!(parse_listen_url("https://radio.example.org").unwrap(), ListenUrl {
+            host: "radio.example.org".to_string(),
+            port: 443,
+            path: "/".to_string(),
+            tls: true,
+        });
+        assert!(parse_listen_url("ftp://radio.example.org/live").is_err());
+        assert!(parse_listen_url("http://:8000/live").is_err());
+    }
+
+    #[test]
+    fn test_envelope_builder() {
+        let mut builder = EnvelopeBuilder::default();
+        assert_eq!(builder.add(0.01, -20.0), None);
+        assert_eq!(builder.add(0.05, -10.0), None);
+        assert_eq!(builder.add(0.12, -30.0), Some((0.05, -15.0)));
+    }
+
+    #[test]
+    fn test_finds_latency() {
+        let input = varying_envelope(0.0, 30.0, 0.0);
+        // The last ten seconds of the input, heard 2.3 seconds later
+        let off_air: Vec<(f64, f32)> = input[200..].iter().map(|(t, level)| (t + 2.3, *level)).collect();
+
+        let comparison = compare_envelopes(&input, &off_air, 5.0).unwrap();
+        assert!((comparison.latency_secs - 2.3).abs() < 0.01);
+        assert!(comparison.difference_db < 0.01);
+    }
+
+    #[test]
+    fn test_detects_mismatch() {
+        let input = varying_envelope(0.0, 30.0, 0.0);
+        // A dead mount that only carries silence
+        let off_air: Vec<(f64, f32)> = input[200..].iter().map(|(t, _)| (t + 1.0, MIN_DB)).collect();
+
+        let comparison = compare_envelopes(&input, &off_air, 5.0).unwrap();
+        assert!(comparison.difference_db > 6.0);
+    }
+
+    #[test]
+    fn test_window_covers_profanity_delay() {
+        let state = std::sync::Arc::new(tokio::sync::RwLock::new(crate::state::AlasState::test()));
+        let config = AlasOffAirConfig::default();
+        assert_eq!(search_window_secs(&state, &config), 15.0);
+
+        state.blocking_write().config.delay = Some(crate::config::AlasDelayConfig {
+            delay_secs: 20,
+            rebuild_percent: 5,
+        });
+        let window_secs = search_window_secs(&state, &config);
+        assert_eq!(window_secs, 35.0);
+
+        // Twenty seconds of delay plus a second of network latency still lines up
+        let input = varying_envelope(0.0, 60.0, 0.0);
+        let off_air: Vec<(f64, f32)> = input[350..450].iter().map(|(t, level)| (t + 21.0, *level)).collect();
+        let comparison = compare_envelopes(&input, &off_air, window_secs).unwrap();
+        assert!((comparison.latency_secs - 21.0).abs() < 0.01);
+        assert!(comparison.difference_db < 0.01);
+    }
+
+    /// A bare-bones stand-in for Icecast that serves a tone to one listener
+    fn start_icecast_stand_in(amplitude: f32) -> String {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let (mut client, _) = server.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = client.read(&mut request);
+            client.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: audio/mpeg\r\n\r\n").unwrap();
+
+            let mut encoder = build_mp3_encoder(Bitrate::Kbps128);
+            let tone: Vec<f32> = (0..48_000)
+                .flat_map(|i| {
+                    let sample = amplitude * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 48_000.0).sin();
+                    [sample, sample]
+                })
+                .collect();
+            for _ in 0..3 {
+                if client.write_all(&make_mp3_samples(&mut encoder, &tone)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        format!("http://{}/live.mp3", address)
+    }
+
+    #[test]
+    fn test_listens_to_local_icecast() {
+        let url = start_icecast_stand_in(0.5);
+        let mut listener = OffAirListener::connect(&url).unwrap();
+
+        let mut samples = Vec::new();
+        while samples.len() < 48_000 * 2 {
+            let audio = listener.next_audio().unwrap();
+            assert_eq!((audio.sample_rate, audio.channels), (48_000, 2));
+            samples.extend(audio.samples);
+        }
+
+        // A sine wave at half scale has an RMS level of about -9 dB
+        let (left, right) = calculate_rms_levels(&samples[4800..], 2);
+        assert!((left + 9.0).abs() < 1.5, "left level was {}", left);
+        assert!((right + 9.0).abs() < 1.5, "right level was {}", right);
+    }
+
+    #[test]
+    fn test_rejects_missing_mount() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut client, _) = server.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = client.read(&mut request);
+            let _ = client.write_all(b"HTTP/1.0 404 File Not Found\r\n\r\n");
+        });
+
+        let result = OffAirListener::connect(&format!("http://{}/missing.mp3", address));
+        assert!(matches!(result, Err(VerifierError::BadResponse(_))));
+    }
+}
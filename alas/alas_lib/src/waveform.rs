@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+use crate::audio::float_to_i16;
+
+/// Samples of each channel folded into every min/max pair while recording.
+/// Coarser waveforms are made from this by merging pixels.
+pub const SAMPLES_PER_PIXEL: u32 = 256;
+const SAMPLE_RATE: u32 = 48_000;
+/// The BBC audiowaveform binary format, version 1: one channel of 16-bit pairs
+const DAT_VERSION: i32 = 1;
+const DAT_HEADER_BYTES: usize = 20;
+const DAT_LENGTH_OFFSET: u64 = 16;
+/// Set in the header's flags when the pairs are 8-bit
+const DAT_FLAG_8_BIT: u32 = 1;
+
+pub fn waveform_path(recording_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.waveform.dat", recording_path))
+}
+
+pub fn remove_waveform(recording_path: &str) {
+    let path = waveform_path(recording_path);
+    if path.exists()
+        && let Err(e) = std::fs::remove_file(&path)
+    {
+        eprintln!("〰️ Could not delete {}: {}", path.display(), e);
+    }
+}
+
+/// Min/max peaks of a recording, laid out like audiowaveform's JSON output
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Waveform {
+    pub version: u32,
+    pub channels: u32,
+    pub sample_rate: u32,
+    pub samples_per_pixel: u32,
+    pub bits: u32,
+    pub length: u32,
+    /// The min and then the max of each pixel
+    pub data: Vec<i16>,
+}
+
+impl Waveform {
+    /// Reads a waveform file, which may still be being written
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+        if bytes.len() < DAT_HEADER_BYTES {
+            return Err(invalid("Waveform header is incomplete"));
+        }
+        let field = |index: usize| u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+        if field(0) != DAT_VERSION as u32 || field(1) & DAT_FLAG_8_BIT != 0 {
+            return Err(invalid("Only 16-bit version 1 waveforms are supported"));
+        }
+
+        // The length is only filled in once the recording has finished
+        let available = (bytes.len() - DAT_HEADER_BYTES) / 4;
+        let length = match field(4) as usize {
+            0 => available,
+            length => length.min(available),
+        };
+        let data = bytes[DAT_HEADER_BYTES..DAT_HEADER_BYTES + length * 4]
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+
+        Ok(Waveform {
+            version: 2,
+            channels: 1,
+            sample_rate: field(2),
+            samples_per_pixel: field(3),
+            bits: 16,
+            length: length as u32,
+            data,
+        })
+    }
+
+    /// Merges every `factor` pixels into one, for a zoomed out view
+    pub fn zoom_out(self, factor: u32) -> Self {
+        if factor <= 1 {
+            return self;
+        }
+        let data: Vec<i16> = self.data
+            .chunks(factor as usize * 2)
+            .flat_map(|pixels| {
+                let min = pixels.iter().step_by(2).copied().min().unwrap_or(0);
+                let max = pixels.iter().skip(1).step_by(2).copied().max().unwrap_or(0);
+                [min, max]
+            })
+            .collect();
+        Waveform {
+            samples_per_pixel: self.samples_per_pixel * factor,
+            length: (data.len() / 2) as u32,
+            data,
+            ..self
+        }
+    }
+
+    /// The waveform in audiowaveform's binary format, as read by e.g. peaks.js
+    pub fn to_dat(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(DAT_HEADER_BYTES + self.data.len() * 2);
+        bytes.extend(DAT_VERSION.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(self.sample_rate.to_le_bytes());
+        bytes.extend(self.samples_per_pixel.to_le_bytes());
+        bytes.extend(self.length.to_le_bytes());
+        for value in &self.data {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// Folds the recording into min/max peaks as it is written, so the waveform
+/// is ready without decoding the MP3 again. Both channels go into the one
+/// waveform.
+pub struct WaveformWriter {
+    file: BufWriter<File>,
+    frames_in_pixel: u32,
+    min: i16,
+    max: i16,
+    length: u32,
+}
+
+impl WaveformWriter {
+    pub fn create(recording_path: &str) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(waveform_path(recording_path))?);
+        file.write_all(&DAT_VERSION.to_le_bytes())?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        file.write_all(&SAMPLES_PER_PIXEL.to_le_bytes())?;
+        // Filled in by finish()
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(WaveformWriter {
+            file,
+            frames_in_pixel: 0,
+            min: i16::MAX,
+            max: i16::MIN,
+            length: 0,
+        })
+    }
+
+    /// Adds interleaved stereo audio
+    pub fn add(&mut self, samples: &[f32]) -> io::Result<()> {
+        for frame in samples.chunks_exact(2) {
+            for sample in frame {
+                let sample = float_to_i16(*sample);
+                self.min = self.min.min(sample);
+                self.max = self.max.max(sample);
+            }
+            self.frames_in_pixel += 1;
+            if self.frames_in_pixel == SAMPLES_PER_PIXEL {
+                self.write_pixel()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_pixel(&mut self) -> io::Result<()> {
+        self.file.write_all(&self.min.to_le_bytes())?;
+        self.file.write_all(&self.max.to_le_bytes())?;
+        self.length += 1;
+        self.frames_in_pixel = 0;
+        self.min = i16::MAX;
+        self.max = i16::MIN;
+        Ok(())
+    }
+
+    /// Writes out the last, partly filled pixel and the final length
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.frames_in_pixel > 0 {
+            self.write_pixel()?;
+        }
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(DAT_LENGTH_OFFSET))?;
+        file.write_all(&self.length.to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording_path() -> String {
+        std::env::temp_dir()
+            .join(format!("alas-waveform-{}.mp3", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn test_writes_peaks_while_recording() {
+        let path = recording_path();
+        let mut writer = WaveformWriter::create(&path).unwrap();
+        // Two and a half pixels of a quiet and then a loud, lopsided signal
+        writer.add(&[0.25, -0.25].repeat(SAMPLES_PER_PIXEL as usize)).unwrap();
+        writer.add(&[0.5, -1.0].repeat(SAMPLES_PER_PIXEL as usize * 3 / 2)).unwrap();
+        writer.file.flush().unwrap();
+
+        // While recording, the pixels written so far can be read
+        let waveform = Waveform::read(&waveform_path(&path)).unwrap();
+        assert_eq!(waveform.length, 2);
+        assert_eq!(waveform.data, vec![-8191, 8191, -32767, 16383]);
+
+        writer.finish().unwrap();
+        let waveform = Waveform::read(&waveform_path(&path)).unwrap();
+        assert_eq!(waveform.length, 3);
+        assert_eq!(waveform.sample_rate, 48_000);
+        assert_eq!(waveform.samples_per_pixel, SAMPLES_PER_PIXEL);
+
+        remove_waveform(&path);
+        assert!(!waveform_path(&path).exists());
+    }
+
+    #[test]
+    fn test_zoom_out_merges_pixels() {
+        let waveform = Waveform {
+            version: 2,
+            channels: 1,
+            sample_rate: 48_000,
+            samples_per_pixel: 256,
+            bits: 16,
+            length: 3,
+            data: vec![-10, 20, -30, 5, -1, 1],
+        };
+        let zoomed = waveform.zoom_out(2);
+        assert_eq!(zoomed.samples_per_pixel, 512);
+        assert_eq!(zoomed.length, 2);
+        assert_eq!(zoomed.data, vec![-30, 20, -1, 1]);
+    }
+
+    #[test]
+    fn test_dat_round_trip() {
+        let path = recording_path();
+        let mut writer = WaveformWriter::create(&path).unwrap();
+        writer.add(&[0.1, -0.2].repeat(SAMPLES_PER_PIXEL as usize * 4)).unwrap();
+        writer.finish().unwrap();
+
+        let file = waveform_path(&path);
+        let waveform = Waveform::read(&file).unwrap();
+        assert_eq!(waveform.to_dat(), std::fs::read(&file).unwrap());
+
+        // Anything but 16-bit version 1 is turned away
+        let mut bytes = waveform.to_dat();
+        bytes[4] = DAT_FLAG_8_BIT as u8;
+        std::fs::write(&file, bytes).unwrap();
+        assert!(Waveform::read(&file).is_err());
+        remove_waveform(&path);
+    }
+}
@@ -1,21 +1,25 @@
 use serde_json::json;
 use tokio::spawn;
 use tokio::sync::broadcast::Receiver;
-use crate::config::AlasConfig;
+use crate::config::{AlasConfig, AlasToneAction};
+use crate::listeners::ListenerStats;
 use crate::state::{AlasMessage, SafeState};
 
 #[derive(Clone, Debug)]
 pub struct WebhookPayload {
     pub version: u32,
     pub state: String,
+    /// Current and peak listeners across our mounts, if the server has been polled
+    pub listeners: Option<(u32, u32)>,
 }
 
-pub async fn send_webhook_notification(config: &AlasConfig, state: &str) {
+pub async fn send_webhook_notification(config: &AlasConfig, state: &str, listeners: &ListenerStats) {
     if let Some(webhook_config) = &config.webhook {
         let url = webhook_config.url.clone();
         let payload = WebhookPayload {
             version: 1,
             state: state.to_string(),
+            listeners: listeners.total_listeners().zip(listeners.total_peak()),
         };
 
         println!("🪝 Attempting to send webhook to: {}", url);
@@ -36,7 +40,9 @@ async fn send_webhook_request(url: &str, payload: WebhookPayload) -> Result<(),
     
     let json_payload = json!({
         "version": payload.version,
-        "state": payload.state
+        "state": payload.state,
+        "listeners": payload.listeners.map(|(listeners, _)| listeners),
+        "listener_peak": payload.listeners.map(|(_, peak)| peak)
     });
     
     let response = client
@@ -64,12 +70,33 @@ pub async fn start_webhook_listener(
         loop {
             match receiver.recv().await {
                 Ok(AlasMessage::StreamingStarted) => {
-                    let config = state.read().await.config.clone();
-                    send_webhook_notification(&config, "recording").await;
+                    let state = state.read().await;
+                    send_webhook_notification(&state.config, "recording", &state.listeners).await;
                 }
                 Ok(AlasMessage::StreamingStopped) => {
-                    let config = state.read().await.config.clone();
-                    send_webhook_notification(&config, "stopped").await;
+                    let state = state.read().await;
+                    send_webhook_notification(&state.config, "stopped", &state.listeners).await;
+                }
+                Ok(AlasMessage::OffAirAlarm { .. }) => {
+                    let state = state.read().await;
+                    send_webhook_notification(&state.config, "off_air_alarm", &state.listeners).await;
+                }
+                Ok(AlasMessage::OffAirRecovered { .. }) => {
+                    let state = state.read().await;
+                    send_webhook_notification(&state.config, "off_air_recovered", &state.listeners).await;
+                }
+                Ok(AlasMessage::FallbackStarted) => {
+                    let state = state.read().await;
+                    send_webhook_notification(&state.config, "fallback_started", &state.listeners).await;
+                }
+                Ok(AlasMessage::FallbackStopped) => {
+                    let state = state.read().await;
+                    send_webhook_notification(&state.config, "fallback_stopped", &state.listeners).await;
+                }
+                Ok(AlasMessage::ToneCommand { trigger, action: AlasToneAction::Webhook }) => {
+                    let state = state.read().await;
+                    let tone_state = format!("tone:{}", trigger);
+                    send_webhook_notification(&state.config, &tone_state, &state.listeners).await;
                 }
                 Ok(AlasMessage::Exit) => {
                     println!("✅ Exiting webhook listener!");
@@ -98,12 +125,24 @@ mod tests {
             audio: AlasAudioConfig {
                 silence_duration_before_deactivation: 15,
                 silence_threshold: -55.0,
+                silence_stop_threshold: None,
+                stream: None,
+                record: None,
+                inputs: Vec::new(),
             },
             icecast: AlasIcecastConfig {
                 hostname: "localhost".to_string(),
                 port: 8000,
                 mount: "/test.mp3".to_string(),
                 password: "password".to_string(),
+                user: "source".to_string(),
+                protocol: Default::default(),
+                tls: false,
+                name: None,
+                genre: None,
+                description: None,
+                url: None,
+                public: false,
             },
             cellular: AlasCellularConfig {
                 apn: "test".to_string(),
@@ -116,6 +155,20 @@ mod tests {
             dropbox: None,
             redundancy: None,
             webhook: webhook_url.map(|url| AlasWebhookConfig { url }),
+            schedule: None,
+            logger: None,
+            storage: None,
+            monitor: None,
+            off_air: None,
+            adaptive_bitrate: None,
+            fallback: None,
+            delay: None,
+            tones: None,
+            level_history: None,
+            ifb: None,
+            transcode: None,
+            podcast: None,
+            encryption: None,
         }
     }
 
@@ -124,6 +177,7 @@ mod tests {
         let payload = WebhookPayload {
             version: 1,
             state: "recording".to_string(),
+            listeners: None,
         };
 
         let json_payload = json!({
@@ -142,7 +196,7 @@ mod tests {
         let config = create_test_config(None);
         
         // This should not panic or cause issues when webhook is None
-        send_webhook_notification(&config, "recording").await;
+        send_webhook_notification(&config, "recording", &ListenerStats::default()).await;
     }
 
     #[tokio::test]
@@ -163,6 +217,33 @@ mod tests {
         let payload = WebhookPayload {
             version: 1,
             state: "recording".to_string(),
+            listeners: None,
+        };
+
+        let result = send_webhook_request(&format!("{}/webhook", mock_server.uri()), payload).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_webhook_request_includes_listeners() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/webhook"))
+            .and(body_partial_json(json!({
+                "state": "stopped",
+                "listeners": 4,
+                "listener_peak": 9
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let payload = WebhookPayload {
+            version: 1,
+            state: "stopped".to_string(),
+            listeners: Some((4, 9)),
         };
 
         let result = send_webhook_request(&format!("{}/webhook", mock_server.uri()), payload).await;
@@ -183,6 +264,7 @@ mod tests {
         let payload = WebhookPayload {
             version: 1,
             state: "recording".to_string(),
+            listeners: None,
         };
 
         let result = send_webhook_request(&format!("{}/webhook", mock_server.uri()), payload).await;
@@ -194,6 +276,7 @@ mod tests {
         let payload = WebhookPayload {
             version: 1,
             state: "recording".to_string(),
+            listeners: None,
         };
 
         let result = send_webhook_request("invalid-url", payload).await;
@@ -205,10 +288,10 @@ mod tests {
         let config = create_test_config(Some("https://example.com/webhook".to_string()));
         
         // This should not panic or block - it spawns a task internally
-        send_webhook_notification(&config, "recording").await;
+        send_webhook_notification(&config, "recording", &ListenerStats::default()).await;
         
         // Test that it works with stopped state too
-        send_webhook_notification(&config, "stopped").await;
+        send_webhook_notification(&config, "stopped", &ListenerStats::default()).await;
         
         // The function should return immediately since it spawns tasks
         assert!(true);
@@ -236,6 +319,7 @@ mod tests {
             is_streaming: false,
             is_recording: false,
             is_audio_present: false,
+            is_on_fallback: false,
             audio_last_seen: 0,
             config,
             upload_state: crate::state::AlasUploadState {
@@ -243,6 +327,19 @@ mod tests {
                 progress: 0,
                 queue: Vec::new(),
             },
+            schedule: Default::default(),
+            disk_free_mb: None,
+            disk_low: false,
+            off_air: Default::default(),
+            listeners: Default::default(),
+            stream: Default::default(),
+            stream_connection: Default::default(),
+            stream_delay: None,
+            record_override: None,
+            calibration: Default::default(),
+            input_levels: Vec::new(),
+            ifb: Default::default(),
+            transcodes: Vec::new(),
         }));
 
         let (sender, receiver) = broadcast::channel(10);
@@ -271,12 +368,24 @@ mod tests {
             audio: AlasAudioConfig {
                 silence_duration_before_deactivation: 15,
                 silence_threshold: -55.0,
+                silence_stop_threshold: None,
+                stream: None,
+                record: None,
+                inputs: Vec::new(),
             },
             icecast: AlasIcecastConfig {
                 hostname: "localhost".to_string(),
                 port: 8000,
                 mount: "/test.mp3".to_string(),
                 password: "password".to_string(),
+                user: "source".to_string(),
+                protocol: Default::default(),
+                tls: false,
+                name: None,
+                genre: None,
+                description: None,
+                url: None,
+                public: false,
             },
             cellular: AlasCellularConfig {
                 apn: "test".to_string(),
@@ -289,6 +398,20 @@ mod tests {
             dropbox: None,
             redundancy: None,
             webhook: Some(webhook_config),
+            schedule: None,
+            logger: None,
+            storage: None,
+            monitor: None,
+            off_air: None,
+            adaptive_bitrate: None,
+            fallback: None,
+            delay: None,
+            tones: None,
+            level_history: None,
+            ifb: None,
+            transcode: None,
+            podcast: None,
+            encryption: None,
         };
 
         let serialized = serde_json::to_string(&config).expect("Failed to serialize config");
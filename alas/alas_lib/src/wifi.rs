@@ -7,7 +7,7 @@ use crate::network_manager::{
     StateChangedArgs,
     WiFiDeviceProxy,
 };
-use crate::state::AlasMessage;
+use crate::state::{ AlasMessage, SafeState };
 use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -245,13 +245,15 @@ pub enum AlasWiFiState {
 /// WiFiObserver allows us to subscribe to signals from D-bus about the state of Wi-Fi.
 pub struct WiFiObserver {
     pub state: RwLock<Option<AlasWiFiState>>,
+    pub app_state: SafeState,
     pub sender: Sender<AlasMessage>,
 }
 
 impl WiFiObserver {
-    pub fn new(sender: Sender<AlasMessage>) -> Self {
+    pub fn new(sender: Sender<AlasMessage>, app_state: &SafeState) -> Self {
         WiFiObserver {
             state: RwLock::new(None),
+            app_state: app_state.clone(),
             sender,
         }
     }
@@ -314,6 +316,8 @@ impl WiFiObserver {
             };
             let mut state = self.state.write().await;
             *state = Some(new_state.clone());
+            // The stream's bitrate depends on which links are up
+            self.app_state.write().await.wifi_on = new_state == AlasWiFiState::Connected;
             let _ = self.sender.send(AlasMessage::NetworkStatusChange {
                 new_state,
             });
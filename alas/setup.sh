@@ -65,6 +65,7 @@ sudo chmod +x "$ENGARDE_BINARY"
 # Create alas folders
 sudo mkdir /var/lib/alas
 sudo mkdir /var/lib/alas/backups
+sudo mkdir /var/lib/alas/logger
 
 # Setup the alas service
 sudo bash -c "cat > /etc/systemd/system/alas.service" <<'EOF'
@@ -1,5 +1,6 @@
 import { useAuthStore } from "./auth-store";
 import { useNavigate } from "react-router-dom";
+import type { MeterReading } from "../types";
 
 export function useApi() {
   const authStore = useAuthStore();
@@ -121,9 +122,19 @@ export function useApi() {
     },
 
     subscribeToVolumeUpdates(callback: (volume: number) => void) {
-      const eventSource = new EventSource(`${API_BASE}/status/meter`);
+      return this.subscribeToMeter({}, (reading) => callback(reading.left.rms_db));
+    },
+
+    subscribeToMeter(
+      options: { rate?: number; spectrum?: boolean },
+      callback: (reading: MeterReading) => void
+    ) {
+      const params = new URLSearchParams();
+      if (options.rate) params.set("rate", options.rate.toString());
+      if (options.spectrum) params.set("spectrum", "true");
+      const eventSource = new EventSource(`${API_BASE}/status/meter?${params}`);
       eventSource.onmessage = (event) => {
-        callback(parseFloat(event.data));
+        callback(JSON.parse(event.data));
       };
       eventSource.onerror = () => {
         // EventSource doesn't provide status codes, so we'll check if auth is still valid
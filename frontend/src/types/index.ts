@@ -49,6 +49,28 @@ export interface SystemStatus {
   };
 }
 
+export interface MeterChannel {
+  rms_db: number;
+  peak_db: number;
+  peak_hold_db: number;
+}
+
+export interface InputLevel {
+  name: string;
+  left_db: number;
+  right_db: number;
+  muted: boolean;
+}
+
+export interface MeterReading {
+  left: MeterChannel;
+  right: MeterChannel;
+  /** 16 log-spaced bands from 31.5 Hz to 16 kHz, in dBFS */
+  spectrum?: number[];
+  /** Each mixed input, when inputs are configured */
+  inputs?: InputLevel[];
+}
+
 export interface AvailableNetwork {
   ssid: string;
   strength: number;